@@ -0,0 +1,62 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::error::Error;
+
+use bson::Bson;
+use tracing::info;
+use web3::types::{Block, BlockId, BlockNumber, H256, U64};
+use web3::Web3;
+
+use blocks_common::rpc::RpcTransport;
+use blocks_common::values::bson_to_decimal;
+use blocks_storage::{Filter, Query, Storage, Table};
+
+const PAGE: i64 = 1000;
+// what H256's Display puts between the first and last two bytes
+const ELLIPSIS: &str = "…";
+
+// Rows crawled before hashes were stored in full carry H256's Display form
+// (0x1234…abcd) in block_hash and txn_hash, which is also their key. Every such
+// row of blocks_table and txns_table is re-read from the node by block_num and
+// replaced by one with the full hashes. Run once with `blocks backfill-hashes`.
+pub async fn hashes(web3: &Web3<RpcTransport>, storage: &dyn Storage) -> Result<(), Box<dyn Error>> {
+    for (table, paths) in [(Table::Blocks, vec!["block_hash"]), (Table::Txns, vec!["block_hash", "txn_hash"])] {
+        let truncated = Filter::Or(paths.iter().map(|p| Filter::Contains(p.to_string(), ELLIPSIS.to_owned())).collect());
+        let key = table.schema().key[0];
+        let mut blocks = HashMap::<u64, Block<H256>>::new();
+        let mut fixed = 0;
+        loop {
+            let rows = storage.find(table, Query::new().filter(truncated.clone()).sort("block_num", true).limit(PAGE)).await?;
+            if rows.is_empty() {
+                break;
+            }
+            for row in rows {
+                let bnum = row.get("block_num").and_then(bson_to_decimal).and_then(|n| n.parse::<u64>().ok())
+                    .ok_or_else(|| format!("{} row without a block_num: {}", table.schema().name, row))?;
+                let block = match blocks.entry(bnum) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(
+                        web3.eth().block(BlockId::Number(BlockNumber::Number(U64::from(bnum)))).await?
+                            .ok_or_else(|| format!("block {} not found", bnum))?,
+                    ),
+                };
+
+                let mut full = row.clone();
+                full.remove("_id");
+                full.insert("block_hash", format!("{:?}", block.hash.unwrap_or_default()));
+                if let Ok(txn_hash) = row.get_str("txn_hash") {
+                    let hash = block.transactions.iter().find(|h| h.to_string() == txn_hash || format!("{:?}", h) == txn_hash)
+                        .ok_or_else(|| format!("no transaction {} in block {}", txn_hash, bnum))?;
+                    full.insert("txn_hash", format!("{:?}", hash));
+                }
+                // the full row goes in first, a crawler run since may have stored it already
+                storage.insert(table, vec![full]).await?;
+                storage.delete(table, vec![Filter::eq(key, row.get(key).cloned().unwrap_or(Bson::Null))]).await?;
+                fixed += 1;
+            }
+            // at most a page worth of blocks is kept around
+            blocks.clear();
+        }
+        info!(table = table.schema().name, fixed, "backfilled full hashes");
+    }
+    Ok(())
+}
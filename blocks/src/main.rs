@@ -1,174 +1,251 @@
-use mongodb::{options::{ClientOptions, InsertOneOptions, ResolverConfig}, Client};
-use std::{env, str::FromStr, sync::Arc};
+use std::env;
+use std::sync::Arc;
 use std::error::Error;
+use std::time::Duration;
 
-use mongodb::bson::doc;
-use web3::{transports::Http, types::{BlockId, H160, U64}};
-use web3::types::{Block, BlockNumber, Transaction};
+use bson::{doc, Document};
+use web3::types::{BlockId, U64};
+use web3::types::{Block, BlockNumber, CallRequest, Transaction};
 use web3::Web3;
-use web3::contract::Contract;
 
-use serde_json::Value;
-use std::fs::File;
-use std::io::Read;
 use tokio::task;
-
-use tokio;
-
-mod logger;
-use logger::{LogLevel, FileLogger};
-
-use crate::logger::Logger;
-
-// DB related constants
-const DB_NAME: &str = "Nexa_Diagnostics";
-const TXN_COLLECTION: &str = "txns_table";
-const EVENT_COLLECTION: &str = "events_table";
-const BLOCK_COLLECTION: &str = "blocks_table";
-
-// log file path constants
-const ERR_LOG_FILE: &str = "./logs/error.log";
-const INFO_LOG_FILE: &str = "./logs/info.log";
-const DEBUG_LOG_FILE: &str = "./logs/debug.log";
-const WARN_LOG_FILE: &str = "./logs/warning.log";
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use blocks_common::admin;
+use blocks_common::calldata::decode_input;
+use blocks_common::logging::{self, LogLevel, Logger, TracingLogger};
+use blocks_common::metrics::RANGE_RETRIES;
+use blocks_common::progress::{end_block, watch_head, Progress};
+use blocks_common::registry::ContractRegistry;
+use blocks_common::revert::{decode_revert, revert_data};
+use blocks_common::rpc::{self, RpcTransport};
+use blocks_common::status::{publish_every, Reporter};
+use blocks_common::timestamps::to_datetime;
+use blocks_storage::{Storage, Table};
+
+mod backfill;
+mod trace;
+use trace::Tracer;
+
+const ADMIN_ADDR: &str = "0.0.0.0:9100";
+const HEAD_POLL_INTERVAL: Duration = Duration::from_secs(15);
+const STATUS_INTERVAL: Duration = Duration::from_secs(15);
+// a block that can't be read is tried this often, the delay doubling from
+// FIRST_RETRY_DELAY, then left failed so the range moves on
+const MAX_ATTEMPTS: u32 = 6;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-   let client_uri =
-      env::var("MONGODB_URI").expect("You must set the MONGODB_URI environment var!");
-
-   let options =
-      ClientOptions::parse_with_resolver_config(&client_uri, ResolverConfig::cloudflare())
-         .await?;
-   let client = Arc::new(Client::with_options(options)?);
-
-
-    let http_transport = Http::new("http://3.20.201.137:8545").unwrap();
-    let web3 = Arc::new(Web3::new(http_transport));
-
-    let mut file = File::open("./src/abi.json").expect("Failed to open ABI file");
-    let mut abi_string = String::new();
-    file.read_to_string(&mut abi_string).expect("Failed to read ABI file");
-
-    let abi_json: Value = serde_json::from_str(&abi_string).expect("Failed to parse ABI JSON");
-    let abi_json_string = serde_json::to_string(&abi_json).expect("Failed to serialize ABI JSON");
-    // println!("ABI JSON Value: {}", abi_json);
-
-    let contract_address = H160::from_str("0xDB54D3Ce2035509d83F86bc982adc62F4AEBe03c")?;
-    let contract_abi = abi_json_string.as_bytes();
-    let contract = Arc::new(Contract::from_json(
-        web3.eth(),
-        contract_address,
-        contract_abi,
-    )?);
-    let mut event_counter: u64 = 0;
+   // LOG_LEVEL, LOG_FORMAT, LOG_SINKS, LOG_DIR, ... see blocks_common::logging
+   let _log_guard = logging::init("blocks")?;
+   // `blocks cache prune [max size]` and `blocks cache stats` manage RPC_CACHE_PATH
+   if let Some(result) = rpc::cache::command(&env::args().collect::<Vec<_>>()) {
+       return result;
+   }
+   let storage = blocks_storage::from_env().await?;
+
+
+    // RPC_PROVIDERS_PATH replaces the default node with a pool
+    let web3 = Arc::new(rpc::from_env(Some("http://3.20.201.137:8545"))?);
+    // `blocks backfill-hashes` repairs rows stored with truncated hashes, see backfill.rs
+    if env::args().nth(1).as_deref() == Some("backfill-hashes") {
+        return backfill::hashes(web3.as_ref(), storage.as_ref()).await;
+    }
+
+    let registry = Arc::new(ContractRegistry::from_env(web3.as_ref()).await?);
+    let tracer = Arc::new(Tracer::from_env());
     let start_block_height: u64 = 1543162;
-    let block_height = web3.eth().block_number().await.unwrap().as_u64();
+    // END_BLOCK runs without asking the node for its head
+    let block_height = end_block(web3.as_ref(), start_block_height).await?;
     let total = block_height - start_block_height;
     let each = total / 1000;
 
-    println!("total blocks to read: {}", total);
-    let mut tasks = Vec::new();
+    info!(total, "total blocks to read");
 
-    // let logger = Arc::new(RefCell::new(FileLogger::new(INFO_LOG_FILE, ERR_LOG_FILE)?));
-    let mut logger = Arc::new(FileLogger::new(INFO_LOG_FILE, ERR_LOG_FILE)?);
+    let progress = Arc::new(Progress::new(start_block_height));
+    progress.set_head(block_height);
+    let (_web3, _progress) = (web3.as_ref().clone(), progress.clone());
+    task::spawn(async move { watch_head(_web3, &_progress, HEAD_POLL_INTERVAL).await });
+
+    // /metrics, /healthz, /readyz and /status on ADMIN_ADDR
+    let reporter = Arc::new(Reporter::new("blocks", &registry, progress.clone(), web3.as_ref().clone(), storage.clone()));
+    admin::spawn(admin::addr_from_env(ADMIN_ADDR), reporter.clone());
+    task::spawn(publish_every(reporter.clone(), STATUS_INTERVAL));
+
+    let mut tasks = Vec::new();
 
     for i in 0..1000u64 {
         let _web3 = web3.clone();
-        let _contract = contract.clone();
-        let _client = client.clone();
-        
-        let mut _logger = logger.clone();
+        let _registry = registry.clone();
+        let _tracer = tracer.clone();
+        let _storage = storage.clone();
+        let _progress = progress.clone();
+        let (start, end) = (start_block_height + (i * each), (i + 1 + start_block_height) * 800);
         tasks.push(task::spawn(async move {
-            process_range(start_block_height + (i * each), (i + 1 + start_block_height) * 800, &_web3, &_contract, &_client, &_logger).await;
-        }));
+            process_range(start, end, &_web3, &_registry, &_tracer, _storage.as_ref(), &_progress).await;
+        }.instrument(info_span!("range", start, end))));
     }
 
     web3::block_on(async {
-        for mut task in tasks {
-            task.await;
+        for task in tasks {
+            if let Err(err) = task.await {
+                error!("Range task failed: {}", err);
+            }
         }
     });
 
-    println!("Done!");
+    if let Err(err) = reporter.publish().await {
+        error!("Failed to publish indexer status: {}", err);
+    }
+    info!("Done!");
 
    Ok(())
 }
 
-async fn process_range(start: u64, end: u64, web3: &Web3<Http>, contract: &Contract<Http>, client: &Client, logger: &FileLogger) {
-    
-    let db = client.database(DB_NAME);
+async fn process_range(start: u64, end: u64, web3: &Web3<RpcTransport>, registry: &ContractRegistry, tracer: &Tracer, storage: &dyn Storage, progress: &Progress) {
     for bnum in start..=end {
-        println!("Block #: {} ", bnum);
-        let bnum_str = format!("{}", bnum);
-
-        let block: Block<Transaction> = web3.eth().block_with_txs(BlockId::Number(BlockNumber::Number(U64::from(bnum)))).await.unwrap().unwrap();
-        let block_hash = block.hash.unwrap().to_string();
-        
-        let mut num_of_events_in_a_block = 0;
-        let mut num_of_txns_in_a_block = 0;
-        let mut event_signatures = Vec::<String>::new();
-
-        for tx in block.transactions {
-            num_of_txns_in_a_block +=1 ;
-          
-            let receipt = web3.eth().transaction_receipt(tx.hash).await.unwrap();
-            let logs = receipt.unwrap().logs;
-            for _log in logs {
-                //println!("log address: {:?}", log.address);
-                
-
-                if _log.address == contract.address() {
-                    // println!("match with contract");
-                    logger.log(LogLevel::Info, "contract matched").await;
-                    // let topics = &log.topics;
-                    // let data = &log.data;
-                    // println!("# of Events: {}", contract.abi().events().map(|_| 1).collect::<Vec<u32>>().len());
-                    
-                    
-                    for event in contract.abi().events() {
-                        num_of_events_in_a_block += 1;
-                        let expected_signature = format!("{}({})", event.name, event.inputs.iter().map(|i| i.kind.to_string()).collect::<Vec<_>>().join(","));
-                        //let log_signature = keccak256(expected_signature.as_bytes());
-                        event_signatures.push(expected_signature);
-                    }
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match process_block(bnum, web3, registry, tracer, storage, &TracingLogger).instrument(info_span!("block", number = bnum)).await {
+                Ok(true) => progress.complete(bnum, bnum),
+                // a block with failed writes stays outstanding and holds the indexed block back
+                Ok(false) => progress.fail(bnum, bnum),
+                // past the head, missing on the node or failing the same way every time
+                Err(err) if attempts >= MAX_ATTEMPTS => {
+                    error!(block = bnum, attempts, "Error reading block, giving up: {}", err);
+                    progress.fail(bnum, bnum);
+                }
+                // inserts skip duplicates, so the whole block is read again
+                Err(err) => {
+                    warn!(block = bnum, attempts, "Error reading block, retrying: {}", err);
+                    RANGE_RETRIES.inc();
+                    tokio::time::sleep(FIRST_RETRY_DELAY * 2u32.pow(attempts - 1)).await;
+                    continue;
                 }
+            }
+            break;
+        }
+    }
+}
+
+async fn process_block(bnum: u64, web3: &Web3<RpcTransport>, registry: &ContractRegistry, tracer: &Tracer, storage: &dyn Storage, logger: &TracingLogger) -> Result<bool, web3::Error> {
+    debug!("reading block");
+    let mut ok = true;
+
+    let block: Block<Transaction> = web3.eth().block_with_txs(BlockId::Number(BlockNumber::Number(U64::from(bnum)))).await?
+        .ok_or_else(|| web3::Error::InvalidResponse(format!("block {} not found", bnum)))?;
+    let block_hash = format!("{:?}", block.hash.unwrap());
+    let block_time = to_datetime(block.timestamp)?;
+
+    let internal_txns = tracer.internal_txns(web3, &block, logger).await?;
+    if let Err(err) = storage.insert(Table::InternalTxns, internal_txns).await {
+        ok = false;
+        logger.log(LogLevel::Err, &format!("Error writing into {}: {}", Table::InternalTxns.schema().name, err)).await
+    }
     
+    let mut num_of_events_in_a_block = 0;
+    let mut num_of_txns_in_a_block = 0;
+    let mut event_signatures = Vec::<String>::new();
+    let mut tx_docs = Vec::<Document>::new();
+
+    for tx in block.transactions {
+        num_of_txns_in_a_block +=1 ;
+      
+        let receipt = web3.eth().transaction_receipt(tx.hash).await?
+            .ok_or_else(|| web3::Error::InvalidResponse(format!("no receipt for {:?}", tx.hash)))?;
+        let failed = receipt.status == Some(U64::zero());
+        for _log in receipt.logs {
+            //println!("log address: {:?}", log.address);
+            
+
+            if let Some(contract) = registry.get(&_log.address) {
+                // println!("match with contract");
+                debug!(contract = ?_log.address, "contract matched");
+                // let topics = &log.topics;
+                // let data = &log.data;
+                // println!("# of Events: {}", contract.abi().events().map(|_| 1).collect::<Vec<u32>>().len());
                 
+                
+                for event in contract.abi_at(bnum).abi.events() {
+                    num_of_events_in_a_block += 1;
+                    let expected_signature = format!("{}({})", event.name, event.inputs.iter().map(|i| i.kind.to_string()).collect::<Vec<_>>().join(","));
+                    //let log_signature = keccak256(expected_signature.as_bytes());
+                    event_signatures.push(expected_signature);
+                }
             }
-            let tx_hash = tx.hash.to_string();
-            let tx_doc =  doc! {
-                "block_num": bnum_str.as_str(),
-                "block_hash": block_hash.as_str(),
-                "txn_hash": tx_hash,
-            };
-            let h =  db.collection(TXN_COLLECTION).insert_one(tx_doc, InsertOneOptions::default()).await;
-            if h.is_err() {
-                logger.log(LogLevel::Err, format!("{:#?}", h.err()).as_str()).await
-            }
-        }
 
-        let blk_doc =  doc! {
-            "block_num": bnum_str,
-            "block_hash": block_hash,
-            "num_of_transactions": num_of_txns_in_a_block,
-            "num_of_events": num_of_events_in_a_block,
-            "event_signatures": event_signatures.join("::").as_str(),
+            
+        }
+        let tx_hash = format!("{:?}", tx.hash);
+        let mut tx_doc =  doc! {
+            "block_num": bnum as i64,
+            "block_hash": block_hash.as_str(),
+            "block_time": block_time,
+            "txn_hash": tx_hash,
         };
-        let h =  db.collection(BLOCK_COLLECTION).insert_one(blk_doc, InsertOneOptions::default()).await;
-        
-        if h.is_err() {
-            logger.log(LogLevel::Err, &format!("Error writing into {}: {:#?}", BLOCK_COLLECTION, h.err())).await
+        tx_doc.extend(decode_input(registry, tx.to.as_ref(), &tx.input.0));
+        tx_doc.insert("status", if failed { 0 } else { 1 });
+        if failed {
+            tx_doc.insert("revert", replay_failed_tx(web3, registry, &tx, bnum).await);
         }
-        logger.log(
-            LogLevel::Info, 
-            &format!(
-                "Block Num: {}\nNum of Txns: {}\nNum of Events: {}", 
-                bnum, 
-                num_of_txns_in_a_block, 
-                num_of_events_in_a_block
-            )
-        ).await;
-        
+        tx_docs.push(tx_doc);
+    }
+    if let Err(err) = storage.insert(Table::Txns, tx_docs).await {
+        ok = false;
+        logger.log(LogLevel::Err, &format!("Error writing into {}: {}", Table::Txns.schema().name, err)).await
+    }
+
+    let blk_doc =  doc! {
+        "block_num": bnum as i64,
+        "block_hash": block_hash,
+        "block_time": block_time,
+        "num_of_transactions": num_of_txns_in_a_block,
+        "num_of_events": num_of_events_in_a_block,
+        "event_signatures": event_signatures.join("::").as_str(),
+    };
+    if let Err(err) = storage.insert(Table::Blocks, vec![blk_doc]).await {
+        ok = false;
+        logger.log(LogLevel::Err, &format!("Error writing into {}: {}", Table::Blocks.schema().name, err)).await
     }
-}
\ No newline at end of file
+    logger.log(
+        LogLevel::Info, 
+        &format!(
+            "Block Num: {}\nNum of Txns: {}\nNum of Events: {}", 
+            bnum, 
+            num_of_txns_in_a_block, 
+            num_of_events_in_a_block
+        )
+    ).await;
+    Ok(ok)
+}
+
+// Re-runs a failed tx with eth_call on top of its parent block to get the revert
+// payload. Earlier txs of the same block aren't applied, so the replay can pass
+// where the original failed; that is recorded as "not_reproduced".
+async fn replay_failed_tx(web3: &Web3<RpcTransport>, registry: &ContractRegistry, tx: &Transaction, bnum: u64) -> Document {
+    let call = CallRequest {
+        from: tx.from,
+        to: tx.to,
+        gas: Some(tx.gas),
+        value: Some(tx.value),
+        data: Some(tx.input.clone()),
+        ..Default::default()
+    };
+    let parent = BlockId::Number(BlockNumber::Number(U64::from(bnum.saturating_sub(1))));
+
+    match web3.eth().call(call, Some(parent)).await {
+        Ok(_) => doc! {
+            "kind": "not_reproduced",
+            "reason": "replay at parent block succeeded",
+        },
+        Err(err) => match revert_data(&err) {
+            Some(data) => decode_revert(registry, tx.to.as_ref(), &data),
+            None => doc! {
+                "kind": "unknown",
+                "reason": err.to_string(),
+            },
+        },
+    }
+}
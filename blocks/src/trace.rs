@@ -0,0 +1,268 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use hex::encode;
+use blocks_common::timestamps::to_datetime;
+use bson::{doc, Bson, DateTime, Document};
+use serde::Deserialize;
+use serde_json::json;
+use web3::error::Error as Web3Error;
+use web3::types::{Action, Block, BlockNumber, Bytes, CallType, Res, Transaction, H160, H256, U256, U64};
+use web3::{Transport, Web3};
+
+use blocks_common::logging::{LogLevel, Logger, TracingLogger};
+use blocks_common::rpc::RpcTransport;
+use blocks_common::values::{uint_sort_key, uint_to_bson};
+
+// json-rpc "method not found"
+const METHOD_NOT_FOUND: i64 = -32601;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceMode {
+    Off,
+    // debug_traceTransaction with the geth callTracer, one call per tx
+    CallTracer,
+    // trace_block (parity/erigon/nethermind), one call per block
+    Parity,
+}
+
+pub struct Tracer {
+    mode: TraceMode,
+    enabled: AtomicBool,
+}
+
+// one flattened call frame, the tx itself (depth 0) is not kept
+struct InternalTx {
+    txn_hash: H256,
+    trace_address: Vec<usize>,
+    call_type: String,
+    from: H160,
+    to: Option<H160>,
+    value: U256,
+    gas: Option<U256>,
+    gas_used: Option<U256>,
+    input: Bytes,
+    output: Option<Bytes>,
+    error: Option<String>,
+}
+
+// callTracer frame as returned by geth
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    #[serde(rename = "type")]
+    call_type: String,
+    from: H160,
+    to: Option<H160>,
+    value: Option<U256>,
+    gas: Option<U256>,
+    gas_used: Option<U256>,
+    #[serde(default)]
+    input: Bytes,
+    output: Option<Bytes>,
+    error: Option<String>,
+    #[serde(default)]
+    calls: Vec<CallFrame>,
+}
+
+impl Tracer {
+    // TRACE_MODE = off (default) | calltracer | parity
+    pub fn from_env() -> Self {
+        let mode = match env::var("TRACE_MODE").unwrap_or_default().to_lowercase().as_str() {
+            "calltracer" | "call_tracer" | "debug" => TraceMode::CallTracer,
+            "parity" | "trace_block" => TraceMode::Parity,
+            _ => TraceMode::Off,
+        };
+        Tracer { mode, enabled: AtomicBool::new(mode != TraceMode::Off) }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    // Internal transaction documents of a block. Returns nothing once the node
+    // turned out not to expose the tracing namespace; any other failure is an
+    // error so the block is read again rather than stored without its traces.
+    pub async fn internal_txns(&self, web3: &Web3<RpcTransport>, block: &Block<Transaction>, logger: &TracingLogger) -> Result<Vec<Document>, Web3Error> {
+        if !self.is_enabled() {
+            return Ok(Vec::new());
+        }
+        let bnum = block.number.unwrap_or_default();
+        let block_hash = format!("{:?}", block.hash.unwrap_or_default());
+        let block_time = to_datetime(block.timestamp)?;
+
+        let traced = match self.mode {
+            TraceMode::Parity => self.trace_block(web3, bnum).await,
+            TraceMode::CallTracer => {
+                let mut all = Vec::new();
+                let mut res = Ok(());
+                for tx in block.transactions.iter() {
+                    match self.trace_transaction(web3, tx.hash).await {
+                        Ok(mut itxs) => all.append(&mut itxs),
+                        Err(err) => {
+                            res = Err(err);
+                            break;
+                        }
+                    }
+                }
+                res.map(|_| all)
+            }
+            TraceMode::Off => Ok(Vec::new()),
+        };
+
+        match traced {
+            Ok(itxs) => Ok(itxs.iter().map(|itx| itx.to_document(bnum.as_u64(), &block_hash, block_time)).collect()),
+            Err(err) if is_unsupported(&err) => {
+                // only the first task to notice logs it
+                if self.enabled.swap(false, Ordering::Relaxed) {
+                    logger.log(LogLevel::Err, &format!("Tracing disabled, node doesn't support {:?} tracing: {}", self.mode, err)).await;
+                }
+                Ok(Vec::new())
+            }
+            Err(err) => {
+                logger.log(LogLevel::Err, &format!("Failed to trace block {}: {}", bnum, err)).await;
+                Err(err)
+            }
+        }
+    }
+
+    async fn trace_transaction(&self, web3: &Web3<RpcTransport>, hash: H256) -> Result<Vec<InternalTx>, Web3Error> {
+        let params = vec![json!(hash), json!({ "tracer": "callTracer" })];
+        let res = web3.transport().execute("debug_traceTransaction", params).await?;
+        let root: CallFrame = serde_json::from_value(res).map_err(|e| Web3Error::Decoder(e.to_string()))?;
+
+        let mut itxs = Vec::new();
+        for (i, call) in root.calls.into_iter().enumerate() {
+            flatten_frame(hash, call, vec![i], &mut itxs);
+        }
+        Ok(itxs)
+    }
+
+    async fn trace_block(&self, web3: &Web3<RpcTransport>, bnum: U64) -> Result<Vec<InternalTx>, Web3Error> {
+        let traces = web3.trace().block(BlockNumber::Number(bnum)).await?;
+
+        Ok(traces.into_iter().filter(|t| !t.trace_address.is_empty()).filter_map(|t| {
+            let txn_hash = t.transaction_hash?;
+            let (gas_used, output) = match t.result {
+                Some(Res::Call(r)) => (Some(r.gas_used), Some(r.output)),
+                Some(Res::Create(r)) => (Some(r.gas_used), Some(r.code)),
+                _ => (None, None),
+            };
+            let itx = match t.action {
+                Action::Call(c) => InternalTx {
+                    txn_hash,
+                    trace_address: t.trace_address,
+                    call_type: call_type_name(&c.call_type).to_owned(),
+                    from: c.from,
+                    to: Some(c.to),
+                    value: c.value,
+                    gas: Some(c.gas),
+                    gas_used,
+                    input: c.input,
+                    output,
+                    error: t.error,
+                },
+                Action::Create(c) => InternalTx {
+                    txn_hash,
+                    trace_address: t.trace_address,
+                    call_type: "create".to_owned(),
+                    from: c.from,
+                    to: None,
+                    value: c.value,
+                    gas: Some(c.gas),
+                    gas_used,
+                    input: c.init,
+                    output,
+                    error: t.error,
+                },
+                Action::Suicide(s) => InternalTx {
+                    txn_hash,
+                    trace_address: t.trace_address,
+                    call_type: "selfdestruct".to_owned(),
+                    from: s.address,
+                    to: Some(s.refund_address),
+                    value: s.balance,
+                    gas: None,
+                    gas_used: None,
+                    input: Bytes::default(),
+                    output: None,
+                    error: t.error,
+                },
+                Action::Reward(_) => return None,
+            };
+            Some(itx)
+        }).collect())
+    }
+}
+
+impl InternalTx {
+    fn to_document(&self, bnum: u64, block_hash: &str, block_time: DateTime) -> Document {
+        let opt_u256 = |v: Option<U256>| v.as_ref().map(uint_to_bson).unwrap_or(Bson::Null);
+        doc! {
+            "block_num": bnum as i64,
+            "block_hash": block_hash,
+            "block_time": block_time,
+            "txn_hash": format!("{:?}", self.txn_hash),
+            "depth": self.trace_address.len() as i32,
+            "trace_address": self.trace_address.iter().map(|i| *i as i64).collect::<Vec<_>>(),
+            "call_type": self.call_type.as_str(),
+            "from": format!("{:?}", self.from),
+            "to": self.to.map(|to| Bson::String(format!("{:?}", to))).unwrap_or(Bson::Null),
+            "value": uint_to_bson(&self.value),
+            "value_sort": uint_sort_key(&self.value),
+            "gas": opt_u256(self.gas),
+            "gas_used": opt_u256(self.gas_used),
+            "input": format!("0x{}", encode(&self.input.0)),
+            "output": self.output.as_ref().map(|o| Bson::String(format!("0x{}", encode(&o.0)))).unwrap_or(Bson::Null),
+            "error": self.error.as_ref().map(|e| Bson::String(e.clone())).unwrap_or(Bson::Null),
+        }
+    }
+}
+
+// depth first, so a tx's internal calls come out in execution order
+fn flatten_frame(txn_hash: H256, frame: CallFrame, trace_address: Vec<usize>, out: &mut Vec<InternalTx>) {
+    let CallFrame { call_type, from, to, value, gas, gas_used, input, output, error, calls } = frame;
+    out.push(InternalTx {
+        txn_hash,
+        trace_address: trace_address.clone(),
+        call_type: call_type.to_lowercase(),
+        from,
+        to,
+        value: value.unwrap_or_default(),
+        gas,
+        gas_used,
+        input,
+        output,
+        error,
+    });
+    for (i, call) in calls.into_iter().enumerate() {
+        let mut child = trace_address.clone();
+        child.push(i);
+        flatten_frame(txn_hash, call, child, out);
+    }
+}
+
+fn call_type_name(call_type: &CallType) -> &'static str {
+    match call_type {
+        CallType::Call => "call",
+        CallType::CallCode => "callcode",
+        CallType::DelegateCall => "delegatecall",
+        CallType::StaticCall => "staticcall",
+        CallType::None => "none",
+    }
+}
+
+// method not found / namespace not enabled on this node
+fn is_unsupported(err: &Web3Error) -> bool {
+    match err {
+        Web3Error::Rpc(rpc_err) => {
+            let msg = rpc_err.message.to_lowercase();
+            rpc_err.code.code() == METHOD_NOT_FOUND
+                || msg.contains("does not exist")
+                || msg.contains("not available")
+                || msg.contains("not enabled")
+                || msg.contains("not supported")
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,425 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ethabi::Contract as Abi;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use web3::signing::keccak256;
+use web3::types::H160;
+use web3::{Transport, Web3};
+
+// files bigger than one unixfs chunk get a different ipfs hash, solc metadata never is
+const IPFS_CHUNK: usize = 262_144;
+// build-info holds whole compiler inputs and outputs, node_modules other projects
+const SKIPPED_DIRS: [&str; 3] = ["build-info", "node_modules", "cache"];
+
+// (start, length) byte ranges of library links and immutables
+type Ranges = Vec<(usize, usize)>;
+
+// A compiled contract found under ABI_SOURCES: a Hardhat or Foundry build
+// artifact, a Solidity metadata json or a metadata.json of a Sourcify tree.
+pub struct Artifact {
+    pub name: String,
+    pub path: PathBuf,
+    pub abi: Abi,
+    // deployed bytecode, library links and immutables zeroed
+    code: Vec<u8>,
+    masked: Ranges,
+    // ipfs multihash of the metadata json, solc embeds it at the end of the code
+    metadata_hash: Option<Vec<u8>>,
+    // chain id and address from a Sourcify path, .../full_match/<chain>/<address>/metadata.json
+    deployed_at: Option<(u64, H160)>,
+}
+
+impl Artifact {
+    fn from_json(path: &Path, raw: &[u8]) -> Result<Option<Self>, Box<dyn Error>> {
+        let value: Value = serde_json::from_slice(raw)?;
+        let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+
+        // hardhat has a hex string, foundry an object with the immutable references
+        if let Some(abi) = value.get("abi") {
+            let (hex, immutables) = match value.get("deployedBytecode") {
+                Some(Value::String(hex)) => (hex.as_str(), None),
+                Some(Value::Object(obj)) => (obj.get("object").and_then(Value::as_str).unwrap_or_default(), obj.get("immutableReferences")),
+                _ => ("", None),
+            };
+            let (code, mut masked) = decode_code(hex)?;
+            // interfaces and abstract contracts have no code to match
+            if code.is_empty() {
+                return Ok(None);
+            }
+            masked.extend(immutables.and_then(Value::as_object).into_iter().flat_map(|refs| refs.values())
+                .filter_map(Value::as_array).flatten()
+                .filter_map(|r| Some((r.get("start")?.as_u64()? as usize, r.get("length")?.as_u64()? as usize))));
+            let name = value.get("contractName").and_then(Value::as_str).unwrap_or(name);
+            let mut artifact = Artifact::new(name, path, serde_json::from_value(abi.clone())?, code, masked);
+            artifact.mask();
+            return Ok(Some(artifact));
+        }
+
+        if let Some(abi) = value.pointer("/output/abi") {
+            let name = value.pointer("/settings/compilationTarget").and_then(Value::as_object)
+                .and_then(|target| target.values().next()).and_then(Value::as_str).unwrap_or(name);
+            let mut artifact = Artifact::new(name, path, serde_json::from_value(abi.clone())?, Vec::new(), Vec::new());
+            artifact.metadata_hash = ipfs_hash(raw);
+            artifact.deployed_at = sourcify_location(path);
+            return Ok(Some(artifact));
+        }
+        Ok(None)
+    }
+
+    fn new(name: &str, path: &Path, abi: Abi, code: Vec<u8>, masked: Ranges) -> Self {
+        Artifact { name: name.to_owned(), path: path.to_owned(), abi, code, masked, metadata_hash: None, deployed_at: None }
+    }
+
+    fn mask(&mut self) {
+        self.code = masked(&self.code, &self.masked);
+    }
+
+    // Same code once links and immutables are zeroed, or the same metadata
+    // hash at the end of the code. The latter also holds when only the
+    // constructor arguments baked into immutables differ.
+    fn matches(&self, code: &[u8]) -> bool {
+        let tail = metadata_tail(code);
+        if let Some(hash) = &self.metadata_hash {
+            return tail.is_some_and(|tail| tail.windows(hash.len()).any(|w| w == hash.as_slice()));
+        }
+        if code.len() == self.code.len() && keccak256(&masked(code, &self.masked)) == keccak256(&self.code) {
+            return true;
+        }
+        tail.is_some_and(|tail| metadata_tail(&self.code) == Some(tail))
+    }
+}
+
+pub struct AbiSources {
+    artifacts: Vec<Artifact>,
+}
+
+impl AbiSources {
+    // ABI_SOURCES is a comma separated list of artifact directories or files
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        match env::var("ABI_SOURCES") {
+            Ok(paths) => Ok(Some(AbiSources::load(paths.split(',').map(str::trim).filter(|p| !p.is_empty()))?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn load<'a>(paths: impl Iterator<Item = &'a str>) -> Result<Self, Box<dyn Error>> {
+        let mut files = Vec::new();
+        for path in paths {
+            collect_json(Path::new(path), &mut files)?;
+        }
+        let mut artifacts = Vec::new();
+        for file in files {
+            match fs::read(&file).map_err(Box::<dyn Error>::from).and_then(|raw| Artifact::from_json(&file, &raw)) {
+                Ok(Some(artifact)) => artifacts.push(artifact),
+                Ok(None) => {}
+                Err(err) => tracing::debug!(path = %file.display(), "Skipping json that isn't an artifact: {}", err),
+            }
+        }
+        tracing::info!(artifacts = artifacts.len(), "Loaded ABI sources");
+        Ok(AbiSources { artifacts })
+    }
+
+    // A Sourcify entry for the address on this chain wins, otherwise the code
+    // deployed at the address is compared with every artifact.
+    pub async fn find<T: Transport>(&self, web3: &Web3<T>, address: H160) -> Result<Option<&Artifact>, Box<dyn Error>> {
+        if self.artifacts.iter().any(|a| a.deployed_at.is_some()) {
+            let chain_id = web3.eth().chain_id().await?.as_u64();
+            if let Some(artifact) = self.artifacts.iter().find(|a| a.deployed_at == Some((chain_id, address))) {
+                return Ok(Some(artifact));
+            }
+        }
+        let code = web3.eth().code(address, None).await?;
+        if code.0.is_empty() {
+            return Err(format!("no code deployed at {:?}", address).into());
+        }
+        Ok(self.artifacts.iter().find(|a| a.matches(&code.0)))
+    }
+
+    // the ABI of the contract deployed at `address`, an error when none matches
+    pub async fn abi_for<T: Transport>(&self, web3: &Web3<T>, address: H160) -> Result<Abi, Box<dyn Error>> {
+        let artifact = self.find(web3, address).await?.ok_or_else(|| format!("no artifact in ABI_SOURCES matches the code at {:?}", address))?;
+        tracing::info!(address = ?address, artifact = %artifact.name, path = %artifact.path.display(), "ABI found");
+        Ok(artifact.abi.clone())
+    }
+}
+
+fn collect_json(path: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if path.is_file() {
+        files.push(path.to_owned());
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        if path.is_dir() {
+            if !SKIPPED_DIRS.contains(&name) {
+                collect_json(&path, files)?;
+            }
+        } else if name.ends_with(".json") && !name.ends_with(".dbg.json") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// bytes of a "0x.." bytecode string, unlinked `__$..$__` library slots as zeroes
+fn decode_code(hex: &str) -> Result<(Vec<u8>, Ranges), hex::FromHexError> {
+    let hex = hex.trim_start_matches("0x");
+    let (mut code, mut links) = (Vec::with_capacity(hex.len() / 2), Vec::new());
+    let mut rest = hex;
+    while let Some(at) = rest.find("__") {
+        code.extend(hex::decode(&rest[..at])?);
+        links.push((code.len(), 20));
+        code.extend([0u8; 20]);
+        rest = &rest[(at + 40).min(rest.len())..];
+    }
+    code.extend(hex::decode(rest)?);
+    Ok((code, links))
+}
+
+fn masked(code: &[u8], ranges: &[(usize, usize)]) -> Vec<u8> {
+    let mut code = code.to_vec();
+    for (start, len) in ranges {
+        if let Some(slot) = code.get_mut(*start..start + len) {
+            slot.fill(0);
+        }
+    }
+    code
+}
+
+// the CBOR encoded metadata solc appends, its length is in the last two bytes
+fn metadata_tail(code: &[u8]) -> Option<&[u8]> {
+    let len = u16::from_be_bytes(code.get(code.len().checked_sub(2)?..)?.try_into().ok()?) as usize;
+    let start = code.len().checked_sub(2 + len)?;
+    // a map whose first key is a short text string, "ipfs" or "bzzr0/1"
+    (len > 0 && code[start] & 0xf0 == 0xa0).then(|| &code[start..code.len() - 2])
+}
+
+// CIDv0 multihash of a single chunk file: sha256 over the dag-pb node wrapping
+// the unixfs file, as `ipfs add` and solc compute it
+fn ipfs_hash(raw: &[u8]) -> Option<Vec<u8>> {
+    if raw.len() > IPFS_CHUNK {
+        return None;
+    }
+    let mut unixfs = vec![0x08, 0x02, 0x12];
+    varint(raw.len(), &mut unixfs);
+    unixfs.extend_from_slice(raw);
+    unixfs.push(0x18);
+    varint(raw.len(), &mut unixfs);
+
+    let mut node = vec![0x0a];
+    varint(unixfs.len(), &mut node);
+    node.extend(unixfs);
+
+    let mut hash = vec![0x12, 0x20];
+    hash.extend(Sha256::digest(&node));
+    Some(hash)
+}
+
+fn varint(mut n: usize, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn sourcify_location(path: &Path) -> Option<(u64, H160)> {
+    let address = path.parent()?;
+    let chain = address.parent()?;
+    let address = H160::from_str(address.file_name()?.to_str()?).ok()?;
+    Some((chain.file_name()?.to_str()?.parse().ok()?, address))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures::future::{ready, Ready};
+    use jsonrpc_core::Call;
+    use serde_json::json;
+    use web3::{helpers, RequestId};
+
+    use super::*;
+
+    const ABI: &str = r#"[{"type": "function", "name": "ping", "inputs": [], "outputs": [], "stateMutability": "view"}]"#;
+    const LINK: &str = "__$0123456789abcdef0123456789abcdef01$__";
+
+    // solc's cbor metadata with `hash` as the ipfs entry, length included
+    fn tail(hash: &[u8]) -> String {
+        let mut cbor = vec![0xa2, 0x64];
+        cbor.extend(b"ipfs");
+        cbor.extend([0x58, hash.len() as u8]);
+        cbor.extend(hash);
+        cbor.push(0x64);
+        cbor.extend(b"solc");
+        cbor.extend([0x43, 0x00, 0x08, 0x13]);
+        cbor.extend((cbor.len() as u16).to_be_bytes());
+        hex::encode(cbor)
+    }
+
+    fn hash(byte: u8) -> Vec<u8> {
+        [vec![0x12, 0x20], vec![byte; 32]].concat()
+    }
+
+    fn hardhat() -> Value {
+        json!({ "contractName": "Token", "abi": serde_json::from_str::<Value>(ABI).unwrap(), "deployedBytecode": format!("0x6080{}6001{}", LINK, tail(&hash(1))) })
+    }
+
+    fn foundry() -> Value {
+        json!({
+            "abi": serde_json::from_str::<Value>(ABI).unwrap(),
+            "deployedBytecode": { "object": format!("0x6080{}00", "00".repeat(32)), "immutableReferences": { "7": [{ "start": 2, "length": 32 }] } },
+        })
+    }
+
+    fn metadata() -> Vec<u8> {
+        serde_json::to_vec(&json!({ "output": { "abi": serde_json::from_str::<Value>(ABI).unwrap() }, "settings": { "compilationTarget": { "src/Vault.sol": "Vault" } } })).unwrap()
+    }
+
+    fn artifact(path: &str, value: &Value) -> Option<Artifact> {
+        Artifact::from_json(Path::new(path), &serde_json::to_vec(value).unwrap()).unwrap()
+    }
+
+    fn code(hex: &str) -> Vec<u8> {
+        hex::decode(hex).unwrap()
+    }
+
+    #[test]
+    fn hashes_metadata_like_ipfs() {
+        // `echo "hello world" | ipfs add` gives QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o
+        assert_eq!(hex::encode(ipfs_hash(b"hello world\n").unwrap()), "122046d44814b9c5af141c3aaab7c05dc5e844ead5f91f12858b021eba45768b4c0e");
+        assert!(ipfs_hash(&vec![b' '; IPFS_CHUNK + 1]).is_none());
+    }
+
+    #[test]
+    fn finds_the_metadata_tail() {
+        let with_tail = code(&format!("6080{}", tail(&hash(1))));
+        assert_eq!(metadata_tail(&with_tail), Some(&with_tail[2..with_tail.len() - 2]));
+        assert_eq!(metadata_tail(&code("60806001")), None);
+        assert_eq!(metadata_tail(&code("ff")), None);
+        assert_eq!(metadata_tail(&code("0000")), None);
+    }
+
+    #[test]
+    fn zeroes_library_links() {
+        let (code, links) = decode_code(&format!("0x60{}01", LINK)).unwrap();
+        assert_eq!(code, [vec![0x60], vec![0; 20], vec![0x01]].concat());
+        assert_eq!(links, vec![(1, 20)]);
+        assert!(decode_code("0x6zz0").is_err());
+    }
+
+    #[test]
+    fn matches_deployed_code_up_to_links_and_immutables() {
+        let token = artifact("Token.json", &hardhat()).unwrap();
+        assert_eq!(token.name, "Token");
+        let linked = code(&format!("6080{}6001{}", "ab".repeat(20), tail(&hash(1))));
+        assert!(token.matches(&linked));
+        // other code with the same metadata hash, as when constructor args differ
+        assert!(token.matches(&code(&format!("60806002{}", tail(&hash(1))))));
+        assert!(!token.matches(&code(&format!("6080{}6001{}", "ab".repeat(20), tail(&hash(2))))));
+
+        let vault = artifact("out/Vault.sol/Vault.json", &foundry()).unwrap();
+        assert_eq!(vault.name, "Vault");
+        assert!(vault.matches(&code(&format!("6080{}00", "42".repeat(32)))));
+        assert!(!vault.matches(&code(&format!("6081{}00", "42".repeat(32)))));
+
+        let interface = json!({ "contractName": "IToken", "abi": [], "deployedBytecode": "0x" });
+        assert!(artifact("IToken.json", &interface).is_none());
+        assert!(artifact("package.json", &json!({ "name": "contracts" })).is_none());
+    }
+
+    #[test]
+    fn matches_sourcify_metadata_by_its_hash() {
+        let address = H160::repeat_byte(0x5a);
+        let path = format!("sourcify/full_match/1/{:?}/metadata.json", address);
+        let raw = metadata();
+        let vault = Artifact::from_json(Path::new(&path), &raw).unwrap().unwrap();
+        assert_eq!((vault.name.as_str(), vault.deployed_at), ("Vault", Some((1, address))));
+        let own = ipfs_hash(&raw).unwrap();
+        assert!(vault.matches(&code(&format!("6080{}", tail(&own)))));
+        assert!(!vault.matches(&code(&format!("6080{}", tail(&hash(1))))));
+        assert!(!vault.matches(&code("6080")));
+    }
+
+    // answers eth_chainId and eth_getCode from a map, empty code elsewhere
+    #[derive(Debug, Clone)]
+    struct Node {
+        chain_id: u64,
+        codes: HashMap<H160, String>,
+    }
+
+    impl Transport for Node {
+        type Out = Ready<web3::Result<Value>>;
+
+        fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+            (0, helpers::build_request(0, method, params))
+        }
+
+        fn send(&self, _: RequestId, call: Call) -> Self::Out {
+            let Call::MethodCall(call) = call else { panic!("{:?}", call) };
+            ready(Ok(match call.method.as_str() {
+                "eth_chainId" => json!(format!("{:#x}", self.chain_id)),
+                "eth_getCode" => {
+                    let (address, _): (H160, Value) = call.params.parse().unwrap();
+                    json!(format!("0x{}", self.codes.get(&address).map(String::as_str).unwrap_or_default()))
+                }
+                other => panic!("unexpected {}", other),
+            }))
+        }
+    }
+
+    fn write(dir: &Path, path: &str, raw: &[u8]) {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, raw).unwrap();
+    }
+
+    #[tokio::test]
+    async fn loads_artifact_trees_and_finds_the_deployed_abi() {
+        let dir = env::temp_dir().join(format!("abi_sources_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let sourcified = H160::repeat_byte(0x5a);
+        write(&dir, "artifacts/Token.json", &serde_json::to_vec(&hardhat()).unwrap());
+        write(&dir, "artifacts/Token.dbg.json", &serde_json::to_vec(&foundry()).unwrap());
+        write(&dir, "artifacts/build-info/abc.json", &serde_json::to_vec(&foundry()).unwrap());
+        write(&dir, "artifacts/broken.json", b"{ not json");
+        write(&dir, "out/Vault.sol/Vault.json", &serde_json::to_vec(&foundry()).unwrap());
+        write(&dir, &format!("sourcify/full_match/1/{:?}/metadata.json", sourcified), &metadata());
+
+        let paths = [dir.join("artifacts"), dir.join("out"), dir.join("sourcify")];
+        let sources = AbiSources::load(paths.iter().map(|p| p.to_str().unwrap())).unwrap();
+        let mut names: Vec<&str> = sources.artifacts.iter().map(|a| a.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["Token", "Vault", "Vault"]);
+
+        let (token, vault, unknown, empty) = (H160::repeat_byte(1), H160::repeat_byte(2), H160::repeat_byte(3), H160::repeat_byte(4));
+        let codes = HashMap::from([
+            (token, format!("6080{}6001{}", "ab".repeat(20), tail(&hash(1)))),
+            (vault, format!("6080{}00", "42".repeat(32))),
+            (unknown, "6080".to_owned()),
+        ]);
+        let web3 = Web3::new(Node { chain_id: 1, codes: codes.clone() });
+        let found = |address| {
+            let (sources, web3, dir) = (&sources, &web3, &dir);
+            async move { sources.find(web3, address).await.unwrap().map(|a| a.path.strip_prefix(dir).unwrap().to_owned()) }
+        };
+        assert_eq!(found(token).await, Some(PathBuf::from("artifacts/Token.json")));
+        assert_eq!(found(vault).await, Some(PathBuf::from("out/Vault.sol/Vault.json")));
+        assert_eq!(found(sourcified).await, Some(PathBuf::from(format!("sourcify/full_match/1/{:?}/metadata.json", sourcified))));
+        assert_eq!(found(unknown).await, None);
+        assert!(sources.find(&web3, empty).await.is_err());
+        assert_eq!(sources.abi_for(&web3, token).await.unwrap().functions().next().unwrap().name, "ping");
+        assert!(sources.abi_for(&web3, unknown).await.is_err());
+
+        // on another chain the sourcify entry doesn't apply and its address has no code
+        let web3 = Web3::new(Node { chain_id: 5, codes });
+        assert!(sources.find(&web3, sourcified).await.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
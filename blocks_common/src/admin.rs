@@ -0,0 +1,117 @@
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+
+use crate::metrics;
+use crate::status::{Health, Reporter};
+
+// ADMIN_ADDR overrides the per binary default
+pub fn addr_from_env(default: &str) -> SocketAddr {
+    let addr = env::var("ADMIN_ADDR").unwrap_or(default.to_owned());
+    addr.parse().unwrap_or_else(|_| panic!("ADMIN_ADDR `{}` is not a socket address", addr))
+}
+
+fn json<T: serde::Serialize>(status: StatusCode, body: &T) -> hyper::http::Result<Response<Body>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap_or_default()))
+}
+
+// /healthz answers 200 as long as the process serves requests and lists the
+// dependency checks; /readyz is 503 while any of them fails
+async fn handle(req: Request<Body>, reporter: Arc<Reporter>) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(header::CONTENT_TYPE, metrics::CONTENT_TYPE)
+            .body(Body::from(metrics::render())),
+        (&Method::GET, "/healthz") => json(StatusCode::OK, &Health::new(reporter.checks().await)),
+        (&Method::GET, "/readyz") => {
+            let health = Health::new(reporter.checks().await);
+            json(if health.ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE }, &health)
+        }
+        (&Method::GET, "/status") => json(StatusCode::OK, &reporter.status()),
+        _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::from("not found")),
+    };
+    Ok(response.unwrap_or_default())
+}
+
+// Small HTTP server next to the indexer for /metrics, /healthz, /readyz and
+// /status. Runs on the current tokio runtime until the process exits.
+pub fn spawn(addr: SocketAddr, reporter: Arc<Reporter>) {
+    let make_service = make_service_fn(move |_| {
+        let reporter = reporter.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(req, reporter.clone()))) }
+    });
+    match Server::try_bind(&addr) {
+        Ok(builder) => {
+            tracing::info!("Admin server listening on {}", addr);
+            tokio::spawn(async move {
+                if let Err(err) = builder.serve(make_service).await {
+                    tracing::error!("Admin server stopped: {}", err);
+                }
+            });
+        }
+        Err(err) => tracing::error!("Failed to bind admin server on {}: {}", addr, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks_storage::sqlite::SqliteStorage;
+    use ethabi::Contract as Abi;
+    use web3::types::H160;
+    use web3::Web3;
+
+    use super::*;
+    use crate::progress::Progress;
+    use crate::registry::ContractRegistry;
+    use crate::rpc::{CachedTransport, MeteredTransport, ProviderConfig, ProviderPool};
+
+    // nothing listens on port 1, so the rpc check fails right away
+    async fn reporter() -> Arc<Reporter> {
+        let mut registry = ContractRegistry::new();
+        registry.register("token", H160::repeat_byte(0xaa), Abi::default());
+        let progress = Arc::new(Progress::new(10));
+        progress.complete(10, 19);
+        progress.set_head(22);
+        let pool = ProviderPool::new(&[ProviderConfig::new("http://127.0.0.1:1")]).unwrap();
+        let web3 = Web3::new(CachedTransport::new(MeteredTransport::new(pool), None));
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        Arc::new(Reporter::new("blocks_one", &registry, progress, web3, Arc::new(storage)))
+    }
+
+    async fn get(reporter: &Arc<Reporter>, path: &str) -> (StatusCode, String, String) {
+        let req = Request::get(path).body(Body::empty()).unwrap();
+        let res = handle(req, reporter.clone()).await.unwrap();
+        let content_type = res.headers().get(header::CONTENT_TYPE).map(|v| v.to_str().unwrap().to_owned()).unwrap_or_default();
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, content_type, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_health_and_status() {
+        let reporter = reporter().await;
+        let (status, content_type, body) = get(&reporter, "/metrics").await;
+        assert_eq!((status, content_type.as_str()), (StatusCode::OK, metrics::CONTENT_TYPE));
+        assert!(body.contains("indexer_blocks_indexed_total"), "{}", body);
+
+        let (status, _, body) = get(&reporter, "/status").await;
+        assert_eq!(status, StatusCode::OK);
+        let rows: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(rows[0]["contract"], format!("{:?}", H160::repeat_byte(0xaa)));
+        assert_eq!((&rows[0]["mode"], &rows[0]["indexed_block"], &rows[0]["lag"]), (&"tail".into(), &19.into(), &3.into()));
+
+        // the process is alive but not ready without its node
+        let (status, _, body) = get(&reporter, "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#""name":"rpc","ok":false"#), "{}", body);
+        assert_eq!(get(&reporter, "/readyz").await.0, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(get(&reporter, "/nope").await.0, StatusCode::NOT_FOUND);
+    }
+}
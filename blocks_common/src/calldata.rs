@@ -0,0 +1,36 @@
+use bson::{doc, Bson, Document};
+use hex::encode;
+use web3::types::H160;
+
+use crate::registry::ContractRegistry;
+use crate::tokens::named_args;
+
+// Fields merged into a transaction document. The raw input is always kept,
+// `function`/`args` are only set when the selector matched a registered ABI.
+pub fn decode_input(registry: &ContractRegistry, to: Option<&H160>, input: &[u8]) -> Document {
+    let mut fields = doc! {
+        "input": format!("0x{}", encode(input)),
+    };
+    if input.len() < 4 {
+        fields.insert("selector", Bson::Null);
+        return fields;
+    }
+
+    let (selector, params) = input.split_at(4);
+    fields.insert("selector", format!("0x{}", encode(selector)));
+
+    if let Some((contract, function)) = registry.function_for(to, selector) {
+        match function.decode_input(params) {
+            Ok(tokens) => {
+                fields.insert("function", function.name.as_str());
+                fields.insert("function_signature", format!("{}({})", function.name, function.inputs.iter().map(|i| i.kind.to_string()).collect::<Vec<_>>().join(",")));
+                fields.insert("abi_contract", contract.name.as_str());
+                fields.insert("args", named_args(&function.inputs, &tokens));
+            }
+            Err(err) => {
+                fields.insert("decode_error", format!("{}: {}", function.name, err));
+            }
+        }
+    }
+    fields
+}
@@ -0,0 +1,165 @@
+use bson::{doc, Bson, DateTime, Document};
+use ethabi::RawLog;
+use web3::types::Log;
+
+use crate::registry::ContractRegistry;
+use crate::tokens::named_args;
+
+// A log of a registered contract decoded with the ABI it had at that block,
+// one decoded_events row. Unlike the events_table strings the args keep
+// their names and types, so they can be filtered and aggregated on.
+pub struct DecodedEvent {
+    pub block_number: u64,
+    pub block_hash: String,
+    pub block_time: Option<DateTime>,
+    pub tx_hash: Option<String>,
+    pub log_index: u64,
+    pub address: String,
+    pub contract: String,
+    pub abi_version: String,
+    pub event: String,
+    pub signature: String,
+    pub args: Document,
+}
+
+impl DecodedEvent {
+    // None for unknown events, pending logs and data that does not fit the ABI
+    pub fn decode(registry: &ContractRegistry, log: &Log, block_time: Option<DateTime>) -> Option<Self> {
+        let block_number = log.block_number?.as_u64();
+        let (contract, version, event) = registry.abi_event_at(&log.address, block_number, log.topics.first()?)?;
+        let parsed = event.parse_log(RawLog { topics: log.topics.clone(), data: log.data.0.clone() }).ok()?;
+        let (params, tokens): (Vec<_>, Vec<_>) = event.inputs.iter()
+            .zip(parsed.params)
+            .map(|(input, param)| (ethabi::Param { name: param.name, kind: input.kind.clone(), internal_type: None }, param.value))
+            .unzip();
+        Some(DecodedEvent {
+            block_number,
+            block_hash: format!("{:?}", log.block_hash?),
+            block_time,
+            tx_hash: log.transaction_hash.map(|h| format!("{:?}", h)),
+            log_index: log.log_index?.as_u64(),
+            address: format!("{:?}", log.address),
+            contract: contract.name.clone(),
+            abi_version: version.version.clone(),
+            event: event.name.clone(),
+            signature: format!("{}({})", event.name, event.inputs.iter().map(|i| i.kind.to_string()).collect::<Vec<_>>().join(",")),
+            args: named_args(&params, &tokens),
+        })
+    }
+
+    // back from a decoded_events document
+    pub fn from_document(doc: &Document) -> Option<Self> {
+        Some(DecodedEvent {
+            block_number: doc.get_i64("block_number").ok()? as u64,
+            block_hash: doc.get_str("block_hash").ok()?.to_owned(),
+            block_time: doc.get_datetime("block_time").ok().copied(),
+            tx_hash: doc.get_str("tx_hash").ok().map(str::to_owned),
+            log_index: doc.get_i64("log_index").ok()? as u64,
+            address: doc.get_str("address").ok()?.to_owned(),
+            contract: doc.get_str("contract").ok()?.to_owned(),
+            abi_version: doc.get_str("abi_version").ok()?.to_owned(),
+            event: doc.get_str("event").ok()?.to_owned(),
+            signature: doc.get_str("signature").ok()?.to_owned(),
+            args: doc.get_document("args").ok()?.clone(),
+        })
+    }
+
+    pub fn to_document(&self) -> Document {
+        doc! {
+            "block_number": self.block_number as i64,
+            "block_hash": &self.block_hash,
+            "block_time": self.block_time.map(Bson::DateTime).unwrap_or(Bson::Null),
+            "tx_hash": &self.tx_hash,
+            "log_index": self.log_index as i64,
+            "address": &self.address,
+            "contract": &self.contract,
+            "abi_version": &self.abi_version,
+            "event": &self.event,
+            "signature": &self.signature,
+            "args": self.args.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethabi::{encode, Contract as Abi, Token};
+    use web3::signing::keccak256;
+    use web3::types::{Bytes, H160, H256, U256};
+
+    use super::*;
+    use crate::values::uint_to_bson;
+
+    const ABI: &str = r#"[{"type":"event","name":"Transfer","anonymous":false,"inputs":[
+        {"name":"from","type":"address","indexed":true},
+        {"name":"to","type":"address","indexed":true},
+        {"name":"value","type":"uint256","indexed":false}]}]"#;
+
+    fn registry() -> ContractRegistry {
+        let mut registry = ContractRegistry::new();
+        registry.register("token", H160::repeat_byte(0xaa), serde_json::from_str::<Abi>(ABI).unwrap());
+        registry
+    }
+
+    fn transfer_log(value: U256) -> Log {
+        Log {
+            address: H160::repeat_byte(0xaa),
+            topics: vec![
+                H256(keccak256(b"Transfer(address,address,uint256)")),
+                H256::from(H160::repeat_byte(0x01)),
+                H256::from(H160::repeat_byte(0x02)),
+            ],
+            data: Bytes(encode(&[Token::Uint(value)])),
+            block_hash: Some(H256::repeat_byte(0xbb)),
+            block_number: Some(7.into()),
+            transaction_hash: Some(H256::repeat_byte(0xcc)),
+            transaction_index: None,
+            log_index: Some(3.into()),
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    #[test]
+    fn decodes_named_args_with_the_registered_abi() {
+        let event = DecodedEvent::decode(&registry(), &transfer_log(U256::MAX), None).unwrap();
+        assert_eq!((event.contract.as_str(), event.event.as_str()), ("token", "Transfer"));
+        assert_eq!(event.signature, "Transfer(address,address,uint256)");
+        assert_eq!((event.block_number, event.log_index), (7, 3));
+        assert_eq!(event.address, format!("{:?}", H160::repeat_byte(0xaa)));
+        assert_eq!(event.args, doc! {
+            "from": format!("{:?}", H160::repeat_byte(0x01)),
+            "to": format!("{:?}", H160::repeat_byte(0x02)),
+            "value": uint_to_bson(&U256::MAX),
+        });
+    }
+
+    #[test]
+    fn skips_logs_the_abi_does_not_describe() {
+        let mut unknown = transfer_log(1.into());
+        unknown.topics[0] = H256::repeat_byte(0x11);
+        assert!(DecodedEvent::decode(&registry(), &unknown, None).is_none());
+
+        let mut other_contract = transfer_log(1.into());
+        other_contract.address = H160::repeat_byte(0xab);
+        assert!(DecodedEvent::decode(&registry(), &other_contract, None).is_none());
+
+        let mut short = transfer_log(1.into());
+        short.data = Bytes(vec![0; 4]);
+        assert!(DecodedEvent::decode(&registry(), &short, None).is_none());
+
+        let mut pending = transfer_log(1.into());
+        pending.block_number = None;
+        assert!(DecodedEvent::decode(&registry(), &pending, None).is_none());
+    }
+
+    #[test]
+    fn round_trips_through_documents() {
+        let time = DateTime::from_millis(1_700_000_000_000);
+        let event = DecodedEvent::decode(&registry(), &transfer_log(1000.into()), Some(time)).unwrap();
+        let back = DecodedEvent::from_document(&event.to_document()).unwrap();
+        assert_eq!(back.to_document(), event.to_document());
+        assert_eq!(back.block_time, Some(time));
+    }
+}
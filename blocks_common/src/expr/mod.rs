@@ -0,0 +1,228 @@
+mod parse;
+
+use std::cmp::Ordering;
+
+use blocks_storage::{get_path, sort_key, Filter, Op};
+use bson::{Bson, Document};
+
+use crate::values::{bson_to_decimal, decimal_to_bson};
+
+pub use parse::{parse, ParseError};
+
+// decoded_events columns an expression can name, any other bare name is an
+// event argument
+pub const COLUMNS: [&str; 9] = ["block_number", "block_hash", "tx_hash", "log_index", "address", "contract", "abi_version", "event", "signature"];
+// plain integers in storage rather than Decimal128
+const INT_COLUMNS: [&str; 2] = ["block_number", "log_index"];
+
+// Conditions over decoded events, written like
+//
+//   event = "Transfer" and value > 10^18 and to = 0x00000000219ab540356cbb839cbe05303d7705fa
+//   contract in ["vault", "router"] and not (sender = 0x.. or amount < 1.5e18)
+//
+// Names are decoded_events columns (see COLUMNS) or named arguments, which
+// can also be written as args.<name> when a column shadows them. Integers
+// take any size, with `_` separators, exponents and powers (10^18, 2.5e6);
+// hex literals and strings of hex are lowercased like the stored addresses
+// and hashes. `parse` turns the text into an Expr, which compiles to a
+// storage Filter for queries and also evaluates against a document in
+// memory. Both compare integers by value whether they are stored as ints,
+// Decimal128 or decimal strings; every backend orders them that way too.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Cmp(String, Op, Literal),
+    In(String, Vec<Literal>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    // signed decimal
+    Int(String),
+    Str(String),
+    Bool(bool),
+    Null,
+}
+
+impl Literal {
+    // the form stored at `path`
+    fn to_bson(&self, path: &str) -> Bson {
+        match self {
+            Literal::Int(i) if INT_COLUMNS.contains(&path) => i.parse().map_or_else(|_| Bson::String(i.clone()), Bson::Int64),
+            Literal::Int(i) => decimal_to_bson(i.clone()),
+            Literal::Str(s) => Bson::String(s.clone()),
+            Literal::Bool(b) => Bson::Boolean(*b),
+            Literal::Null => Bson::Null,
+        }
+    }
+}
+
+// Integers compare by value whichever form they are stored in. Anything else
+// only compares for equality, hex strings like addresses regardless of case.
+pub fn order(a: &Bson, b: &Bson) -> Option<Ordering> {
+    Some(sort_key(&bson_to_decimal(a)?)?.cmp(&sort_key(&bson_to_decimal(b)?)?))
+}
+
+pub fn same(a: &Bson, b: &Bson) -> bool {
+    if let Some(ordering) = order(a, b) {
+        return ordering.is_eq();
+    }
+    match (a, b) {
+        (Bson::String(a), Bson::String(b)) if a.starts_with("0x") && b.starts_with("0x") => a.eq_ignore_ascii_case(b),
+        _ => a == b,
+    }
+}
+
+impl Expr {
+    pub fn to_filter(&self) -> Filter {
+        match self {
+            Expr::Cmp(path, op, literal) => Filter::Cmp(path.clone(), *op, literal.to_bson(path)),
+            Expr::In(path, literals) => Filter::In(path.clone(), literals.iter().map(|l| l.to_bson(path)).collect()),
+            Expr::And(exprs) => Filter::And(exprs.iter().map(Expr::to_filter).collect()),
+            Expr::Or(exprs) => Filter::Or(exprs.iter().map(Expr::to_filter).collect()),
+            Expr::Not(expr) => Filter::Not(Box::new(expr.to_filter())),
+        }
+    }
+
+    // A missing field is null: it equals null and nothing else, and no
+    // ordering holds for it.
+    pub fn matches(&self, event: &Document) -> bool {
+        match self {
+            Expr::Cmp(path, op, literal) => {
+                let actual = get_path(event, path).unwrap_or(&Bson::Null);
+                let value = literal.to_bson(path);
+                match op {
+                    Op::Eq => same(actual, &value),
+                    Op::Ne => !same(actual, &value),
+                    op => order(actual, &value).is_some_and(|ordering| match op {
+                        Op::Gt => ordering.is_gt(),
+                        Op::Gte => ordering.is_ge(),
+                        Op::Lt => ordering.is_lt(),
+                        _ => ordering.is_le(),
+                    }),
+                }
+            }
+            Expr::In(path, literals) => {
+                let actual = get_path(event, path).unwrap_or(&Bson::Null);
+                literals.iter().any(|l| same(actual, &l.to_bson(path)))
+            }
+            Expr::And(exprs) => exprs.iter().all(|e| e.matches(event)),
+            Expr::Or(exprs) => exprs.iter().any(|e| e.matches(event)),
+            Expr::Not(expr) => !expr.matches(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks_storage::sqlite::SqliteStorage;
+    use blocks_storage::{Query, Storage, Table};
+    use bson::doc;
+
+    use super::*;
+
+    fn event(log_index: i64, args: Document) -> Document {
+        doc! {
+            "block_number": 7_i64,
+            "block_hash": "0x07",
+            "log_index": log_index,
+            "address": "0x00000000219ab540356cbb839cbe05303d7705fa",
+            "contract": "token",
+            "event": "Transfer",
+            "args": args,
+        }
+    }
+
+    #[test]
+    fn evaluates_against_documents() {
+        let transfer = event(3, doc! {
+            "from": "0x00000000219AB540356cbb839cbe05303d7705fa",
+            "value": decimal_to_bson("1500000000000000000".to_owned()),
+            "count": 2_i32,
+            "memo": "Hello",
+            "ok": true,
+            "nested": { "id": "42" },
+        });
+        let holds = |src: &str| parse(src).unwrap().matches(&transfer);
+
+        assert!(holds("event = \"Transfer\" and value > 10^18 and value = 1.5e18"));
+        assert!(holds("value >= 1500000000000000000 and value <= 15e17 and value != 1"));
+        assert!(!holds("value > 1.5e18 or value < 1.5e18"));
+        assert!(holds("count = 2 and count < 3 and args.nested.id = 42"));
+        assert!(holds("args.nested.id > 41"));
+        // addresses compare regardless of case, other strings don't
+        assert!(holds("from = 0x00000000219ab540356CBB839cbe05303d7705fa"));
+        assert!(holds("memo = \"Hello\" and memo != \"hello\""));
+        assert!(holds("ok = true and ok != false"));
+        assert!(holds("contract in [\"vault\", \"token\"] and event not in [\"Approval\"]"));
+        assert!(holds("log_index = 3 and block_number in [6, 7]"));
+        // a missing field is null and has no order
+        assert!(holds("missing = null and memo != null"));
+        assert!(!holds("missing < 1 or missing >= 1 or missing = 0"));
+        assert!(holds("not missing < 1"));
+        // strings that aren't numbers have no order either
+        assert!(!holds("memo > 0 or memo < 0"));
+    }
+
+    // decoded_events whose value is a number of every width and form
+    fn values() -> Vec<String> {
+        let mut values = vec!["0".to_owned(), "1".to_owned(), "9".to_owned(), "10".to_owned()];
+        for digits in [17, 18, 19, 33, 34, 35, 77, 78] {
+            for first in ['1', '5', '9'] {
+                let value = format!("{}{}", first, "0".repeat(digits - 1));
+                values.push(format!("-{}", value));
+                values.push(value);
+            }
+            values.push("9".repeat(digits));
+            values.push(format!("-{}", "9".repeat(digits)));
+        }
+        values
+    }
+
+    #[tokio::test]
+    async fn storage_filters_agree_with_matching() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        let mut events: Vec<Document> = values().into_iter().enumerate()
+            .map(|(i, value)| event(i as i64, doc! { "value": decimal_to_bson(value) }))
+            .collect();
+        let more = events.len() as i64;
+        events.push(event(more, doc! { "value": 5_i64 }));
+        events.push(event(more + 1, doc! { "value": "5" }));
+        events.push(event(more + 2, doc! { "value": "not a number" }));
+        events.push(event(more + 3, doc! {}));
+        storage.insert(Table::DecodedEvents, events.clone()).await.unwrap();
+
+        let eighteen = "123456789012345678";
+        let thirty_four = "1234567890123456789012345678901234";
+        let seventy_eight = format!("1{}", "0".repeat(77));
+        let u256_max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        let mut literals = vec!["5".to_owned(), "0".to_owned()];
+        for digits in [eighteen, thirty_four, seventy_eight.as_str()] {
+            literals.push(digits.to_owned());
+            literals.push(format!("-{}", digits));
+        }
+        literals.push(format!("1{}", "0".repeat(34)));
+        literals.push(u256_max.to_owned());
+        literals.push(format!("-{}", u256_max));
+
+        for literal in &literals {
+            for op in ["=", "!=", ">", ">=", "<", "<="] {
+                for src in [format!("value {} {}", op, literal), format!("not value {} {}", op, literal)] {
+                    let expr = parse(&src).unwrap();
+                    let found = storage.find(Table::DecodedEvents, Query::new().filter(expr.to_filter())).await.unwrap();
+                    let mut found: Vec<i64> = found.iter().map(|d| d.get_i64("log_index").unwrap()).collect();
+                    found.sort();
+                    let matched: Vec<i64> = events.iter().filter(|d| expr.matches(d)).map(|d| d.get_i64("log_index").unwrap()).collect();
+                    assert_eq!(found, matched, "{}", src);
+                }
+            }
+            let src = format!("value in [{}, 1, -{}]", literal, literal.trim_start_matches('-'));
+            let expr = parse(&src).unwrap();
+            let found = storage.find(Table::DecodedEvents, Query::new().filter(expr.to_filter())).await.unwrap();
+            assert_eq!(found.len(), events.iter().filter(|d| expr.matches(d)).count(), "{}", src);
+        }
+    }
+}
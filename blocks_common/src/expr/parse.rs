@@ -0,0 +1,481 @@
+use std::error::Error;
+use std::fmt;
+
+use blocks_storage::Op;
+use web3::types::U256;
+
+use super::{Expr, Literal, COLUMNS};
+
+// bounds on what a request can make the parser do
+const MAX_LENGTH: usize = 4096;
+const MAX_DEPTH: usize = 32;
+// digits of 2^256
+const MAX_EXPONENT: usize = 78;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    // byte offset into the expression
+    pub at: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.at)
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    // as written, digits with `_`, a fraction and an exponent
+    Number(String),
+    // lowercased 0x..
+    Hex(String),
+    Str(String),
+    Cmp(Op),
+    And,
+    Or,
+    Not,
+    In,
+    True,
+    False,
+    Null,
+    Open,
+    Close,
+    OpenList,
+    CloseList,
+    Comma,
+    Power,
+    Minus,
+}
+
+fn error<T>(at: usize, message: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError { at, message: message.into() })
+}
+
+// strings of hex are compared with what the indexer stores, lowercase
+fn normalize(s: String) -> String {
+    let hex = s.strip_prefix("0x").or(s.strip_prefix("0X"));
+    match hex {
+        Some(digits) if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit()) => format!("0x{}", digits.to_ascii_lowercase()),
+        _ => s,
+    }
+}
+
+fn lex(src: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<(usize, char)> = src.char_indices().collect();
+    let at = |i: usize| chars.get(i).map_or(src.len(), |(at, _)| *at);
+    let peek = |i: usize| chars.get(i).map(|(_, c)| *c);
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while let Some(c) = peek(i) {
+        let start = i;
+        let two = (c, peek(i + 1));
+        let token = match two {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('(', _) => Token::Open,
+            (')', _) => Token::Close,
+            ('[', _) => Token::OpenList,
+            (']', _) => Token::CloseList,
+            (',', _) => Token::Comma,
+            ('^', _) => Token::Power,
+            ('-', _) => Token::Minus,
+            ('=', Some('=')) | ('!', Some('=')) | ('<', Some('=')) | ('>', Some('=')) | ('&', Some('&')) | ('|', Some('|')) => {
+                i += 1;
+                match c {
+                    '=' => Token::Cmp(Op::Eq),
+                    '!' => Token::Cmp(Op::Ne),
+                    '<' => Token::Cmp(Op::Lte),
+                    '>' => Token::Cmp(Op::Gte),
+                    '&' => Token::And,
+                    _ => Token::Or,
+                }
+            }
+            ('=', _) => Token::Cmp(Op::Eq),
+            ('<', _) => Token::Cmp(Op::Lt),
+            ('>', _) => Token::Cmp(Op::Gt),
+            ('!', _) => Token::Not,
+            ('"', _) | ('\'', _) => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match peek(i) {
+                        None => return error(at(start), "unterminated string"),
+                        Some('\\') => {
+                            match peek(i + 1) {
+                                Some(escaped) => s.push(escaped),
+                                None => return error(at(start), "unterminated string"),
+                            }
+                            i += 2;
+                        }
+                        Some(q) if q == c => break,
+                        Some(other) => {
+                            s.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+                Token::Str(normalize(s))
+            }
+            ('0', Some('x' | 'X')) => {
+                i += 2;
+                while peek(i).is_some_and(|c| c.is_ascii_hexdigit()) {
+                    i += 1;
+                }
+                if i == start + 2 {
+                    return error(at(start), "hex literal without digits");
+                }
+                i -= 1;
+                Token::Hex(src[at(start)..at(i + 1)].to_ascii_lowercase())
+            }
+            _ if c.is_ascii_digit() => {
+                while peek(i + 1).is_some_and(|c| c.is_ascii_digit() || c == '_') {
+                    i += 1;
+                }
+                if peek(i + 1) == Some('.') && peek(i + 2).is_some_and(|c| c.is_ascii_digit()) {
+                    i += 1;
+                    while peek(i + 1).is_some_and(|c| c.is_ascii_digit() || c == '_') {
+                        i += 1;
+                    }
+                }
+                let sign = usize::from(peek(i + 2) == Some('+'));
+                if matches!(peek(i + 1), Some('e' | 'E')) && peek(i + 2 + sign).is_some_and(|c| c.is_ascii_digit()) {
+                    i += 1 + sign;
+                    while peek(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+                        i += 1;
+                    }
+                }
+                Token::Number(src[at(start)..at(i + 1)].to_owned())
+            }
+            _ if c.is_ascii_alphabetic() || c == '_' => {
+                while peek(i + 1).is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+                    i += 1;
+                }
+                let word = &src[at(start)..at(i + 1)];
+                match word.to_ascii_lowercase().as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    "in" => Token::In,
+                    "true" => Token::True,
+                    "false" => Token::False,
+                    "null" => Token::Null,
+                    _ => Token::Name(word.to_owned()),
+                }
+            }
+            _ => return error(at(start), format!("unexpected `{}`", c)),
+        };
+        tokens.push((at(start), token));
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+// Value of an integer as written, `1_000` or `1.5e18`. None when it isn't
+// whole or doesn't fit 256 bits.
+fn integer(number: &str) -> Option<U256> {
+    let number = number.replace('_', "");
+    let (mantissa, exponent) = match number.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.trim_start_matches('+').parse::<usize>().ok()?),
+        None => (number.as_str(), 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let fraction = fraction.trim_end_matches('0');
+    if exponent > MAX_EXPONENT || fraction.len() > exponent {
+        return None;
+    }
+    U256::from_dec_str(&format!("{}{}{}", whole, fraction, "0".repeat(exponent - fraction.len()))).ok()
+}
+
+// event columns as they are, other names are arguments
+fn field(name: &str) -> Option<String> {
+    let valid = |path: &str| path.split('.').all(|part| !part.is_empty());
+    if let Some(arg) = name.strip_prefix("args.") {
+        return valid(arg).then(|| name.to_owned());
+    }
+    if COLUMNS.contains(&name) {
+        return Some(name.to_owned());
+    }
+    (!name.contains('.')).then(|| format!("args.{}", name))
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    // where the input ends, for errors past the last token
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn at(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(at, _)| *at)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &Token, what: &str) -> Result<(), ParseError> {
+        if self.eat(token) {
+            return Ok(());
+        }
+        error(self.at(), format!("expected {}", what))
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut exprs = vec![self.and()?];
+        while self.eat(&Token::Or) {
+            exprs.push(self.and()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { Expr::Or(exprs) })
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut exprs = vec![self.unary()?];
+        while self.eat(&Token::And) {
+            exprs.push(self.unary()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { Expr::And(exprs) })
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.depth >= MAX_DEPTH {
+            return error(self.at(), "nested too deep");
+        }
+        self.depth += 1;
+        let expr = if self.eat(&Token::Not) {
+            Expr::Not(Box::new(self.unary()?))
+        } else if self.eat(&Token::Open) {
+            let expr = self.or()?;
+            self.expect(&Token::Close, "`)`")?;
+            expr
+        } else {
+            self.comparison()?
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        let at = self.at();
+        let path = match self.next() {
+            Some(Token::Name(name)) => field(&name).ok_or(ParseError { at, message: format!("unknown field `{}`", name) })?,
+            _ => return error(at, "expected a field"),
+        };
+        let at = self.at();
+        match self.next() {
+            Some(Token::Cmp(op)) => {
+                let at = self.at();
+                let literal = self.literal()?;
+                if !matches!(op, Op::Eq | Op::Ne) && !matches!(literal, Literal::Int(_)) {
+                    return error(at, format!("{} compares with a number", path));
+                }
+                Ok(Expr::Cmp(path, op, literal))
+            }
+            Some(Token::In) => Ok(Expr::In(path, self.list()?)),
+            Some(Token::Not) => {
+                self.expect(&Token::In, "`in` after `not`")?;
+                Ok(Expr::Not(Box::new(Expr::In(path, self.list()?))))
+            }
+            _ => error(at, format!("expected a comparison or `in` after {}", path)),
+        }
+    }
+
+    fn list(&mut self) -> Result<Vec<Literal>, ParseError> {
+        let close = match self.next() {
+            Some(Token::OpenList) => Token::CloseList,
+            Some(Token::Open) => Token::Close,
+            _ => {
+                self.pos -= 1;
+                return error(self.at(), "expected a list");
+            }
+        };
+        let mut literals = Vec::new();
+        if self.eat(&close) {
+            return Ok(literals);
+        }
+        loop {
+            literals.push(self.literal()?);
+            if self.eat(&close) {
+                return Ok(literals);
+            }
+            self.expect(&Token::Comma, "`,` or the end of the list")?;
+        }
+    }
+
+    fn integer(&mut self) -> Result<(usize, U256), ParseError> {
+        let at = self.at();
+        let Some(Token::Number(number)) = self.next() else {
+            return error(at, "expected a number");
+        };
+        let value = integer(&number).ok_or(ParseError { at, message: format!("{} is not a 256-bit integer", number) })?;
+        Ok((at, value))
+    }
+
+    // a^b^c is a^(b^c), worked out from the right in a loop so a long chain
+    // doesn't recurse
+    fn number(&mut self) -> Result<U256, ParseError> {
+        let mut chain = vec![self.integer()?];
+        while self.eat(&Token::Power) {
+            chain.push(self.integer()?);
+        }
+        let (_, mut value) = chain.pop().unwrap_or_default();
+        while let Some((at, base)) = chain.pop() {
+            value = base.checked_pow(value).ok_or(ParseError { at, message: "power does not fit 256 bits".to_owned() })?;
+        }
+        Ok(value)
+    }
+
+    fn literal(&mut self) -> Result<Literal, ParseError> {
+        let at = self.at();
+        match self.peek() {
+            Some(Token::Minus) => {
+                self.pos += 1;
+                let value = self.number()?;
+                Ok(Literal::Int(if value.is_zero() { "0".to_owned() } else { format!("-{}", value) }))
+            }
+            Some(Token::Number(_)) => Ok(Literal::Int(self.number()?.to_string())),
+            _ => match self.next() {
+                Some(Token::Hex(hex)) | Some(Token::Str(hex)) => Ok(Literal::Str(hex)),
+                Some(Token::True) => Ok(Literal::Bool(true)),
+                Some(Token::False) => Ok(Literal::Bool(false)),
+                Some(Token::Null) => Ok(Literal::Null),
+                _ => error(at, "expected a value"),
+            },
+        }
+    }
+}
+
+pub fn parse(src: &str) -> Result<Expr, ParseError> {
+    if src.len() > MAX_LENGTH {
+        return error(MAX_LENGTH, format!("longer than {} bytes", MAX_LENGTH));
+    }
+    let mut parser = Parser { tokens: lex(src)?, pos: 0, end: src.len(), depth: 0 };
+    if parser.tokens.is_empty() {
+        return error(0, "empty expression");
+    }
+    let expr = parser.or()?;
+    if parser.pos < parser.tokens.len() {
+        return error(parser.at(), "expected `and`, `or` or the end");
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(i: &str) -> Literal {
+        Literal::Int(i.to_owned())
+    }
+
+    fn message(src: &str) -> String {
+        parse(src).unwrap_err().message
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_than_or() {
+        let cmp = |path: &str, i: &str| Expr::Cmp(path.to_owned(), Op::Eq, int(i));
+        assert_eq!(
+            parse("a = 1 or b = 2 and not c = 3").unwrap(),
+            Expr::Or(vec![cmp("args.a", "1"), Expr::And(vec![cmp("args.b", "2"), Expr::Not(Box::new(cmp("args.c", "3")))])])
+        );
+        assert_eq!(
+            parse("(a = 1 || b = 2) && !c = 3").unwrap(),
+            Expr::And(vec![Expr::Or(vec![cmp("args.a", "1"), cmp("args.b", "2")]), Expr::Not(Box::new(cmp("args.c", "3")))])
+        );
+        assert_eq!(parse("block_number >= 5").unwrap(), Expr::Cmp("block_number".to_owned(), Op::Gte, int("5")));
+        assert_eq!(parse("args.event = 1").unwrap(), cmp("args.event", "1"));
+    }
+
+    #[test]
+    fn lists() {
+        let names = vec![Literal::Str("vault".to_owned()), Literal::Str("router".to_owned())];
+        assert_eq!(parse(r#"contract in ["vault", 'router']"#).unwrap(), Expr::In("contract".to_owned(), names.clone()));
+        assert_eq!(
+            parse(r#"contract not in ("vault", "router")"#).unwrap(),
+            Expr::Not(Box::new(Expr::In("contract".to_owned(), names)))
+        );
+        assert_eq!(parse("value in []").unwrap(), Expr::In("args.value".to_owned(), vec![]));
+        assert_eq!(message("contract not = 1"), "expected `in` after `not`");
+        assert_eq!(message("contract in [1 2]"), "expected `,` or the end of the list");
+    }
+
+    #[test]
+    fn numbers() {
+        let value = |src: &str| match parse(&format!("value = {}", src)).unwrap() {
+            Expr::Cmp(_, _, Literal::Int(i)) => i,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(value("10^18"), "1000000000000000000");
+        assert_eq!(value("1.5e18"), "1500000000000000000");
+        assert_eq!(value("2.50E+1"), "25");
+        assert_eq!(value("1_000_000"), "1000000");
+        assert_eq!(value("2^3^2"), "512");
+        assert_eq!(value("-10^2"), "-100");
+        assert_eq!(value("-0"), "0");
+        assert_eq!(value("2^255"), "57896044618658097711785492504343953926634992332820282019728792003956564819968");
+        assert_eq!(message("value = 1.5"), "1.5 is not a 256-bit integer");
+        assert_eq!(message("value = 1e79"), "1e79 is not a 256-bit integer");
+        assert_eq!(message("value = 2^256"), "power does not fit 256 bits");
+        assert_eq!(message(r#"value > "1""#), "args.value compares with a number");
+        // long chains are no deeper than short ones
+        assert_eq!(value(&format!("{}1", "1^".repeat(1_500))), "1");
+    }
+
+    #[test]
+    fn hex_is_lowercased() {
+        let to = |src: &str| match parse(&format!("to = {}", src)).unwrap() {
+            Expr::Cmp(_, _, literal) => literal,
+            other => panic!("{:?}", other),
+        };
+        let address = Literal::Str("0x00000000219ab540356cbb839cbe05303d7705fa".to_owned());
+        assert_eq!(to("0x00000000219AB540356cBB839Cbe05303d7705Fa"), address);
+        assert_eq!(to("0X00000000219AB540356cBB839Cbe05303d7705Fa"), address);
+        assert_eq!(to(r#""0x00000000219AB540356cBB839Cbe05303d7705Fa""#), address);
+        assert_eq!(to(r#""Hello""#), Literal::Str("Hello".to_owned()));
+        assert_eq!(to(r#""0xnot hex""#), Literal::Str("0xnot hex".to_owned()));
+        assert_eq!(message("to = 0x"), "hex literal without digits");
+    }
+
+    #[test]
+    fn bounds_and_errors() {
+        let long = format!("value = 1{}", " or value = 1".repeat(MAX_LENGTH / 13));
+        assert!(long.len() > MAX_LENGTH);
+        assert_eq!(parse(&long).unwrap_err(), ParseError { at: MAX_LENGTH, message: format!("longer than {} bytes", MAX_LENGTH) });
+
+        let nested = |depth: usize| format!("{}a = 1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse(&nested(MAX_DEPTH - 1)).is_ok());
+        assert_eq!(parse(&nested(MAX_DEPTH)).unwrap_err(), ParseError { at: MAX_DEPTH, message: "nested too deep".to_owned() });
+        assert_eq!(message(&format!("{}a = 1", "not ".repeat(MAX_DEPTH))), "nested too deep");
+
+        assert_eq!(parse("").unwrap_err(), ParseError { at: 0, message: "empty expression".to_owned() });
+        assert_eq!(parse("a = 1 b = 2").unwrap_err(), ParseError { at: 6, message: "expected `and`, `or` or the end".to_owned() });
+        assert_eq!(parse("a = ").unwrap_err(), ParseError { at: 4, message: "expected a value".to_owned() });
+        assert_eq!(message("a.b = 1"), "unknown field `a.b`");
+        assert_eq!(message("args. = 1"), "unknown field `args.`");
+        assert_eq!(message(r#"a = "open"#), "unterminated string");
+        assert_eq!(message("a = 1 $"), "unexpected `$`");
+    }
+}
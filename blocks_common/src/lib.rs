@@ -0,0 +1,22 @@
+pub mod abi_sources;
+pub mod admin;
+pub mod calldata;
+pub mod events;
+pub mod expr;
+pub mod logging;
+pub mod metrics;
+pub mod progress;
+pub mod projections;
+pub mod proxy;
+pub mod registry;
+pub mod reorgs;
+pub mod revert;
+pub mod rollups;
+pub mod rpc;
+pub mod signatures;
+pub mod sinks;
+pub mod status;
+pub mod timestamps;
+pub mod tokens;
+pub mod values;
+pub mod webhooks;
@@ -0,0 +1,151 @@
+mod rotate;
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::future::{self, Future};
+use std::io;
+use std::path::PathBuf;
+
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt as tfmt, EnvFilter, Layer, Registry};
+
+pub use rotate::{RotatingFile, Rotation};
+
+const DEFAULT_LOG_DIR: &str = "./logs";
+const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_MAX_FILES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+// Read from the environment:
+//   LOG_LEVEL     filter directives, e.g. "info" or "info,blocks_one=debug" (info)
+//   LOG_FORMAT    text | json (text)
+//   LOG_SINKS     comma separated stderr, file (stderr,file)
+//   LOG_DIR       directory of the log file (./logs)
+//   LOG_ROTATION  hourly | daily | never (daily)
+//   LOG_MAX_BYTES rotate once the file gets bigger, 0 disables (100 MiB)
+//   LOG_MAX_FILES rotated files to keep (10)
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    pub level: String,
+    pub format: Format,
+    pub stderr: bool,
+    pub file: bool,
+    pub dir: PathBuf,
+    pub rotation: Rotation,
+    pub max_bytes: u64,
+    pub max_files: usize,
+}
+
+impl LogConfig {
+    pub fn from_env() -> Self {
+        let format = match env::var("LOG_FORMAT").unwrap_or_default().to_lowercase().as_str() {
+            "json" => Format::Json,
+            _ => Format::Text,
+        };
+        let sinks = env::var("LOG_SINKS").unwrap_or("stderr,file".to_owned()).to_lowercase();
+        let sinks: Vec<&str> = sinks.split(',').map(|s| s.trim()).collect();
+        let rotation = match env::var("LOG_ROTATION").unwrap_or_default().to_lowercase().as_str() {
+            "hourly" => Rotation::Hourly,
+            "never" => Rotation::Never,
+            _ => Rotation::Daily,
+        };
+
+        LogConfig {
+            level: env::var("LOG_LEVEL").unwrap_or("info".to_owned()),
+            format,
+            stderr: sinks.contains(&"stderr"),
+            file: sinks.contains(&"file"),
+            dir: PathBuf::from(env::var("LOG_DIR").unwrap_or(DEFAULT_LOG_DIR.to_owned())),
+            rotation,
+            max_bytes: env::var("LOG_MAX_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_MAX_BYTES),
+            max_files: env::var("LOG_MAX_FILES").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_MAX_FILES),
+        }
+    }
+}
+
+// Keeps the background writers alive, dropping it flushes what is buffered.
+// Hold it until main returns.
+pub struct LogGuard {
+    _guards: Vec<WorkerGuard>,
+}
+
+fn layer(format: Format, writer: NonBlocking) -> Box<dyn Layer<Registry> + Send + Sync> {
+    match format {
+        Format::Text => tfmt::layer().with_writer(writer).with_ansi(false).boxed(),
+        Format::Json => tfmt::layer().json().with_current_span(true).with_span_list(true).with_writer(writer).boxed(),
+    }
+}
+
+// Installs the global subscriber. `app` names the log file (<dir>/<app>.log).
+pub fn init(app: &str) -> Result<LogGuard, Box<dyn Error>> {
+    let config = LogConfig::from_env();
+    let filter = EnvFilter::try_new(&config.level)?;
+
+    let mut guards = Vec::new();
+    let mut layers = Vec::new();
+    if config.stderr {
+        let (writer, guard) = tracing_appender::non_blocking(io::stderr());
+        guards.push(guard);
+        layers.push(layer(config.format, writer));
+    }
+    if config.file {
+        let file = RotatingFile::open(&config.dir, app, config.rotation, config.max_bytes, config.max_files)?;
+        let (writer, guard) = tracing_appender::non_blocking(file);
+        guards.push(guard);
+        layers.push(layer(config.format, writer));
+    }
+
+    tracing_subscriber::registry().with(layers).with(filter).try_init()?;
+    Ok(LogGuard { _guards: guards })
+}
+
+// Older call sites log through this trait, it forwards to `tracing` so the
+// current span is attached. Logging never waits, the future is always ready.
+pub trait Logger {
+    fn log(&self, level: LogLevel, msg: &str) -> impl Future<Output = ()> + Send;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Err,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Err => "ERR",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingLogger;
+
+impl Logger for TracingLogger {
+    fn log(&self, level: LogLevel, msg: &str) -> impl Future<Output = ()> + Send {
+        match level {
+            LogLevel::Trace => tracing::trace!("{}", msg),
+            LogLevel::Debug => tracing::debug!("{}", msg),
+            LogLevel::Info => tracing::info!("{}", msg),
+            LogLevel::Warn => tracing::warn!("{}", msg),
+            LogLevel::Err => tracing::error!("{}", msg),
+        }
+        future::ready(())
+    }
+}
@@ -0,0 +1,198 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl Rotation {
+    // rotation happens when this changes
+    fn period(&self) -> String {
+        match self {
+            Rotation::Hourly => Utc::now().format("%Y%m%d%H").to_string(),
+            Rotation::Daily => Utc::now().format("%Y%m%d").to_string(),
+            Rotation::Never => String::new(),
+        }
+    }
+}
+
+// Appends to `<dir>/<prefix>.log` and moves it aside as
+// `<prefix>.<utc time>.log` once it passes `max_bytes` or the rotation period
+// ends. Only the newest `max_files` rotated files are kept. Write errors are
+// reported on stderr once per failure streak, the non-blocking worker in front
+// of this drops them.
+pub struct RotatingFile {
+    dir: PathBuf,
+    prefix: String,
+    rotation: Rotation,
+    max_bytes: u64,
+    max_files: usize,
+    file: BufWriter<File>,
+    size: u64,
+    period: String,
+    failing: bool,
+}
+
+impl RotatingFile {
+    pub fn open(dir: &Path, prefix: &str, rotation: Rotation, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.log", prefix));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            dir: dir.to_owned(),
+            prefix: prefix.to_owned(),
+            rotation,
+            max_bytes,
+            max_files,
+            file: BufWriter::new(file),
+            size,
+            period: rotation.period(),
+            failing: false,
+        })
+    }
+
+    fn active_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.log", self.prefix))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let stamp = Utc::now().format("%Y%m%dT%H%M%S").to_string();
+        let mut target = self.dir.join(format!("{}.{}.log", self.prefix, stamp));
+        let mut n = 1;
+        while target.exists() {
+            target = self.dir.join(format!("{}.{}-{}.log", self.prefix, stamp, n));
+            n += 1;
+        }
+        fs::rename(self.active_path(), target)?;
+
+        self.file = BufWriter::new(OpenOptions::new().create(true).append(true).open(self.active_path())?);
+        self.size = 0;
+        self.period = self.rotation.period();
+        self.prune()
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let rotated_prefix = format!("{}.", self.prefix);
+        let active = format!("{}.log", self.prefix);
+        let mut rotated: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+                name.starts_with(&rotated_prefix) && name.ends_with(".log") && name != active
+            })
+            .collect();
+        rotated.sort_by_key(|path| age(path.file_name().and_then(|n| n.to_str()).unwrap_or_default(), &self.prefix));
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for path in rotated.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+// orders rotated names oldest first: by stamp, then `<stamp>.log` before the
+// `<stamp>-<n>.log` files rotated within the same second
+fn age(name: &str, prefix: &str) -> (String, u32) {
+    let rest = name.strip_prefix(prefix).and_then(|r| r.strip_prefix('.')).and_then(|r| r.strip_suffix(".log")).unwrap_or(name);
+    match rest.split_once('-') {
+        Some((stamp, n)) => (stamp.to_owned(), n.parse().unwrap_or_default()),
+        None => (rest.to_owned(), 0),
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.write_rotating(buf);
+        self.report(&result);
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.file.flush();
+        self.report(&result);
+        result
+    }
+}
+
+impl RotatingFile {
+    fn write_rotating(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let due = (self.max_bytes > 0 && self.size > 0 && self.size + buf.len() as u64 > self.max_bytes)
+            || self.rotation.period() != self.period;
+        if due {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn report<T>(&mut self, result: &io::Result<T>) {
+        match result {
+            Err(err) if !self.failing => {
+                eprintln!("failed to write log file {}: {}", self.active_path().display(), err);
+                self.failing = true;
+            }
+            Err(_) => {}
+            Ok(_) => self.failing = false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rotate_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // contents of the rotated files, oldest first, then of the active one
+    fn contents(dir: &Path) -> (Vec<String>, String) {
+        let mut rotated: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).filter(|p| !p.ends_with("app.log")).collect();
+        rotated.sort_by_key(|p| age(p.file_name().unwrap().to_str().unwrap(), "app"));
+        let rotated = rotated.iter().map(|p| fs::read_to_string(p).unwrap()).collect();
+        (rotated, fs::read_to_string(dir.join("app.log")).unwrap())
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_the_newest_files() {
+        let dir = dir("size");
+        let mut file = RotatingFile::open(&dir, "app", Rotation::Never, 10, 2).unwrap();
+        for line in ["0123456789", "abcdefgh", "ABCDEFGH", "zz", "yyyyyyyyy"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        assert_eq!(contents(&dir), (vec!["abcdefgh".to_owned(), "ABCDEFGHzz".to_owned()], "yyyyyyyyy".to_owned()));
+
+        // reopening picks up the size of what is there
+        drop(file);
+        let mut file = RotatingFile::open(&dir, "app", Rotation::Never, 10, 2).unwrap();
+        file.write_all(b"xx").unwrap();
+        file.flush().unwrap();
+        assert_eq!(contents(&dir).1, "xx");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_when_the_period_ends() {
+        let dir = dir("period");
+        let mut file = RotatingFile::open(&dir, "app", Rotation::Daily, 0, 5).unwrap();
+        file.write_all(b"yesterday").unwrap();
+        file.write_all(b" and today").unwrap();
+        file.period = "19700101".to_owned();
+        file.write_all(b"tomorrow").unwrap();
+        file.flush().unwrap();
+        assert_eq!(contents(&dir), (vec!["yesterday and today".to_owned()], "tomorrow".to_owned()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, GaugeVec, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+// Process wide metrics in the default prometheus registry. Every binary runs
+// one indexer, so there is no indexer label; scrape jobs tell them apart.
+lazy_static! {
+    pub static ref BLOCKS_INDEXED: IntCounter =
+        register_int_counter!("indexer_blocks_indexed_total", "Blocks fully processed").unwrap();
+    pub static ref CHAIN_HEAD: IntGauge =
+        register_int_gauge!("indexer_chain_head_block", "Latest block number reported by the node").unwrap();
+    pub static ref INDEXED_BLOCK: IntGauge =
+        register_int_gauge!("indexer_indexed_block", "Every block up to this one is indexed").unwrap();
+    pub static ref HEAD_LAG: IntGauge =
+        register_int_gauge!("indexer_head_lag_blocks", "Chain head minus the indexed block").unwrap();
+    pub static ref EVENTS_DECODED: IntCounterVec =
+        register_int_counter_vec!("indexer_events_decoded_total", "Decoded event logs by event name", &["event"]).unwrap();
+    pub static ref RANGE_RETRIES: IntCounter =
+        register_int_counter!("indexer_range_retries_total", "Block ranges processed again after a failure").unwrap();
+    pub static ref RANGE_FAILURES: IntCounterVec =
+        register_int_counter_vec!("indexer_range_failures_total", "Failed block ranges by stage", &["stage"]).unwrap();
+    pub static ref REORGS: IntCounter =
+        register_int_counter!("indexer_reorgs_total", "Rollbacks after stored block hashes left the chain").unwrap();
+    pub static ref DEAD_LETTERED: IntCounter =
+        register_int_counter!("indexer_dead_lettered_ranges_total", "Ranges whose documents went to the fallback file").unwrap();
+    pub static ref IMPLEMENTATION_UPGRADES: IntCounterVec = register_int_counter_vec!(
+        "indexer_implementation_upgrades_total",
+        "Proxy upgrades seen, by contract and whether an ABI is registered for the implementation",
+        &["contract", "registered"]
+    )
+    .unwrap();
+    pub static ref RPC_DURATION: HistogramVec =
+        register_histogram_vec!("rpc_request_duration_seconds", "JSON-RPC request latency by method", &["method"]).unwrap();
+    pub static ref RPC_ERRORS: IntCounterVec =
+        register_int_counter_vec!("rpc_errors_total", "Failed JSON-RPC requests by method", &["method"]).unwrap();
+    pub static ref RPC_PROVIDER_SCORE: GaugeVec =
+        register_gauge_vec!("rpc_provider_score", "Health score of an RPC provider, 0 to 1", &["provider"]).unwrap();
+    pub static ref RPC_FAILOVERS: IntCounterVec =
+        register_int_counter_vec!("rpc_failovers_total", "Requests moved to another provider after this one failed", &["provider"]).unwrap();
+    pub static ref RPC_PROVIDER_EXCLUDED: IntGaugeVec =
+        register_int_gauge_vec!("rpc_provider_excluded", "1 while the cross check keeps a lagging or forked provider out", &["provider"]).unwrap();
+    pub static ref RPC_CACHE_REQUESTS: IntCounterVec =
+        register_int_counter_vec!("rpc_cache_requests_total", "RPC cache lookups by method and hit/miss", &["method", "result"]).unwrap();
+    pub static ref WEBHOOK_DELIVERIES: IntCounterVec = register_int_counter_vec!(
+        "webhook_deliveries_total",
+        "Webhook attempts by outcome: delivered, retrying or failed after the last attempt",
+        &["outcome"]
+    )
+    .unwrap();
+    pub static ref SINK_MESSAGES: IntCounterVec =
+        register_int_counter_vec!("sink_messages_total", "Messages a sink acknowledged, by sink and kind", &["sink", "kind"]).unwrap();
+    pub static ref SINK_WAITING_BATCHES: IntGauge =
+        register_int_gauge!("sink_waiting_batches", "Batches held back until the sinks catch up or recover").unwrap();
+    pub static ref HTTP_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Request latency by route",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+}
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// everything registered so far in the text exposition format
+pub fn render() -> String {
+    let mut buf = Vec::new();
+    if let Err(err) = TextEncoder::new().encode(&prometheus::gather(), &mut buf) {
+        tracing::error!("Failed to encode metrics: {}", err);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_registered_metrics_with_their_labels() {
+        EVENTS_DECODED.with_label_values(&["RenderedEvent"]).inc_by(3);
+        BLOCKS_INDEXED.inc();
+        let text = render();
+        assert!(text.contains("# TYPE indexer_events_decoded_total counter\n"), "{}", text);
+        assert!(text.contains("indexer_events_decoded_total{event=\"RenderedEvent\"} 3\n"), "{}", text);
+        assert!(text.contains("# HELP indexer_blocks_indexed_total Blocks fully processed\n"), "{}", text);
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use web3::{Transport, Web3};
+
+use crate::metrics::{BLOCKS_INDEXED, CHAIN_HEAD, HEAD_LAG, INDEXED_BLOCK};
+
+// this close to the chain head the indexer counts as tailing
+const TAIL_DISTANCE: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    // catching up on history
+    Backfill,
+    // following the chain head
+    Tail,
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Backfill => "backfill",
+            Mode::Tail => "tail",
+        }
+    }
+}
+
+// Tracks which blocks are done when ranges finish out of order. The indexed
+// block is the end of the contiguous run from `start`, finished ranges past a
+// gap wait in `pending` until the gap closes.
+pub struct Progress {
+    state: Mutex<State>,
+}
+
+struct State {
+    // next block the contiguous run needs
+    next: u64,
+    // start -> end (inclusive) of finished ranges above `next`
+    pending: BTreeMap<u64, u64>,
+    head: Option<u64>,
+    // start -> end (inclusive) of ranges that failed and weren't redone yet
+    failed: BTreeMap<u64, u64>,
+}
+
+impl Progress {
+    pub fn new(start: u64) -> Self {
+        Progress {
+            state: Mutex::new(State { next: start, pending: BTreeMap::new(), head: None, failed: BTreeMap::new() }),
+        }
+    }
+
+    // marks [start, end] as indexed
+    pub fn complete(&self, start: u64, end: u64) {
+        BLOCKS_INDEXED.inc_by(end.saturating_sub(start) + 1);
+        let mut state = self.state.lock().unwrap();
+        state.failed.retain(|s, e| *s < start || *e > end);
+        if end < state.next {
+            return;
+        }
+        let end = state.pending.get(&start).map_or(end, |e| (*e).max(end));
+        state.pending.insert(start, end);
+        while let Some((&s, &e)) = state.pending.first_key_value() {
+            if s > state.next {
+                break;
+            }
+            state.pending.remove(&s);
+            state.next = state.next.max(e + 1);
+        }
+        state.publish();
+    }
+
+    // marks [start, end] as failed until a `complete` covers it
+    pub fn fail(&self, start: u64, end: u64) {
+        let mut state = self.state.lock().unwrap();
+        let end = state.failed.get(&start).map_or(end, |e| (*e).max(end));
+        state.failed.insert(start, end);
+    }
+
+    // Forgets what was indexed from `block` on, after a rollback took it away,
+    // and returns the last block that was done and has to be indexed again.
+    pub fn rewind(&self, block: u64) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        let last = state.pending.values().copied().chain(state.indexed()).max().filter(|last| *last >= block)?;
+        state.pending = state.pending.iter().filter(|(s, _)| **s < block).map(|(s, e)| (*s, (*e).min(block - 1))).collect();
+        state.next = state.next.min(block);
+        state.publish();
+        Some(last)
+    }
+
+    pub fn set_head(&self, head: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.head = Some(head);
+        state.publish();
+    }
+
+    // last block of the contiguous run, None before the first one finishes
+    pub fn indexed(&self) -> Option<u64> {
+        self.state.lock().unwrap().indexed()
+    }
+
+    pub fn head(&self) -> Option<u64> {
+        self.state.lock().unwrap().head
+    }
+
+    pub fn lag(&self) -> Option<u64> {
+        self.state.lock().unwrap().lag()
+    }
+
+    pub fn failed(&self) -> Vec<(u64, u64)> {
+        self.state.lock().unwrap().failed.iter().map(|(s, e)| (*s, *e)).collect()
+    }
+
+    // backfill until the head is known and within TAIL_DISTANCE
+    pub fn mode(&self) -> Mode {
+        match self.lag() {
+            Some(lag) if lag <= TAIL_DISTANCE => Mode::Tail,
+            _ => Mode::Backfill,
+        }
+    }
+}
+
+impl State {
+    fn indexed(&self) -> Option<u64> {
+        self.next.checked_sub(1)
+    }
+
+    fn lag(&self) -> Option<u64> {
+        self.head.map(|head| head.saturating_sub(self.indexed().unwrap_or(0)))
+    }
+
+    fn publish(&self) {
+        if let Some(indexed) = self.indexed() {
+            INDEXED_BLOCK.set(indexed as i64);
+        }
+        if let (Some(head), Some(lag)) = (self.head, self.lag()) {
+            CHAIN_HEAD.set(head as i64);
+            HEAD_LAG.set(lag as i64);
+        }
+    }
+}
+
+// polls eth_blockNumber so head lag stays current while ranges are running
+// END_BLOCK sets where a run stops without asking the node, so a re-index
+// can run from the RPC cache alone; the node's head otherwise
+pub async fn end_block<T: Transport>(web3: &Web3<T>, start: u64) -> Result<u64, Box<dyn Error>> {
+    let end = match env::var("END_BLOCK") {
+        Ok(end) => end.parse().map_err(|_| format!("invalid END_BLOCK `{}`", end))?,
+        Err(_) => web3.eth().block_number().await
+            .map_err(|err| format!("Failed to read the chain head, set END_BLOCK to run without it: {}", err))?
+            .as_u64(),
+    };
+    if end < start {
+        return Err(format!("end block {} is below the start block {}", end, start).into());
+    }
+    Ok(end)
+}
+
+pub async fn watch_head<T: Transport>(web3: Web3<T>, progress: &Progress, every: Duration) {
+    loop {
+        match web3.eth().block_number().await {
+            Ok(head) => progress.set_head(head.as_u64()),
+            Err(err) => tracing::warn!("Failed to read chain head: {}", err),
+        }
+        tokio::time::sleep(every).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewinds_the_run_and_pending_ranges() {
+        let progress = Progress::new(10);
+        progress.complete(10, 19);
+        progress.complete(30, 39);
+        assert_eq!(progress.indexed(), Some(19));
+
+        // everything from the fork on is redone, ranges past the gap included
+        assert_eq!(progress.rewind(15), Some(39));
+        assert_eq!(progress.indexed(), Some(14));
+        progress.complete(20, 29);
+        assert_eq!(progress.indexed(), Some(14));
+        progress.complete(15, 39);
+        assert_eq!(progress.indexed(), Some(39));
+
+        assert_eq!(progress.rewind(40), None);
+        assert_eq!(progress.indexed(), Some(39));
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+
+use async_trait::async_trait;
+use blocks_storage::{BigSum, Filter};
+use bson::{Bson, Document};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{EntityStore, HandlerError, Projection};
+use crate::events::DecodedEvent;
+use crate::values::{bson_to_decimal, decimal_to_bson};
+
+// A projection written as config. PROJECTIONS_PATH holds a json list like
+//
+// [{"name": "positions", "handlers": [
+//   {"event": "Deposit", "contract": "vault", "entity": "position", "id": ["args.user"],
+//    "set": {"user": "args.user"}, "add": {"amount": "args.amount", "deposits": 1}},
+//   {"event": "Withdraw", "entity": "position", "id": ["args.user"], "sub": {"amount": "args.amount"}},
+//   {"event": "Closed", "entity": "position", "id": ["args.user"], "remove": true}]}]
+//
+// Every handler matching an event updates the entity whose id is its `id`
+// values joined with `:`. In set/add/sub a string is a path into the decoded
+// event (args.*, block_number, tx_hash, address, contract, ...), anything
+// else a literal. add/sub work on integers of any size.
+#[derive(Debug, Deserialize)]
+pub struct MappingProjection {
+    name: String,
+    handlers: Vec<Handler>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Handler {
+    event: String,
+    // registered contract name, any contract if missing
+    #[serde(default)]
+    contract: Option<String>,
+    entity: String,
+    id: Vec<String>,
+    #[serde(default)]
+    set: BTreeMap<String, Value>,
+    #[serde(default)]
+    add: BTreeMap<String, Value>,
+    #[serde(default)]
+    sub: BTreeMap<String, Value>,
+    #[serde(default)]
+    remove: bool,
+}
+
+impl MappingProjection {
+    pub fn load(path: &str) -> Result<Vec<Self>, Box<dyn Error>> {
+        let mappings: Vec<Self> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if let Some(m) = mappings.iter().find(|m| m.handlers.iter().any(|h| h.id.is_empty())) {
+            return Err(format!("projection `{}` has a handler without id", m.name).into());
+        }
+        Ok(mappings)
+    }
+}
+
+fn lookup<'d>(doc: &'d Document, path: &str) -> Option<&'d Bson> {
+    let mut parts = path.split('.');
+    let mut value = doc.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_document()?.get(part)?;
+    }
+    Some(value)
+}
+
+fn resolve(event: &Document, value: &Value) -> Result<Bson, HandlerError> {
+    match value {
+        Value::String(path) => lookup(event, path).cloned().ok_or_else(|| format!("event has no `{}`", path).into()),
+        literal => Ok(Bson::try_from(literal.clone())?),
+    }
+}
+
+fn id_part(value: &Bson) -> String {
+    match value {
+        Bson::String(s) => s.clone(),
+        other => bson_to_decimal(other).unwrap_or_else(|| other.to_string()),
+    }
+}
+
+fn negate(decimal: &str) -> String {
+    match decimal.strip_prefix('-') {
+        Some(positive) => positive.to_owned(),
+        None => format!("-{}", decimal),
+    }
+}
+
+impl Handler {
+    fn matches(&self, event: &DecodedEvent) -> bool {
+        self.event == event.event && self.contract.as_ref().is_none_or(|c| *c == event.contract)
+    }
+
+    async fn apply(&self, event: &DecodedEvent, doc: &Document, store: &mut EntityStore<'_>) -> Result<(), HandlerError> {
+        let id = self.id.iter().map(|path| resolve(doc, &Value::String(path.clone())).map(|v| id_part(&v))).collect::<Result<Vec<_>, _>>()?.join(":");
+        if self.remove {
+            store.remove(&self.entity, &id);
+            return Ok(());
+        }
+        let mut entity = store.get(&self.entity, &id).await?.unwrap_or_default();
+        for (field, value) in &self.set {
+            entity.insert(field, resolve(doc, value)?);
+        }
+        for (fields, sign) in [(&self.add, false), (&self.sub, true)] {
+            for (field, value) in fields {
+                let operand = bson_to_decimal(&resolve(doc, value)?)
+                    .ok_or_else(|| format!("{} of {} at block {} is not an integer", field, event.event, event.block_number))?;
+                let current = entity.get(field).and_then(bson_to_decimal).unwrap_or("0".to_owned());
+                let mut sum = BigSum::default();
+                sum.add(&current);
+                sum.add(&if sign { negate(&operand) } else { operand });
+                entity.insert(field, decimal_to_bson(sum.value()));
+            }
+        }
+        store.set(&self.entity, &id, entity);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Projection for MappingProjection {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn filter(&self) -> Option<Filter> {
+        Some(Filter::Or(self.handlers.iter().map(|h| {
+            let mut filters = vec![Filter::eq("event", h.event.as_str())];
+            if let Some(contract) = &h.contract {
+                filters.push(Filter::eq("contract", contract.as_str()));
+            }
+            Filter::And(filters)
+        }).collect()))
+    }
+
+    async fn handle(&self, event: &DecodedEvent, store: &mut EntityStore<'_>) -> Result<(), HandlerError> {
+        let doc = event.to_document();
+        for handler in self.handlers.iter().filter(|h| h.matches(event)) {
+            handler.apply(event, &doc, store).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks_storage::sqlite::SqliteStorage;
+    use blocks_storage::{DbGroup, Query, Storage, Table};
+    use bson::doc;
+
+    use super::*;
+    use crate::projections::{restore, rolled_back, run};
+
+    const POSITIONS: &str = r#"[{"name": "positions", "handlers": [
+        {"event": "Deposit", "contract": "vault", "entity": "position", "id": ["args.user"],
+         "set": {"user": "args.user", "version": 2}, "add": {"amount": "args.amount", "deposits": 1}},
+        {"event": "Withdraw", "entity": "position", "id": ["args.user"], "sub": {"amount": "args.amount"}},
+        {"event": "Closed", "entity": "position", "id": ["args.user"], "remove": true}]}]"#;
+
+    fn event(block: i64, contract: &str, name: &str, user: &str, amount: &str) -> Document {
+        doc! {
+            "block_number": block,
+            "block_hash": format!("0x{:064x}", block),
+            "block_time": Bson::Null,
+            "tx_hash": Bson::Null,
+            "log_index": 0i64,
+            "address": "0xaa",
+            "contract": contract,
+            "abi_version": "v1",
+            "event": name,
+            "signature": format!("{}(address,uint256)", name),
+            "args": { "user": user, "amount": decimal_to_bson(amount.to_owned()) },
+        }
+    }
+
+    fn positions() -> MappingProjection {
+        serde_json::from_str::<Vec<MappingProjection>>(POSITIONS).unwrap().remove(0)
+    }
+
+    async fn storage(events: Vec<Document>) -> SqliteStorage {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        // distinct log indexes, so events of one block don't share a key
+        let events = events.into_iter().enumerate().map(|(i, mut e)| {
+            e.insert("log_index", i as i64);
+            e
+        }).collect();
+        storage.insert(Table::DecodedEvents, events).await.unwrap();
+        storage
+    }
+
+    // user -> (amount, deposits) of the stored entities
+    async fn entities(storage: &dyn Storage) -> BTreeMap<String, (String, String)> {
+        let rows = storage.find(Table::ProjectionEntities, Query::new().filter(Filter::eq("projection", "positions"))).await.unwrap();
+        rows.iter().map(|row| {
+            let data = row.get_document("data").unwrap();
+            let number = |field| data.get(field).and_then(bson_to_decimal).unwrap_or_default();
+            // anything but a string is a literal
+            assert_eq!(number("version"), "2");
+            (data.get_str("user").unwrap().to_owned(), (number("amount"), number("deposits")))
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn handlers_set_add_sub_and_remove() {
+        let big = format!("1{}", "0".repeat(40));
+        let storage = storage(vec![
+            event(1, "vault", "Deposit", "alice", &big),
+            event(2, "vault", "Deposit", "alice", "5"),
+            // only Deposits of the vault count
+            event(2, "other", "Deposit", "alice", "7"),
+            event(3, "vault", "Withdraw", "alice", "6"),
+            event(3, "vault", "Deposit", "bob", "1"),
+            event(4, "vault", "Closed", "bob", "0"),
+        ]).await;
+        run(&positions(), &storage, 4).await.unwrap();
+
+        let expected = ("9".repeat(40), "2".to_owned());
+        assert_eq!(entities(&storage).await, BTreeMap::from([("alice".to_owned(), expected)]));
+        // every block a position changed in left a version, the removal included
+        let versions = storage.find(Table::ProjectionVersions, Query::new()).await.unwrap();
+        assert_eq!(versions.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_the_last_remaining_version() {
+        let storage = storage(vec![
+            event(1, "vault", "Deposit", "alice", "10"),
+            event(2, "vault", "Withdraw", "alice", "4"),
+            event(2, "vault", "Deposit", "bob", "1"),
+        ]).await;
+        run(&positions(), &storage, 2).await.unwrap();
+
+        let entities_before = rolled_back(&storage, 2).await.unwrap();
+        storage.rollback(DbGroup::Events, 2).await.unwrap();
+        restore(&storage, entities_before).await.unwrap();
+        assert_eq!(entities(&storage).await, BTreeMap::from([("alice".to_owned(), ("10".to_owned(), "1".to_owned()))]));
+
+        // the next run projects the replacement blocks on top of what is left
+        let mut withdraw = event(2, "vault", "Withdraw", "alice", "1");
+        withdraw.insert("block_hash", "0x02");
+        storage.insert(Table::DecodedEvents, vec![withdraw]).await.unwrap();
+        run(&positions(), &storage, 2).await.unwrap();
+        assert_eq!(entities(&storage).await, BTreeMap::from([("alice".to_owned(), ("9".to_owned(), "1".to_owned()))]));
+    }
+
+    #[tokio::test]
+    async fn missing_paths_and_non_integers_fail_the_chunk() {
+        let mut no_user = event(1, "vault", "Deposit", "alice", "1");
+        no_user.get_document_mut("args").unwrap().remove("user");
+        let storage = storage(vec![no_user]).await;
+        let err = run(&positions(), &storage, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "event has no `args.user`");
+
+        let mut text = event(1, "vault", "Deposit", "alice", "1");
+        text.get_document_mut("args").unwrap().insert("amount", "ten");
+        let storage = self::storage(vec![text]).await;
+        let err = run(&positions(), &storage, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "amount of Deposit at block 1 is not an integer");
+        assert!(entities(&storage).await.is_empty());
+    }
+
+    #[test]
+    fn load_rejects_handlers_without_id() {
+        let path = std::env::temp_dir().join(format!("mappings-{}.json", std::process::id()));
+        fs::write(&path, r#"[{"name": "broken", "handlers": [{"event": "Deposit", "entity": "position", "id": []}]}]"#).unwrap();
+        let err = MappingProjection::load(path.to_str().unwrap()).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.to_string(), "projection `broken` has a handler without id");
+    }
+}
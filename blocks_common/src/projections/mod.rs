@@ -0,0 +1,327 @@
+mod mapping;
+mod owners;
+mod wasm;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
+use std::error::Error;
+
+use async_trait::async_trait;
+use blocks_storage::{Filter, Op, Query, Storage, Table};
+use bson::{doc, Bson, Document};
+use tracing::{info, warn};
+
+use crate::events::DecodedEvent;
+
+pub use mapping::MappingProjection;
+pub use owners::Owners;
+pub use wasm::WasmProjection;
+
+// blocks of decoded_events handed to a projection per round
+const CHUNK: u64 = 10_000;
+// checkpoints closer to the tip than this are kept, older ones pruned
+const KEEP_CHECKPOINTS: u64 = 1_000;
+
+pub type HandlerError = Box<dyn Error + Send + Sync>;
+
+// (entity, id) -> block and state of its latest version, None once removed
+type Latest = BTreeMap<(String, String), (u64, Option<Document>)>;
+
+// Derived state built from decoded events, like a subgraph mapping. Events
+// come in chain order and the handler keeps whatever entities it needs
+// through the store; reorgs and rebuilds are taken care of around it.
+#[async_trait]
+pub trait Projection: Send + Sync {
+    // keys everything the projection stores, so it has to stay the same
+    fn name(&self) -> &str;
+
+    // decoded_events condition for the events it wants, all of them by default
+    fn filter(&self) -> Option<Filter> {
+        None
+    }
+
+    async fn handle(&self, event: &DecodedEvent, store: &mut EntityStore<'_>) -> Result<(), HandlerError>;
+}
+
+// handlers written in rust, by the name PROJECTIONS knows them under
+pub fn builtin(name: &str) -> Option<Box<dyn Projection>> {
+    match name {
+        "owners" => Some(Box::new(Owners)),
+        _ => None,
+    }
+}
+
+// Entity reads and writes of one chunk. Reads see the versions up to the
+// projection's checkpoint plus the chunk's own writes, so a chunk that was
+// cut short runs again from the same state.
+pub struct EntityStore<'a> {
+    storage: &'a dyn Storage,
+    projection: &'a str,
+    checkpoint: Option<u64>,
+    // block of the event being handled
+    block: u64,
+    // derived_events row of the event being handled, without name and data
+    source: Document,
+    seq: i64,
+    // derived_events rows emitted in this chunk
+    emitted: Vec<Document>,
+    // (entity, id) -> current state, None once removed or never written
+    state: HashMap<(String, String), Option<Document>>,
+    // (entity, id, block) -> state at the end of that block
+    versions: BTreeMap<(String, String, u64), Option<Document>>,
+}
+
+impl<'a> EntityStore<'a> {
+    fn new(storage: &'a dyn Storage, projection: &'a str, checkpoint: Option<u64>) -> Self {
+        EntityStore { storage, projection, checkpoint, block: 0, source: Document::new(), seq: 0, emitted: Vec::new(), state: HashMap::new(), versions: BTreeMap::new() }
+    }
+
+    pub async fn get(&mut self, entity: &str, id: &str) -> Result<Option<Document>, HandlerError> {
+        let key = (entity.to_owned(), id.to_owned());
+        if let Some(state) = self.state.get(&key) {
+            return Ok(state.clone());
+        }
+        let stored = match self.checkpoint {
+            Some(checkpoint) => {
+                let query = Query::new()
+                    .filter(Filter::eq("projection", self.projection))
+                    .filter(Filter::eq("entity", entity))
+                    .filter(Filter::eq("id", id))
+                    .filter(Filter::cmp("block_number", Op::Lte, checkpoint as i64))
+                    .sort("block_number", false)
+                    .limit(1);
+                self.storage.find(Table::ProjectionVersions, query).await?.into_iter().next().and_then(version_data)
+            }
+            None => None,
+        };
+        self.state.insert(key, stored.clone());
+        Ok(stored)
+    }
+
+    pub fn set(&mut self, entity: &str, id: &str, data: Document) {
+        self.write(entity, id, Some(data));
+    }
+
+    pub fn remove(&mut self, entity: &str, id: &str) {
+        self.write(entity, id, None);
+    }
+
+    // a derived event, stored with the block and log of the one being handled
+    pub fn emit(&mut self, name: &str, data: Document) {
+        let mut row = self.source.clone();
+        row.insert("seq", self.seq);
+        self.seq += 1;
+        row.insert("name", name);
+        row.insert("data", data);
+        self.emitted.push(row);
+    }
+
+    fn at(&mut self, event: &DecodedEvent) {
+        self.block = event.block_number;
+        self.seq = 0;
+        self.source = doc! {
+            "projection": self.projection,
+            "block_number": event.block_number as i64,
+            "log_index": event.log_index as i64,
+            "block_time": event.block_time.map(Bson::DateTime).unwrap_or(Bson::Null),
+            "tx_hash": event.tx_hash.clone().map(Bson::String).unwrap_or(Bson::Null),
+        };
+    }
+
+    fn write(&mut self, entity: &str, id: &str, data: Option<Document>) {
+        self.state.insert((entity.to_owned(), id.to_owned()), data.clone());
+        self.versions.insert((entity.to_owned(), id.to_owned(), self.block), data);
+    }
+
+    // versions and derived events first, then the latest state, then the
+    // checkpoint that makes them count
+    async fn flush(self, end: u64) -> Result<usize, HandlerError> {
+        let (storage, projection) = (self.storage, self.projection);
+        let mut latest = Latest::new();
+        let mut rows = Vec::new();
+        for ((entity, id, block), data) in self.versions {
+            rows.push(doc! {
+                "projection": projection,
+                "entity": &entity,
+                "id": &id,
+                "block_number": block as i64,
+                "deleted": data.is_none(),
+                "data": data.clone().map(Bson::Document).unwrap_or(Bson::Null),
+            });
+            latest.insert((entity, id), (block, data));
+        }
+        let written = rows.len();
+        storage.upsert(Table::ProjectionVersions, rows).await?;
+        storage.upsert(Table::DerivedEvents, self.emitted).await?;
+        write_entities(storage, projection, latest).await?;
+
+        storage.insert(Table::ProjectionCheckpoints, vec![doc! { "projection": projection, "block_number": end as i64 }]).await?;
+        let keep = end.saturating_sub(KEEP_CHECKPOINTS);
+        if let Some(oldest) = checkpoint(storage, projection, Some(keep)).await? {
+            let filters = vec![Filter::eq("projection", projection), Filter::cmp("block_number", Op::Lt, oldest as i64)];
+            storage.delete(Table::ProjectionCheckpoints, filters).await?;
+        }
+        Ok(written)
+    }
+}
+
+fn version_data(version: Document) -> Option<Document> {
+    if version.get_bool("deleted").unwrap_or(false) {
+        return None;
+    }
+    version.get_document("data").ok().cloned()
+}
+
+// projection_entities rows for the given latest versions
+async fn write_entities(storage: &dyn Storage, projection: &str, latest: Latest) -> blocks_storage::Result<()> {
+    let mut rows = Vec::new();
+    for ((entity, id), (block, data)) in latest {
+        match data {
+            Some(data) => rows.push(doc! { "projection": projection, "entity": entity, "id": id, "block_number": block as i64, "data": data }),
+            None => {
+                let filters = vec![Filter::eq("projection", projection), Filter::eq("entity", entity), Filter::eq("id", id)];
+                storage.delete(Table::ProjectionEntities, filters).await?;
+            }
+        }
+    }
+    storage.upsert(Table::ProjectionEntities, rows).await
+}
+
+// highest checkpoint, at or below `at_most` if given
+async fn checkpoint(storage: &dyn Storage, projection: &str, at_most: Option<u64>) -> Result<Option<u64>, HandlerError> {
+    let mut query = Query::new().filter(Filter::eq("projection", projection)).sort("block_number", false).limit(1);
+    if let Some(at_most) = at_most {
+        query = query.filter(Filter::cmp("block_number", Op::Lte, at_most as i64));
+    }
+    let docs = storage.find(Table::ProjectionCheckpoints, query).await?;
+    Ok(docs.first().and_then(|d| d.get_i64("block_number").ok()).map(|b| b as u64))
+}
+
+async fn first_event_block(storage: &dyn Storage) -> Result<Option<u64>, HandlerError> {
+    let docs = storage.find(Table::DecodedEvents, Query::new().sort("block_number", true).limit(1)).await?;
+    Ok(docs.first().and_then(|d| d.get_i64("block_number").ok()).map(|b| b as u64))
+}
+
+// Runs the PROJECTIONS (built-in handlers by name), the mappings in
+// PROJECTIONS_PATH and the modules in WASM_PROJECTIONS_PATH over
+// decoded_events, each from its own checkpoint.
+pub struct Projections {
+    projections: Vec<Box<dyn Projection>>,
+}
+
+impl Projections {
+    pub fn new(projections: Vec<Box<dyn Projection>>) -> Self {
+        Projections { projections }
+    }
+
+    // None when none of them is set
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let mut projections = Vec::new();
+        for name in env::var("PROJECTIONS").unwrap_or_default().split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            projections.push(builtin(name).ok_or_else(|| format!("unknown projection `{}` in PROJECTIONS", name))?);
+        }
+        if let Ok(path) = env::var("PROJECTIONS_PATH") {
+            for mapping in MappingProjection::load(&path)? {
+                projections.push(Box::new(mapping) as Box<dyn Projection>);
+            }
+        }
+        if let Ok(path) = env::var("WASM_PROJECTIONS_PATH") {
+            for module in WasmProjection::load(&path)? {
+                projections.push(Box::new(module) as Box<dyn Projection>);
+            }
+        }
+        let mut names = BTreeSet::new();
+        if let Some(p) = projections.iter().find(|p| !names.insert(p.name().to_owned())) {
+            return Err(format!("projection `{}` is defined twice", p.name()).into());
+        }
+        Ok((!projections.is_empty()).then(|| Projections::new(projections)))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Projection> {
+        self.projections.iter().find(|p| p.name() == name).map(|p| p.as_ref())
+    }
+
+    // Brings every projection up to block `to`. A failing one stays at its
+    // checkpoint and picks up from there on the next call.
+    pub async fn run(&self, storage: &dyn Storage, to: u64) {
+        for projection in &self.projections {
+            if let Err(err) = run(projection.as_ref(), storage, to).await {
+                warn!(projection = projection.name(), "Projection failed, retrying on the next round: {}", err);
+            }
+        }
+    }
+}
+
+pub async fn run(projection: &dyn Projection, storage: &dyn Storage, to: u64) -> Result<(), HandlerError> {
+    let name = projection.name();
+    let mut checkpoint = checkpoint(storage, name, None).await?;
+    let mut start = match checkpoint {
+        Some(checkpoint) => checkpoint + 1,
+        None => match first_event_block(storage).await? {
+            Some(first) => first,
+            None => return Ok(()),
+        },
+    };
+    while start <= to {
+        let end = to.min(start + CHUNK - 1);
+        let mut query = Query::new()
+            .filter(Filter::cmp("block_number", Op::Gte, start as i64))
+            .filter(Filter::cmp("block_number", Op::Lte, end as i64))
+            .sort("block_number", true)
+            .sort("log_index", true);
+        if let Some(filter) = projection.filter() {
+            query = query.filter(filter);
+        }
+        let events = storage.find(Table::DecodedEvents, query).await?;
+        let mut store = EntityStore::new(storage, name, checkpoint);
+        for event in events.iter().filter_map(DecodedEvent::from_document) {
+            store.at(&event);
+            projection.handle(&event, &mut store).await?;
+        }
+        let written = store.flush(end).await?;
+        if !events.is_empty() {
+            info!(projection = name, events = events.len(), versions = written, "Projected blocks {} to {}", start, end);
+        }
+        checkpoint = Some(end);
+        start = end + 1;
+    }
+    Ok(())
+}
+
+// Drops everything a projection wrote, its next run starts from the first
+// decoded event.
+pub async fn reset(storage: &dyn Storage, name: &str) -> blocks_storage::Result<()> {
+    for table in [Table::ProjectionCheckpoints, Table::ProjectionVersions, Table::ProjectionEntities, Table::DerivedEvents] {
+        storage.delete(table, vec![Filter::eq("projection", name)]).await?;
+    }
+    Ok(())
+}
+
+// (projection, entity, id) of the entities that changed from `block` on.
+// Storage::rollback drops their versions and checkpoints; `restore` then
+// points projection_entities back at what is left.
+pub async fn rolled_back(storage: &dyn Storage, block: u64) -> blocks_storage::Result<BTreeSet<(String, String, String)>> {
+    let query = Query::new().filter(Filter::cmp("block_number", Op::Gte, block as i64));
+    Ok(storage.find(Table::ProjectionVersions, query).await?.iter()
+        .filter_map(|v| Some((v.get_str("projection").ok()?.to_owned(), v.get_str("entity").ok()?.to_owned(), v.get_str("id").ok()?.to_owned())))
+        .collect())
+}
+
+pub async fn restore(storage: &dyn Storage, entities: BTreeSet<(String, String, String)>) -> blocks_storage::Result<()> {
+    let mut latest: BTreeMap<String, Latest> = BTreeMap::new();
+    for (projection, entity, id) in entities {
+        let query = Query::new()
+            .filter(Filter::eq("projection", projection.as_str()))
+            .filter(Filter::eq("entity", entity.as_str()))
+            .filter(Filter::eq("id", id.as_str()))
+            .sort("block_number", false)
+            .limit(1);
+        let version = storage.find(Table::ProjectionVersions, query).await?.into_iter().next();
+        let block = version.as_ref().and_then(|v| v.get_i64("block_number").ok()).unwrap_or_default() as u64;
+        latest.entry(projection).or_default().insert((entity, id), (block, version.and_then(version_data)));
+    }
+    for (projection, entities) in latest {
+        write_entities(storage, &projection, entities).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use blocks_storage::Filter;
+use bson::doc;
+
+use super::{EntityStore, HandlerError, Projection};
+use crate::events::DecodedEvent;
+
+// Current owner of every Ownable contract, from OpenZeppelin's
+// OwnershipTransferred(previousOwner, newOwner). Renounced contracts end up
+// with the zero address.
+pub struct Owners;
+
+#[async_trait]
+impl Projection for Owners {
+    fn name(&self) -> &str {
+        "owners"
+    }
+
+    fn filter(&self) -> Option<Filter> {
+        Some(Filter::eq("signature", "OwnershipTransferred(address,address)"))
+    }
+
+    async fn handle(&self, event: &DecodedEvent, store: &mut EntityStore<'_>) -> Result<(), HandlerError> {
+        // by position, the param names differ between versions
+        let Some(owner) = event.args.values().nth(1).and_then(|v| v.as_str()) else {
+            return Ok(());
+        };
+        store.set("owner", &event.address, doc! {
+            "contract": &event.contract,
+            "owner": owner,
+            "since_block": event.block_number as i64,
+            "tx_hash": &event.tx_hash,
+        });
+        Ok(())
+    }
+}
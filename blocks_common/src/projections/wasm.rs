@@ -0,0 +1,397 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use blocks_storage::Filter;
+use bson::{Bson, Document};
+use serde::Deserialize;
+use tokio::time::{timeout_at, Instant};
+use tracing::info;
+use wasmi::core::{HostError, Trap};
+use wasmi::{Caller, Config, Engine, Extern, Linker, Memory, Module, ResumableCall, Store, StoreLimits, StoreLimitsBuilder, Value};
+
+use super::{EntityStore, HandlerError, Projection};
+use crate::events::DecodedEvent;
+
+// per event, about one unit per instruction
+const DEFAULT_FUEL: u64 = 10_000_000;
+// 16 MiB
+const DEFAULT_MEMORY_PAGES: u32 = 256;
+// storage calls per event, entity_get in a loop costs little fuel
+const DEFAULT_HOST_CALLS: u32 = 10_000;
+// per event, storage reads included
+const DEFAULT_TIMEOUT_MS: u64 = 5_000;
+const PAGE_SIZE: usize = 65_536;
+
+// A projection whose handler is a WebAssembly module, so teams can ship their
+// own logic without a new indexer build. WASM_PROJECTIONS_PATH holds a json
+// list like
+//
+// [{"name": "vault_stats", "module": "vault_stats.wasm", "events": ["Deposit", "Withdraw"],
+//   "contracts": ["vault"], "fuel": 10000000, "memory_pages": 256, "host_calls": 10000,
+//   "timeout_ms": 5000}]
+//
+// with module paths relative to the list. The module exports `memory` and
+// `handle(len: i32) -> i32`, which gets the length of the event's json and
+// returns 0 when it went fine. Host functions are imported from "blocks",
+// strings are (ptr, len) of utf-8 in the module's memory:
+//
+//   read(ptr)                          copies the last input, the event or
+//                                      what entity_get found, to ptr
+//   entity_get(entity, id) -> i32      length of the entity's json, -1 if none
+//   entity_set(entity, id, json)
+//   entity_remove(entity, id)
+//   emit(name, json)                   a derived_events row
+//   log(message)
+//
+// Every event runs in a fresh instance with `fuel` to spend, at most
+// `memory_pages` of memory, `host_calls` calls that leave the sandbox and
+// `timeout_ms` to finish in. Running out, trapping or a non-zero result fail
+// the event like any handler error: the projection stays at its checkpoint
+// and the indexer and other projections carry on.
+#[derive(Debug, Deserialize)]
+struct WasmConfig {
+    name: String,
+    module: String,
+    // event names, all events if empty
+    #[serde(default)]
+    events: Vec<String>,
+    // registered contract names, all contracts if empty
+    #[serde(default)]
+    contracts: Vec<String>,
+    #[serde(default)]
+    fuel: Option<u64>,
+    #[serde(default)]
+    memory_pages: Option<u32>,
+    #[serde(default)]
+    host_calls: Option<u32>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+pub struct WasmProjection {
+    name: String,
+    events: Vec<String>,
+    contracts: Vec<String>,
+    fuel: u64,
+    memory_pages: u32,
+    host_calls: u32,
+    timeout: Duration,
+    engine: Engine,
+    module: Module,
+    // one per engine, shared by its modules
+    linker: Arc<Linker<HostState>>,
+}
+
+struct HostState {
+    projection: String,
+    // what `read` copies out
+    input: Vec<u8>,
+    limits: StoreLimits,
+}
+
+// Storage calls leave the sandbox as host errors, since entity reads are
+// async; the call resumes once the EntityStore has served them.
+#[derive(Debug, Clone)]
+enum HostCall {
+    Get(String, String),
+    Set(String, String, Document),
+    Remove(String, String),
+    Emit(String, Document),
+}
+
+impl fmt::Display for HostCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl HostError for HostCall {}
+
+fn memory(caller: &Caller<'_, HostState>) -> Result<Memory, Trap> {
+    caller.get_export("memory").and_then(Extern::into_memory).ok_or_else(|| Trap::new("module exports no memory"))
+}
+
+fn read_str(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<String, Trap> {
+    let data = memory(caller)?.data(caller);
+    let start = ptr as u32 as usize;
+    let bytes = data.get(start..start + len as u32 as usize).ok_or_else(|| Trap::new("string out of bounds"))?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| Trap::new("string is not utf-8"))
+}
+
+fn read_json(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Document, Trap> {
+    let value: serde_json::Value = serde_json::from_str(&read_str(caller, ptr, len)?).map_err(|err| Trap::new(format!("invalid json: {}", err)))?;
+    match Bson::try_from(value) {
+        Ok(Bson::Document(doc)) => Ok(doc),
+        _ => Err(Trap::new("json is not an object")),
+    }
+}
+
+fn to_json(doc: Document) -> Vec<u8> {
+    Bson::Document(doc).into_relaxed_extjson().to_string().into_bytes()
+}
+
+fn linker(engine: &Engine) -> Result<Linker<HostState>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap("blocks", "read", |mut caller: Caller<'_, HostState>, ptr: i32| -> Result<(), Trap> {
+        let memory = memory(&caller)?;
+        let (data, state) = memory.data_and_store_mut(&mut caller);
+        let start = ptr as u32 as usize;
+        let target = data.get_mut(start..start + state.input.len()).ok_or_else(|| Trap::new("read out of bounds"))?;
+        target.copy_from_slice(&state.input);
+        Ok(())
+    })?;
+    linker.func_wrap("blocks", "entity_get", |caller: Caller<'_, HostState>, entity: i32, entity_len: i32, id: i32, id_len: i32| -> Result<i32, Trap> {
+        Err(HostCall::Get(read_str(&caller, entity, entity_len)?, read_str(&caller, id, id_len)?).into())
+    })?;
+    linker.func_wrap("blocks", "entity_set", |caller: Caller<'_, HostState>, entity: i32, entity_len: i32, id: i32, id_len: i32, json: i32, json_len: i32| -> Result<(), Trap> {
+        Err(HostCall::Set(read_str(&caller, entity, entity_len)?, read_str(&caller, id, id_len)?, read_json(&caller, json, json_len)?).into())
+    })?;
+    linker.func_wrap("blocks", "entity_remove", |caller: Caller<'_, HostState>, entity: i32, entity_len: i32, id: i32, id_len: i32| -> Result<(), Trap> {
+        Err(HostCall::Remove(read_str(&caller, entity, entity_len)?, read_str(&caller, id, id_len)?).into())
+    })?;
+    linker.func_wrap("blocks", "emit", |caller: Caller<'_, HostState>, name: i32, name_len: i32, json: i32, json_len: i32| -> Result<(), Trap> {
+        Err(HostCall::Emit(read_str(&caller, name, name_len)?, read_json(&caller, json, json_len)?).into())
+    })?;
+    linker.func_wrap("blocks", "log", |caller: Caller<'_, HostState>, message: i32, message_len: i32| -> Result<(), Trap> {
+        info!(projection = %caller.data().projection, "{}", read_str(&caller, message, message_len)?);
+        Ok(())
+    })?;
+    Ok(linker)
+}
+
+impl WasmProjection {
+    pub fn load(path: &str) -> Result<Vec<Self>, Box<dyn Error>> {
+        let configs: Vec<WasmConfig> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new("."));
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let linker = Arc::new(linker(&engine)?);
+        let mut projections = Vec::new();
+        for config in configs {
+            let module_path = dir.join(&config.module);
+            let bytes = fs::read(&module_path).map_err(|err| format!("{}: {}", module_path.display(), err))?;
+            let module = Module::new(&engine, &bytes[..]).map_err(|err| format!("{}: {}", module_path.display(), err))?;
+            if !module.exports().any(|e| e.name() == "handle") {
+                return Err(format!("{} exports no handle", module_path.display()).into());
+            }
+            let projection = WasmProjection {
+                name: config.name,
+                events: config.events,
+                contracts: config.contracts,
+                fuel: config.fuel.unwrap_or(DEFAULT_FUEL),
+                memory_pages: config.memory_pages.unwrap_or(DEFAULT_MEMORY_PAGES),
+                host_calls: config.host_calls.unwrap_or(DEFAULT_HOST_CALLS),
+                timeout: Duration::from_millis(config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS)),
+                linker: linker.clone(),
+                engine: engine.clone(),
+                module,
+            };
+            // unknown imports and oversized memories show up here rather than at the first event
+            projection.instantiate(Vec::new()).map_err(|err| format!("{}: {}", module_path.display(), err))?;
+            projections.push(projection);
+        }
+        Ok(projections)
+    }
+
+    fn instantiate(&self, input: Vec<u8>) -> Result<(Store<HostState>, wasmi::Instance), Box<dyn Error + Send + Sync>> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.memory_pages as usize * PAGE_SIZE)
+            .instances(1)
+            .memories(1)
+            .tables(1)
+            .trap_on_grow_failure(true)
+            .build();
+        let mut sandbox = Store::new(&self.engine, HostState { projection: self.name.clone(), input, limits });
+        sandbox.limiter(|state| &mut state.limits);
+        sandbox.add_fuel(self.fuel).map_err(|err| err.to_string())?;
+        let instance = self.linker.instantiate(&mut sandbox, &self.module)?.start(&mut sandbox)?;
+        Ok((sandbox, instance))
+    }
+
+    async fn call(&self, event: &DecodedEvent, store: &mut EntityStore<'_>) -> Result<(), HandlerError> {
+        // fuel bounds the time inside the sandbox, the deadline also covers
+        // the storage reads between resumes
+        let deadline = Instant::now() + self.timeout;
+        let timed_out = || format!("no result within {}ms", self.timeout.as_millis());
+        let input = to_json(event.to_document());
+        let len = input.len() as i32;
+        let (mut sandbox, instance) = self.instantiate(input)?;
+        let handle = instance.get_func(&sandbox, "handle").ok_or("module exports no handle")?;
+        let mut result = [Value::I32(0)];
+        let mut call = handle.call_resumable(&mut sandbox, &[Value::I32(len)], &mut result)?;
+        let mut calls = 0;
+        while let ResumableCall::Resumable(invocation) = call {
+            calls += 1;
+            if calls > self.host_calls {
+                return Err(format!("more than {} host calls", self.host_calls).into());
+            }
+            if Instant::now() >= deadline {
+                return Err(timed_out().into());
+            }
+            let Some(request) = invocation.host_error().downcast_ref::<HostCall>().cloned() else {
+                return Err(invocation.host_error().to_string().into());
+            };
+            let answer = match request {
+                HostCall::Get(entity, id) => {
+                    let found = timeout_at(deadline, store.get(&entity, &id)).await.map_err(|_| timed_out())??.map(to_json);
+                    let len = found.as_ref().map_or(-1, |json| json.len() as i32);
+                    sandbox.data_mut().input = found.unwrap_or_default();
+                    vec![Value::I32(len)]
+                }
+                HostCall::Set(entity, id, data) => {
+                    store.set(&entity, &id, data);
+                    Vec::new()
+                }
+                HostCall::Remove(entity, id) => {
+                    store.remove(&entity, &id);
+                    Vec::new()
+                }
+                HostCall::Emit(name, data) => {
+                    store.emit(&name, data);
+                    Vec::new()
+                }
+            };
+            call = invocation.resume(&mut sandbox, &answer, &mut result)?;
+        }
+        match result[0] {
+            Value::I32(0) => Ok(()),
+            ref code => Err(format!("handle returned {:?}", code).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl Projection for WasmProjection {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn filter(&self) -> Option<Filter> {
+        let mut filters = Vec::new();
+        if !self.events.is_empty() {
+            filters.push(Filter::In("event".to_owned(), self.events.iter().map(|e| Bson::String(e.clone())).collect()));
+        }
+        if !self.contracts.is_empty() {
+            filters.push(Filter::In("contract".to_owned(), self.contracts.iter().map(|c| Bson::String(c.clone())).collect()));
+        }
+        (!filters.is_empty()).then_some(Filter::And(filters))
+    }
+
+    async fn handle(&self, event: &DecodedEvent, store: &mut EntityStore<'_>) -> Result<(), HandlerError> {
+        self.call(event, store).await
+            .map_err(|err| format!("{} at block {} log {}: {}", event.event, event.block_number, event.log_index, err).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks_storage::sqlite::SqliteStorage;
+    use blocks_storage::{Query, Storage, Table};
+    use bson::doc;
+
+    use super::*;
+    use crate::projections::run;
+
+    // sets position/alice to {"n":1}, then asks for it `gets` times
+    const SET_AND_GET: &str = r#"(module
+        (import "blocks" "entity_get" (func $get (param i32 i32 i32 i32) (result i32)))
+        (import "blocks" "entity_set" (func $set (param i32 i32 i32 i32 i32 i32)))
+        (memory (export "memory") 1)
+        (global $gets (mut i32) (i32.const GETS))
+        (data (i32.const 0) "positionalice{\"n\":1}")
+        (func (export "handle") (param i32) (result i32)
+            (call $set (i32.const 0) (i32.const 8) (i32.const 8) (i32.const 5) (i32.const 13) (i32.const 7))
+            (block $done
+                (loop $again
+                    (br_if $done (i32.eqz (global.get $gets)))
+                    (global.set $gets (i32.sub (global.get $gets) (i32.const 1)))
+                    (drop (call $get (i32.const 0) (i32.const 8) (i32.const 8) (i32.const 5)))
+                    (br $again)))
+            (i32.const 0)))"#;
+
+    // (name, wat, extra config) written out as a WASM_PROJECTIONS_PATH list
+    fn load(test: &str, modules: &[(&str, String, serde_json::Value)]) -> Vec<WasmProjection> {
+        let dir = std::env::temp_dir().join(format!("wasm-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut configs = Vec::new();
+        for (name, wat, extra) in modules {
+            fs::write(dir.join(format!("{}.wasm", name)), wat::parse_str(wat).unwrap()).unwrap();
+            let mut config = serde_json::json!({ "name": name, "module": format!("{}.wasm", name) });
+            config.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+            configs.push(config);
+        }
+        let path = dir.join("projections.json");
+        fs::write(&path, serde_json::to_string(&configs).unwrap()).unwrap();
+        let projections = WasmProjection::load(path.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        projections
+    }
+
+    fn set_and_get(gets: u32) -> String {
+        SET_AND_GET.replace("GETS", &gets.to_string())
+    }
+
+    async fn storage() -> SqliteStorage {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        storage.insert(Table::DecodedEvents, vec![doc! {
+            "block_number": 1i64,
+            "block_hash": "0x01",
+            "block_time": Bson::Null,
+            "tx_hash": Bson::Null,
+            "log_index": 0i64,
+            "address": "0xaa",
+            "contract": "vault",
+            "abi_version": "v1",
+            "event": "Deposit",
+            "signature": "Deposit(address,uint256)",
+            "args": { "user": "alice", "amount": "1" },
+        }]).await.unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn host_calls_reach_the_entity_store() {
+        let projections = load("store", &[("stats", set_and_get(3), serde_json::json!({}))]);
+        let storage = storage().await;
+        run(&projections[0], &storage, 1).await.unwrap();
+        let entities = storage.find(Table::ProjectionEntities, Query::new()).await.unwrap();
+        assert_eq!(entities.len(), 1);
+        assert_eq!(entities[0].get_str("id").unwrap(), "alice");
+        assert_eq!(entities[0].get_document("data").unwrap().get_i32("n").unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn host_calls_past_the_budget_fail_the_event() {
+        let projections = load("budget", &[
+            ("within", set_and_get(9), serde_json::json!({ "host_calls": 10 })),
+            ("past", set_and_get(10), serde_json::json!({ "host_calls": 10 })),
+        ]);
+        let storage = storage().await;
+        run(&projections[0], &storage, 1).await.unwrap();
+        let err = run(&projections[1], &storage, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "Deposit at block 1 log 0: more than 10 host calls");
+    }
+
+    #[tokio::test]
+    async fn events_past_the_timeout_fail() {
+        let projections = load("timeout", &[("slow", set_and_get(1), serde_json::json!({ "timeout_ms": 0 }))]);
+        let err = run(&projections[0], &storage().await, 1).await.unwrap_err();
+        assert_eq!(err.to_string(), "Deposit at block 1 log 0: no result within 0ms");
+    }
+
+    #[test]
+    fn modules_of_one_list_share_the_linker() {
+        let projections = load("linker", &[
+            ("a", set_and_get(0), serde_json::json!({})),
+            ("b", set_and_get(0), serde_json::json!({})),
+        ]);
+        assert!(Arc::ptr_eq(&projections[0].linker, &projections[1].linker));
+    }
+}
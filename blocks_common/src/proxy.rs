@@ -0,0 +1,98 @@
+use web3::signing::keccak256;
+use web3::types::{BlockId, BlockNumber, Bytes, CallRequest, Log, H160, H256, U256, U64};
+use web3::{Transport, Web3};
+
+use crate::metrics::IMPLEMENTATION_UPGRADES;
+use crate::registry::{ContractRegistry, RegisteredContract};
+
+// implementation() of an UpgradeableBeacon
+const IMPLEMENTATION_SELECTOR: [u8; 4] = [0x5c, 0x60, 0xda, 0x1b];
+
+lazy_static::lazy_static! {
+    // EIP-1967 storage slots, keccak256 of the name minus one
+    static ref IMPLEMENTATION_SLOT: U256 = U256::from(keccak256(b"eip1967.proxy.implementation")) - 1;
+    static ref BEACON_SLOT: U256 = U256::from(keccak256(b"eip1967.proxy.beacon")) - 1;
+    // EIP-1967, emitted by the proxy and by UpgradeableBeacon
+    static ref UPGRADED: H256 = H256(keccak256(b"Upgraded(address)"));
+    static ref BEACON_UPGRADED: H256 = H256(keccak256(b"BeaconUpgraded(address)"));
+}
+
+fn topic_address(log: &Log) -> Option<H160> {
+    log.topics.get(1).map(|t| H160::from_slice(&t.as_bytes()[12..]))
+}
+
+// Looks for implementation changes of registered proxies in a range's logs and
+// warns when the new implementation has no ABI version registered, or when
+// the version for it activates at another block than the upgrade.
+pub async fn check_upgrades<T: Transport>(web3: &Web3<T>, registry: &ContractRegistry, logs: &[Log]) {
+    for log in logs {
+        let (Some(topic0), Some(block)) = (log.topics.first(), log.block_number) else { continue };
+        let block = block.as_u64();
+
+        if *topic0 == *UPGRADED {
+            let Some(implementation) = topic_address(log) else { continue };
+            if let Some(contract) = registry.get(&log.address) {
+                check_implementation(contract, implementation, block);
+            }
+            for contract in registry.contracts().filter(|c| c.beacon == Some(log.address)) {
+                check_implementation(contract, implementation, block);
+            }
+        } else if *topic0 == *BEACON_UPGRADED {
+            let (Some(contract), Some(beacon)) = (registry.get(&log.address), topic_address(log)) else { continue };
+            if contract.beacon != Some(beacon) {
+                tracing::warn!(contract = %contract.name, beacon = ?beacon, block,
+                    "Proxy switched to a beacon that isn't configured, its later upgrades won't be seen");
+            }
+            match beacon_implementation(web3, beacon, block).await {
+                Ok(implementation) => check_implementation(contract, implementation, block),
+                Err(err) => tracing::warn!(contract = %contract.name, beacon = ?beacon, block, "Failed to read beacon implementation: {}", err),
+            }
+        }
+    }
+}
+
+fn check_implementation(contract: &RegisteredContract, implementation: H160, block: u64) {
+    match contract.versions.iter().find(|v| v.implementation == Some(implementation)) {
+        Some(version) => {
+            IMPLEMENTATION_UPGRADES.with_label_values(&[&contract.name, "true"]).inc();
+            if version.from_block != block {
+                tracing::warn!(contract = %contract.name, version = %version.version, implementation = ?implementation, block,
+                    "ABI version activates at block {} but the proxy was upgraded at {}", version.from_block, block);
+            } else {
+                tracing::info!(contract = %contract.name, version = %version.version, implementation = ?implementation, block, "Proxy upgraded");
+            }
+        }
+        None => {
+            IMPLEMENTATION_UPGRADES.with_label_values(&[&contract.name, "false"]).inc();
+            tracing::warn!(contract = %contract.name, implementation = ?implementation, block,
+                "Proxy upgraded to an implementation without a registered ABI, add it to CONTRACTS_PATH and redecode");
+        }
+    }
+}
+
+// implementation an EIP-1967 proxy delegates to now, directly or through its beacon
+pub async fn current_implementation<T: Transport>(web3: &Web3<T>, proxy: H160) -> web3::Result<Option<H160>> {
+    let slot = web3.eth().storage(proxy, *IMPLEMENTATION_SLOT, None).await?;
+    if !slot.is_zero() {
+        return Ok(Some(H160::from(slot)));
+    }
+    let beacon = web3.eth().storage(proxy, *BEACON_SLOT, None).await?;
+    if beacon.is_zero() {
+        return Ok(None);
+    }
+    let head = web3.eth().block_number().await?.as_u64();
+    beacon_implementation(web3, H160::from(beacon), head).await.map(Some)
+}
+
+async fn beacon_implementation<T: Transport>(web3: &Web3<T>, beacon: H160, block: u64) -> web3::Result<H160> {
+    let call = CallRequest {
+        to: Some(beacon),
+        data: Some(Bytes(IMPLEMENTATION_SELECTOR.to_vec())),
+        ..Default::default()
+    };
+    let out = web3.eth().call(call, Some(BlockId::Number(BlockNumber::Number(U64::from(block))))).await?;
+    if out.0.len() < 32 {
+        return Err(web3::Error::Decoder(format!("implementation() returned {} bytes", out.0.len())));
+    }
+    Ok(H160::from_slice(&out.0[12..32]))
+}
@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+use ethabi::{AbiError, Contract as Abi, Event, Function};
+use serde::Deserialize;
+use web3::signing::keccak256;
+use web3::types::{H160, H256};
+use web3::{Transport, Web3};
+
+use crate::abi_sources::AbiSources;
+use crate::proxy;
+
+// used when neither CONTRACTS_PATH nor CONTRACT_ADDRESS is set
+const DEFAULT_CONTRACT_ADDRESS: &str = "0xDB54D3Ce2035509d83F86bc982adc62F4AEBe03c";
+const DEFAULT_ABI_PATH: &str = "./src/abi.json";
+
+// topic0 -> (event signature, comma separated indexed flags)
+pub type EventSighashes = HashMap<[u8; 32], (String, String)>;
+
+// One ABI of a contract, in use from `from_block` until the next version
+// starts. Behind a proxy, `implementation` is the logic contract it belongs to.
+pub struct AbiVersion {
+    pub version: String,
+    pub from_block: u64,
+    pub implementation: Option<H160>,
+    pub abi: Abi,
+    events: EventSighashes,
+}
+
+impl AbiVersion {
+    pub fn new(version: &str, from_block: u64, implementation: Option<H160>, abi: Abi) -> Self {
+        let events = event_sighashes(&abi);
+        AbiVersion { version: version.to_owned(), from_block, implementation, abi, events }
+    }
+}
+
+pub struct RegisteredContract {
+    pub name: String,
+    pub address: H160,
+    // UpgradeableBeacon the proxy reads its implementation from
+    pub beacon: Option<H160>,
+    // sorted by from_block, never empty
+    pub versions: Vec<AbiVersion>,
+}
+
+impl RegisteredContract {
+    // the version active at `block`, the first one for blocks before it
+    pub fn abi_at(&self, block: u64) -> &AbiVersion {
+        self.versions.iter().rev().find(|v| v.from_block <= block).unwrap_or(&self.versions[0])
+    }
+
+    pub fn latest(&self) -> &AbiVersion {
+        &self.versions[self.versions.len() - 1]
+    }
+
+    pub fn version(&self, version: &str) -> Option<&AbiVersion> {
+        self.versions.iter().find(|v| v.version == version)
+    }
+
+    // inclusive block range a version is active in, None as end for the latest
+    pub fn version_range(&self, version: &str) -> Option<(u64, Option<u64>)> {
+        let i = self.versions.iter().position(|v| v.version == version)?;
+        let start = if i == 0 { 0 } else { self.versions[i].from_block };
+        Some((start, self.versions.get(i + 1).map(|next| next.from_block - 1)))
+    }
+
+    // no block is known for calldata and reverts, the newest ABI wins
+    fn function(&self, selector: &[u8]) -> Option<(&RegisteredContract, &Function)> {
+        self.versions.iter().rev()
+            .find_map(|v| v.abi.functions().find(|f| f.short_signature() == selector))
+            .map(|f| (self, f))
+    }
+
+    fn error(&self, selector: &[u8]) -> Option<(&RegisteredContract, &AbiError)> {
+        self.versions.iter().rev()
+            .find_map(|v| v.abi.errors().find(|e| &e.signature()[..4] == selector))
+            .map(|e| (self, e))
+    }
+}
+
+// entry of the json file pointed to by CONTRACTS_PATH, either with a single
+// abi_path or with the `abis` of every upgrade. Without an abi_path the ABI is
+// looked up in ABI_SOURCES by the deployed code.
+#[derive(Debug, Deserialize)]
+struct ContractEntry {
+    name: String,
+    address: String,
+    #[serde(default)]
+    abi_path: Option<String>,
+    #[serde(default)]
+    abis: Vec<VersionEntry>,
+    #[serde(default)]
+    beacon: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionEntry {
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    abi_path: Option<String>,
+    #[serde(default)]
+    from_block: u64,
+    #[serde(default)]
+    implementation: Option<String>,
+}
+
+impl ContractEntry {
+    async fn versions<T: Transport>(&self, address: H160, sources: Option<&AbiSources>, web3: &Web3<T>) -> Result<Vec<AbiVersion>, Box<dyn Error>> {
+        if self.abis.is_empty() {
+            let abi = match &self.abi_path {
+                Some(path) => load_abi(path)?,
+                None => deployed_abi(&self.name, address, sources, web3).await?,
+            };
+            return Ok(vec![AbiVersion::new("v1", 0, None, abi)]);
+        }
+        let mut versions = Vec::new();
+        for (i, entry) in self.abis.iter().enumerate() {
+            let version = entry.version.clone().unwrap_or_else(|| format!("v{}", i + 1));
+            let implementation = entry.implementation.as_deref().map(H160::from_str).transpose()?;
+            let abi = match (&entry.abi_path, implementation, sources) {
+                (Some(path), _, _) => load_abi(path)?,
+                (None, Some(implementation), Some(sources)) => sources.abi_for(web3, implementation).await?,
+                _ => return Err(format!("{} {}: abi_path, or implementation with ABI_SOURCES, is required", self.name, version).into()),
+            };
+            versions.push(AbiVersion::new(&version, entry.from_block, implementation, abi));
+        }
+        Ok(versions)
+    }
+}
+
+// ABI of the code at an address, of the current implementation for an EIP-1967 proxy
+async fn deployed_abi<T: Transport>(name: &str, address: H160, sources: Option<&AbiSources>, web3: &Web3<T>) -> Result<Abi, Box<dyn Error>> {
+    let sources = sources.ok_or_else(|| format!("{}: abi_path is required without ABI_SOURCES", name))?;
+    match proxy::current_implementation(web3, address).await? {
+        Some(implementation) => sources.abi_for(web3, implementation).await,
+        None => sources.abi_for(web3, address).await,
+    }
+}
+
+#[derive(Default)]
+pub struct ContractRegistry {
+    contracts: HashMap<H160, RegisteredContract>,
+}
+
+impl ContractRegistry {
+    pub fn new() -> Self {
+        ContractRegistry { contracts: HashMap::new() }
+    }
+
+    // CONTRACTS_PATH takes a json list of {name, address, abi_path}; without it
+    // the single contract from CONTRACT_ADDRESS/ABI_PATH is registered. ABIs
+    // that aren't given are matched from ABI_SOURCES through the node.
+    pub async fn from_env<T: Transport>(web3: &Web3<T>) -> Result<Self, Box<dyn Error>> {
+        let mut registry = ContractRegistry::new();
+        let sources = AbiSources::from_env()?;
+
+        if let Ok(path) = env::var("CONTRACTS_PATH") {
+            let entries: Vec<ContractEntry> = serde_json::from_str(&read_file(&path)?)?;
+            for entry in entries {
+                let address = H160::from_str(&entry.address)?;
+                let beacon = entry.beacon.as_deref().map(H160::from_str).transpose()?;
+                let versions = entry.versions(address, sources.as_ref(), web3).await?;
+                registry.register_versions(&entry.name, address, beacon, versions)?;
+            }
+        } else {
+            let address = H160::from_str(&env::var("CONTRACT_ADDRESS").unwrap_or(DEFAULT_CONTRACT_ADDRESS.to_owned()))?;
+            let abi = match (env::var("ABI_PATH"), &sources) {
+                (Err(_), Some(_)) => deployed_abi("default", address, sources.as_ref(), web3).await?,
+                (abi_path, _) => load_abi(&abi_path.unwrap_or(DEFAULT_ABI_PATH.to_owned()))?,
+            };
+            registry.register("default", address, abi);
+        }
+
+        Ok(registry)
+    }
+
+    pub fn register(&mut self, name: &str, address: H160, abi: Abi) {
+        let versions = vec![AbiVersion::new("v1", 0, None, abi)];
+        self.contracts.insert(address, RegisteredContract { name: name.to_owned(), address, beacon: None, versions });
+    }
+
+    pub fn register_versions(&mut self, name: &str, address: H160, beacon: Option<H160>, mut versions: Vec<AbiVersion>) -> Result<(), Box<dyn Error>> {
+        if versions.is_empty() {
+            return Err(format!("{}: no ABI versions", name).into());
+        }
+        versions.sort_by_key(|v| v.from_block);
+        if let Some(pair) = versions.windows(2).find(|pair| pair[0].from_block == pair[1].from_block) {
+            return Err(format!("{}: versions {} and {} both start at block {}", name, pair[0].version, pair[1].version, pair[1].from_block).into());
+        }
+        self.contracts.insert(address, RegisteredContract { name: name.to_owned(), address, beacon, versions });
+        Ok(())
+    }
+
+    pub fn get(&self, address: &H160) -> Option<&RegisteredContract> {
+        self.contracts.get(address)
+    }
+
+    pub fn addresses(&self) -> Vec<H160> {
+        self.contracts.keys().copied().collect()
+    }
+
+    // beacons of registered proxies, their logs carry implementation changes
+    pub fn beacons(&self) -> Vec<H160> {
+        let mut beacons: Vec<H160> = self.contracts.values().filter_map(|c| c.beacon).collect();
+        beacons.sort();
+        beacons.dedup();
+        beacons
+    }
+
+    pub fn contracts(&self) -> impl Iterator<Item = &RegisteredContract> {
+        self.contracts.values()
+    }
+
+    // events of every registered ABI version
+    pub fn event_sighashes(&self) -> EventSighashes {
+        self.contracts.values()
+            .flat_map(|c| &c.versions)
+            .flat_map(|v| v.events.iter().map(|(h, e)| (*h, e.clone())))
+            .collect()
+    }
+
+    // event of a log, looked up in the ABI its contract had at that block
+    pub fn event_at(&self, address: &H160, block: u64, topic0: &[u8]) -> Option<&(String, String)> {
+        self.contracts.get(address)?.abi_at(block).events.get(topic0)
+    }
+
+    // the ABI event itself, for decoding into named args
+    pub fn abi_event_at(&self, address: &H160, block: u64, topic0: &H256) -> Option<(&RegisteredContract, &AbiVersion, &Event)> {
+        let contract = self.contracts.get(address)?;
+        let version = contract.abi_at(block);
+        let event = version.abi.events().find(|e| !e.anonymous && e.signature() == *topic0)?;
+        Some((contract, version, event))
+    }
+
+    // Function of a call to a registered contract. Selectors like
+    // transfer(address,uint256) are shared by unrelated contracts, so other
+    // addresses aren't decoded, and a creation (no `to`) starts with init
+    // code rather than a selector.
+    pub fn function_for(&self, to: Option<&H160>, selector: &[u8]) -> Option<(&RegisteredContract, &Function)> {
+        self.contracts.get(to?)?.function(selector)
+    }
+
+    // custom error of a call to a registered contract, see function_for
+    pub fn error_for(&self, to: Option<&H160>, selector: &[u8]) -> Option<(&RegisteredContract, &AbiError)> {
+        self.contracts.get(to?)?.error(selector)
+    }
+}
+
+fn event_sighashes(abi: &Abi) -> EventSighashes {
+    abi.events().map(|event| {
+        let sig = format!("{}({})", event.name, event.inputs.iter().map(|i| i.kind.to_string()).collect::<Vec<_>>().join(","));
+        let index_str = event.inputs.iter().map(|ip| if ip.indexed { "1" } else { "0" }).collect::<Vec<_>>().join(",");
+
+        (keccak256(sig.as_bytes()), (sig, index_str))
+    }).collect()
+}
+
+pub fn load_abi(path: &str) -> Result<Abi, Box<dyn Error>> {
+    Ok(Abi::load(read_file(path)?.as_bytes())?)
+}
+
+fn read_file(path: &str) -> Result<String, std::io::Error> {
+    let mut file = File::open(path)?;
+    let mut s = String::new();
+    file.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = r#"[
+        {"type": "function", "name": "transfer", "stateMutability": "nonpayable", "outputs": [{"name": "", "type": "bool"}],
+         "inputs": [{"name": "to", "type": "address"}, {"name": "value", "type": "uint256"}]},
+        {"type": "error", "name": "Insufficient", "inputs": [{"name": "needed", "type": "uint256"}]}
+    ]"#;
+
+    fn registry() -> (ContractRegistry, H160) {
+        let mut registry = ContractRegistry::new();
+        let token = H160::repeat_byte(1);
+        registry.register("token", token, Abi::load(TOKEN.as_bytes()).unwrap());
+        (registry, token)
+    }
+
+    #[test]
+    fn decodes_calls_to_registered_contracts_only() {
+        let (registry, token) = registry();
+        let transfer = keccak256(b"transfer(address,uint256)");
+        let insufficient = keccak256(b"Insufficient(uint256)");
+
+        let (contract, function) = registry.function_for(Some(&token), &transfer[..4]).unwrap();
+        assert_eq!((contract.name.as_str(), function.name.as_str()), ("token", "transfer"));
+        let (contract, error) = registry.error_for(Some(&token), &insufficient[..4]).unwrap();
+        assert_eq!((contract.name.as_str(), error.name.as_str()), ("token", "Insufficient"));
+        assert!(registry.function_for(Some(&token), &[0, 0, 0, 0]).is_none());
+
+        // another ERC-20 has the same selectors
+        let other = H160::repeat_byte(2);
+        assert!(registry.function_for(Some(&other), &transfer[..4]).is_none());
+        assert!(registry.error_for(Some(&other), &insufficient[..4]).is_none());
+        // a creation's input is init code
+        assert!(registry.function_for(None, &transfer[..4]).is_none());
+        assert!(registry.error_for(None, &insufficient[..4]).is_none());
+    }
+
+    const V1: &str = r#"[{"type": "event", "name": "Deposit", "anonymous": false, "inputs": [{"name": "amount", "type": "uint256", "indexed": false}]}]"#;
+    const V2: &str = r#"[{"type": "event", "name": "Deposit", "anonymous": false,
+        "inputs": [{"name": "user", "type": "address", "indexed": true}, {"name": "amount", "type": "uint256", "indexed": false}]}]"#;
+
+    fn version(name: &str, from_block: u64, abi: &str) -> AbiVersion {
+        AbiVersion::new(name, from_block, None, Abi::load(abi.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn picks_the_abi_version_active_at_a_block() {
+        let mut registry = ContractRegistry::new();
+        let vault = H160::repeat_byte(3);
+        // given out of order, kept by from_block
+        registry.register_versions("vault", vault, None, vec![version("v2", 100, V2), version("v1", 10, V1), version("v3", 200, V1)]).unwrap();
+        let contract = registry.get(&vault).unwrap();
+
+        let at = |block| contract.abi_at(block).version.as_str();
+        assert_eq!([at(0), at(10), at(99), at(100), at(199), at(200), at(10_000)], ["v1", "v1", "v1", "v2", "v2", "v3", "v3"]);
+        assert_eq!(contract.latest().version, "v3");
+        assert_eq!(contract.version_range("v1"), Some((0, Some(99))));
+        assert_eq!(contract.version_range("v2"), Some((100, Some(199))));
+        assert_eq!(contract.version_range("v3"), Some((200, None)));
+        assert_eq!(contract.version_range("v4"), None);
+
+        let v1 = H256(keccak256(b"Deposit(uint256)"));
+        let v2 = H256(keccak256(b"Deposit(address,uint256)"));
+        assert_eq!(registry.event_at(&vault, 50, v1.as_bytes()).map(|e| e.1.as_str()), Some("0"));
+        assert_eq!(registry.event_at(&vault, 150, v1.as_bytes()), None);
+        assert_eq!(registry.event_at(&vault, 150, v2.as_bytes()).map(|e| e.1.as_str()), Some("1,0"));
+        let (_, version, event) = registry.abi_event_at(&vault, 150, &v2).unwrap();
+        assert_eq!((version.version.as_str(), event.inputs.len()), ("v2", 2));
+        assert!(registry.abi_event_at(&vault, 250, &v2).is_none());
+        assert_eq!(registry.event_sighashes().len(), 2);
+    }
+
+    #[test]
+    fn refuses_empty_and_clashing_versions() {
+        let mut registry = ContractRegistry::new();
+        let vault = H160::repeat_byte(3);
+        let err = registry.register_versions("vault", vault, None, Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "vault: no ABI versions");
+        let err = registry.register_versions("vault", vault, None, vec![version("v1", 0, V1), version("v2", 5, V2), version("v3", 5, V1)]).unwrap_err();
+        assert_eq!(err.to_string(), "vault: versions v2 and v3 both start at block 5");
+        assert!(registry.get(&vault).is_none());
+    }
+}
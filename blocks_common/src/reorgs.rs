@@ -0,0 +1,55 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+
+use blocks_storage::{Aggregation, Filter, GroupBy, Metric, Op, Storage, Table};
+use web3::types::{BlockId, BlockNumber, U64};
+use web3::{Transport, Web3};
+
+// blocks this close to the head can still be replaced
+const DEFAULT_DEPTH: u64 = 64;
+// block scoped rows that keep the hash of their block
+const TABLES: [Table; 2] = [Table::RawLogs, Table::TokenTransfers];
+
+// REORG_DEPTH, how far below the head stored blocks are checked (64)
+pub fn depth_from_env() -> Result<u64, Box<dyn Error>> {
+    match env::var("REORG_DEPTH") {
+        Ok(depth) => depth.parse().ok().filter(|d| *d > 0).ok_or_else(|| format!("invalid REORG_DEPTH `{}`", depth).into()),
+        Err(_) => Ok(DEFAULT_DEPTH),
+    }
+}
+
+// Lowest block in [from, to] whose stored rows carry a hash the chain no
+// longer has at that height, None while the index is on the chain. Blocks are
+// checked from the top down; one that matches settles everything below it.
+pub async fn fork_point<T: Transport>(web3: &Web3<T>, storage: &dyn Storage, from: u64, to: u64) -> Result<Option<u64>, Box<dyn Error>> {
+    let mut stored: BTreeMap<u64, BTreeSet<String>> = BTreeMap::new();
+    for table in TABLES {
+        let Some(column) = table.schema().block_column else { continue };
+        let aggregation = Aggregation {
+            filters: vec![Filter::cmp(column, Op::Gte, from as i64), Filter::cmp(column, Op::Lte, to as i64)],
+            group_by: vec![GroupBy::Path(column.to_owned()), GroupBy::Path("block_hash".to_owned())],
+            metric: Metric::Count,
+            by_value: false,
+            limit: None,
+        };
+        for group in storage.aggregate(table, aggregation).await? {
+            if let [Some(block), Some(hash)] = group.key.as_slice() {
+                let block = block.parse().map_err(|_| format!("{} row with block `{}`", table.schema().name, block))?;
+                stored.entry(block).or_default().insert(hash.to_lowercase());
+            }
+        }
+    }
+
+    let mut fork = None;
+    for (block, hashes) in stored.iter().rev() {
+        let chain = web3.eth().block(BlockId::Number(BlockNumber::Number(U64::from(*block)))).await?
+            .and_then(|b| b.hash)
+            .map(|h| format!("{:?}", h));
+        if chain.is_some_and(|h| hashes.len() == 1 && hashes.contains(&h)) {
+            break;
+        }
+        fork = Some(*block);
+    }
+    Ok(fork)
+}
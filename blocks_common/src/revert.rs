@@ -0,0 +1,100 @@
+use bson::{doc, Bson, Document};
+use ethabi::{decode, ParamType, Token};
+use hex::encode;
+use serde_json::Value;
+use web3::types::H160;
+
+use crate::registry::ContractRegistry;
+use crate::tokens::named_args;
+
+// Error(string) and Panic(uint256) selectors
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+// Pulls the revert payload out of a failed eth_call. Nodes put it in the
+// rpc error's `data`, either as a hex string or as an object with a `data` key.
+pub fn revert_data(err: &web3::Error) -> Option<Vec<u8>> {
+    let data = match err {
+        web3::Error::Rpc(rpc_err) => rpc_err.data.as_ref()?,
+        _ => return None,
+    };
+    let hex_str = match data {
+        Value::String(s) => s.as_str(),
+        Value::Object(o) => o.get("data")?.as_str()?,
+        _ => return None,
+    };
+    hex::decode(hex_str.trim_start_matches("0x")).ok()
+}
+
+// Builds the `revert` sub-document stored on a failed transaction.
+// kind is one of "error", "panic", "custom", "unknown".
+pub fn decode_revert(registry: &ContractRegistry, to: Option<&H160>, data: &[u8]) -> Document {
+    let mut revert = doc! {
+        "data": format!("0x{}", encode(data)),
+    };
+    if data.len() < 4 {
+        revert.insert("kind", "unknown");
+        revert.insert("reason", if data.is_empty() { "reverted without data" } else { "malformed revert data" });
+        return revert;
+    }
+
+    let (selector, params) = data.split_at(4);
+    if selector == ERROR_SELECTOR {
+        if let Ok(tokens) = decode(&[ParamType::String], params) {
+            if let Some(Token::String(reason)) = tokens.into_iter().next() {
+                revert.insert("kind", "error");
+                revert.insert("reason", reason);
+                return revert;
+            }
+        }
+    } else if selector == PANIC_SELECTOR {
+        if let Ok(tokens) = decode(&[ParamType::Uint(256)], params) {
+            if let Some(Token::Uint(code)) = tokens.into_iter().next() {
+                revert.insert("kind", "panic");
+                revert.insert("panic_code", code.low_u64() as i64);
+                revert.insert("reason", panic_description(code.low_u64()));
+                return revert;
+            }
+        }
+    } else if let Some((contract, error)) = registry.error_for(to, selector) {
+        if let Ok(tokens) = error.decode(params) {
+            let args = named_args(&error.inputs, &tokens);
+            let rendered = args.iter().map(|(k, v)| format!("{}={}", k, bson_display(v))).collect::<Vec<_>>().join(", ");
+            revert.insert("kind", "custom");
+            revert.insert("error", error.name.as_str());
+            revert.insert("abi_contract", contract.name.as_str());
+            revert.insert("reason", format!("{}({})", error.name, rendered));
+            revert.insert("args", args);
+            return revert;
+        }
+    }
+
+    revert.insert("kind", "unknown");
+    revert.insert("reason", format!("unknown error selector 0x{}", encode(selector)));
+    revert
+}
+
+// https://docs.soliditylang.org/en/latest/control-structures.html#panic-via-assert-and-error-via-require
+fn panic_description(code: u64) -> String {
+    let desc = match code {
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array encoding",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function",
+        _ => "unknown panic code",
+    };
+    format!("Panic(0x{:02x}): {}", code, desc)
+}
+
+fn bson_display(v: &Bson) -> String {
+    match v {
+        Bson::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
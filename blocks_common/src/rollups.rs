@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::error::Error;
+
+use blocks_storage::{Aggregation, DbGroup, Filter, GroupBy, Interval, Metric, Op, Query, Result, Storage, Table};
+use bson::{doc, Bson, DateTime, Document};
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+const HOUR_MILLIS: i64 = 3_600_000;
+const DAY_MILLIS: i64 = 24 * HOUR_MILLIS;
+// dimension values per query when refreshing totals
+const REFRESH_CHUNK: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rollup {
+    // decoded events per contract and event name
+    Events,
+    // decoded events an address shows up in as an argument
+    Addresses,
+}
+
+impl Rollup {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rollup::Events => "events",
+            Rollup::Addresses => "addresses",
+        }
+    }
+
+    // the column totals are refreshed by
+    fn dimension(&self) -> &'static str {
+        match self {
+            Rollup::Events => "contract",
+            Rollup::Addresses => "address",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Block,
+    Hour,
+    Day,
+    Total,
+}
+
+impl Granularity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Block => "block",
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+            Granularity::Total => "total",
+        }
+    }
+
+    fn millis(&self) -> Option<i64> {
+        match self {
+            Granularity::Hour => Some(HOUR_MILLIS),
+            Granularity::Day => Some(DAY_MILLIS),
+            Granularity::Block | Granularity::Total => None,
+        }
+    }
+
+    // hour/day labels as the storage backends print them in aggregations
+    fn label(&self, start: i64) -> String {
+        let time = Utc.timestamp_millis_opt(start).unwrap();
+        match self {
+            Granularity::Hour => time.format("%Y-%m-%dT%H:00:00Z").to_string(),
+            _ => time.format("%Y-%m-%d").to_string(),
+        }
+    }
+}
+
+// Buckets and dimension values whose derived rows have to be recomputed
+#[derive(Default)]
+struct Touched {
+    buckets: BTreeSet<(Rollup, Granularity, i64)>,
+    keys: BTreeSet<(Rollup, String)>,
+}
+
+// (rollup, block, contract, event, address) -> (block time, events)
+type BlockCounts = BTreeMap<(Rollup, i64, String, String, String), (Option<DateTime>, i64)>;
+
+// Event counts the indexer keeps up to date as it writes decoded events,
+// enabled with e.g. ROLLUPS=events:hour,events:day,addresses:total. Every
+// rollup keeps per-block rows, written under their key so retried ranges
+// and redecodes just replace them, and dropped by Storage::rollback like any
+// other block scoped row. Hour/day/total rows are recomputed from the block
+// rows of the buckets a write touched, never incremented, so they can't drift.
+pub struct Rollups {
+    // derived granularities per rollup, block rows are always kept
+    enabled: BTreeMap<Rollup, Vec<Granularity>>,
+    // one refresh at a time, so an older recount never lands after a newer one
+    refresh: Mutex<()>,
+}
+
+impl Rollups {
+    pub fn new(enabled: BTreeMap<Rollup, Vec<Granularity>>) -> Self {
+        Rollups { enabled, refresh: Mutex::new(()) }
+    }
+
+    // None when ROLLUPS is unset or empty
+    pub fn from_env() -> std::result::Result<Option<Self>, Box<dyn Error>> {
+        let spec = env::var("ROLLUPS").unwrap_or_default();
+        let mut enabled: BTreeMap<Rollup, Vec<Granularity>> = BTreeMap::new();
+        for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (name, granularity) = item.split_once(':').unwrap_or((item, "block"));
+            let rollup = match name {
+                "events" => Rollup::Events,
+                "addresses" => Rollup::Addresses,
+                _ => return Err(format!("unknown rollup `{}` in ROLLUPS, expected events or addresses", name).into()),
+            };
+            let granularity = match granularity {
+                "block" => None,
+                "hour" => Some(Granularity::Hour),
+                "day" => Some(Granularity::Day),
+                "total" => Some(Granularity::Total),
+                _ => return Err(format!("unknown granularity `{}` in ROLLUPS, expected block, hour, day or total", granularity).into()),
+            };
+            let granularities = enabled.entry(rollup).or_default();
+            if let Some(g) = granularity.filter(|g| !granularities.contains(g)) {
+                granularities.push(g);
+            }
+        }
+        Ok((!enabled.is_empty()).then(|| Rollups::new(enabled)))
+    }
+
+    pub fn is_enabled(&self, rollup: Rollup, granularity: Granularity) -> bool {
+        self.enabled.get(&rollup).is_some_and(|g| granularity == Granularity::Block || g.contains(&granularity))
+    }
+
+    // Counts the decoded_events documents of whole blocks into the rollups.
+    pub async fn apply(&self, storage: &dyn Storage, events: &[Document]) -> Result<()> {
+        let rows: Vec<Document> = self.block_counts(events).into_iter().map(|((rollup, block, contract, event, address), (time, count))| doc! {
+            "rollup": rollup.as_str(),
+            "granularity": Granularity::Block.as_str(),
+            "bucket": block.to_string(),
+            "bucket_time": time.map(Bson::DateTime).unwrap_or(Bson::Null),
+            "contract": contract,
+            "event": event,
+            "address": address,
+            "block_number": block,
+            "count": count,
+        }).collect();
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let touched = self.touched(&rows);
+        storage.upsert(Table::Rollups, rows).await?;
+        self.refresh(storage, touched).await
+    }
+
+    // Storage::rollback plus a recount of the derived rows it leaves behind.
+    // Should the recount fail, `blocks_one rollups rebuild` repairs them.
+    pub async fn rollback(&self, storage: &dyn Storage, block: u64) -> Result<()> {
+        let query = Query::new()
+            .filter(Filter::eq("granularity", Granularity::Block.as_str()))
+            .filter(Filter::cmp("block_number", Op::Gte, block as i64));
+        let touched = self.touched(&storage.find(Table::Rollups, query).await?);
+        storage.rollback(DbGroup::Events, block).await?;
+        self.refresh(storage, touched).await
+    }
+
+    fn block_counts(&self, events: &[Document]) -> BlockCounts {
+        let mut counts = BlockCounts::new();
+        for event in events {
+            let Ok(block) = event.get_i64("block_number") else { continue };
+            let time = event.get_datetime("block_time").ok().copied();
+            let mut add = |rollup: Rollup, contract: &str, name: &str, address: &str| {
+                if self.enabled.contains_key(&rollup) {
+                    let entry = counts.entry((rollup, block, contract.to_owned(), name.to_owned(), address.to_owned())).or_insert((time, 0));
+                    entry.1 += 1;
+                }
+            };
+            add(Rollup::Events, event.get_str("contract").unwrap_or_default(), event.get_str("event").unwrap_or_default(), "");
+            if let Ok(args) = event.get_document("args") {
+                for address in arg_addresses(args) {
+                    add(Rollup::Addresses, "", "", &address);
+                }
+            }
+        }
+        counts
+    }
+
+    // derived rows depending on the given block rows
+    fn touched(&self, rows: &[Document]) -> Touched {
+        let mut touched = Touched::default();
+        for row in rows {
+            let rollup = match row.get_str("rollup") {
+                Ok("events") => Rollup::Events,
+                Ok("addresses") => Rollup::Addresses,
+                _ => continue,
+            };
+            let time = row.get_datetime("bucket_time").ok().map(|t| t.timestamp_millis());
+            for granularity in self.enabled.get(&rollup).into_iter().flatten() {
+                match (granularity.millis(), time) {
+                    (Some(millis), Some(time)) => {
+                        touched.buckets.insert((rollup, *granularity, time - time.rem_euclid(millis)));
+                    }
+                    (None, _) => {
+                        let key = row.get_str(rollup.dimension()).unwrap_or_default();
+                        touched.keys.insert((rollup, key.to_owned()));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        touched
+    }
+
+    async fn refresh(&self, storage: &dyn Storage, touched: Touched) -> Result<()> {
+        let _guard = self.refresh.lock().await;
+        for (rollup, granularity, start) in touched.buckets {
+            let millis = granularity.millis().unwrap_or_default();
+            let label = granularity.label(start);
+            let source = vec![
+                Filter::cmp("bucket_time", Op::Gte, DateTime::from_millis(start)),
+                Filter::cmp("bucket_time", Op::Lt, DateTime::from_millis(start + millis)),
+            ];
+            let stored = vec![Filter::eq("bucket", label.as_str())];
+            recount(storage, rollup, granularity, (&label, Some(DateTime::from_millis(start))), source, stored).await?;
+        }
+
+        let mut keys: BTreeMap<Rollup, Vec<String>> = BTreeMap::new();
+        for (rollup, key) in touched.keys {
+            keys.entry(rollup).or_default().push(key);
+        }
+        for (rollup, keys) in keys {
+            for chunk in keys.chunks(REFRESH_CHUNK) {
+                let values: Vec<Bson> = chunk.iter().map(|k| Bson::String(k.clone())).collect();
+                let filter = Filter::In(rollup.dimension().to_owned(), values);
+                recount(storage, rollup, Granularity::Total, ("", None), vec![filter.clone()], vec![filter]).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // The same aggregation answered from rollup rows, when it only counts
+    // events by time, contract and event over whole buckets.
+    pub fn plan(&self, aggregation: &Aggregation, from: Option<DateTime>, to: Option<DateTime>) -> Option<Aggregation> {
+        if !matches!(aggregation.metric, Metric::Count) {
+            return None;
+        }
+        let mut finest = None;
+        for group in &aggregation.group_by {
+            match group {
+                GroupBy::Time(interval) => finest = Some(*interval),
+                GroupBy::Path(path) if path == "contract" || path == "event" => {}
+                GroupBy::Path(_) => return None,
+            }
+        }
+        let filters_match = aggregation.filters.iter().all(|f| matches!(f, Filter::Cmp(path, Op::Eq, _) if path == "contract" || path == "event"));
+        if !filters_match {
+            return None;
+        }
+
+        let unbounded = from.is_none() && to.is_none() && finest.is_none();
+        let aligned = |millis: i64| [from, to].iter().flatten().all(|t| t.timestamp_millis().rem_euclid(millis) == 0);
+        let granularity = [Granularity::Total, Granularity::Day, Granularity::Hour, Granularity::Block].into_iter()
+            .filter(|g| self.is_enabled(Rollup::Events, *g))
+            .find(|g| match g {
+                Granularity::Total => unbounded,
+                Granularity::Day => !matches!(finest, Some(Interval::Hour)) && aligned(DAY_MILLIS),
+                Granularity::Hour => aligned(HOUR_MILLIS),
+                Granularity::Block => true,
+            })?;
+
+        let mut filters = aggregation.filters.clone();
+        filters.push(Filter::eq("rollup", Rollup::Events.as_str()));
+        filters.push(Filter::eq("granularity", granularity.as_str()));
+        filters.push(Filter::cmp("count", Op::Gt, 0i64));
+        if let Some(from) = from {
+            filters.push(Filter::cmp("bucket_time", Op::Gte, from));
+        }
+        if let Some(to) = to {
+            filters.push(Filter::cmp("bucket_time", Op::Lt, to));
+        }
+        Some(Aggregation {
+            filters,
+            group_by: aggregation.group_by.clone(),
+            metric: Metric::Sum("count".to_owned()),
+            by_value: aggregation.by_value,
+            limit: aggregation.limit,
+        })
+    }
+}
+
+// Rewrites the derived rows of one bucket (or one set of totals) from the
+// block rows selected by `source`. Rows that lost all their events stay with
+// a count of 0, readers skip those.
+async fn recount(storage: &dyn Storage, rollup: Rollup, granularity: Granularity, bucket: (&str, Option<DateTime>), source: Vec<Filter>, stored: Vec<Filter>) -> Result<()> {
+    let mut filters = vec![Filter::eq("rollup", rollup.as_str()), Filter::eq("granularity", Granularity::Block.as_str())];
+    filters.extend(source);
+    let aggregation = Aggregation {
+        filters,
+        group_by: ["contract", "event", "address"].iter().map(|p| GroupBy::Path(p.to_string())).collect(),
+        metric: Metric::Sum("count".to_owned()),
+        by_value: false,
+        limit: None,
+    };
+    let mut counts: BTreeMap<Vec<String>, i64> = storage.aggregate(Table::Rollups, aggregation).await?.into_iter()
+        .map(|g| (g.key.into_iter().map(Option::unwrap_or_default).collect(), g.value.parse().unwrap_or_default()))
+        .collect();
+
+    let mut query = Query::new()
+        .filter(Filter::eq("rollup", rollup.as_str()))
+        .filter(Filter::eq("granularity", granularity.as_str()));
+    for filter in stored {
+        query = query.filter(filter);
+    }
+    for row in storage.find(Table::Rollups, query).await? {
+        let key = ["contract", "event", "address"].iter().map(|p| row.get_str(p).unwrap_or_default().to_owned()).collect();
+        counts.entry(key).or_insert(0);
+    }
+
+    let (label, time) = bucket;
+    let rows: Vec<Document> = counts.into_iter().map(|(key, count)| doc! {
+        "rollup": rollup.as_str(),
+        "granularity": granularity.as_str(),
+        "bucket": label,
+        "bucket_time": time.map(Bson::DateTime).unwrap_or(Bson::Null),
+        "contract": &key[0],
+        "event": &key[1],
+        "address": &key[2],
+        "count": count,
+    }).collect();
+    storage.upsert(Table::Rollups, rows).await
+}
+
+// Address typed args, and addresses in array args. Decoded args carry no
+// types, so this goes by the 0x + 40 hex digits shape.
+fn arg_addresses(args: &Document) -> BTreeSet<String> {
+    let is_address = |s: &str| s.len() == 42 && s.starts_with("0x") && s[2..].chars().all(|c| c.is_ascii_hexdigit());
+    args.values()
+        .flat_map(|v| match v {
+            Bson::Array(items) => items.iter().collect(),
+            v => vec![v],
+        })
+        .filter_map(|v| v.as_str().filter(|s| is_address(s)).map(|s| s.to_lowercase()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks_storage::sqlite::SqliteStorage;
+
+    use super::*;
+
+    const JAN_1: i64 = 1_704_067_200_000;
+    const ALICE: &str = "0x00000000000000000000000000000000000000a1";
+    const BOB: &str = "0x00000000000000000000000000000000000000B2";
+
+    fn rollups(granularities: &[Granularity]) -> Rollups {
+        let mut enabled = BTreeMap::new();
+        enabled.insert(Rollup::Events, granularities.to_vec());
+        enabled.insert(Rollup::Addresses, vec![Granularity::Total]);
+        Rollups::new(enabled)
+    }
+
+    fn event(block: i64, millis: i64, name: &str, from: &str, to: &str) -> Document {
+        doc! {
+            "block_number": block,
+            "block_time": DateTime::from_millis(millis),
+            "contract": "token",
+            "event": name,
+            "args": { "from": from, "to": [to, "not an address"], "value": "1" },
+        }
+    }
+
+    async fn counts(storage: &dyn Storage, rollup: Rollup, granularity: Granularity) -> Vec<(String, String, String, i64)> {
+        let query = Query::new()
+            .filter(Filter::eq("rollup", rollup.as_str()))
+            .filter(Filter::eq("granularity", granularity.as_str()));
+        let mut rows: Vec<_> = storage.find(Table::Rollups, query).await.unwrap().into_iter()
+            .map(|r| {
+                let text = |p: &str| r.get_str(p).unwrap_or_default().to_owned();
+                (text("bucket"), text("event"), text("address"), r.get_i64("count").unwrap())
+            })
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn counts_events_and_address_args_per_block() {
+        let rollups = rollups(&[]);
+        let counts = rollups.block_counts(&[
+            event(1, JAN_1, "Transfer", ALICE, BOB),
+            event(1, JAN_1, "Transfer", BOB, ALICE),
+            event(2, JAN_1, "Approval", ALICE, ALICE),
+        ]);
+        let events = |block, name: &str| counts[&(Rollup::Events, block, "token".to_owned(), name.to_owned(), String::new())].1;
+        assert_eq!(events(1, "Transfer"), 2);
+        assert_eq!(events(2, "Approval"), 1);
+        // the same address twice in one event counts once, addresses are lowercased
+        let addresses = |block, address: &str| counts[&(Rollup::Addresses, block, String::new(), String::new(), address.to_owned())].1;
+        assert_eq!(addresses(1, ALICE), 2);
+        assert_eq!(addresses(1, &BOB.to_lowercase()), 2);
+        assert_eq!(addresses(2, ALICE), 1);
+    }
+
+    #[tokio::test]
+    async fn derived_rows_follow_writes_and_rollbacks() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        let rollups = rollups(&[Granularity::Hour, Granularity::Day]);
+        rollups.apply(&storage, &[
+            event(1, JAN_1 + 60_000, "Transfer", ALICE, BOB),
+            event(1, JAN_1 + 60_000, "Transfer", ALICE, BOB),
+            event(2, JAN_1 + HOUR_MILLIS + 1, "Transfer", ALICE, BOB),
+        ]).await.unwrap();
+        // a retried block replaces its rows instead of adding to them
+        rollups.apply(&storage, &[event(2, JAN_1 + HOUR_MILLIS + 1, "Transfer", ALICE, BOB)]).await.unwrap();
+
+        let transfers = |bucket: &str, count| (bucket.to_owned(), "Transfer".to_owned(), String::new(), count);
+        assert_eq!(counts(&storage, Rollup::Events, Granularity::Hour).await, vec![
+            transfers("2024-01-01T00:00:00Z", 2),
+            transfers("2024-01-01T01:00:00Z", 1),
+        ]);
+        assert_eq!(counts(&storage, Rollup::Events, Granularity::Day).await, vec![transfers("2024-01-01", 3)]);
+        let total = |address: &str, count| (String::new(), String::new(), address.to_owned(), count);
+        assert_eq!(counts(&storage, Rollup::Addresses, Granularity::Total).await, vec![
+            total(ALICE, 3),
+            total(&BOB.to_lowercase(), 3),
+        ]);
+
+        rollups.rollback(&storage, 2).await.unwrap();
+        assert_eq!(counts(&storage, Rollup::Events, Granularity::Hour).await, vec![
+            transfers("2024-01-01T00:00:00Z", 2),
+            transfers("2024-01-01T01:00:00Z", 0),
+        ]);
+        assert_eq!(counts(&storage, Rollup::Events, Granularity::Day).await, vec![transfers("2024-01-01", 2)]);
+        assert_eq!(counts(&storage, Rollup::Addresses, Granularity::Total).await, vec![
+            total(ALICE, 2),
+            total(&BOB.to_lowercase(), 2),
+        ]);
+    }
+
+    #[test]
+    fn plans_the_coarsest_aligned_granularity() {
+        let rollups = rollups(&[Granularity::Hour, Granularity::Day]);
+        let count_by = |group_by| Aggregation { filters: Vec::new(), group_by, metric: Metric::Count, by_value: false, limit: None };
+        let granularity = |plan: Option<Aggregation>| plan.map(|a| {
+            a.filters.iter().find_map(|f| match f {
+                Filter::Cmp(path, Op::Eq, Bson::String(g)) if path == "granularity" => Some(g.clone()),
+                _ => None,
+            }).unwrap()
+        });
+        let day = Some(DateTime::from_millis(JAN_1));
+        let hour = Some(DateTime::from_millis(JAN_1 + HOUR_MILLIS));
+
+        let by_day = count_by(vec![GroupBy::Time(Interval::Day)]);
+        assert_eq!(granularity(rollups.plan(&by_day, day, None)).as_deref(), Some("day"));
+        assert_eq!(granularity(rollups.plan(&by_day, hour, None)).as_deref(), Some("hour"));
+        let by_hour = count_by(vec![GroupBy::Time(Interval::Hour)]);
+        assert_eq!(granularity(rollups.plan(&by_hour, day, None)).as_deref(), Some("hour"));
+        // no totals enabled, unaligned bounds still have block rows
+        let unaligned = Some(DateTime::from_millis(JAN_1 + 1));
+        assert_eq!(granularity(rollups.plan(&count_by(Vec::new()), None, None)).as_deref(), Some("day"));
+        assert_eq!(granularity(rollups.plan(&by_day, unaligned, None)).as_deref(), Some("block"));
+
+        let plan = rollups.plan(&by_day, day, None).unwrap();
+        assert!(matches!(&plan.metric, Metric::Sum(path) if path == "count"));
+        assert!(rollups.plan(&count_by(vec![GroupBy::Path("args.from".to_owned())]), None, None).is_none());
+        let sum = Aggregation { metric: Metric::Sum("args.value".to_owned()), ..count_by(Vec::new()) };
+        assert!(rollups.plan(&sum, None, None).is_none());
+    }
+}
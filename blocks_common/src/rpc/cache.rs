@@ -0,0 +1,568 @@
+use std::env;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use futures::future::BoxFuture;
+use jsonrpc_core::{Call, Params, Value};
+use rusqlite::{params, Connection, OptionalExtension};
+use tokio::task;
+use web3::signing::keccak256;
+use web3::{BatchTransport, RequestId, Transport};
+
+use crate::metrics::RPC_CACHE_REQUESTS;
+
+// blocks this deep are treated as final
+const DEFAULT_CONFIRMATIONS: u64 = 64;
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024 * 1024;
+// last_used is only rewritten when older than this, so hits stay reads
+const TOUCH_EVERY_SECS: i64 = 3600;
+
+// answers that only depend on the block they came from
+const CACHED_METHODS: &[&str] = &[
+    "eth_getLogs",
+    "eth_getBlockByNumber",
+    "eth_getBlockByHash",
+    "eth_getTransactionReceipt",
+    "eth_getTransactionByHash",
+    "trace_block",
+    "trace_replayBlockTransactions",
+    "debug_traceBlockByNumber",
+    "trace_transaction",
+    "trace_replayTransaction",
+    "debug_traceTransaction",
+];
+
+fn now_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+fn number(value: &Value) -> Option<u64> {
+    u64::from_str_radix(value.as_str()?.strip_prefix("0x")?, 16).ok()
+}
+
+fn compress(value: &Value) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let _ = encoder.write_all(value.to_string().as_bytes());
+    encoder.finish().unwrap_or_default()
+}
+
+fn decompress(bytes: &[u8]) -> Option<Value> {
+    let mut text = String::new();
+    ZlibDecoder::new(bytes).read_to_string(&mut text).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+// On-disk cache of finalized answers keyed by method and params. Only
+// answers about blocks at least `confirmations` below the observed head are
+// written, so a hit never needs a freshness check and works offline.
+#[derive(Debug)]
+pub struct RpcCache {
+    conn: Arc<Mutex<Connection>>,
+    confirmations: u64,
+    // highest eth_blockNumber answer that went through the cache, kept in the
+    // file so a run that doesn't ask for the head still caches
+    head: AtomicU64,
+}
+
+impl RpcCache {
+    pub fn open(path: &str, confirmations: u64) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        // auto_vacuum only takes effect before the first table exists
+        conn.execute_batch(
+            "PRAGMA auto_vacuum = INCREMENTAL;
+             PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             PRAGMA busy_timeout = 5000;
+             CREATE TABLE IF NOT EXISTS entries (
+                 key       TEXT PRIMARY KEY,
+                 method    TEXT NOT NULL,
+                 value     BLOB NOT NULL,
+                 size      INTEGER NOT NULL,
+                 last_used INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS entries_last_used ON entries (last_used);
+             CREATE TABLE IF NOT EXISTS tx_blocks (
+                 tx_hash TEXT PRIMARY KEY,
+                 block   INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS meta (
+                 name  TEXT PRIMARY KEY,
+                 value INTEGER NOT NULL
+             );",
+        )?;
+        let head: Option<i64> = conn.query_row("SELECT value FROM meta WHERE name = 'head'", [], |row| row.get(0)).optional()?;
+        Ok(RpcCache { conn: Arc::new(Mutex::new(conn)), confirmations, head: AtomicU64::new(head.unwrap_or(0) as u64) })
+    }
+
+    // RPC_CACHE_PATH turns the cache on, RPC_CACHE_CONFIRMATIONS sets the depth (64)
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let Ok(path) = env::var("RPC_CACHE_PATH") else { return Ok(None) };
+        let confirmations = env::var("RPC_CACHE_CONFIRMATIONS").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_CONFIRMATIONS);
+        Ok(Some(RpcCache::open(&path, confirmations)?))
+    }
+
+    fn key(method: &str, params: &[Value]) -> String {
+        hex::encode(keccak256(format!("{}\n{}", method, Value::Array(params.to_vec())).as_bytes()))
+    }
+
+    async fn observe_head(&self, result: &Value) {
+        let Some(head) = number(result) else { return };
+        if self.head.fetch_max(head, Ordering::Relaxed) < head {
+            self.with_conn(move |conn| {
+                conn.execute("INSERT INTO meta (name, value) VALUES ('head', ?1) ON CONFLICT (name) DO UPDATE SET value = max(value, ?1)", params![head as i64])
+            }).await;
+        }
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let result = task::spawn_blocking(move || {
+            let conn = conn.lock().map_err(|_| rusqlite::Error::InvalidQuery)?;
+            f(&conn)
+        }).await;
+        match result {
+            Ok(Ok(value)) => Some(value),
+            Ok(Err(err)) => {
+                tracing::warn!("RPC cache: {}", err);
+                None
+            }
+            Err(err) => {
+                tracing::warn!("RPC cache task: {}", err);
+                None
+            }
+        }
+    }
+
+    async fn get(&self, method: &str, params: &[Value]) -> Option<Value> {
+        let key = Self::key(method, params);
+        let bytes = self.with_conn(move |conn| {
+            let now = now_secs();
+            let bytes: Option<Vec<u8>> = conn.query_row("SELECT value FROM entries WHERE key = ?1", params![key], |row| row.get(0)).optional()?;
+            if bytes.is_some() {
+                conn.execute("UPDATE entries SET last_used = ?2 WHERE key = ?1 AND last_used < ?3", params![key, now, now - TOUCH_EVERY_SECS])?;
+            }
+            Ok(bytes)
+        }).await.flatten();
+        let value = bytes.as_deref().and_then(decompress);
+        RPC_CACHE_REQUESTS.with_label_values(&[method, if value.is_some() { "hit" } else { "miss" }]).inc();
+        value
+    }
+
+    // stores the answer when the block it is about is final
+    async fn put(&self, method: &str, params: &[Value], result: &Value) {
+        let head = self.head.load(Ordering::Relaxed);
+        if result.is_null() || head < self.confirmations {
+            return;
+        }
+        let safe = head - self.confirmations;
+        let first = params.first().cloned().unwrap_or_default();
+        let block = match method {
+            // a missing toBlock means latest
+            "eth_getLogs" => first.get("toBlock").and_then(number),
+            "eth_getBlockByNumber" | "trace_block" | "trace_replayBlockTransactions" | "debug_traceBlockByNumber" => number(&first),
+            "eth_getBlockByHash" => result.get("number").and_then(number),
+            "eth_getTransactionReceipt" | "eth_getTransactionByHash" => result.get("blockNumber").and_then(number),
+            // traces by hash don't say where the tx is, it is looked up below
+            _ => None,
+        };
+        let tx_hash = first.as_str().map(|h| h.to_lowercase());
+        let by_tx = block.is_none() && matches!(method, "trace_transaction" | "trace_replayTransaction" | "debug_traceTransaction");
+        if !by_tx && block.is_none_or(|b| b > safe) {
+            return;
+        }
+
+        let key = Self::key(method, params);
+        let method = method.to_owned();
+        let value = compress(result);
+        self.with_conn(move |conn| {
+            if by_tx {
+                // only final once the receipt of the same tx was cached
+                let Some(hash) = &tx_hash else { return Ok(()) };
+                let known: Option<i64> = conn.query_row("SELECT block FROM tx_blocks WHERE tx_hash = ?1", params![hash], |row| row.get(0)).optional()?;
+                if known.is_none_or(|b| b as u64 > safe) {
+                    return Ok(());
+                }
+            }
+            conn.execute(
+                "INSERT OR REPLACE INTO entries (key, method, value, size, last_used) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![key, method, value, value.len() as i64, now_secs()],
+            )?;
+            if method == "eth_getTransactionReceipt" || method == "eth_getTransactionByHash" {
+                if let (Some(hash), Some(block)) = (&tx_hash, block) {
+                    conn.execute("INSERT OR IGNORE INTO tx_blocks (tx_hash, block) VALUES (?1, ?2)", params![hash, block as i64])?;
+                }
+            }
+            Ok(())
+        }).await;
+    }
+
+    // drops least recently used entries until the values fit in `max_bytes`,
+    // returns the number of entries and bytes removed
+    pub fn prune(&self, max_bytes: u64) -> rusqlite::Result<(u64, u64)> {
+        let mut conn = self.conn.lock().map_err(|_| rusqlite::Error::InvalidQuery)?;
+        let total: i64 = conn.query_row("SELECT COALESCE(SUM(size), 0) FROM entries", [], |row| row.get(0))?;
+        let mut excess = (total as u64).saturating_sub(max_bytes);
+        if excess == 0 {
+            return Ok((0, 0));
+        }
+
+        let tx = conn.transaction()?;
+        let (mut entries, mut bytes) = (0, 0);
+        {
+            let mut select = tx.prepare("SELECT key, size FROM entries ORDER BY last_used")?;
+            let mut delete = tx.prepare("DELETE FROM entries WHERE key = ?1")?;
+            let mut rows = select.query([])?;
+            while let Some(row) = rows.next()? {
+                if excess == 0 {
+                    break;
+                }
+                let (key, size): (String, i64) = (row.get(0)?, row.get(1)?);
+                delete.execute(params![key])?;
+                excess = excess.saturating_sub(size as u64);
+                entries += 1;
+                bytes += size as u64;
+            }
+        }
+        tx.commit()?;
+        conn.execute_batch("PRAGMA incremental_vacuum")?;
+        Ok((entries, bytes))
+    }
+
+    // (method, entries, bytes)
+    pub fn stats(&self) -> rusqlite::Result<Vec<(String, u64, u64)>> {
+        let conn = self.conn.lock().map_err(|_| rusqlite::Error::InvalidQuery)?;
+        let mut stmt = conn.prepare("SELECT method, count(*), SUM(size) FROM entries GROUP BY method ORDER BY method")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64)))?;
+        rows.collect()
+    }
+}
+
+// accepts plain bytes or a K, M, G suffix (powers of 1024)
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim().to_uppercase();
+    let (digits, unit) = match s.chars().last()? {
+        'K' => (&s[..s.len() - 1], 1024),
+        'M' => (&s[..s.len() - 1], 1024 * 1024),
+        'G' => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s.as_str(), 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+// `<binary> cache prune [max size]` and `<binary> cache stats` on the file
+// in RPC_CACHE_PATH. None when the arguments are not a cache command.
+pub fn command(args: &[String]) -> Option<Result<(), Box<dyn Error>>> {
+    if args.get(1).map(String::as_str) != Some("cache") {
+        return None;
+    }
+    Some(run_command(&args[2..]))
+}
+
+fn run_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let cache = RpcCache::from_env()?.ok_or("You must set the RPC_CACHE_PATH environment var!")?;
+    match args.first().map(String::as_str) {
+        Some("prune") => {
+            let max_bytes = match args.get(1).or(env::var("RPC_CACHE_MAX_BYTES").ok().as_ref()) {
+                Some(size) => parse_size(size).ok_or_else(|| format!("invalid size `{}`", size))?,
+                None => DEFAULT_MAX_BYTES,
+            };
+            let (entries, bytes) = cache.prune(max_bytes)?;
+            println!("removed {} entries, {} bytes", entries, bytes);
+        }
+        Some("stats") => {
+            for (method, entries, bytes) in cache.stats()? {
+                println!("{:<32} {:>10} entries {:>14} bytes", method, entries, bytes);
+            }
+        }
+        _ => return Err("usage: cache prune [max size] | cache stats".into()),
+    }
+    Ok(())
+}
+
+fn method_call(call: &Call) -> Option<(String, Vec<Value>)> {
+    let Call::MethodCall(c) = call else { return None };
+    let params = match &c.params {
+        Params::Array(values) => values.clone(),
+        Params::Map(map) => vec![Value::Object(map.clone())],
+        Params::None => Vec::new(),
+    };
+    Some((c.method.clone(), params))
+}
+
+// Reads finalized answers from the cache and sends everything else on to
+// `inner`. Without a cache it only forwards.
+#[derive(Debug, Clone)]
+pub struct CachedTransport<T> {
+    inner: T,
+    cache: Option<Arc<RpcCache>>,
+}
+
+impl<T> CachedTransport<T> {
+    pub fn new(inner: T, cache: Option<RpcCache>) -> Self {
+        CachedTransport { inner, cache: cache.map(Arc::new) }
+    }
+}
+
+impl<T> Transport for CachedTransport<T>
+where
+    T: Transport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let (Some(cache), Some((method, params))) = (self.cache.clone(), method_call(&request)) else {
+            return Box::pin(self.inner.send(id, request));
+        };
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            if method == "eth_blockNumber" {
+                let result = inner.send(id, request).await?;
+                cache.observe_head(&result).await;
+                return Ok(result);
+            }
+            if !CACHED_METHODS.contains(&method.as_str()) {
+                return inner.send(id, request).await;
+            }
+            if let Some(value) = cache.get(&method, &params).await {
+                return Ok(value);
+            }
+            let result = inner.send(id, request).await?;
+            cache.put(&method, &params, &result).await;
+            Ok(result)
+        })
+    }
+}
+
+// hits are answered locally, only the misses go out as one batch
+impl<T> BatchTransport for CachedTransport<T>
+where
+    T: BatchTransport + Send + Sync + 'static,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<Value>>>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let Some(cache) = self.cache.clone() else {
+            return Box::pin(self.inner.send_batch(requests));
+        };
+        let requests: Vec<(RequestId, Call)> = requests.into_iter().collect();
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            let mut answers: Vec<Option<web3::Result<Value>>> = Vec::with_capacity(requests.len());
+            let mut misses = Vec::new();
+            for (i, (id, call)) in requests.iter().enumerate() {
+                let hit = match method_call(call) {
+                    Some((method, params)) if CACHED_METHODS.contains(&method.as_str()) => cache.get(&method, &params).await,
+                    _ => None,
+                };
+                if hit.is_none() {
+                    misses.push((i, *id, call.clone()));
+                }
+                answers.push(hit.map(Ok));
+            }
+
+            if !misses.is_empty() {
+                let fetched = inner.send_batch(misses.iter().map(|(_, id, call)| (*id, call.clone()))).await?;
+                for ((i, _, call), result) in misses.iter().zip(fetched) {
+                    if let (Ok(value), Some((method, params))) = (&result, method_call(call)) {
+                        if method == "eth_blockNumber" {
+                            cache.observe_head(value).await;
+                        } else if CACHED_METHODS.contains(&method.as_str()) {
+                            cache.put(&method, &params, value).await;
+                        }
+                    }
+                    answers[*i] = Some(result);
+                }
+            }
+            Ok(answers.into_iter().map(|a| a.unwrap_or(Err(web3::Error::Unreachable))).collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::{ready, Ready};
+    use serde_json::json;
+    use web3::helpers;
+
+    use super::*;
+
+    fn block(number: &str) -> Vec<Value> {
+        vec![json!(number), json!(false)]
+    }
+
+    // 100 seen as head, so blocks up to 90 are final
+    async fn cache() -> RpcCache {
+        let cache = RpcCache::open(":memory:", 10).unwrap();
+        cache.observe_head(&json!("0x64")).await;
+        cache
+    }
+
+    #[tokio::test]
+    async fn stores_final_answers_only() {
+        let cache = RpcCache::open(":memory:", 10).unwrap();
+        cache.put("eth_getBlockByNumber", &block("0x1"), &json!({ "number": "0x1" })).await;
+        assert_eq!(cache.get("eth_getBlockByNumber", &block("0x1")).await, None);
+
+        let cache = self::cache().await;
+        for number in ["0x5a", "0x5b"] {
+            cache.put("eth_getBlockByNumber", &block(number), &json!({ "number": number })).await;
+        }
+        assert_eq!(cache.get("eth_getBlockByNumber", &block("0x5a")).await, Some(json!({ "number": "0x5a" })));
+        assert_eq!(cache.get("eth_getBlockByNumber", &block("0x5b")).await, None);
+
+        cache.put("eth_getBlockByNumber", &block("0x1"), &Value::Null).await;
+        assert_eq!(cache.get("eth_getBlockByNumber", &block("0x1")).await, None);
+
+        // a filter without toBlock runs to the head
+        let open = vec![json!({ "fromBlock": "0x1" })];
+        let closed = vec![json!({ "fromBlock": "0x1", "toBlock": "0x50" })];
+        cache.put("eth_getLogs", &open, &json!([])).await;
+        cache.put("eth_getLogs", &closed, &json!([])).await;
+        assert_eq!(cache.get("eth_getLogs", &open).await, None);
+        assert_eq!(cache.get("eth_getLogs", &closed).await, Some(json!([])));
+    }
+
+    #[tokio::test]
+    async fn traces_by_hash_wait_for_a_final_receipt() {
+        let cache = cache().await;
+        let tx = vec![json!("0xAB")];
+        cache.put("trace_transaction", &tx, &json!([{ "type": "call" }])).await;
+        assert_eq!(cache.get("trace_transaction", &tx).await, None);
+
+        cache.put("eth_getTransactionReceipt", &[json!("0xab")], &json!({ "blockNumber": "0x5b" })).await;
+        cache.put("trace_transaction", &tx, &json!([{ "type": "call" }])).await;
+        assert_eq!(cache.get("trace_transaction", &tx).await, None);
+
+        let other = vec![json!("0xcd")];
+        cache.put("eth_getTransactionReceipt", &other, &json!({ "blockNumber": "0x5a" })).await;
+        cache.put("trace_transaction", &other, &json!([{ "type": "call" }])).await;
+        assert_eq!(cache.get("trace_transaction", &other).await, Some(json!([{ "type": "call" }])));
+    }
+
+    #[tokio::test]
+    async fn head_survives_reopening() {
+        let path = std::env::temp_dir().join(format!("rpc_cache_head_{}.sqlite", std::process::id()));
+        let path = path.to_str().unwrap();
+        RpcCache::open(path, 10).unwrap().observe_head(&json!("0x64")).await;
+        let cache = RpcCache::open(path, 10).unwrap();
+        assert_eq!(cache.head.load(Ordering::Relaxed), 100);
+        drop(cache);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn prune_drops_least_recently_used_first() {
+        let cache = cache().await;
+        for (i, number) in ["0x1", "0x2", "0x3"].iter().enumerate() {
+            cache.put("eth_getBlockByNumber", &block(number), &json!({ "number": number })).await;
+            let key = RpcCache::key("eth_getBlockByNumber", &block(number));
+            cache.conn.lock().unwrap().execute("UPDATE entries SET last_used = ?2 WHERE key = ?1", params![key, [3, 1, 2][i]]).unwrap();
+        }
+        let stats = cache.stats().unwrap();
+        let total = stats[0].2;
+        assert_eq!(cache.prune(total).unwrap(), (0, 0));
+
+        let (entries, bytes) = cache.prune(total - 1).unwrap();
+        assert_eq!(entries, 1);
+        assert_eq!(cache.stats().unwrap(), vec![("eth_getBlockByNumber".to_owned(), 2, total - bytes)]);
+        assert_eq!(cache.get("eth_getBlockByNumber", &block("0x2")).await, None);
+        assert!(cache.get("eth_getBlockByNumber", &block("0x1")).await.is_some());
+
+        assert_eq!(cache.prune(0).unwrap().0, 2);
+        assert!(cache.stats().unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("2k"), Some(2048));
+        assert_eq!(parse_size(" 3M "), Some(3 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("G"), None);
+        assert_eq!(parse_size("-1"), None);
+    }
+
+    // answers every call with its method and first param, noting what it was asked
+    #[derive(Debug, Clone, Default)]
+    struct Node {
+        asked: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Node {
+        fn answer(&self, call: &Call) -> web3::Result<Value> {
+            let (method, params) = method_call(call).unwrap();
+            self.asked.lock().unwrap().push(method.clone());
+            match method.as_str() {
+                "eth_blockNumber" => Ok(json!("0x64")),
+                _ => Ok(json!({ "number": params.first() })),
+            }
+        }
+    }
+
+    impl Transport for Node {
+        type Out = Ready<web3::Result<Value>>;
+
+        fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+            (1, helpers::build_request(1, method, params))
+        }
+
+        fn send(&self, _id: RequestId, request: Call) -> Self::Out {
+            ready(self.answer(&request))
+        }
+    }
+
+    impl BatchTransport for Node {
+        type Batch = Ready<web3::Result<Vec<web3::Result<Value>>>>;
+
+        fn send_batch<I>(&self, requests: I) -> Self::Batch
+        where
+            I: IntoIterator<Item = (RequestId, Call)>,
+        {
+            ready(Ok(requests.into_iter().map(|(_, call)| self.answer(&call)).collect()))
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_send_only_the_misses() {
+        let cache = RpcCache::open(":memory:", 10).unwrap();
+        let node = Node::default();
+        let transport = CachedTransport::new(node.clone(), Some(cache));
+        let request = |method: &str, params: Vec<Value>| (0, helpers::build_request(0, method, params));
+
+        // the head comes from the batch itself, the blocks after it are cached
+        let first = transport.send_batch(vec![request("eth_blockNumber", Vec::new()), request("eth_getBlockByNumber", block("0x1"))]).await.unwrap();
+        assert_eq!(first.into_iter().map(Result::unwrap).collect::<Vec<_>>(), vec![json!("0x64"), json!({ "number": "0x1" })]);
+        transport.send_batch(vec![request("eth_getBlockByNumber", block("0x2"))]).await.unwrap();
+        node.asked.lock().unwrap().clear();
+
+        let answers = transport.send_batch(vec![
+            request("eth_getBlockByNumber", block("0x2")),
+            request("eth_chainId", Vec::new()),
+            request("eth_getBlockByNumber", block("0x5b")),
+            request("eth_getBlockByNumber", block("0x1")),
+        ]).await.unwrap();
+        let answers: Vec<Value> = answers.into_iter().map(Result::unwrap).collect();
+        assert_eq!(answers, vec![json!({ "number": "0x2" }), json!({ "number": Value::Null }), json!({ "number": "0x5b" }), json!({ "number": "0x1" })]);
+        assert_eq!(*node.asked.lock().unwrap(), vec!["eth_chainId", "eth_getBlockByNumber"]);
+    }
+}
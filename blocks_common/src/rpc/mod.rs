@@ -0,0 +1,125 @@
+pub mod cache;
+mod pool;
+
+use std::env;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use futures::future::BoxFuture;
+use jsonrpc_core::{Call, Value};
+use web3::{BatchTransport, RequestId, Transport, Web3};
+
+use crate::metrics::{RPC_DURATION, RPC_ERRORS};
+
+pub use cache::{CachedTransport, RpcCache};
+pub use pool::{ProviderConfig, ProviderPool};
+
+// transport every binary talks to the nodes through; cache hits never reach
+// the metered part, so rpc metrics only count real requests
+pub type RpcTransport = CachedTransport<MeteredTransport<ProviderPool>>;
+
+// RPC_PROVIDERS_PATH takes a json list of {url, name, weight, rps,
+// max_concurrency, timeout_secs}; without it `fallback` is the only provider.
+// RPC_CROSS_CHECK_SECS compares block hashes between providers that often,
+// RPC_CACHE_PATH keeps finalized answers on disk (see RpcCache).
+pub fn from_env(fallback: Option<&str>) -> Result<Web3<RpcTransport>, Box<dyn Error>> {
+    let configs = match env::var("RPC_PROVIDERS_PATH") {
+        Ok(path) => ProviderConfig::load(&path)?,
+        Err(_) => vec![ProviderConfig::new(fallback.ok_or("You must set RPC_PROVIDERS_PATH or an RPC url!")?)],
+    };
+    let pool = ProviderPool::new(&configs)?;
+
+    if let Some(every) = env::var("RPC_CROSS_CHECK_SECS").ok().and_then(|v| v.parse().ok()) {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            loop {
+                pool.cross_check().await;
+                tokio::time::sleep(Duration::from_secs(every)).await;
+            }
+        });
+    }
+    let cache = RpcCache::from_env()?;
+    Ok(Web3::new(CachedTransport::new(MeteredTransport::new(pool), cache)))
+}
+
+// Records latency and failures of every request by JSON-RPC method.
+#[derive(Debug, Clone)]
+pub struct MeteredTransport<T> {
+    inner: T,
+}
+
+impl<T> MeteredTransport<T> {
+    pub fn new(inner: T) -> Self {
+        MeteredTransport { inner }
+    }
+}
+
+fn method_of(call: &Call) -> String {
+    match call {
+        Call::MethodCall(c) => c.method.clone(),
+        Call::Notification(n) => n.method.clone(),
+        Call::Invalid { .. } => "invalid".to_owned(),
+    }
+}
+
+impl<T> Transport for MeteredTransport<T>
+where
+    T: Transport,
+    T::Out: Send + 'static,
+{
+    type Out = BoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let method = method_of(&request);
+        let response = self.inner.send(id, request);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = response.await;
+            RPC_DURATION.with_label_values(&[&method]).observe(started.elapsed().as_secs_f64());
+            if result.is_err() {
+                RPC_ERRORS.with_label_values(&[&method]).inc();
+            }
+            result
+        })
+    }
+}
+
+// a batch is timed once, under the method of its first request
+impl<T> BatchTransport for MeteredTransport<T>
+where
+    T: BatchTransport,
+    T::Out: Send + 'static,
+    T::Batch: Send + 'static,
+{
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<Value>>>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let requests: Vec<(RequestId, Call)> = requests.into_iter().collect();
+        let methods: Vec<String> = requests.iter().map(|(_, call)| method_of(call)).collect();
+        let response = self.inner.send_batch(requests);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = response.await;
+            let label = format!("batch:{}", methods.first().map(|m| m.as_str()).unwrap_or("empty"));
+            RPC_DURATION.with_label_values(&[&label]).observe(started.elapsed().as_secs_f64());
+            match &result {
+                Ok(items) => {
+                    for (item, method) in items.iter().zip(methods.iter()) {
+                        if item.is_err() {
+                            RPC_ERRORS.with_label_values(&[method]).inc();
+                        }
+                    }
+                }
+                Err(_) => RPC_ERRORS.with_label_values(&[&label]).inc(),
+            }
+            result
+        })
+    }
+}
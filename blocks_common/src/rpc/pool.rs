@@ -0,0 +1,543 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::{join_all, BoxFuture};
+use jsonrpc_core::{Call, ErrorCode, Value};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+use web3::error::TransportError;
+use web3::transports::Http;
+use web3::types::{BlockId, BlockNumber, U64};
+use web3::{helpers, BatchTransport, RequestId, Transport, Web3};
+
+use crate::metrics::{RPC_FAILOVERS, RPC_PROVIDER_EXCLUDED, RPC_PROVIDER_SCORE};
+
+// weight of the newest sample in the latency and error averages
+const EWMA: f64 = 0.2;
+// consecutive failures before a provider is skipped for a while
+const FAILURES_BEFORE_COOLDOWN: u32 = 3;
+const MIN_COOLDOWN: Duration = Duration::from_secs(5);
+const MAX_COOLDOWN: Duration = Duration::from_secs(60);
+// a provider this far behind the best head is left out by the cross check
+const MAX_HEAD_LAG: u64 = 10;
+// reqwest waits forever by default, a hung node would keep its permits
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+// entry of the json file pointed to by RPC_PROVIDERS_PATH
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderConfig {
+    pub url: String,
+    // label in logs and metrics, the url's host by default
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    // requests per second, 0 is unlimited
+    #[serde(default)]
+    pub rps: u32,
+    #[serde(default = "default_concurrency")]
+    pub max_concurrency: usize,
+    // a request without an answer by then moves on to the next provider
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_concurrency() -> usize {
+    16
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_TIMEOUT_SECS
+}
+
+impl ProviderConfig {
+    pub fn new(url: &str) -> Self {
+        ProviderConfig { url: url.to_owned(), name: None, weight: default_weight(), rps: 0, max_concurrency: default_concurrency(), timeout_secs: default_timeout_secs() }
+    }
+
+    pub fn load(path: &str) -> Result<Vec<Self>, Box<dyn Error>> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+}
+
+// Token bucket that hands out reservations: the caller sleeps for the returned
+// time, so a burst queues up instead of failing.
+struct RateLimit {
+    rps: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimit {
+    fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        let refill = now.duration_since(self.updated).as_secs_f64() * self.rps;
+        self.tokens = (self.tokens + refill).min(self.rps.max(1.0));
+        self.updated = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rps)
+        }
+    }
+}
+
+struct Health {
+    // seconds
+    latency: f64,
+    // share of failed requests, 0 to 1
+    errors: f64,
+    failures: u32,
+    down_until: Option<Instant>,
+    // why the cross check left the provider out
+    excluded: Option<String>,
+}
+
+impl Health {
+    // 1 for a fast provider that never fails, towards 0 as it gets slow or flaky
+    fn score(&self) -> f64 {
+        (1.0 - self.errors) / (1.0 + self.latency)
+    }
+}
+
+struct Provider {
+    name: String,
+    weight: f64,
+    http: Http,
+    limit: Option<Mutex<RateLimit>>,
+    permits: Semaphore,
+    timeout: Duration,
+    health: Mutex<Health>,
+}
+
+impl Provider {
+    fn new(config: &ProviderConfig) -> Result<Self, web3::Error> {
+        let name = config.name.clone().unwrap_or_else(|| host(&config.url));
+        let limit = (config.rps > 0).then(|| {
+            Mutex::new(RateLimit { rps: config.rps as f64, tokens: config.rps as f64, updated: Instant::now() })
+        });
+        RPC_PROVIDER_SCORE.with_label_values(&[&name]).set(1.0);
+        RPC_PROVIDER_EXCLUDED.with_label_values(&[&name]).set(0);
+        Ok(Provider {
+            name,
+            weight: config.weight.max(1) as f64,
+            http: Http::new(&config.url)?,
+            limit,
+            permits: Semaphore::new(config.max_concurrency.max(1)),
+            timeout: Duration::from_secs(config.timeout_secs.max(1)),
+            health: Mutex::new(Health { latency: 0.0, errors: 0.0, failures: 0, down_until: None, excluded: None }),
+        })
+    }
+
+    fn available(&self, now: Instant) -> bool {
+        let health = self.health.lock().unwrap();
+        health.excluded.is_none() && health.down_until.is_none_or(|t| now >= t)
+    }
+
+    fn effective_weight(&self) -> f64 {
+        self.weight * self.health.lock().unwrap().score().max(0.01)
+    }
+
+    async fn throttle(&self) {
+        let wait = match &self.limit {
+            Some(limit) => limit.lock().unwrap().reserve(),
+            None => Duration::ZERO,
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // a request that runs out of time fails like an unreachable node
+    async fn timed<R>(&self, request: impl Future<Output = web3::Result<R>>) -> web3::Result<R> {
+        match tokio::time::timeout(self.timeout, request).await {
+            Ok(result) => result,
+            Err(_) => Err(web3::Error::Transport(TransportError::Message(format!("no answer within {}s", self.timeout.as_secs())))),
+        }
+    }
+
+    fn record(&self, elapsed: Duration, ok: bool) {
+        let mut health = self.health.lock().unwrap();
+        health.latency = health.latency * (1.0 - EWMA) + elapsed.as_secs_f64() * EWMA;
+        health.errors = health.errors * (1.0 - EWMA) + if ok { 0.0 } else { EWMA };
+        if ok {
+            health.failures = 0;
+            health.down_until = None;
+        } else {
+            health.failures += 1;
+            if health.failures >= FAILURES_BEFORE_COOLDOWN {
+                let cooldown = MIN_COOLDOWN * 2u32.saturating_pow((health.failures - FAILURES_BEFORE_COOLDOWN).min(8));
+                health.down_until = Some(Instant::now() + cooldown.min(MAX_COOLDOWN));
+            }
+        }
+        RPC_PROVIDER_SCORE.with_label_values(&[&self.name]).set(health.score());
+    }
+
+    fn exclude(&self, reason: Option<String>) {
+        let mut health = self.health.lock().unwrap();
+        if health.excluded != reason {
+            match &reason {
+                Some(reason) => tracing::warn!(provider = %self.name, "Leaving RPC provider out: {}", reason),
+                None => tracing::info!(provider = %self.name, "RPC provider is back in line"),
+            }
+        }
+        RPC_PROVIDER_EXCLUDED.with_label_values(&[&self.name]).set(reason.is_some() as i64);
+        health.excluded = reason;
+    }
+}
+
+fn host(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    rest.split('/').next().unwrap_or(rest).to_owned()
+}
+
+// errors another provider might not have; JSON-RPC errors are answers and go
+// back to the caller, except -32005 which nodes use for "limit exceeded"
+fn retryable(err: &web3::Error) -> bool {
+    match err {
+        web3::Error::Unreachable | web3::Error::Transport(_) => true,
+        web3::Error::Rpc(err) => err.code == ErrorCode::ServerError(-32005),
+        _ => false,
+    }
+}
+
+// the hash most providers answered; a tie is no quorum and outvotes nobody
+fn majority(hashes: &[Option<String>]) -> Option<&str> {
+    let mut votes: HashMap<&str, usize> = HashMap::new();
+    for hash in hashes.iter().flatten() {
+        *votes.entry(hash.as_str()).or_default() += 1;
+    }
+    let top = votes.values().max().copied()?;
+    let mut leaders = votes.into_iter().filter(|(_, n)| *n == top).map(|(h, _)| h);
+    let leader = leaders.next()?;
+    leaders.next().is_none().then_some(leader)
+}
+
+struct Inner {
+    providers: Vec<Provider>,
+    // smooth weighted round robin state, one entry per provider
+    current: Mutex<Vec<f64>>,
+    ids: AtomicUsize,
+}
+
+impl Inner {
+    // next provider by weight times health, skipping the ones already tried.
+    // When every provider is cooling down or excluded the best scored one
+    // still gets the request.
+    fn pick(&self, tried: &[usize]) -> Option<usize> {
+        let now = Instant::now();
+        let candidates: Vec<usize> = (0..self.providers.len()).filter(|i| !tried.contains(i)).collect();
+        let available: Vec<usize> = candidates.iter().copied().filter(|i| self.providers[*i].available(now)).collect();
+        if available.is_empty() {
+            return candidates.into_iter().max_by(|a, b| {
+                self.providers[*a].effective_weight().total_cmp(&self.providers[*b].effective_weight())
+            });
+        }
+
+        let mut current = self.current.lock().unwrap();
+        let mut total = 0.0;
+        let mut best: Option<usize> = None;
+        for i in available {
+            let weight = self.providers[i].effective_weight();
+            current[i] += weight;
+            total += weight;
+            if best.is_none_or(|b| current[i] > current[b]) {
+                best = Some(i);
+            }
+        }
+        if let Some(b) = best {
+            current[b] -= total;
+        }
+        best
+    }
+
+    // runs `send` on one provider after another until one answers
+    async fn dispatch<R, F>(&self, send: F) -> web3::Result<R>
+    where
+        F: Fn(&Http) -> BoxFuture<'static, web3::Result<R>>,
+    {
+        let mut tried = Vec::new();
+        let mut last_err = web3::Error::Unreachable;
+        while let Some(i) = self.pick(&tried) {
+            tried.push(i);
+            let provider = &self.providers[i];
+            let _permit = provider.permits.acquire().await.map_err(|_| web3::Error::Unreachable)?;
+            provider.throttle().await;
+
+            let started = Instant::now();
+            match provider.timed(send(&provider.http)).await {
+                Err(err) if retryable(&err) => {
+                    provider.record(started.elapsed(), false);
+                    RPC_FAILOVERS.with_label_values(&[&provider.name]).inc();
+                    tracing::warn!(provider = %provider.name, "RPC request failed, trying the next provider: {}", err);
+                    last_err = err;
+                }
+                result => {
+                    provider.record(started.elapsed(), true);
+                    return result;
+                }
+            }
+        }
+        Err(last_err)
+    }
+}
+
+// Spreads requests over several nodes. Each provider has its own rate and
+// concurrency limit; failed requests move on to the next provider and
+// repeatedly failing ones cool down before they get traffic again.
+#[derive(Clone)]
+pub struct ProviderPool {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for ProviderPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.inner.providers.iter().map(|p| p.name.as_str()).collect();
+        f.debug_struct("ProviderPool").field("providers", &names).finish()
+    }
+}
+
+impl ProviderPool {
+    pub fn new(configs: &[ProviderConfig]) -> Result<Self, web3::Error> {
+        if configs.is_empty() {
+            return Err(web3::Error::Unreachable);
+        }
+        let providers = configs.iter().map(Provider::new).collect::<Result<Vec<_>, _>>()?;
+        let current = Mutex::new(vec![0.0; providers.len()]);
+        Ok(ProviderPool { inner: Arc::new(Inner { providers, current, ids: AtomicUsize::new(1) }) })
+    }
+
+    // Asks every provider for its head and for the hash of a block they all
+    // should have. Providers more than MAX_HEAD_LAG behind, or outvoted on the
+    // hash, are left out until a later round finds them in line again. A tie
+    // on the hash leaves nobody out.
+    pub async fn cross_check(&self) {
+        let providers = &self.inner.providers;
+        if providers.len() < 2 {
+            return;
+        }
+        let heads: Vec<Option<u64>> = join_all(providers.iter().map(|p| async move {
+            p.timed(Web3::new(p.http.clone()).eth().block_number()).await.ok().map(|n| n.as_u64())
+        })).await;
+        let Some(best) = heads.iter().flatten().max().copied() else { return };
+
+        let in_range = |head: &Option<u64>| head.is_some_and(|h| h + MAX_HEAD_LAG >= best);
+        let Some(probe) = heads.iter().filter(|h| in_range(h)).flatten().min().copied() else { return };
+        let block = BlockId::Number(BlockNumber::Number(U64::from(probe)));
+        let hashes: Vec<Option<String>> = join_all(providers.iter().zip(heads.iter()).map(|(p, head)| async move {
+            if !in_range(head) {
+                return None;
+            }
+            let block = p.timed(Web3::new(p.http.clone()).eth().block(block)).await.ok().flatten()?;
+            block.hash.map(|h| format!("{:?}", h))
+        })).await;
+
+        let majority = majority(&hashes);
+        if majority.is_none() && hashes.iter().any(Option::is_some) {
+            tracing::warn!(block = probe, "RPC providers tie on the block hash, leaving them all in");
+        }
+
+        for ((provider, head), hash) in providers.iter().zip(heads.iter()).zip(hashes.iter()) {
+            // unreachable providers are the health score's business
+            let Some(head) = head else { continue };
+            if *head + MAX_HEAD_LAG < best {
+                provider.exclude(Some(format!("head {} is {} blocks behind {}", head, best - head, best)));
+            } else if hash.as_deref().is_some_and(|h| majority.is_some_and(|m| h != m)) {
+                provider.exclude(Some(format!("block {} hash {} differs from the majority", probe, hash.as_deref().unwrap_or_default())));
+            } else {
+                provider.exclude(None);
+            }
+        }
+    }
+}
+
+impl Transport for ProviderPool {
+    type Out = BoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.inner.ids.fetch_add(1, Ordering::Relaxed);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let inner = self.inner.clone();
+        Box::pin(async move { inner.dispatch(|http| Box::pin(http.send(id, request.clone()))).await })
+    }
+}
+
+// a batch goes to a single provider and fails over as a whole
+impl BatchTransport for ProviderPool {
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<Value>>>>;
+
+    fn send_batch<I>(&self, requests: I) -> Self::Batch
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let requests: Vec<(RequestId, Call)> = requests.into_iter().collect();
+        let inner = self.inner.clone();
+        Box::pin(async move { inner.dispatch(|http| Box::pin(http.send_batch(requests.clone()))).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+    use serde_json::json;
+    use web3::types::{Block, H256};
+
+    use super::*;
+
+    // a node on a local port answering eth_blockNumber and eth_getBlockByNumber,
+    // or a 500 to everything when it is down
+    struct Node {
+        head: u64,
+        hash: u64,
+        up: bool,
+        hits: AtomicUsize,
+    }
+
+    async fn answer(req: Request<Body>, node: Arc<Node>) -> Result<Response<Body>, Infallible> {
+        node.hits.fetch_add(1, Ordering::SeqCst);
+        if !node.up {
+            return Ok(Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty()).unwrap());
+        }
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        let call: Value = serde_json::from_slice(&body).unwrap();
+        let result = match call["method"].as_str() {
+            Some("eth_blockNumber") => json!(U64::from(node.head)),
+            Some("eth_getBlockByNumber") => {
+                let block = Block::<H256> { hash: Some(H256::from_low_u64_be(node.hash)), ..Default::default() };
+                serde_json::to_value(block).unwrap()
+            }
+            _ => Value::Null,
+        };
+        let response = json!({ "jsonrpc": "2.0", "id": call["id"], "result": result });
+        Ok(Response::new(Body::from(response.to_string())))
+    }
+
+    fn serve(head: u64, hash: u64, up: bool) -> (ProviderConfig, Arc<Node>) {
+        let node = Arc::new(Node { head, hash, up, hits: AtomicUsize::new(0) });
+        let served = node.clone();
+        let make_svc = make_service_fn(move |_conn| {
+            let node = served.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| answer(req, node.clone()))) }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let config = ProviderConfig { weight: 1, ..ProviderConfig::new(&format!("http://{}", server.local_addr())) };
+        tokio::spawn(server);
+        (config, node)
+    }
+
+    fn excluded(pool: &ProviderPool) -> Vec<bool> {
+        pool.inner.providers.iter().map(|p| p.health.lock().unwrap().excluded.is_some()).collect()
+    }
+
+    fn weighted(weight: u32) -> ProviderConfig {
+        ProviderConfig { weight, ..ProviderConfig::new("http://127.0.0.1:1") }
+    }
+
+    #[test]
+    fn token_bucket_queues_a_burst() {
+        let mut limit = RateLimit { rps: 2.0, tokens: 2.0, updated: Instant::now() };
+        assert_eq!(limit.reserve(), Duration::ZERO);
+        assert_eq!(limit.reserve(), Duration::ZERO);
+        let wait = limit.reserve();
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500), "{:?}", wait);
+        let wait = limit.reserve();
+        assert!(wait > Duration::from_millis(950) && wait <= Duration::from_secs(1), "{:?}", wait);
+    }
+
+    #[test]
+    fn picks_by_weight_and_skips_tried_providers() {
+        let pool = ProviderPool::new(&[weighted(3), weighted(1)]).unwrap();
+        let picks: Vec<usize> = (0..8).map(|_| pool.inner.pick(&[]).unwrap()).collect();
+        assert_eq!(picks.iter().filter(|i| **i == 0).count(), 6);
+        assert_eq!(pool.inner.pick(&[0]), Some(1));
+        assert_eq!(pool.inner.pick(&[0, 1]), None);
+    }
+
+    #[test]
+    fn health_averages_samples_and_cools_down() {
+        let pool = ProviderPool::new(&[weighted(1), weighted(1)]).unwrap();
+        let provider = &pool.inner.providers[0];
+        provider.record(Duration::from_secs(1), true);
+        provider.record(Duration::from_secs(1), false);
+        {
+            let health = provider.health.lock().unwrap();
+            assert!((health.latency - 0.36).abs() < 1e-9);
+            assert!((health.errors - 0.2).abs() < 1e-9);
+            assert!((health.score() - 0.8 / 1.36).abs() < 1e-9);
+        }
+        assert!(provider.available(Instant::now()));
+
+        provider.record(Duration::ZERO, false);
+        provider.record(Duration::ZERO, false);
+        assert!(!provider.available(Instant::now()));
+        assert!(provider.available(Instant::now() + MIN_COOLDOWN));
+        assert_eq!((0..4).map(|_| pool.inner.pick(&[]).unwrap()).collect::<Vec<_>>(), vec![1; 4]);
+
+        // with everyone down the best scored one still gets the request
+        pool.inner.providers[1].exclude(Some("test".to_owned()));
+        assert_eq!(pool.inner.pick(&[]), Some(1));
+
+        provider.record(Duration::ZERO, true);
+        assert!(provider.available(Instant::now()));
+    }
+
+    #[tokio::test]
+    async fn fails_over_to_a_live_provider() {
+        let (down, down_node) = serve(0, 0, false);
+        let (up, up_node) = serve(42, 0, true);
+        let pool = ProviderPool::new(&[ProviderConfig { weight: 10, ..down }, up]).unwrap();
+        let head = Web3::new(pool.clone()).eth().block_number().await.unwrap();
+        assert_eq!(head.as_u64(), 42);
+        assert_eq!(down_node.hits.load(Ordering::SeqCst), 1);
+        assert_eq!(up_node.hits.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.inner.providers[0].health.lock().unwrap().failures, 1);
+    }
+
+    #[tokio::test]
+    async fn cross_check_leaves_out_laggards_and_the_outvoted() {
+        let nodes = [serve(100, 1, true), serve(100, 1, true), serve(98, 2, true), serve(50, 1, true)];
+        let pool = ProviderPool::new(&nodes.iter().map(|(c, _)| c.clone()).collect::<Vec<_>>()).unwrap();
+        pool.cross_check().await;
+        assert_eq!(excluded(&pool), vec![false, false, true, true]);
+    }
+
+    #[tokio::test]
+    async fn cross_check_tie_leaves_everyone_in() {
+        let nodes = [serve(100, 1, true), serve(100, 2, true)];
+        let pool = ProviderPool::new(&nodes.iter().map(|(c, _)| c.clone()).collect::<Vec<_>>()).unwrap();
+        pool.inner.providers[1].exclude(Some("earlier round".to_owned()));
+        pool.cross_check().await;
+        assert_eq!(excluded(&pool), vec![false, false]);
+    }
+
+    fn hashes(answers: &[Option<&str>]) -> Vec<Option<String>> {
+        answers.iter().map(|a| a.map(str::to_owned)).collect()
+    }
+
+    #[test]
+    fn majority_needs_a_strict_lead() {
+        assert_eq!(majority(&hashes(&[Some("0xa"), Some("0xa"), Some("0xb")])), Some("0xa"));
+        assert_eq!(majority(&hashes(&[Some("0xa"), None, Some("0xa")])), Some("0xa"));
+        assert_eq!(majority(&hashes(&[Some("0xa"), Some("0xb")])), None);
+        assert_eq!(majority(&hashes(&[Some("0xa"), Some("0xb"), Some("0xb"), Some("0xa"), None])), None);
+        assert_eq!(majority(&hashes(&[None, None])), None);
+    }
+}
@@ -0,0 +1,285 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::sync::Mutex;
+
+use ethabi::param_type::Reader;
+use ethabi::{decode, encode, ParamType, Token};
+use rusqlite::{params, Connection, OptionalExtension};
+use web3::signing::keccak256;
+use web3::types::H256;
+
+// more params than this aren't tried in every indexed/data split
+const MAX_GUESSED_PARAMS: usize = 12;
+
+// Local database of text signatures keyed by their keccak hash, filled from
+// dumps like the 4byte.directory or openchain exports. Names logs no
+// registered ABI knows; a function selector is the first 4 bytes of the hash.
+pub struct SignatureDb {
+    conn: Mutex<Connection>,
+    // lookups done so far, misses included
+    seen: Mutex<HashMap<H256, Option<String>>>,
+}
+
+impl SignatureDb {
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA busy_timeout = 5000;
+             CREATE TABLE IF NOT EXISTS signatures (
+                 hash      BLOB PRIMARY KEY,
+                 signature TEXT NOT NULL
+             );",
+        )?;
+        Ok(SignatureDb { conn: Mutex::new(conn), seen: Mutex::new(HashMap::new()) })
+    }
+
+    // SIGNATURES_PATH is the sqlite file, `signatures import` fills it
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let Ok(path) = env::var("SIGNATURES_PATH") else { return Ok(None) };
+        Ok(Some(SignatureDb::open(&path)?))
+    }
+
+    // Takes every signature found in the text, so plain lists, the 4byte csv
+    // and json exports all work. Returns (signatures read, new ones).
+    pub fn import(&self, text: &str) -> rusqlite::Result<(usize, usize)> {
+        let found = find_signatures(text);
+        let mut conn = self.conn.lock().map_err(|_| rusqlite::Error::InvalidQuery)?;
+        let tx = conn.transaction()?;
+        let mut added = 0;
+        {
+            let mut insert = tx.prepare("INSERT OR IGNORE INTO signatures (hash, signature) VALUES (?1, ?2)")?;
+            for sig in &found {
+                added += insert.execute(params![keccak256(sig.as_bytes()).to_vec(), sig])?;
+            }
+        }
+        tx.commit()?;
+        Ok((found.len(), added))
+    }
+
+    pub fn count(&self) -> rusqlite::Result<u64> {
+        let conn = self.conn.lock().map_err(|_| rusqlite::Error::InvalidQuery)?;
+        conn.query_row("SELECT COUNT(*) FROM signatures", [], |row| row.get(0))
+    }
+
+    // text signature whose hash is the log's topic0
+    pub fn event(&self, topic0: &H256) -> Option<String> {
+        if let Some(hit) = self.seen.lock().ok()?.get(topic0) {
+            return hit.clone();
+        }
+        let conn = self.conn.lock().ok()?;
+        let found = conn.query_row("SELECT signature FROM signatures WHERE hash = ?1", params![topic0.as_bytes()], |row| row.get(0))
+            .optional()
+            .unwrap_or_else(|err| {
+                tracing::warn!("Signature lookup failed: {}", err);
+                None
+            });
+        self.seen.lock().ok()?.insert(*topic0, found.clone());
+        found
+    }
+}
+
+// name and param types of `name(type,..)`, None unless every type parses
+pub fn parse_signature(sig: &str) -> Option<(String, Vec<ParamType>)> {
+    let open = sig.find('(')?;
+    let inner = sig[open + 1..].strip_suffix(')')?;
+    let mut types = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(read_type(&inner[start..i])?);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !inner.is_empty() {
+        types.push(read_type(&inner[start..])?);
+    }
+    Some((sig[..open].to_owned(), types))
+}
+
+// ethabi reads any unknown name as an enum (uint8) and takes odd sizes like
+// uint7, neither of which a real signature has
+fn read_type(name: &str) -> Option<ParamType> {
+    let kind = Reader::read(name).ok()?;
+    let aliased = name.split_inclusive(['(', ',', '[']).map(|part| {
+        let (word, rest) = part.split_at(part.find(['(', ')', ',', '[']).unwrap_or(part.len()));
+        match word {
+            "uint" | "int" => format!("{}256{}", word, rest),
+            _ => part.to_owned(),
+        }
+    }).collect::<String>();
+    (valid_type(&kind) && kind.to_string() == aliased).then_some(kind)
+}
+
+fn valid_type(kind: &ParamType) -> bool {
+    match kind {
+        ParamType::Uint(n) | ParamType::Int(n) => *n % 8 == 0 && (8..=256).contains(n),
+        ParamType::FixedBytes(n) => (1..=32).contains(n),
+        ParamType::Array(inner) | ParamType::FixedArray(inner, _) => valid_type(inner),
+        ParamType::Tuple(inner) => !inner.is_empty() && inner.iter().all(valid_type),
+        _ => true,
+    }
+}
+
+// canonical form, aliases like uint resolved, as the hash is taken over it
+fn canonical(sig: &str) -> Option<String> {
+    let (name, types) = parse_signature(sig)?;
+    Some(format!("{}({})", name, types.iter().map(ParamType::to_string).collect::<Vec<_>>().join(",")))
+}
+
+fn find_signatures(text: &str) -> Vec<String> {
+    let bytes = text.as_bytes();
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'$';
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'(' {
+            i += 1;
+            continue;
+        }
+        let mut start = i;
+        while start > 0 && is_ident(bytes[start - 1]) {
+            start -= 1;
+        }
+        // walk to the matching paren over characters a type list can have
+        let (mut depth, mut end) = (0, None);
+        for (j, &b) in bytes.iter().enumerate().skip(i) {
+            match b {
+                b'(' => depth += 1,
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = Some(j + 1);
+                        break;
+                    }
+                }
+                b if b.is_ascii_lowercase() || b.is_ascii_digit() || b == b',' || b == b'[' || b == b']' => {}
+                _ => break,
+            }
+        }
+        match end {
+            Some(end) if start < i && !bytes[start].is_ascii_digit() => {
+                found.extend(canonical(&text[start..end]));
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    found
+}
+
+// Text signatures don't say which params are indexed. Every split with as
+// many indexed params as the log has extra topics is tried, the first params
+// indexed first, and the first whose data decodes to exactly the log data is
+// taken. Returns the indexed flags and the decoded data params.
+pub fn guess_event(types: &[ParamType], topics: usize, data: &[u8]) -> Option<(Vec<bool>, Vec<Token>)> {
+    let indexed = topics.checked_sub(1)?;
+    if indexed > types.len() || types.len() > MAX_GUESSED_PARAMS {
+        return None;
+    }
+    let mut masks: Vec<u32> = (0..1u32 << types.len()).filter(|m| m.count_ones() as usize == indexed).collect();
+    // splits with earlier params indexed come first
+    masks.sort_by_key(|m| Reverse(m.reverse_bits()));
+    masks.into_iter().find_map(|mask| {
+        let flags: Vec<bool> = (0..types.len()).map(|i| mask & (1 << i) != 0).collect();
+        let data_types: Vec<ParamType> = types.iter().zip(&flags).filter(|(_, f)| !**f).map(|(t, _)| t.clone()).collect();
+        let tokens = decode(&data_types, data).ok()?;
+        (encode(&tokens).len() == data.len()).then_some((flags, tokens))
+    })
+}
+
+// `<bin> signatures import <file>..` and `<bin> signatures stats` manage SIGNATURES_PATH
+pub fn command(args: &[String]) -> Option<Result<(), Box<dyn Error>>> {
+    if args.get(1).map(String::as_str) != Some("signatures") {
+        return None;
+    }
+    Some(run_command(&args[2..]))
+}
+
+fn run_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let db = SignatureDb::from_env()?.ok_or("You must set the SIGNATURES_PATH environment var!")?;
+    match args.first().map(String::as_str) {
+        Some("import") if args.len() > 1 => {
+            for path in &args[1..] {
+                let (read, added) = db.import(&fs::read_to_string(path)?)?;
+                println!("{}: {} signatures, {} new", path, read, added);
+            }
+        }
+        Some("stats") => println!("{} signatures", db.count()?),
+        _ => return Err("usage: signatures import <file>.. | signatures stats".into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_signatures_in_csv_json_and_plain_lists() {
+        let text = "id,created_at,text_signature\n\
+                    1,2018-05-11,transfer(address,uint)\n\
+                    {\"text_signature\": \"Transfer(address,address,uint256)\"}\n\
+                    swap((address,uint256)[],bytes32)\n\
+                    Approval(address,Address), 12(uint256), not a call (x y), ok(mystruct)";
+        assert_eq!(find_signatures(text), vec![
+            "transfer(address,uint256)",
+            "Transfer(address,address,uint256)",
+            "swap((address,uint256)[],bytes32)",
+        ]);
+    }
+
+    #[test]
+    fn parses_nested_types() {
+        let (name, types) = parse_signature("f((uint256,address)[],bytes32,int)").unwrap();
+        assert_eq!(name, "f");
+        assert_eq!(types, vec![
+            ParamType::Array(Box::new(ParamType::Tuple(vec![ParamType::Uint(256), ParamType::Address]))),
+            ParamType::FixedBytes(32),
+            ParamType::Int(256),
+        ]);
+        assert_eq!(parse_signature("g()").unwrap().1, vec![]);
+        assert!(parse_signature("h(uint256,strin)").is_none());
+        assert!(parse_signature("h(uint7)").is_none());
+        assert!(parse_signature("h(bytes33)").is_none());
+        assert_eq!(parse_signature("h(uint[2],(int,bool)[])").unwrap().1, vec![
+            ParamType::FixedArray(Box::new(ParamType::Uint(256)), 2),
+            ParamType::Array(Box::new(ParamType::Tuple(vec![ParamType::Int(256), ParamType::Bool]))),
+        ]);
+    }
+
+    #[test]
+    fn import_counts_new_signatures_and_looks_them_up() {
+        let db = SignatureDb::open(":memory:").unwrap();
+        assert_eq!(db.import("Transfer(address,address,uint256)\nApproval(address,address,uint256)").unwrap(), (2, 2));
+        assert_eq!(db.import("Transfer(address,address,uint256)\nDeposit(address,uint)").unwrap(), (2, 1));
+        assert_eq!(db.count().unwrap(), 3);
+
+        let topic0 = H256(keccak256(b"Deposit(address,uint256)"));
+        assert_eq!(db.event(&topic0).as_deref(), Some("Deposit(address,uint256)"));
+        assert_eq!(db.event(&H256::zero()), None);
+    }
+
+    #[test]
+    fn guesses_indexed_params_from_topics_and_data() {
+        let (_, types) = parse_signature("Transfer(address,address,uint256)").unwrap();
+        let data = encode(&[Token::Uint(1000.into())]);
+        let (flags, tokens) = guess_event(&types, 3, &data).unwrap();
+        assert_eq!(flags, vec![true, true, false]);
+        assert_eq!(tokens, vec![Token::Uint(1000.into())]);
+
+        // erc721 style, everything indexed and no data
+        assert_eq!(guess_event(&types, 4, &[]).unwrap().0, vec![true, true, true]);
+        // data that can't be the remaining params
+        assert!(guess_event(&types, 3, &[0; 31]).is_none());
+        assert!(guess_event(&types, 5, &[]).is_none());
+    }
+}
@@ -0,0 +1,76 @@
+use async_trait::async_trait;
+use lapin::options::{BasicPublishOptions, ConfirmSelectOptions, ExchangeDeclareOptions};
+use lapin::types::FieldTable;
+use lapin::{BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind};
+use tokio::sync::Mutex;
+
+use super::{Envelope, Sink, SinkError};
+
+const PERSISTENT: u8 = 2;
+
+// A durable topic exchange named after the prefix, with routing keys
+// `events.<contract>.<event>`, `blocks` and `reorgs`. Messages are
+// persistent and count as published once the broker confirms them; a broken
+// connection is opened again on the next batch.
+pub struct AmqpSink {
+    url: String,
+    exchange: String,
+    channel: Mutex<Option<(Connection, Channel)>>,
+}
+
+impl AmqpSink {
+    pub async fn connect(url: &str, prefix: &str) -> Result<Self, lapin::Error> {
+        let sink = AmqpSink { url: url.to_owned(), exchange: prefix.to_owned(), channel: Mutex::new(None) };
+        *sink.channel.lock().await = Some(sink.open().await?);
+        Ok(sink)
+    }
+
+    async fn open(&self) -> Result<(Connection, Channel), lapin::Error> {
+        let connection = Connection::connect(&self.url, ConnectionProperties::default()).await?;
+        let channel = connection.create_channel().await?;
+        channel.confirm_select(ConfirmSelectOptions::default()).await?;
+        let options = ExchangeDeclareOptions { durable: true, ..Default::default() };
+        channel.exchange_declare(&self.exchange, ExchangeKind::Topic, options, FieldTable::default()).await?;
+        Ok((connection, channel))
+    }
+
+    async fn send(&self, channel: &Channel, envelopes: &[Envelope]) -> Result<(), SinkError> {
+        let mut confirms = Vec::new();
+        for envelope in envelopes {
+            let properties = BasicProperties::default()
+                .with_delivery_mode(PERSISTENT)
+                .with_content_type("application/json".into())
+                .with_message_id(envelope.id.as_str().into());
+            let payload = envelope.to_json()?;
+            confirms.push(channel.basic_publish(&self.exchange, &envelope.subject(), BasicPublishOptions::default(), &payload, properties).await?);
+        }
+        for confirm in confirms {
+            if confirm.await?.is_nack() {
+                return Err("broker nacked a message".into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for AmqpSink {
+    fn name(&self) -> &str {
+        "amqp"
+    }
+
+    async fn publish(&self, envelopes: &[Envelope]) -> Result<(), SinkError> {
+        let mut current = self.channel.lock().await;
+        if !current.as_ref().is_some_and(|(_, channel)| channel.status().connected()) {
+            *current = Some(self.open().await?);
+        }
+        let Some((_, channel)) = current.as_ref() else {
+            return Err("no amqp channel".into());
+        };
+        let result = self.send(channel, envelopes).await;
+        if result.is_err() {
+            *current = None;
+        }
+        result
+    }
+}
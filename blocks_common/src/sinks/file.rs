@@ -0,0 +1,40 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{Envelope, Sink, SinkError};
+
+// Appends envelopes as json lines to SINK_FILE_PATH, synced before
+// `publish` returns. For trying the stream out and for consumers that tail
+// a file.
+pub struct FileSink {
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink { file: Mutex::new(file) })
+    }
+}
+
+#[async_trait]
+impl Sink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn publish(&self, envelopes: &[Envelope]) -> Result<(), SinkError> {
+        let mut lines = Vec::new();
+        for envelope in envelopes {
+            lines.extend(envelope.to_json()?);
+            lines.push(b'\n');
+        }
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&lines)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use async_trait::async_trait;
+use futures::future::join_all;
+use rdkafka::error::KafkaError;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use rdkafka::ClientConfig;
+
+use super::{Envelope, Sink, SinkError};
+
+// how long librdkafka keeps retrying a message before the batch fails
+const MESSAGE_TIMEOUT_MS: &str = "30000";
+
+// Topics `<prefix>.events`, `<prefix>.blocks` and `<prefix>.reorgs`, written
+// by an idempotent producer that waits for all in-sync replicas.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    prefix: String,
+}
+
+impl KafkaSink {
+    pub fn new(brokers: &str, prefix: &str) -> Result<Self, KafkaError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("acks", "all")
+            .set("enable.idempotence", "true")
+            .set("message.timeout.ms", MESSAGE_TIMEOUT_MS)
+            .create()?;
+        Ok(KafkaSink { producer, prefix: prefix.to_owned() })
+    }
+}
+
+#[async_trait]
+impl Sink for KafkaSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn publish(&self, envelopes: &[Envelope]) -> Result<(), SinkError> {
+        let messages = envelopes.iter()
+            .map(|e| Ok((format!("{}.{}", self.prefix, e.topic()), e.key(), e.to_json()?)))
+            .collect::<Result<Vec<_>, serde_json::Error>>()?;
+        // all in flight at once, the idempotent producer keeps their order
+        let sends = messages.iter()
+            .map(|(topic, key, payload)| self.producer.send(FutureRecord::to(topic).key(*key).payload(payload), Timeout::Never));
+        for result in join_all(sends).await {
+            result.map_err(|(err, _)| err)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,504 @@
+mod amqp;
+mod file;
+mod kafka;
+mod nats;
+
+use std::collections::BTreeSet;
+use std::env;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use blocks_storage::{Filter, Op, Query, Storage, Table};
+use bson::{doc, Bson};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::events::DecodedEvent;
+use crate::expr::{self, Expr};
+use crate::metrics::{SINK_MESSAGES, SINK_WAITING_BATCHES};
+
+pub use amqp::AmqpSink;
+pub use file::FileSink;
+pub use kafka::KafkaSink;
+pub use nats::NatsSink;
+
+// version of the envelope below, bumped on any change consumers would notice
+pub const SCHEMA_VERSION: u32 = 1;
+// decoded_events rows read per query
+const PAGE: usize = 5_000;
+// envelopes handed to a sink at once, a checkpoint follows every batch
+const BATCH: usize = 500;
+// checkpoints closer to the tip than this are kept, older ones pruned
+const KEEP_CHECKPOINTS: u64 = 1_000;
+// how far indexing may run ahead of what every sink has published
+const DEFAULT_MAX_LAG: u64 = 100_000;
+const DEFAULT_TOPIC_PREFIX: &str = "blocks";
+const GATE_POLL: Duration = Duration::from_secs(1);
+
+pub type SinkError = Box<dyn Error + Send + Sync>;
+
+// What every sink publishes, one json message each:
+//
+// {"schema": 1, "kind": "event", "id": "<block hash>:<log index>", "block_number": 12,
+//  "block_hash": "0x..", "data": {decoded_events row, block_time as rfc3339}}
+// {"schema": 1, "kind": "block", "id": "<block hash>", "block_number": 12,
+//  "block_hash": "0x..", "data": {"block_time": "..", "events": 2}}
+// {"schema": 1, "kind": "reorg", "id": "reorg:<block>:<published to>", "block_number": 10,
+//  "block_hash": null, "data": {"published_to": 12}}
+//
+// A block message follows the events of every block that had some
+// published, which SINK_FILTER can narrow down. A reorg
+// message says everything published from `block_number` to `published_to`
+// is void; the replacement events follow it. Delivery is at least once, the
+// id is what consumers deduplicate on.
+#[derive(Debug, Clone, Serialize)]
+pub struct Envelope {
+    pub schema: u32,
+    pub kind: Kind,
+    pub id: String,
+    pub block_number: u64,
+    pub block_hash: Option<String>,
+    pub data: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Event,
+    Block,
+    Reorg,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Event => "event",
+            Kind::Block => "block",
+            Kind::Reorg => "reorg",
+        }
+    }
+}
+
+fn rfc3339(time: Option<bson::DateTime>) -> Value {
+    time.and_then(|t| t.try_to_rfc3339_string().ok()).map_or(Value::Null, Value::String)
+}
+
+impl Envelope {
+    pub fn event(event: &DecodedEvent) -> Self {
+        Envelope {
+            schema: SCHEMA_VERSION,
+            kind: Kind::Event,
+            id: format!("{}:{}", event.block_hash, event.log_index),
+            block_number: event.block_number,
+            block_hash: Some(event.block_hash.clone()),
+            data: json!({
+                "block_number": event.block_number,
+                "block_hash": &event.block_hash,
+                "block_time": rfc3339(event.block_time),
+                "tx_hash": &event.tx_hash,
+                "log_index": event.log_index,
+                "address": &event.address,
+                "contract": &event.contract,
+                "abi_version": &event.abi_version,
+                "event": &event.event,
+                "signature": &event.signature,
+                "args": Bson::Document(event.args.clone()).into_relaxed_extjson(),
+            }),
+        }
+    }
+
+    // closes the events of a block
+    pub fn block(last: &DecodedEvent, events: usize) -> Self {
+        Envelope {
+            schema: SCHEMA_VERSION,
+            kind: Kind::Block,
+            id: last.block_hash.clone(),
+            block_number: last.block_number,
+            block_hash: Some(last.block_hash.clone()),
+            data: json!({ "block_time": rfc3339(last.block_time), "events": events }),
+        }
+    }
+
+    pub fn reorg(from: u64, published_to: u64) -> Self {
+        Envelope {
+            schema: SCHEMA_VERSION,
+            kind: Kind::Reorg,
+            id: format!("reorg:{}:{}", from, published_to),
+            block_number: from,
+            block_hash: None,
+            data: json!({ "published_to": published_to }),
+        }
+    }
+
+    // events.<contract>.<event>, blocks or reorgs; sinks put their prefix in front
+    pub fn subject(&self) -> String {
+        match self.kind {
+            Kind::Event => format!(
+                "events.{}.{}",
+                self.data["contract"].as_str().unwrap_or_default(),
+                self.data["event"].as_str().unwrap_or_default()
+            ),
+            Kind::Block => "blocks".to_owned(),
+            Kind::Reorg => "reorgs".to_owned(),
+        }
+    }
+
+    // the first part of the subject
+    pub fn topic(&self) -> &'static str {
+        match self.kind {
+            Kind::Event => "events",
+            Kind::Block => "blocks",
+            Kind::Reorg => "reorgs",
+        }
+    }
+
+    // Partition key: a contract's events stay in order, blocks and reorgs
+    // share one key so they do too.
+    pub fn key(&self) -> &str {
+        match self.kind {
+            Kind::Event => self.data["address"].as_str().unwrap_or_default(),
+            _ => "chain",
+        }
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec(self)
+    }
+}
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    // what its checkpoints are stored under
+    fn name(&self) -> &str;
+
+    // Returns once the broker has taken every envelope, in order. On an
+    // error any of them may or may not have gone out.
+    async fn publish(&self, envelopes: &[Envelope]) -> Result<(), SinkError>;
+}
+
+// highest checkpoint, at or below `at_most` if given
+async fn checkpoint(storage: &dyn Storage, sink: &str, at_most: Option<u64>) -> blocks_storage::Result<Option<u64>> {
+    let mut query = Query::new().filter(Filter::eq("sink", sink)).sort("block_number", false).limit(1);
+    if let Some(at_most) = at_most {
+        query = query.filter(Filter::cmp("block_number", Op::Lte, at_most as i64));
+    }
+    let docs = storage.find(Table::SinkCheckpoints, query).await?;
+    Ok(docs.first().and_then(|d| d.get_i64("block_number").ok()).map(|b| b as u64))
+}
+
+async fn cursor(storage: &dyn Storage, sink: &str) -> blocks_storage::Result<Option<u64>> {
+    let docs = storage.find(Table::SinkCursors, Query::new().filter(Filter::eq("sink", sink)).limit(1)).await?;
+    Ok(docs.first().and_then(|d| d.get_i64("block_number").ok()).map(|b| b as u64))
+}
+
+async fn set_cursor(storage: &dyn Storage, sink: &str, block: Option<u64>) -> blocks_storage::Result<()> {
+    match block {
+        Some(block) => storage.upsert(Table::SinkCursors, vec![doc! { "sink": sink, "block_number": block as i64 }]).await,
+        None => storage.delete(Table::SinkCursors, vec![Filter::eq("sink", sink)]).await.map(drop),
+    }
+}
+
+async fn save_checkpoint(storage: &dyn Storage, sink: &str, end: u64) -> blocks_storage::Result<()> {
+    storage.insert(Table::SinkCheckpoints, vec![doc! { "sink": sink, "block_number": end as i64 }]).await?;
+    set_cursor(storage, sink, Some(end)).await?;
+    if let Some(oldest) = checkpoint(storage, sink, Some(end.saturating_sub(KEEP_CHECKPOINTS))).await? {
+        let filters = vec![Filter::eq("sink", sink), Filter::cmp("block_number", Op::Lt, oldest as i64)];
+        storage.delete(Table::SinkCheckpoints, filters).await?;
+    }
+    Ok(())
+}
+
+async fn first_event_block(storage: &dyn Storage) -> blocks_storage::Result<Option<u64>> {
+    let docs = storage.find(Table::DecodedEvents, Query::new().sort("block_number", true).limit(1)).await?;
+    Ok(docs.first().and_then(|d| d.get_i64("block_number").ok()).map(|b| b as u64))
+}
+
+async fn send(sink: &dyn Sink, envelopes: &[Envelope]) -> Result<(), SinkError> {
+    sink.publish(envelopes).await?;
+    for envelope in envelopes {
+        SINK_MESSAGES.with_label_values(&[sink.name(), envelope.kind.as_str()]).inc();
+    }
+    Ok(())
+}
+
+// Publishes the events matching `filter` up to block `to` from the sink's
+// checkpoint and returns the new checkpoint, None while there is nothing to
+// publish. The checkpoint only moves once the broker has the messages, so a
+// crash or an outage sends some of them again rather than losing any.
+pub async fn run(sink: &dyn Sink, storage: &dyn Storage, to: u64, filter: Option<&Expr>) -> Result<Option<u64>, SinkError> {
+    run_paged(sink, storage, to, filter, PAGE).await
+}
+
+// decoded_events in [start, to] after the (block, log index) already read,
+// matching `filter`, in chain order
+fn page_query(start: u64, to: u64, after: Option<(u64, u64)>, filter: Option<&Expr>, page: usize) -> Query {
+    let mut query = Query::new()
+        .filter(Filter::cmp("block_number", Op::Lte, to as i64))
+        .sort("block_number", true)
+        .sort("log_index", true)
+        .limit(page as i64);
+    query = match after {
+        None => query.filter(Filter::cmp("block_number", Op::Gte, start as i64)),
+        Some((block, log_index)) => query.filter(Filter::Or(vec![
+            Filter::cmp("block_number", Op::Gt, block as i64),
+            Filter::And(vec![Filter::eq("block_number", block as i64), Filter::cmp("log_index", Op::Gt, log_index as i64)]),
+        ])),
+    };
+    match filter {
+        Some(filter) => query.filter(filter.to_filter()),
+        None => query,
+    }
+}
+
+async fn run_paged(sink: &dyn Sink, storage: &dyn Storage, to: u64, filter: Option<&Expr>, page: usize) -> Result<Option<u64>, SinkError> {
+    let name = sink.name();
+    let published = checkpoint(storage, name, None).await?;
+    // a rollback took away checkpoints of blocks that went out
+    if let Some(cursor) = cursor(storage, name).await? {
+        if published.is_none_or(|p| p < cursor) {
+            let from = published.map_or(0, |p| p + 1);
+            send(sink, &[Envelope::reorg(from, cursor)]).await?;
+            set_cursor(storage, name, published).await?;
+            warn!(sink = name, "Published reorg of blocks {} to {}", from, cursor);
+        }
+    }
+    let start = match published {
+        Some(checkpoint) => checkpoint + 1,
+        None => match first_event_block(storage).await? {
+            Some(first) => first,
+            None => return Ok(None),
+        },
+    };
+    if start > to {
+        return Ok(published);
+    }
+
+    // pages of matching rows, so sparse matches don't mean reading every
+    // event and a busy block can span pages
+    let (mut batch, mut in_block, mut count) = (Vec::new(), 0, 0);
+    let mut last: Option<DecodedEvent> = None;
+    loop {
+        let after = last.as_ref().map(|e| (e.block_number, e.log_index));
+        let docs = storage.find(Table::DecodedEvents, page_query(start, to, after, filter, page)).await?;
+        let done = docs.len() < page;
+        for event in docs.iter().filter_map(DecodedEvent::from_document) {
+            if let Some(previous) = last.as_ref().filter(|p| p.block_number != event.block_number) {
+                batch.push(Envelope::block(previous, in_block));
+                in_block = 0;
+                // only whole blocks are checkpointed
+                if batch.len() >= BATCH {
+                    send(sink, &batch).await?;
+                    save_checkpoint(storage, name, previous.block_number).await?;
+                    batch.clear();
+                }
+            }
+            batch.push(Envelope::event(&event));
+            in_block += 1;
+            count += 1;
+            last = Some(event);
+        }
+        if done || docs.is_empty() {
+            break;
+        }
+    }
+    if let Some(last) = &last {
+        batch.push(Envelope::block(last, in_block));
+    }
+    if !batch.is_empty() {
+        send(sink, &batch).await?;
+    }
+    save_checkpoint(storage, name, to).await?;
+    if count > 0 {
+        info!(sink = name, events = count, "Published blocks {} to {}", start, to);
+    }
+    Ok(Some(to))
+}
+
+// The SINKS the indexer publishes to and the gate that holds indexing back
+// while they can't keep up.
+pub struct Sinks {
+    sinks: Vec<Box<dyn Sink>>,
+    // only matching events are published
+    filter: Option<Expr>,
+    max_lag: u64,
+    // one round at a time, so two of them don't publish the same blocks
+    round: tokio::sync::Mutex<()>,
+    // lowest checkpoint of the sinks after the last round
+    published: Mutex<Option<u64>>,
+    // false from a failed round until one goes through
+    healthy: AtomicBool,
+}
+
+impl Sinks {
+    pub fn new(sinks: Vec<Box<dyn Sink>>, filter: Option<Expr>, max_lag: u64) -> Self {
+        Sinks { sinks, filter, max_lag, round: tokio::sync::Mutex::new(()), published: Mutex::new(None), healthy: AtomicBool::new(true) }
+    }
+
+    // SINKS lists kafka (KAFKA_BROKERS), nats (NATS_URL), amqp (AMQP_URL)
+    // and file (SINK_FILE_PATH). Topics, subjects and the AMQP exchange start
+    // with SINK_TOPIC_PREFIX, SINK_MAX_LAG is in blocks and SINK_FILTER an
+    // expression events have to match (see blocks_common::expr). None when
+    // SINKS is not set.
+    pub async fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let prefix = env::var("SINK_TOPIC_PREFIX").unwrap_or(DEFAULT_TOPIC_PREFIX.to_owned());
+        let max_lag = match env::var("SINK_MAX_LAG") {
+            Ok(lag) => lag.parse()?,
+            Err(_) => DEFAULT_MAX_LAG,
+        };
+        let filter = match env::var("SINK_FILTER") {
+            Ok(filter) => Some(expr::parse(&filter).map_err(|err| format!("SINK_FILTER: {}", err))?),
+            Err(_) => None,
+        };
+        let required = |var: &str| env::var(var).map_err(|_| format!("{} is required by SINKS", var));
+        let (mut sinks, mut names) = (Vec::new(), BTreeSet::new());
+        for name in env::var("SINKS").unwrap_or_default().split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            if !names.insert(name) {
+                return Err(format!("sink `{}` is listed twice", name).into());
+            }
+            let sink: Box<dyn Sink> = match name {
+                "kafka" => Box::new(KafkaSink::new(&required("KAFKA_BROKERS")?, &prefix)?),
+                "nats" => Box::new(NatsSink::connect(&required("NATS_URL")?, &prefix).await?),
+                "amqp" => Box::new(AmqpSink::connect(&required("AMQP_URL")?, &prefix).await?),
+                "file" => Box::new(FileSink::open(&required("SINK_FILE_PATH")?)?),
+                other => return Err(format!("unknown sink `{}` in SINKS", other).into()),
+            };
+            info!(sink = name, "Publishing to sink");
+            sinks.push(sink);
+        }
+        Ok((!sinks.is_empty()).then(|| Sinks::new(sinks, filter, max_lag)))
+    }
+
+    // Brings every sink up to block `to`. A failing one stays at its
+    // checkpoint and holds indexing back until a later round gets through.
+    pub async fn run(&self, storage: &dyn Storage, to: u64) {
+        let _round = self.round.lock().await;
+        let mut healthy = true;
+        let mut lowest: Option<u64> = None;
+        for sink in &self.sinks {
+            let published = match run(sink.as_ref(), storage, to, self.filter.as_ref()).await {
+                Ok(published) => published,
+                Err(err) => {
+                    warn!(sink = sink.name(), "Sink failed, indexing waits for it: {}", err);
+                    healthy = false;
+                    checkpoint(storage, sink.name(), None).await.ok().flatten()
+                }
+            };
+            lowest = match (lowest, published) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+        }
+        *self.published.lock().unwrap() = lowest;
+        self.healthy.store(healthy, Ordering::SeqCst);
+    }
+
+    pub fn healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    fn open(&self, start: u64) -> bool {
+        self.healthy() && self.published.lock().unwrap().is_none_or(|p| start <= p.saturating_add(self.max_lag))
+    }
+
+    // Holds a batch starting at `start` back while a sink is down or more
+    // than SINK_MAX_LAG blocks behind, so an outage stalls indexing instead
+    // of piling up events nobody was told about.
+    pub async fn wait(&self, start: u64) {
+        if self.open(start) {
+            return;
+        }
+        SINK_WAITING_BATCHES.inc();
+        while !self.open(start) {
+            tokio::time::sleep(GATE_POLL).await;
+        }
+        SINK_WAITING_BATCHES.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks_storage::sqlite::SqliteStorage;
+
+    use super::*;
+    use crate::values::decimal_to_bson;
+
+    struct Collect {
+        name: &'static str,
+        envelopes: Mutex<Vec<Envelope>>,
+    }
+
+    #[async_trait]
+    impl Sink for Collect {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn publish(&self, envelopes: &[Envelope]) -> Result<(), SinkError> {
+            self.envelopes.lock().unwrap().extend_from_slice(envelopes);
+            Ok(())
+        }
+    }
+
+    fn event(block: u64, log_index: u64, value: &str) -> bson::Document {
+        DecodedEvent {
+            block_number: block,
+            block_hash: format!("0x{:064x}", block),
+            block_time: None,
+            tx_hash: None,
+            log_index,
+            address: "0x00000000219ab540356cbb839cbe05303d7705fa".to_owned(),
+            contract: "token".to_owned(),
+            abi_version: "1".to_owned(),
+            event: "Transfer".to_owned(),
+            signature: "Transfer(address,address,uint256)".to_owned(),
+            args: doc! { "value": decimal_to_bson(value.to_owned()) },
+        }
+        .to_document()
+    }
+
+    fn published(sink: &Collect) -> Vec<String> {
+        sink.envelopes.lock().unwrap().iter()
+            .map(|e| match e.kind {
+                Kind::Event => format!("{}:{}", e.block_number, e.data["log_index"]),
+                Kind::Block => format!("{} ({})", e.block_number, e.data["events"]),
+                Kind::Reorg => format!("reorg {}", e.block_number),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pages_by_rows_and_filters_in_storage() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        let events = vec![
+            event(10, 0, "1"),
+            event(10, 1, "5"),
+            event(10, 2, "7"),
+            event(10, 3, "9"),
+            event(11, 0, "2"),
+            event(12, 0, "1000000000000000000000000000000000000000"),
+            event(12, 4, "6"),
+        ];
+        storage.insert(Table::DecodedEvents, events).await.unwrap();
+
+        // a block of three matches spans pages of two
+        let sink = Collect { name: "filtered", envelopes: Mutex::default() };
+        let filter = expr::parse("value >= 5").unwrap();
+        assert_eq!(run_paged(&sink, &storage, 11, Some(&filter), 2).await.unwrap(), Some(11));
+        assert_eq!(published(&sink), ["10:1", "10:2", "10:3", "10 (3)"]);
+        assert_eq!(checkpoint(&storage, "filtered", None).await.unwrap(), Some(11));
+
+        assert_eq!(run_paged(&sink, &storage, 20, Some(&filter), 2).await.unwrap(), Some(20));
+        assert_eq!(published(&sink)[4..], ["12:0", "12:4", "12 (2)"]);
+        assert_eq!(run_paged(&sink, &storage, 20, Some(&filter), 2).await.unwrap(), Some(20));
+        assert_eq!(published(&sink).len(), 7);
+
+        let sink = Collect { name: "all", envelopes: Mutex::default() };
+        assert_eq!(run_paged(&sink, &storage, 20, None, 3).await.unwrap(), Some(20));
+        assert_eq!(published(&sink), ["10:0", "10:1", "10:2", "10:3", "10 (4)", "11:0", "11 (1)", "12:0", "12:4", "12 (2)"]);
+    }
+}
@@ -0,0 +1,38 @@
+use async_nats::jetstream;
+use async_trait::async_trait;
+
+use super::{Envelope, Sink, SinkError};
+
+// Subjects `<prefix>.events.<contract>.<event>`, `<prefix>.blocks` and
+// `<prefix>.reorgs`, published through JetStream so every message is acked
+// by a stream. The stream capturing `<prefix>.>` has to exist.
+pub struct NatsSink {
+    jetstream: jetstream::Context,
+    prefix: String,
+}
+
+impl NatsSink {
+    pub async fn connect(url: &str, prefix: &str) -> Result<Self, async_nats::ConnectError> {
+        let client = async_nats::connect(url).await?;
+        Ok(NatsSink { jetstream: jetstream::new(client), prefix: prefix.to_owned() })
+    }
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    fn name(&self) -> &str {
+        "nats"
+    }
+
+    async fn publish(&self, envelopes: &[Envelope]) -> Result<(), SinkError> {
+        let mut acks = Vec::new();
+        for envelope in envelopes {
+            let subject = format!("{}.{}", self.prefix, envelope.subject());
+            acks.push(self.jetstream.publish(subject, envelope.to_json()?.into()).await?);
+        }
+        for ack in acks {
+            ack.await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use blocks_storage::{Storage, StorageError, Table};
+use bson::{doc, Bson, DateTime, Document};
+use serde::{ser, Serialize, Serializer};
+use web3::{Transport, Web3};
+
+use crate::progress::{Mode, Progress};
+use crate::registry::ContractRegistry;
+use crate::rpc::RpcTransport;
+
+// a dependency that doesn't answer within this counts as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// body of /healthz and /readyz
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl Health {
+    pub fn new(checks: Vec<Check>) -> Self {
+        Health { ok: checks.iter().all(|c| c.ok), checks }
+    }
+}
+
+async fn check<T, E: ToString>(name: &'static str, call: impl Future<Output = Result<T, E>>) -> Check {
+    let error = match tokio::time::timeout(CHECK_TIMEOUT, call).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(err)) => Some(err.to_string()),
+        Err(_) => Some(format!("no answer within {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    Check { name, ok: error.is_none(), error }
+}
+
+pub async fn check_rpc<T: Transport>(web3: &Web3<T>) -> Check {
+    check("rpc", web3.eth().block_number()).await
+}
+
+pub async fn check_storage(storage: &dyn Storage) -> Check {
+    check("storage", storage.ping()).await
+}
+
+// one row of /status and of the indexer_status table
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexerStatus {
+    pub indexer: String,
+    pub contract: String,
+    pub name: String,
+    pub mode: Mode,
+    pub indexed_block: Option<u64>,
+    pub chain_head: Option<u64>,
+    pub lag: Option<u64>,
+    // inclusive ranges that failed and weren't redone yet
+    pub failed_ranges: Vec<(u64, u64)>,
+    #[serde(serialize_with = "rfc3339")]
+    pub updated_at: DateTime,
+}
+
+fn rfc3339<S: Serializer>(time: &DateTime, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&time.try_to_rfc3339_string().map_err(ser::Error::custom)?)
+}
+
+impl IndexerStatus {
+    pub fn to_document(&self) -> Document {
+        let failed: Vec<Bson> = self.failed_ranges.iter()
+            .map(|(s, e)| Bson::Array(vec![Bson::Int64(*s as i64), Bson::Int64(*e as i64)]))
+            .collect();
+        doc! {
+            "indexer": self.indexer.as_str(),
+            "contract": self.contract.as_str(),
+            "name": self.name.as_str(),
+            "mode": self.mode.as_str(),
+            "indexed_block": self.indexed_block.map(|b| b as i64),
+            "chain_head": self.chain_head.map(|b| b as i64),
+            "lag": self.lag.map(|b| b as i64),
+            "failed_ranges": failed,
+            "updated_at": self.updated_at,
+        }
+    }
+}
+
+// Health and status of one indexer process. Block ranges cover every
+// registered contract at once, so they all share the same progress.
+pub struct Reporter {
+    indexer: &'static str,
+    // (address, name)
+    contracts: Vec<(String, String)>,
+    progress: Arc<Progress>,
+    web3: Web3<RpcTransport>,
+    storage: Arc<dyn Storage>,
+}
+
+impl Reporter {
+    pub fn new(indexer: &'static str, registry: &ContractRegistry, progress: Arc<Progress>, web3: Web3<RpcTransport>, storage: Arc<dyn Storage>) -> Self {
+        let mut contracts: Vec<(String, String)> = registry.contracts().map(|c| (format!("{:?}", c.address), c.name.clone())).collect();
+        contracts.sort();
+        Reporter { indexer, contracts, progress, web3, storage }
+    }
+
+    pub async fn checks(&self) -> Vec<Check> {
+        let (rpc, storage) = futures::join!(check_rpc(&self.web3), check_storage(self.storage.as_ref()));
+        vec![rpc, storage]
+    }
+
+    pub fn status(&self) -> Vec<IndexerStatus> {
+        let updated_at = DateTime::now();
+        let (mode, indexed_block, chain_head, lag, failed_ranges) =
+            (self.progress.mode(), self.progress.indexed(), self.progress.head(), self.progress.lag(), self.progress.failed());
+        self.contracts.iter().map(|(address, name)| IndexerStatus {
+            indexer: self.indexer.to_owned(),
+            contract: address.clone(),
+            name: name.clone(),
+            mode,
+            indexed_block,
+            chain_head,
+            lag,
+            failed_ranges: failed_ranges.clone(),
+            updated_at,
+        }).collect()
+    }
+
+    // writes the current status for blocks_query to read
+    pub async fn publish(&self) -> Result<(), StorageError> {
+        let docs = self.status().iter().map(IndexerStatus::to_document).collect();
+        self.storage.upsert(Table::IndexerStatus, docs).await
+    }
+}
+
+pub async fn publish_every(reporter: Arc<Reporter>, every: Duration) {
+    loop {
+        if let Err(err) = reporter.publish().await {
+            tracing::warn!("Failed to publish indexer status: {}", err);
+        }
+        tokio::time::sleep(every).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_failing_check_makes_health_not_ok() {
+        let up = check("rpc", async { Ok::<_, String>(1) }).await;
+        let down = check("storage", async { Err::<(), _>("connection refused") }).await;
+        assert_eq!(serde_json::to_value(&down).unwrap(), serde_json::json!({ "name": "storage", "ok": false, "error": "connection refused" }));
+        assert!(Health::new(vec![up]).ok);
+        let up = check("rpc", async { Ok::<_, String>(1) }).await;
+        assert!(!Health::new(vec![up, down]).ok);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use bson::DateTime;
+use serde_json::json;
+use web3::error::Error as Web3Error;
+use web3::types::{Block, H256, U256, U64};
+use web3::{BatchTransport, Web3};
+
+const DEFAULT_CAPACITY: usize = 100_000;
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+// Block number -> timestamp cache, filled with batched eth_getBlockByNumber
+// calls. Once full, the lowest block numbers are evicted first since backfills
+// move forward.
+pub struct BlockTimestamps {
+    cache: Mutex<BTreeMap<u64, DateTime>>,
+    capacity: usize,
+    batch_size: usize,
+}
+
+impl Default for BlockTimestamps {
+    fn default() -> Self {
+        BlockTimestamps::new(DEFAULT_CAPACITY, DEFAULT_BATCH_SIZE)
+    }
+}
+
+impl BlockTimestamps {
+    pub fn new(capacity: usize, batch_size: usize) -> Self {
+        BlockTimestamps {
+            cache: Mutex::new(BTreeMap::new()),
+            capacity,
+            batch_size: batch_size.max(1),
+        }
+    }
+
+    pub async fn resolve<T: BatchTransport>(&self, web3: &Web3<T>, blocks: &[u64]) -> Result<HashMap<u64, DateTime>, Web3Error> {
+        let mut resolved = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let cache = self.cache.lock().unwrap();
+            for bnum in blocks {
+                match cache.get(bnum) {
+                    Some(time) => { resolved.insert(*bnum, *time); }
+                    None => missing.push(*bnum),
+                }
+            }
+        }
+        missing.sort_unstable();
+        missing.dedup();
+
+        for chunk in missing.chunks(self.batch_size) {
+            let transport = web3.transport();
+            let requests: Vec<_> = chunk.iter()
+                .map(|bnum| transport.prepare("eth_getBlockByNumber", vec![json!(U64::from(*bnum)), json!(false)]))
+                .collect();
+
+            let mut fetched = Vec::with_capacity(chunk.len());
+            for (bnum, res) in chunk.iter().zip(transport.send_batch(requests).await?) {
+                let block: Option<Block<H256>> = serde_json::from_value(res?).map_err(|e| Web3Error::Decoder(e.to_string()))?;
+                let block = block.ok_or_else(|| Web3Error::InvalidResponse(format!("block {} not found", bnum)))?;
+                let time = to_datetime(block.timestamp)?;
+                resolved.insert(*bnum, time);
+                fetched.push((*bnum, time));
+            }
+            self.store(&fetched);
+        }
+        Ok(resolved)
+    }
+
+    // drops the times of blocks from `block` on, a reorg may have replaced them
+    pub fn forget(&self, block: u64) {
+        self.cache.lock().unwrap().split_off(&block);
+    }
+
+    fn store(&self, entries: &[(u64, DateTime)]) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.extend(entries.iter().copied());
+        while cache.len() > self.capacity {
+            cache.pop_first();
+        }
+    }
+}
+
+// a node's unix seconds, refused when the milliseconds don't fit an i64
+pub fn to_datetime(timestamp: U256) -> Result<DateTime, Web3Error> {
+    i64::try_from(timestamp).ok()
+        .and_then(|secs| secs.checked_mul(1000))
+        .map(DateTime::from_millis)
+        .ok_or_else(|| Web3Error::InvalidResponse(format!("block timestamp {} is out of range", timestamp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::future::{ready, Ready};
+    use jsonrpc_core::{Call, Value};
+    use web3::{helpers, RequestId, Transport};
+
+    use super::*;
+
+    // blocks at 10 seconds per number, none past 1000 and block 666 with a
+    // timestamp no DateTime holds; remembers the numbers of every batch
+    #[derive(Debug, Clone, Default)]
+    struct Node {
+        batches: Arc<Mutex<Vec<Vec<u64>>>>,
+    }
+
+    impl Node {
+        fn block(bnum: u64) -> Value {
+            let timestamp = match bnum {
+                666 => U256::MAX,
+                bnum => U256::from(bnum * 10),
+            };
+            match bnum {
+                1001.. => Value::Null,
+                _ => serde_json::to_value(Block::<H256> { number: Some(U64::from(bnum)), timestamp, ..Default::default() }).unwrap(),
+            }
+        }
+    }
+
+    impl Transport for Node {
+        type Out = Ready<web3::Result<Value>>;
+
+        fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+            (0, helpers::build_request(0, method, params))
+        }
+
+        fn send(&self, _: RequestId, _: Call) -> Self::Out {
+            ready(Err(Web3Error::Unreachable))
+        }
+    }
+
+    impl BatchTransport for Node {
+        type Batch = Ready<web3::Result<Vec<web3::Result<Value>>>>;
+
+        fn send_batch<I: IntoIterator<Item = (RequestId, Call)>>(&self, requests: I) -> Self::Batch {
+            let numbers: Vec<u64> = requests.into_iter().map(|(_, call)| match call {
+                Call::MethodCall(call) => {
+                    let (number, _): (U64, bool) = call.params.parse().unwrap();
+                    number.as_u64()
+                }
+                other => panic!("{:?}", other),
+            }).collect();
+            self.batches.lock().unwrap().push(numbers.clone());
+            ready(Ok(numbers.into_iter().map(|n| Ok(Node::block(n))).collect()))
+        }
+    }
+
+    fn seconds(times: &HashMap<u64, DateTime>, bnum: u64) -> i64 {
+        times[&bnum].timestamp_millis() / 1000
+    }
+
+    #[tokio::test]
+    async fn fetches_missing_blocks_in_batches_and_caches_them() {
+        let node = Node::default();
+        let web3 = Web3::new(node.clone());
+        let timestamps = BlockTimestamps::new(4, 2);
+
+        let times = timestamps.resolve(&web3, &[3, 1, 2, 1]).await.unwrap();
+        assert_eq!((seconds(&times, 1), seconds(&times, 2), seconds(&times, 3)), (10, 20, 30));
+        assert_eq!(*node.batches.lock().unwrap(), [vec![1, 2], vec![3]]);
+
+        // only block 4 is asked for, the rest come from the cache
+        let times = timestamps.resolve(&web3, &[2, 3, 4]).await.unwrap();
+        assert_eq!(times.len(), 3);
+        assert_eq!(node.batches.lock().unwrap()[2..], [vec![4]]);
+
+        // full at 4 entries, block 1 went first
+        timestamps.resolve(&web3, &[5]).await.unwrap();
+        timestamps.resolve(&web3, &[1, 5]).await.unwrap();
+        assert_eq!(node.batches.lock().unwrap()[3..], [vec![5], vec![1]]);
+
+        // after a reorg the times are read again
+        timestamps.forget(5);
+        timestamps.resolve(&web3, &[5]).await.unwrap();
+        assert_eq!(node.batches.lock().unwrap().last().unwrap(), &vec![5]);
+        assert!(timestamps.resolve(&web3, &[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn refuses_missing_blocks_and_out_of_range_times() {
+        let web3 = Web3::new(Node::default());
+        let timestamps = BlockTimestamps::default();
+        let err = timestamps.resolve(&web3, &[1001]).await.unwrap_err();
+        assert_eq!(err.to_string(), Web3Error::InvalidResponse("block 1001 not found".to_owned()).to_string());
+        assert!(timestamps.resolve(&web3, &[666]).await.is_err());
+        assert_eq!(timestamps.resolve(&web3, &[665]).await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn converts_seconds_that_fit() {
+        assert_eq!(to_datetime(U256::from(1_700_000_000)).unwrap().timestamp_millis(), 1_700_000_000_000);
+        assert_eq!(to_datetime(U256::zero()).unwrap().timestamp_millis(), 0);
+        let largest = i64::MAX / 1000;
+        assert_eq!(to_datetime(U256::from(largest)).unwrap().timestamp_millis(), largest * 1000);
+        assert!(to_datetime(U256::from(largest + 1)).is_err());
+        assert!(to_datetime(U256::from(u64::MAX)).is_err());
+        assert!(to_datetime(U256::MAX).is_err());
+    }
+}
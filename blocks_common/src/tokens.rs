@@ -0,0 +1,148 @@
+use bson::{Bson, Document};
+use ethabi::{Param, ParamType, Token};
+use hex::encode;
+use web3::signing::keccak256;
+use web3::types::{H160, H256, U256};
+
+use crate::values::{int_to_bson, signed_decimal, uint_to_bson};
+
+// uint/int values are Decimal128 where they fit, decimal strings otherwise
+pub fn token_to_bson(token: &Token) -> Bson {
+    match token {
+        Token::Address(a) => Bson::String(format!("{:?}", a)),
+        Token::FixedBytes(b) | Token::Bytes(b) => Bson::String(format!("0x{}", encode(b))),
+        Token::Int(i) => int_to_bson(i),
+        Token::Uint(u) => uint_to_bson(u),
+        Token::Bool(b) => Bson::Boolean(*b),
+        Token::String(s) => Bson::String(s.clone()),
+        Token::FixedArray(tokens) | Token::Array(tokens) | Token::Tuple(tokens) => {
+            Bson::Array(tokens.iter().map(token_to_bson).collect())
+        }
+    }
+}
+
+// unnamed params become arg0, arg1, ...
+pub fn named_args(params: &[Param], tokens: &[Token]) -> Document {
+    let mut args = Document::new();
+    for (i, (param, token)) in params.iter().zip(tokens).enumerate() {
+        let name = if param.name.is_empty() { format!("arg{}", i) } else { param.name.clone() };
+        args.insert(name, token_to_bson(token));
+    }
+    args
+}
+
+// EIP-55 mixed case address
+pub fn checksum(address: &H160) -> String {
+    let lower = encode(address.as_bytes());
+    let hash = keccak256(lower.as_bytes());
+    let mixed: String = lower.chars().enumerate().map(|(i, c)| {
+        let nibble = (hash[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0f;
+        if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+    }).collect();
+    format!("0x{}", mixed)
+}
+
+// Value of an indexed event param. Strings, bytes, arrays and tuples are
+// only there as the keccak hash of their encoding, so they come out as
+// Hash(0x..) instead of passing for the value.
+pub fn topic_to_string(kind: &ParamType, topic: &H256) -> String {
+    let word = topic.as_bytes();
+    match kind {
+        ParamType::Address => checksum(&H160::from_slice(&word[12..])),
+        ParamType::Bool => (word[31] != 0).to_string(),
+        ParamType::Uint(_) => U256::from_big_endian(word).to_string(),
+        // sign extended to 32 bytes, whatever the width
+        ParamType::Int(_) => signed_decimal(&U256::from_big_endian(word)),
+        ParamType::FixedBytes(len) => format!("0x{}", encode(&word[..(*len).min(32)])),
+        ParamType::String | ParamType::Bytes | ParamType::Array(_) | ParamType::FixedArray(..) | ParamType::Tuple(_) => {
+            format!("Hash({:?})", topic)
+        }
+    }
+}
+
+// Value of a decoded param in event strings: integers in decimal, addresses
+// checksummed, arrays and tuples as [..] and (..).
+pub fn token_to_string(token: &Token) -> String {
+    let join = |tokens: &[Token]| tokens.iter().map(token_to_string).collect::<Vec<_>>().join(",");
+    match token {
+        Token::Address(a) => checksum(a),
+        Token::FixedBytes(b) | Token::Bytes(b) => format!("0x{}", encode(b)),
+        Token::Int(i) => signed_decimal(i),
+        Token::Uint(u) => u.to_string(),
+        Token::Bool(b) => b.to_string(),
+        Token::String(s) => s.clone(),
+        Token::FixedArray(tokens) | Token::Array(tokens) => format!("[{}]", join(tokens)),
+        Token::Tuple(tokens) => format!("({})", join(tokens)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(hex: &str) -> H256 {
+        format!("{:0>64}", hex).parse().unwrap()
+    }
+
+    #[test]
+    fn checksums_addresses() {
+        // EIP-55 test vectors
+        for expected in ["0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed", "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359", "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB"] {
+            assert_eq!(checksum(&expected.to_lowercase().parse().unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn reads_addresses_from_padded_topics() {
+        let topic = word("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+        assert_eq!(topic_to_string(&ParamType::Address, &topic), "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+    }
+
+    #[test]
+    fn reads_integers_and_bools() {
+        assert_eq!(topic_to_string(&ParamType::Uint(256), &word("0de0b6b3a7640000")), "1000000000000000000");
+        assert_eq!(topic_to_string(&ParamType::Uint(256), &H256::repeat_byte(0xff)), U256::MAX.to_string());
+        // int8 -1 and int256 min, both sign extended
+        assert_eq!(topic_to_string(&ParamType::Int(8), &H256::repeat_byte(0xff)), "-1");
+        let mut min = [0u8; 32];
+        min[0] = 0x80;
+        assert_eq!(
+            topic_to_string(&ParamType::Int(256), &H256(min)),
+            "-57896044618658097711785492504343953926634992332820282019728792003956564819968",
+        );
+        assert_eq!(topic_to_string(&ParamType::Int(32), &word("7b")), "123");
+        assert_eq!(topic_to_string(&ParamType::Bool, &word("1")), "true");
+        assert_eq!(topic_to_string(&ParamType::Bool, &word("0")), "false");
+    }
+
+    #[test]
+    fn keeps_fixed_bytes_and_marks_hashed_dynamic_types() {
+        let mut bytes = [0u8; 32];
+        bytes[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(topic_to_string(&ParamType::FixedBytes(4), &H256(bytes)), "0xdeadbeef");
+
+        let hash = H256(keccak256(b"hello"));
+        for kind in [ParamType::String, ParamType::Bytes, ParamType::Array(Box::new(ParamType::Uint(256))), ParamType::Tuple(vec![ParamType::Bool])] {
+            assert_eq!(topic_to_string(&kind, &hash), format!("Hash({:?})", hash));
+        }
+    }
+
+    #[test]
+    fn formats_decoded_tokens() {
+        let token = Token::Tuple(vec![
+            Token::Int(U256::MAX),
+            Token::Array(vec![Token::Uint(1.into()), Token::Uint(2.into())]),
+            Token::FixedBytes(vec![0xab]),
+        ]);
+        assert_eq!(token_to_string(&token), "(-1,[1,2],0xab)");
+    }
+
+    #[test]
+    fn names_unnamed_args_by_position() {
+        let param = |name: &str| Param { name: name.to_owned(), kind: ParamType::Uint(256), internal_type: None };
+        let args = named_args(&[param("value"), param("")], &[Token::Uint(5.into()), Token::Uint(U256::MAX)]);
+        assert_eq!(args.get("value"), Some(&uint_to_bson(&5.into())));
+        // past 34 digits integers stay decimal strings
+        assert_eq!(args.get("arg1"), Some(&Bson::String(U256::MAX.to_string())));
+    }
+}
@@ -0,0 +1,118 @@
+use bson::{Bson, Decimal128};
+use web3::types::U256;
+
+// lives next to the sql backends, which use it for json paths
+pub use blocks_storage::sort_key;
+use blocks_storage::{is_integer, DECIMAL128_DIGITS, SORT_DIGITS};
+
+// Integers go into documents as Decimal128 when they fit, so mongo can sort
+// and sum them, and as decimal strings otherwise. Either way nothing is lost;
+// `sort_key` gives an ordering that holds over both forms.
+
+pub fn uint_to_bson(value: &U256) -> Bson {
+    decimal_to_bson(value.to_string())
+}
+
+// two's complement, as ethabi hands out int values
+pub fn int_to_bson(value: &U256) -> Bson {
+    decimal_to_bson(signed_decimal(value))
+}
+
+// signed decimal string to Decimal128 or, when too long, the string itself
+pub fn decimal_to_bson(decimal: String) -> Bson {
+    if decimal.trim_start_matches('-').len() <= DECIMAL128_DIGITS {
+        if let Ok(d) = decimal.parse::<Decimal128>() {
+            return Bson::Decimal128(d);
+        }
+    }
+    Bson::String(decimal)
+}
+
+pub fn signed_decimal(value: &U256) -> String {
+    if value.bit(255) {
+        format!("-{}", (!*value).overflowing_add(U256::one()).0)
+    } else {
+        value.to_string()
+    }
+}
+
+// Integer value of a stored number: Decimal128, int32/int64 or decimal string.
+pub fn bson_to_decimal(value: &Bson) -> Option<String> {
+    let decimal = match value {
+        Bson::Int32(i) => i.to_string(),
+        Bson::Int64(i) => i.to_string(),
+        Bson::Decimal128(d) => d.to_string(),
+        Bson::String(s) => s.clone(),
+        _ => return None,
+    };
+    is_integer(&decimal).then_some(decimal)
+}
+
+pub fn uint_sort_key(value: &U256) -> String {
+    format!("1{:0>width$}", value.to_string(), width = SORT_DIGITS)
+}
+
+// token amount in whole units, "1500000000000000000" with 18 decimals is "1.5"
+pub fn scaled(decimal: &str, decimals: u8) -> Option<String> {
+    if !is_integer(decimal) {
+        return None;
+    }
+    let (sign, digits) = match decimal.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", decimal),
+    };
+    let decimals = decimals as usize;
+    let padded = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = padded.split_at(padded.len() - decimals);
+    let whole = whole.trim_start_matches('0');
+    let whole = if whole.is_empty() { "0" } else { whole };
+    let fraction = fraction.trim_end_matches('0');
+    let sign = if whole == "0" && fraction.is_empty() { "" } else { sign };
+    if fraction.is_empty() {
+        Some(format!("{}{}", sign, whole))
+    } else {
+        Some(format!("{}{}.{}", sign, whole, fraction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_are_decimal128_up_to_34_digits() {
+        let widest = "9".repeat(34);
+        assert!(matches!(decimal_to_bson(widest.clone()), Bson::Decimal128(_)));
+        assert!(matches!(decimal_to_bson(format!("-{}", widest)), Bson::Decimal128(_)));
+        assert_eq!(decimal_to_bson(format!("1{}", "0".repeat(34))), Bson::String(format!("1{}", "0".repeat(34))));
+        assert_eq!(bson_to_decimal(&decimal_to_bson(widest.clone())), Some(widest));
+        assert_eq!(bson_to_decimal(&uint_to_bson(&U256::MAX)), Some(U256::MAX.to_string()));
+    }
+
+    #[test]
+    fn ints_are_twos_complement() {
+        assert_eq!(signed_decimal(&U256::MAX), "-1");
+        assert_eq!(signed_decimal(&U256::from(42)), "42");
+        assert_eq!(bson_to_decimal(&int_to_bson(&(U256::MAX - 9))), Some("-10".to_owned()));
+    }
+
+    #[test]
+    fn uint_sort_keys_match_sort_key() {
+        for value in [U256::zero(), U256::from(1_000_000u64), U256::MAX] {
+            assert_eq!(Some(uint_sort_key(&value)), sort_key(&value.to_string()));
+        }
+    }
+
+    #[test]
+    fn scales_by_decimals() {
+        assert_eq!(scaled("1500000000000000000", 18).as_deref(), Some("1.5"));
+        assert_eq!(scaled("1", 18).as_deref(), Some("0.000000000000000001"));
+        assert_eq!(scaled("-2500", 3).as_deref(), Some("-2.5"));
+        assert_eq!(scaled("120", 0).as_deref(), Some("120"));
+        assert_eq!(scaled("0", 6).as_deref(), Some("0"));
+        assert_eq!(scaled("-0", 6).as_deref(), Some("0"));
+        assert_eq!(scaled(&U256::MAX.to_string(), 18).as_deref(), Some("115792089237316195423570985008687907853269984665640564039457.584007913129639935"));
+        assert_eq!(scaled("1.5", 2), None);
+        assert_eq!(scaled(&"1".repeat(79), 2), None);
+    }
+}
@@ -0,0 +1,602 @@
+mod stub;
+mod targets;
+
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use blocks_storage::{get_path, Filter, Op, Query, Storage, StorageError, Table};
+use bson::{doc, Bson, DateTime, Document};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::expr::{self, order, same, Expr};
+use crate::metrics::WEBHOOK_DELIVERIES;
+use crate::values::bson_to_decimal;
+
+pub use stub::serve_stub;
+pub use targets::Targets;
+
+// blocks of decoded_events scanned per round
+const CHUNK: u64 = 10_000;
+// checkpoints closer to the tip than this are kept, older ones pruned
+const KEEP_CHECKPOINTS: u64 = 1_000;
+const DEFAULT_MAX_ATTEMPTS: u32 = 8;
+const DEFAULT_TIMEOUT_SECS: u64 = 10;
+// doubled after every failed attempt up to MAX_BACKOFF
+const DEFAULT_FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+pub const SIGNATURE_HEADER: &str = "X-Blocks-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Blocks-Timestamp";
+pub const DELIVERY_HEADER: &str = "X-Blocks-Delivery";
+
+type HmacSha256 = Hmac<Sha256>;
+pub type DeliveryError = Box<dyn Error + Send + Sync>;
+
+// A webhook: decoded events of `contract` and `event` for which all the
+// conditions and the filter expression hold are POSTed to `url` as
+//
+// {"id": "<subscription>:<block hash>:<log index>", "subscription": "..", "event": {decoded_events row}}
+//
+// signed with the secret (see `sign`). Events go out one at a time in chain
+// order; a failing one is retried with backoff and, after the last attempt,
+// logged as failed so the ones after it aren't held up forever.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: String,
+    pub url: String,
+    pub secret: String,
+    // registered contract name, any contract if missing
+    #[serde(default)]
+    pub contract: Option<String>,
+    #[serde(default)]
+    pub event: Option<String>,
+    #[serde(default, rename = "where")]
+    pub conditions: Vec<Condition>,
+    // an expression like `value > 10^18 and to = 0x..`, see blocks_common::expr
+    #[serde(default)]
+    pub filter: Option<String>,
+    // nothing before this block is sent
+    pub from_block: i64,
+    pub created_at: DateTime,
+}
+
+// {"path": "args.value", "op": "gte", "value": "1000000000000000000000"}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Condition {
+    // into the decoded event: args.*, address, tx_hash, ...
+    pub path: String,
+    pub op: Compare,
+    // a list for `in`
+    pub value: Bson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compare {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    In,
+}
+
+// body of POST /webhooks
+#[derive(Debug, Deserialize)]
+pub struct NewSubscription {
+    pub url: String,
+    // generated if missing
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub contract: Option<String>,
+    #[serde(default)]
+    pub event: Option<String>,
+    #[serde(default, rename = "where")]
+    pub conditions: Vec<Condition>,
+    #[serde(default)]
+    pub filter: Option<String>,
+    // the block after the latest decoded event if missing, so only new
+    // events are sent
+    #[serde(default)]
+    pub from_block: Option<u64>,
+}
+
+impl Condition {
+    fn holds(&self, event: &Document) -> bool {
+        let Some(actual) = get_path(event, &self.path) else {
+            return false;
+        };
+        match self.op {
+            Compare::Eq => same(actual, &self.value),
+            Compare::Ne => !same(actual, &self.value),
+            Compare::In => matches!(&self.value, Bson::Array(values) if values.iter().any(|v| same(actual, v))),
+            op => order(actual, &self.value).is_some_and(|ordering| match op {
+                Compare::Gt => ordering.is_gt(),
+                Compare::Gte => ordering.is_ge(),
+                Compare::Lt => ordering.is_lt(),
+                _ => ordering.is_le(),
+            }),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self.op {
+            _ if self.path.is_empty() => Err("condition without path".to_owned()),
+            Compare::In if !matches!(self.value, Bson::Array(_)) => Err(format!("`in` on {} needs a list", self.path)),
+            Compare::Gt | Compare::Gte | Compare::Lt | Compare::Lte if bson_to_decimal(&self.value).is_none() => {
+                Err(format!("{:?} on {} needs an integer", self.op, self.path).to_lowercase())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Subscription {
+    // decoded_events conditions for contract and event, the rest is checked per event
+    fn filters(&self) -> Vec<Filter> {
+        let mut filters = Vec::new();
+        if let Some(contract) = &self.contract {
+            filters.push(Filter::eq("contract", contract.as_str()));
+        }
+        if let Some(event) = &self.event {
+            filters.push(Filter::eq("event", event.as_str()));
+        }
+        filters
+    }
+
+    pub fn expression(&self) -> Result<Option<Expr>, expr::ParseError> {
+        self.filter.as_deref().map(expr::parse).transpose()
+    }
+
+    pub fn matches(&self, event: &Document, expression: Option<&Expr>) -> bool {
+        self.conditions.iter().all(|c| c.holds(event)) && expression.is_none_or(|e| e.matches(event))
+    }
+}
+
+impl NewSubscription {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+            return Err(format!("`{}` is not an http(s) url", self.url));
+        }
+        if self.secret.as_ref().is_some_and(|s| s.is_empty()) {
+            return Err("secret is empty".to_owned());
+        }
+        if let Some(filter) = &self.filter {
+            expr::parse(filter).map_err(|err| format!("filter: {}", err))?;
+        }
+        self.conditions.iter().try_for_each(Condition::validate)
+    }
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut rng = rand::thread_rng();
+    hex::encode((0..bytes).map(|_| rng.gen::<u8>()).collect::<Vec<_>>())
+}
+
+// Stores a validated subscription. The result carries the secret, which is
+// the only time it is handed out when it was generated.
+pub async fn create(storage: &dyn Storage, request: NewSubscription) -> blocks_storage::Result<Subscription> {
+    let from_block = match request.from_block {
+        Some(from_block) => from_block as i64,
+        None => {
+            let latest = storage.find(Table::DecodedEvents, Query::new().sort("block_number", false).limit(1)).await?;
+            latest.first().and_then(|d| d.get_i64("block_number").ok()).map_or(0, |b| b + 1)
+        }
+    };
+    let subscription = Subscription {
+        id: random_hex(8),
+        url: request.url,
+        secret: request.secret.unwrap_or_else(|| random_hex(32)),
+        contract: request.contract,
+        event: request.event,
+        conditions: request.conditions,
+        filter: request.filter,
+        from_block,
+        created_at: DateTime::now(),
+    };
+    storage.insert(Table::WebhookSubscriptions, vec![bson::to_document(&subscription).map_err(|err| StorageError::Other(err.to_string()))?]).await?;
+    info!(subscription = %subscription.id, url = %subscription.url, from_block, "Webhook subscribed");
+    Ok(subscription)
+}
+
+pub async fn get(storage: &dyn Storage, id: &str) -> blocks_storage::Result<Option<Subscription>> {
+    let docs = storage.find(Table::WebhookSubscriptions, Query::new().filter(Filter::eq("id", id)).limit(1)).await?;
+    Ok(docs.into_iter().next().and_then(|d| bson::from_document(d).ok()))
+}
+
+// oldest first
+pub async fn list(storage: &dyn Storage) -> blocks_storage::Result<Vec<Subscription>> {
+    let docs = storage.find(Table::WebhookSubscriptions, Query::new().sort("created_at", true)).await?;
+    Ok(docs.into_iter().filter_map(|d| bson::from_document(d).ok()).collect())
+}
+
+// false for unknown ids; the delivery log stays
+pub async fn delete(storage: &dyn Storage, id: &str) -> blocks_storage::Result<bool> {
+    let deleted = storage.delete(Table::WebhookSubscriptions, vec![Filter::eq("id", id)]).await?;
+    storage.delete(Table::WebhookCheckpoints, vec![Filter::eq("subscription", id)]).await?;
+    Ok(deleted > 0)
+}
+
+fn mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+// `sha256=` and the hex HMAC-SHA256 of `<timestamp>.<body>` keyed with the
+// secret, sent in X-Blocks-Signature next to the X-Blocks-Timestamp it covers
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    format!("sha256={}", hex::encode(mac(secret, timestamp, body).finalize().into_bytes()))
+}
+
+// constant time, what a receiver does with the two headers
+pub fn verify(secret: &str, timestamp: i64, body: &[u8], signature: &str) -> bool {
+    let Some(Ok(expected)) = signature.strip_prefix("sha256=").map(hex::decode) else {
+        return false;
+    };
+    mac(secret, timestamp, body).verify_slice(&expected).is_ok()
+}
+
+// highest checkpoint, at or below `at_most` if given
+async fn checkpoint(storage: &dyn Storage, id: &str, at_most: Option<u64>) -> blocks_storage::Result<Option<u64>> {
+    let mut query = Query::new().filter(Filter::eq("subscription", id)).sort("block_number", false).limit(1);
+    if let Some(at_most) = at_most {
+        query = query.filter(Filter::cmp("block_number", Op::Lte, at_most as i64));
+    }
+    let docs = storage.find(Table::WebhookCheckpoints, query).await?;
+    Ok(docs.first().and_then(|d| d.get_i64("block_number").ok()).map(|b| b as u64))
+}
+
+async fn save_checkpoint(storage: &dyn Storage, id: &str, end: u64) -> blocks_storage::Result<()> {
+    storage.insert(Table::WebhookCheckpoints, vec![doc! { "subscription": id, "block_number": end as i64 }]).await?;
+    if let Some(oldest) = checkpoint(storage, id, Some(end.saturating_sub(KEEP_CHECKPOINTS))).await? {
+        let filters = vec![Filter::eq("subscription", id), Filter::cmp("block_number", Op::Lt, oldest as i64)];
+        storage.delete(Table::WebhookCheckpoints, filters).await?;
+    }
+    Ok(())
+}
+
+// (block_hash, log_index) of the events in [start, end] that are done with,
+// delivered or failed, after a round that was cut short
+async fn finished(storage: &dyn Storage, id: &str, start: u64, end: u64) -> blocks_storage::Result<BTreeSet<(String, i64)>> {
+    let query = Query::new()
+        .filter(Filter::eq("subscription", id))
+        .filter(Filter::cmp("block_number", Op::Gte, start as i64))
+        .filter(Filter::cmp("block_number", Op::Lte, end as i64))
+        .filter(Filter::In("status".to_owned(), vec!["delivered".into(), "failed".into()]));
+    Ok(storage.find(Table::WebhookDeliveries, query).await?.iter()
+        .filter_map(|d| Some((d.get_str("block_hash").ok()?.to_owned(), d.get_i64("log_index").ok()?)))
+        .collect())
+}
+
+async fn first_event_block(storage: &dyn Storage) -> blocks_storage::Result<Option<u64>> {
+    let docs = storage.find(Table::DecodedEvents, Query::new().sort("block_number", true).limit(1)).await?;
+    Ok(docs.first().and_then(|d| d.get_i64("block_number").ok()).map(|b| b as u64))
+}
+
+#[derive(Clone)]
+struct Sender {
+    client: reqwest::Client,
+    targets: Targets,
+    max_attempts: u32,
+    first_backoff: Duration,
+}
+
+impl Sender {
+    // the client resolves names through `targets` and follows no redirects,
+    // so a delivery can't be pointed elsewhere once it passed the check
+    fn new(targets: Targets, timeout: Duration, max_attempts: u32, first_backoff: Duration) -> reqwest::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .dns_resolver(Arc::new(targets.clone()))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(Sender { client, targets, max_attempts, first_backoff })
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        self.first_backoff.saturating_mul(1 << attempts.saturating_sub(1).min(16)).min(MAX_BACKOFF)
+    }
+
+    async fn post(&self, subscription: &Subscription, delivery: &str, body: &[u8]) -> Result<reqwest::StatusCode, DeliveryError> {
+        // subscriptions stored before the check, and hosts that moved since
+        self.targets.check(&subscription.url).await?;
+        let timestamp = chrono::Utc::now().timestamp();
+        let response = self.client.post(&subscription.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(SIGNATURE_HEADER, sign(&subscription.secret, timestamp, body))
+            .header(DELIVERY_HEADER, delivery)
+            .body(body.to_vec())
+            .send()
+            .await?;
+        Ok(response.status())
+    }
+
+    // Sends one event until it is delivered or out of attempts, logging each
+    // attempt. false once the subscription is gone.
+    async fn send(&self, storage: &dyn Storage, subscription: &Subscription, mut event: Document) -> Result<bool, DeliveryError> {
+        event.remove("_id");
+        let block_hash = event.get_str("block_hash")?.to_owned();
+        let log_index = event.get_i64("log_index")?;
+        let delivery = format!("{}:{}:{}", subscription.id, block_hash, log_index);
+        let mut row = doc! {
+            "subscription": &subscription.id,
+            "block_number": event.get("block_number").cloned().unwrap_or(Bson::Null),
+            "block_hash": &block_hash,
+            "log_index": log_index,
+            "tx_hash": event.get("tx_hash").cloned().unwrap_or(Bson::Null),
+            "event": event.get("event").cloned().unwrap_or(Bson::Null),
+        };
+        let body = serde_json::to_vec(&serde_json::json!({
+            "id": &delivery,
+            "subscription": &subscription.id,
+            "event": Bson::Document(event).into_relaxed_extjson(),
+        }))?;
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let (status, error) = match self.post(subscription, &delivery, &body).await {
+                Ok(status) if status.is_success() => (Some(status.as_u16()), None),
+                Ok(status) => (Some(status.as_u16()), Some(format!("answered {}", status))),
+                Err(err) => (None, Some(err.to_string())),
+            };
+            let outcome = match (&error, attempts >= self.max_attempts) {
+                (None, _) => "delivered",
+                (Some(_), false) => "retrying",
+                (Some(_), true) => "failed",
+            };
+            WEBHOOK_DELIVERIES.with_label_values(&[outcome]).inc();
+            row.insert("status", outcome);
+            row.insert("attempts", attempts as i64);
+            row.insert("response_status", status.map_or(Bson::Null, |s| Bson::Int64(s as i64)));
+            row.insert("error", error.clone().map_or(Bson::Null, Bson::String));
+            row.insert("updated_at", DateTime::now());
+            storage.upsert(Table::WebhookDeliveries, vec![row.clone()]).await?;
+            let error = error.unwrap_or_default();
+            match outcome {
+                "delivered" => return Ok(true),
+                "failed" => {
+                    warn!(subscription = %subscription.id, delivery = %delivery, attempts, "Webhook failed, giving up on this event: {}", error);
+                    return Ok(true);
+                }
+                _ => warn!(subscription = %subscription.id, delivery = %delivery, attempts, "Webhook attempt failed: {}", error),
+            }
+            tokio::time::sleep(self.backoff(attempts)).await;
+            if get(storage, &subscription.id).await?.is_none() {
+                return Ok(false);
+            }
+        }
+    }
+
+    // everything matching up to block `to`, from the subscription's checkpoint
+    async fn deliver(&self, storage: &dyn Storage, subscription: &Subscription, to: u64) -> Result<(), DeliveryError> {
+        let id = subscription.id.as_str();
+        let expression = subscription.expression()?;
+        let from_block = subscription.from_block.max(0) as u64;
+        let mut start = match checkpoint(storage, id, None).await? {
+            Some(checkpoint) => (checkpoint + 1).max(from_block),
+            None => match first_event_block(storage).await? {
+                Some(first) => first.max(from_block),
+                None => return Ok(()),
+            },
+        };
+        while start <= to {
+            let end = to.min(start + CHUNK - 1);
+            let mut query = Query::new()
+                .filter(Filter::cmp("block_number", Op::Gte, start as i64))
+                .filter(Filter::cmp("block_number", Op::Lte, end as i64))
+                .sort("block_number", true)
+                .sort("log_index", true);
+            for filter in subscription.filters() {
+                query = query.filter(filter);
+            }
+            let events = storage.find(Table::DecodedEvents, query).await?;
+            let done = finished(storage, id, start, end).await?;
+            for event in events.into_iter().filter(|e| subscription.matches(e, expression.as_ref())) {
+                let key = (event.get_str("block_hash").unwrap_or_default().to_owned(), event.get_i64("log_index").unwrap_or_default());
+                if done.contains(&key) {
+                    continue;
+                }
+                if !self.send(storage, subscription, event).await? {
+                    info!(subscription = id, "Webhook deleted, stopping its deliveries");
+                    return Ok(());
+                }
+            }
+            save_checkpoint(storage, id, end).await?;
+            start = end + 1;
+        }
+        Ok(())
+    }
+}
+
+// Delivers for every subscription in storage, each in its own task so a
+// slow or failing endpoint only holds up its own events.
+pub struct Webhooks {
+    sender: Sender,
+    // subscription id -> its delivery task of the current round
+    running: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl Webhooks {
+    // WEBHOOK_TIMEOUT_SECS per request, WEBHOOK_MAX_ATTEMPTS per event,
+    // WEBHOOK_ALLOWED_HOSTS for private targets (see Targets)
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let timeout = match env::var("WEBHOOK_TIMEOUT_SECS") {
+            Ok(secs) => secs.parse()?,
+            Err(_) => DEFAULT_TIMEOUT_SECS,
+        };
+        let max_attempts = match env::var("WEBHOOK_MAX_ATTEMPTS") {
+            Ok(attempts) => attempts.parse::<u32>()?.max(1),
+            Err(_) => DEFAULT_MAX_ATTEMPTS,
+        };
+        let sender = Sender::new(Targets::from_env(), Duration::from_secs(timeout), max_attempts, DEFAULT_FIRST_BACKOFF)?;
+        Ok(Webhooks { sender, running: Mutex::new(HashMap::new()) })
+    }
+
+    // Starts delivering up to block `to` for the subscriptions whose last
+    // task is done; the others pick up the new blocks on a later call.
+    pub async fn run(&self, storage: &Arc<dyn Storage>, to: u64) {
+        let subscriptions = match list(storage.as_ref()).await {
+            Ok(subscriptions) => subscriptions,
+            Err(err) => {
+                warn!("Failed to read webhook subscriptions: {}", err);
+                return;
+            }
+        };
+        let mut running = self.running.lock().unwrap();
+        running.retain(|_, task| !task.is_finished());
+        for subscription in subscriptions {
+            if running.contains_key(&subscription.id) {
+                continue;
+            }
+            let (sender, storage) = (self.sender.clone(), Arc::clone(storage));
+            let id = subscription.id.clone();
+            running.insert(id, tokio::spawn(async move {
+                if let Err(err) = sender.deliver(storage.as_ref(), &subscription, to).await {
+                    warn!(subscription = %subscription.id, "Webhook deliveries failed, retrying on the next round: {}", err);
+                }
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{SocketAddr, TcpListener};
+    use std::time::Instant;
+
+    use blocks_storage::sqlite::SqliteStorage;
+
+    use super::*;
+
+    #[test]
+    fn signatures_cover_secret_timestamp_and_body() {
+        let signature = sign("s3cret", 1_700_000_000, b"{\"id\":1}");
+        assert!(signature.starts_with("sha256=") && signature.len() == 7 + 64);
+        assert!(verify("s3cret", 1_700_000_000, b"{\"id\":1}", &signature));
+        assert!(!verify("other", 1_700_000_000, b"{\"id\":1}", &signature));
+        assert!(!verify("s3cret", 1_700_000_001, b"{\"id\":1}", &signature));
+        assert!(!verify("s3cret", 1_700_000_000, b"{\"id\":2}", &signature));
+        assert!(!verify("s3cret", 1_700_000_000, b"{\"id\":1}", signature.trim_start_matches("sha256=")));
+        assert!(!verify("s3cret", 1_700_000_000, b"{\"id\":1}", "sha256=zz"));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let sender = Sender::new(Targets::default(), Duration::from_secs(1), 3, Duration::from_secs(1)).unwrap();
+        let backoffs: Vec<u64> = [1, 2, 3, 10, 11, 40].iter().map(|a| sender.backoff(*a).as_secs()).collect();
+        assert_eq!(backoffs, vec![1, 2, 4, 512, 600, 600]);
+    }
+
+    fn event(block: i64, name: &str) -> Document {
+        doc! {
+            "block_number": block,
+            "block_hash": format!("0x{:064x}", block),
+            "block_time": Bson::Null,
+            "tx_hash": "0xt",
+            "log_index": 0i64,
+            "address": "0xaa",
+            "contract": "vault",
+            "abi_version": "v1",
+            "event": name,
+            "signature": format!("{}(uint256)", name),
+            "args": { "amount": "5" },
+        }
+    }
+
+    // the stub on a free port, once it accepts connections
+    async fn stub(secret: &str, fail: u32) -> SocketAddr {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let secret = secret.to_owned();
+        tokio::spawn(async move {
+            let _ = serve_stub(addr, Some(secret), fail).await;
+        });
+        while tokio::net::TcpStream::connect(addr).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        addr
+    }
+
+    async fn subscribe(storage: &dyn Storage, addr: SocketAddr, secret: &str) -> Subscription {
+        let request = NewSubscription {
+            url: format!("http://{}/hook", addr),
+            secret: Some(secret.to_owned()),
+            contract: Some("vault".to_owned()),
+            event: Some("Deposit".to_owned()),
+            conditions: Vec::new(),
+            filter: None,
+            from_block: Some(0),
+        };
+        create(storage, request).await.unwrap()
+    }
+
+    // (block, status, attempts, response status, error) of a subscription's log
+    async fn log(storage: &dyn Storage, id: &str) -> Vec<(i64, String, i64, Option<i64>, Option<String>)> {
+        let query = Query::new().filter(Filter::eq("subscription", id)).sort("block_number", true);
+        storage.find(Table::WebhookDeliveries, query).await.unwrap().iter().map(|row| (
+            row.get_i64("block_number").unwrap(),
+            row.get_str("status").unwrap().to_owned(),
+            row.get_i64("attempts").unwrap(),
+            row.get_i64("response_status").ok(),
+            row.get_str("error").ok().map(str::to_owned),
+        )).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn delivers_signed_events_with_retries() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        storage.insert(Table::DecodedEvents, vec![event(1, "Deposit"), event(2, "Withdraw"), event(3, "Deposit")]).await.unwrap();
+        // the first two signed requests get a 500
+        let addr = stub("s3cret", 2).await;
+        let sender = Sender::new(Targets::new(["127.0.0.1"]), Duration::from_secs(5), 3, Duration::from_millis(20)).unwrap();
+
+        let subscription = subscribe(&storage, addr, "s3cret").await;
+        let started = Instant::now();
+        sender.deliver(&storage, &subscription, 3).await.unwrap();
+        // 20ms after the first failure, 40ms after the second
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        assert_eq!(log(&storage, &subscription.id).await, vec![
+            (1, "delivered".to_owned(), 3, Some(200), None),
+            (3, "delivered".to_owned(), 1, Some(200), None),
+        ]);
+        assert_eq!(checkpoint(&storage, &subscription.id, None).await.unwrap(), Some(3));
+
+        // a secret the receiver doesn't share is turned down until the last attempt
+        let wrong = subscribe(&storage, addr, "guess").await;
+        sender.deliver(&storage, &wrong, 3).await.unwrap();
+        let unauthorized = Some("answered 401 Unauthorized".to_owned());
+        assert_eq!(log(&storage, &wrong.id).await, vec![
+            (1, "failed".to_owned(), 3, Some(401), unauthorized.clone()),
+            (3, "failed".to_owned(), 3, Some(401), unauthorized),
+        ]);
+        assert!(delete(&storage, &wrong.id).await.unwrap());
+        assert!(!delete(&storage, &wrong.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn private_targets_fail_without_a_request() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        storage.insert(Table::DecodedEvents, vec![event(1, "Deposit")]).await.unwrap();
+        let sender = Sender::new(Targets::default(), Duration::from_secs(5), 1, Duration::from_millis(1)).unwrap();
+        let subscription = subscribe(&storage, "127.0.0.1:9".parse().unwrap(), "s3cret").await;
+        sender.deliver(&storage, &subscription, 1).await.unwrap();
+        let refused = Some("127.0.0.1 is 127.0.0.1, which webhooks may not be sent to".to_owned());
+        assert_eq!(log(&storage, &subscription.id).await, vec![(1, "failed".to_owned(), 1, None, refused)]);
+    }
+}
@@ -0,0 +1,56 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use tracing::info;
+
+use super::{verify, DELIVERY_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+struct Stub {
+    secret: Option<String>,
+    // requests still to answer with a 500
+    failures: AtomicU32,
+}
+
+fn header<'r>(req: &'r Request<Body>, name: &str) -> &'r str {
+    req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or_default()
+}
+
+async fn receive(req: Request<Body>, stub: Arc<Stub>) -> Result<Response<Body>, Infallible> {
+    let delivery = header(&req, DELIVERY_HEADER).to_owned();
+    let timestamp = header(&req, TIMESTAMP_HEADER).parse::<i64>().unwrap_or_default();
+    let signature = header(&req, SIGNATURE_HEADER).to_owned();
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+    let signed = match &stub.secret {
+        Some(secret) => if verify(secret, timestamp, &body, &signature) { "valid" } else { "INVALID" },
+        None => "unchecked",
+    };
+    let status = if signed == "INVALID" {
+        StatusCode::UNAUTHORIZED
+    } else if stub.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::OK
+    };
+    info!(delivery = %delivery, signature = signed, answered = status.as_u16(), "{}", String::from_utf8_lossy(&body));
+    Ok(Response::builder().status(status).body(Body::empty()).unwrap_or_default())
+}
+
+// A local receiver to try subscriptions against: logs every delivery and
+// whether its signature checks out with `secret`, answering a 401 when it
+// doesn't, and answers the first `fail` signed ones with a 500 to watch the
+// retries.
+pub async fn serve_stub(addr: SocketAddr, secret: Option<String>, fail: u32) -> Result<(), Box<dyn Error>> {
+    let stub = Arc::new(Stub { secret, failures: AtomicU32::new(fail) });
+    let make_svc = make_service_fn(move |_conn| {
+        let stub = stub.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| receive(req, stub.clone()))) }
+    });
+    info!(%addr, "Webhook stub listening");
+    Server::try_bind(&addr)?.serve(make_svc).await?;
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::Url;
+
+// Where webhooks may be sent. Anyone who can subscribe picks the url, so
+// without a check the indexer would POST to its own loopback, the private
+// network or a cloud metadata service (169.254.169.254). Hosts resolving to
+// anything but public addresses are refused unless WEBHOOK_ALLOWED_HOSTS
+// lists them, e.g. "127.0.0.1,hooks.internal" to try the stub.
+#[derive(Debug, Clone, Default)]
+pub struct Targets {
+    allowed: Arc<BTreeSet<String>>,
+}
+
+impl Targets {
+    pub fn new<S: Into<String>>(allowed: impl IntoIterator<Item = S>) -> Self {
+        Targets { allowed: Arc::new(allowed.into_iter().map(|h| h.into().to_lowercase()).collect()) }
+    }
+
+    pub fn from_env() -> Self {
+        let allowed = env::var("WEBHOOK_ALLOWED_HOSTS").unwrap_or_default();
+        Targets::new(allowed.split(',').map(str::trim).filter(|h| !h.is_empty()))
+    }
+
+    // hosts as Url prints them, ipv6 ones in brackets
+    fn allows(&self, host: &str) -> bool {
+        self.allowed.contains(&host.to_lowercase())
+    }
+
+    // An http(s) url whose host is allowed, or resolves to public addresses
+    // only. Checked on subscribing and before every attempt; the resolver
+    // below checks again when connecting, should the name have changed.
+    pub async fn check(&self, url: &str) -> Result<(), String> {
+        let parsed = Url::parse(url).map_err(|err| format!("`{}` is not a url: {}", url, err))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(format!("`{}` is not an http(s) url", url));
+        }
+        let host = parsed.host_str().ok_or_else(|| format!("`{}` has no host", url))?;
+        if self.allows(host) {
+            return Ok(());
+        }
+        let port = parsed.port_or_known_default().unwrap_or_default();
+        let addrs: Vec<SocketAddr> = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+            Err(_) => tokio::net::lookup_host((host, port)).await.map_err(|err| format!("can't resolve {}: {}", host, err))?.collect(),
+        };
+        public(host, &addrs)
+    }
+}
+
+fn public(host: &str, addrs: &[SocketAddr]) -> Result<(), String> {
+    match addrs.iter().find(|a| !is_public(a.ip())) {
+        Some(addr) => Err(format!("{} is {}, which webhooks may not be sent to", host, addr.ip())),
+        None => Ok(()),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // this network, carrier-grade nat, benchmarking and reserved
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(v4);
+    }
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local fc00::/7 and link-local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+impl Resolve for Targets {
+    fn resolve(&self, name: Name) -> Resolving {
+        let targets = self.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+            if !targets.allows(host) {
+                public(host, &addrs)?;
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn refuses_non_public_hosts_unless_allowed() {
+        let targets = Targets::default();
+        for url in [
+            "http://127.0.0.1:9100/hook",
+            "http://localhost/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/",
+            "https://192.168.0.10/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(targets.check(url).await.is_err(), "{}", url);
+        }
+        assert_eq!(targets.check("http://10.1.2.3/").await.unwrap_err(), "10.1.2.3 is 10.1.2.3, which webhooks may not be sent to");
+        assert!(targets.check("ftp://example.com/").await.is_err());
+        assert!(targets.check("not a url").await.is_err());
+
+        assert!(targets.check("http://93.184.216.34/hook").await.is_ok());
+        assert!(targets.check("https://[2606:2800:220:1::1]/").await.is_ok());
+
+        let targets = Targets::new(["127.0.0.1", "LOCALHOST", "[::1]"]);
+        assert!(targets.check("http://127.0.0.1:9100/hook").await.is_ok());
+        assert!(targets.check("http://localhost:9100/hook").await.is_ok());
+        assert!(targets.check("http://[::1]:9100/hook").await.is_ok());
+        assert!(targets.check("http://127.0.0.2/").await.is_err());
+    }
+}
@@ -1,114 +1,151 @@
-use mongodb::{options::{ClientOptions, InsertManyOptions, ResolverConfig, WriteConcern}, Client, error::Error as MError};
-use std::{collections::HashMap, env, str::FromStr, sync::Arc};
+use std::{collections::HashMap, env, sync::Arc};
+use std::time::Duration;
 use std::error::Error;
 
 use flate2::{write::ZlibEncoder, read::ZlibDecoder, Compression};
 use std::io::{Write, Read};
-use mongodb::bson::{doc, Document};
-use web3::{signing::keccak256, transports::Http, types::{FilterBuilder, H160, H256, U64}};
+use bson::{doc, Bson, DateTime, Document};
+use web3::types::{FilterBuilder, H256, U64};
 use web3::types::{Log, BlockNumber};
 use web3::Web3;
-use web3::contract::Contract;
 
-use serde_json::Value;
-use std::fs::File;
 use tokio::task;
-
-use tokio;
-
-mod logger;
-use logger::{LogLevel, FileLogger};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+mod projections;
+mod raw_logs;
+mod rollups;
+mod tokens;
+mod webhooks;
+use tokens::TokenTracker;
 use std::fs::OpenOptions;
 
-use crate::logger::Logger;
 use hex::encode;
 // use regex::Regex;
 use ethabi::{ParamType, decode};
 use async_std::sync::Mutex;
-use bson::{Bson};
-use std::sync::atomic::{AtomicUsize, Ordering};     
-use tokio::sync::Semaphore;
-
-// DB related constants
-const DB_NAME: &str = "Nexa_Events_Data_4";
-// const TXN_COLLECTION: &str = "txns_table";
-const EVT_COLLECTION: &str = "events_table";
-// const BLOCK_COLLECTION: &str = "blocks_table";
-
-// log file path constants
-const ERR_LOG_FILE: &str = "./error.log";
-const INFO_LOG_FILE: &str = "./info.log";
+use tokio::sync::{RwLock, Semaphore};
+
+use blocks_common::admin;
+use blocks_common::events::DecodedEvent;
+use blocks_common::logging::{self, LogLevel, Logger, TracingLogger};
+use blocks_common::metrics::{DEAD_LETTERED, EVENTS_DECODED, RANGE_FAILURES, RANGE_RETRIES, REORGS};
+use blocks_common::progress::{end_block, watch_head, Progress};
+use blocks_common::projections::Projections;
+use blocks_common::proxy;
+use blocks_common::registry::ContractRegistry;
+use blocks_common::reorgs;
+use blocks_common::rollups::Rollups;
+use blocks_common::rpc::{self, RpcTransport};
+use blocks_common::signatures::{self, SignatureDb};
+use blocks_common::sinks::Sinks;
+use blocks_common::status::{publish_every, Reporter};
+use blocks_common::timestamps::BlockTimestamps;
+use blocks_common::tokens::{token_to_string, topic_to_string};
+use blocks_common::webhooks::Webhooks;
+use blocks_storage::{Storage, Table};
+
 const CSV_FILE: &str = "./events.csv";
-// const DEBUG_LOG_FILE: &str = "./debug.log";
-// const WARN_LOG_FILE: &str = "./warning.log";
-static PROCESSED_BATCHES: AtomicUsize = AtomicUsize::new(0);
+const ADMIN_ADDR: &str = "0.0.0.0:9101";
+const HEAD_POLL_INTERVAL: Duration = Duration::from_secs(15);
+const STATUS_INTERVAL: Duration = Duration::from_secs(15);
+const PROJECTION_INTERVAL: Duration = Duration::from_secs(5);
+const WEBHOOK_INTERVAL: Duration = Duration::from_secs(5);
+const SINK_INTERVAL: Duration = Duration::from_secs(5);
+const REORG_INTERVAL: Duration = Duration::from_secs(15);
 
 lazy_static::lazy_static! {
     static ref FILE_MUTEX: Mutex<()> = Mutex::new(());
 }
 type SafeFile = Arc<Mutex<std::io::BufWriter<std::fs::File>>>;
 
+// state shared by every batch task
+struct IndexerCtx {
+    web3: Web3<RpcTransport>,
+    registry: ContractRegistry,
+    signatures: Option<SignatureDb>,
+    logger: TracingLogger,
+    storage: Arc<dyn Storage>,
+    safe_file: SafeFile,
+    tokens: Option<TokenTracker>,
+    rollups: Option<Rollups>,
+    timestamps: BlockTimestamps,
+    progress: Arc<Progress>,
+    // ranges write under a read guard, a reorg rollback takes it exclusively
+    writes: RwLock<()>,
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-   let client_uri =
-      env::var("MONGODB_URI").expect("You must set the MONGODB_URI environment var!");
-   let rpc_url =
-      env::var("RPC_URL3").expect("You must set the RPC_URL environment var!");
-   let abi_path =
-      env::var("ABI_PATH").expect("You must set the RPC_URL environment var!");
-
-   let mut options =
-      ClientOptions::parse_with_resolver_config(&client_uri, ResolverConfig::cloudflare())
-         .await?;
-    options.write_concern = Some(WriteConcern::MAJORITY);
-   
-   let client = Arc::new(Client::with_options(options)?);
-
-
-    let http_transport = Http::new(&rpc_url).unwrap();
-    let web3 = Arc::new(Web3::new(http_transport));
-
-    let mut file = File::open(abi_path).expect("Failed to open ABI file");
-    
-    let mut abi_string = String::new();
-    file.read_to_string(&mut abi_string).expect("Failed to read ABI file");
-
-    let abi_json: Value = serde_json::from_str(&abi_string).expect("Failed to parse ABI JSON");
-    let abi_json_string = serde_json::to_string(&abi_json).expect("Failed to serialize ABI JSON");
-    // println!("ABI JSON Value: {}", abi_json);
-
-    let contract_address = H160::from_str("0xDB54D3Ce2035509d83F86bc982adc62F4AEBe03c")?;
-    let contract_abi = abi_json_string.as_bytes();
-    let contract = Arc::new(Contract::from_json(
-        web3.eth(),
-        contract_address,
-        contract_abi,
-    )?);
+   // LOG_LEVEL, LOG_FORMAT, LOG_SINKS, LOG_DIR, ... see blocks_common::logging
+   let _log_guard = logging::init("blocks_one")?;
+   // `blocks_one cache prune [max size]` and `blocks_one cache stats` manage RPC_CACHE_PATH
+   if let Some(result) = rpc::cache::command(&env::args().collect::<Vec<_>>()) {
+       return result;
+   }
+   // `blocks_one signatures import <file>..` fills SIGNATURES_PATH from a signature dump
+   if let Some(result) = signatures::command(&env::args().collect::<Vec<_>>()) {
+       return result;
+   }
+   // `blocks_one webhooks stub ..` is a local receiver to try subscriptions against
+   if let Some(result) = webhooks::command(&env::args().collect::<Vec<_>>()).await {
+       return result;
+   }
+   // RPC_PROVIDERS_PATH lists several nodes, RPC_URL3 is the single node otherwise
+   let rpc_url = env::var("RPC_URL3").ok();
+
+   // STORAGE_BACKEND picks mongo (MONGODB_URI) or postgres (POSTGRES_URL)
+   let storage = blocks_storage::from_env().await?;
+   // `blocks_one rollups rebuild ..`, `blocks_one projections rebuild <name>` and
+   // `blocks_one rollback <block>` only touch storage
+   match env::args().nth(1).as_deref() {
+       Some("rollups") => return rollups::command(&env::args().skip(2).collect::<Vec<_>>(), storage.as_ref()).await,
+       Some("projections") => return projections::command(&env::args().skip(2).collect::<Vec<_>>(), storage.as_ref()).await,
+       Some("rollback") => return rollups::rollback(&env::args().skip(2).collect::<Vec<_>>(), storage.as_ref()).await,
+       _ => {}
+   }
+   // ROLLUPS lists the event counts kept next to decoded_events
+   let rollups = Rollups::from_env()?;
+   // PROJECTIONS (built-in handlers), PROJECTIONS_PATH (json mappings) and
+   // WASM_PROJECTIONS_PATH (sandboxed modules) follow decoded_events
+   let projections = Projections::from_env()?.map(Arc::new);
+   // WEBHOOK_TIMEOUT_SECS, WEBHOOK_MAX_ATTEMPTS and WEBHOOK_ALLOWED_HOSTS, subscriptions come from storage
+   let webhooks = Webhooks::from_env()?;
+   // SINKS publishes decoded events to kafka, nats, amqp or a file, see blocks_common::sinks
+   let sinks = Sinks::from_env().await?.map(Arc::new);
+   // REORG_DEPTH, how far below the head stored block hashes are checked each round
+   let reorg_depth = reorgs::depth_from_env()?;
+
+
+    let web3 = rpc::from_env(rpc_url.as_deref())?;
+
+    // ABI_PATH (or CONTRACTS_PATH for several contracts) is read by the registry,
+    // ABI_SOURCES points it to build artifacts to match by deployed code
+    let registry = ContractRegistry::from_env(&web3).await?;
+    // names events no registered ABI knows
+    let signatures = SignatureDb::from_env()?;
+
+    // `blocks_one redecode [--from N] [--to N] [--contract 0x..] [--abi-version v2]` only reads raw_logs
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("redecode") {
+        return raw_logs::redecode(&args[2..], &registry, signatures.as_ref(), rollups.as_ref(), storage.as_ref()).await;
+    }
     
-    let ev_sighashs = Arc::new(Mutex::new(
-        contract.abi().events().into_iter().map(|event| { 
-            let sig = format!("{}({})", event.name, event.inputs.iter().map(|i| i.kind.to_string()).collect::<Vec<_>>().join(","));
-            let index_str = event.inputs.iter().map(|ip| if ip.indexed { "1" } else { "0" }).collect::<Vec<_>>().join(",");
-            let h = keccak256(sig.as_bytes());
-            println!("{}\t\t\t{:?}\n{}\n", sig, encode(h), index_str);
-            
-            (h, (sig, index_str))
-        }).collect::<HashMap<[u8; 32], (String, String)>>()
-    ));
-    println!("\n\n");
+    for (h, (sig, index_str)) in registry.event_sighashes().iter() {
+        debug!(signature = %sig, sighash = %encode(h), indexed = %index_str, "event signature");
+    }
     let start_block_height: usize = 1543162;
     //let start_block_height: usize = 1801212;
     //let end_block_height: usize = 1801214;
     let num_of_batches = 10000;
-    let block_height = web3.eth().block_number().await.unwrap().as_usize();
+    // END_BLOCK runs without asking the node for its head
+    let block_height = end_block(&web3, start_block_height as u64).await? as usize;
     let total = block_height - start_block_height;
     let batch_size = total / num_of_batches;
-    let mut failed_batches = Arc::new(Mutex::new(Vec::<(usize, usize)>::new()));
     let semaphore = Arc::new(Semaphore::new(10));
     
     let file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(CSV_FILE)?;
     let safe_file = Arc::new(Mutex::new(std::io::BufWriter::new(file)));
@@ -117,18 +154,85 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // return Ok(());
     let mut tasks = Vec::new();
 
-    //let logger = Arc::new(RefCell::new(FileLogger::new(INFO_LOG_FILE, ERR_LOG_FILE)?));
-    let logger = Arc::new(FileLogger::new(INFO_LOG_FILE, ERR_LOG_FILE)?);
+    let logger = TracingLogger;
+
+    let ctx = Arc::new(IndexerCtx {
+        web3,
+        registry,
+        signatures,
+        logger,
+        storage,
+        safe_file,
+        tokens: TokenTracker::from_env(),
+        rollups,
+        timestamps: BlockTimestamps::default(),
+        progress: Arc::new(Progress::new(start_block_height as u64)),
+        writes: RwLock::new(()),
+    });
+
+    ctx.progress.set_head(block_height as u64);
+    let _ctx = Arc::clone(&ctx);
+    task::spawn(async move { watch_head(_ctx.web3.clone(), &_ctx.progress, HEAD_POLL_INTERVAL).await });
+
+    // blocks near the head the chain replaced are rolled back and indexed again
+    let _ctx = Arc::clone(&ctx);
+    task::spawn(async move {
+        loop {
+            if let Err(err) = check_reorg(&_ctx, reorg_depth).await {
+                warn!("Reorg check failed: {}", err);
+            }
+            tokio::time::sleep(REORG_INTERVAL).await;
+        }
+    });
+
+    // /metrics, /healthz, /readyz and /status on ADMIN_ADDR
+    let reporter = Arc::new(Reporter::new("blocks_one", &ctx.registry, ctx.progress.clone(), ctx.web3.clone(), ctx.storage.clone()));
+    admin::spawn(admin::addr_from_env(ADMIN_ADDR), reporter.clone());
+    task::spawn(publish_every(reporter.clone(), STATUS_INTERVAL));
+
+    // projections only see blocks the contiguous run has passed, so events reach them in order
+    if let Some(projections) = projections.clone() {
+        let _ctx = Arc::clone(&ctx);
+        task::spawn(async move {
+            loop {
+                if let Some(indexed) = _ctx.progress.indexed() {
+                    projections.run(_ctx.storage.as_ref(), indexed).await;
+                }
+                tokio::time::sleep(PROJECTION_INTERVAL).await;
+            }
+        });
+    }
+
+    // the same goes for webhooks; deliveries cut short by the exit resume from
+    // their checkpoints on the next start
+    let _ctx = Arc::clone(&ctx);
+    task::spawn(async move {
+        loop {
+            if let Some(indexed) = _ctx.progress.indexed() {
+                webhooks.run(&_ctx.storage, indexed).await;
+            }
+            tokio::time::sleep(WEBHOOK_INTERVAL).await;
+        }
+    });
+
+    // sinks publish what the contiguous run has passed as well, and hold the
+    // batches back while they are down or too far behind
+    if let Some(sinks) = sinks.clone() {
+        let _ctx = Arc::clone(&ctx);
+        task::spawn(async move {
+            loop {
+                if let Some(indexed) = _ctx.progress.indexed() {
+                    sinks.run(_ctx.storage.as_ref(), indexed).await;
+                }
+                tokio::time::sleep(SINK_INTERVAL).await;
+            }
+        });
+    }
 
     for i in 0..num_of_batches {
-        let _web3 = Arc::clone(&web3);
-        let _contract = Arc::clone(&contract);
-        let _client = Arc::clone(&client);
-        let _failed_batches = Arc::clone(&mut failed_batches);
-        let _ev_sighashs = Arc::clone(&ev_sighashs);
-        let mut _logger = Arc::clone(&logger);
+        let _ctx = Arc::clone(&ctx);
         let semaphore = Arc::clone(&semaphore);
-        let _safe_file = Arc::clone(&safe_file);
+        let sinks = sinks.clone();
 
         let bstart = start_block_height + i * batch_size;
         let bend = if i == num_of_batches - 1 {
@@ -138,29 +242,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
         };
 
         let task = task::spawn(async move {
+            if let Some(sinks) = &sinks {
+                sinks.wait(bstart as u64).await;
+            }
             let _permit = semaphore.acquire().await.expect("Failed to acquire semaphore permit");
+            // held until the range is marked complete, so a rollback can't fall in between
+            let _writing = _ctx.writes.read().await;
             loop {
-                if let Err(err) = process_range(
-                    bstart, 
-                    bend, 
-                    Arc::clone(&_web3), 
-                    Arc::clone(&_contract), 
-                    Arc::clone(&_failed_batches),
-                    Arc::clone(&_ev_sighashs), 
-                    Arc::clone(&_logger), 
-                    Arc::clone(&_client),
-                    Arc::clone(&_safe_file)
-                ).await {
-                    eprintln!("Error processing range: {}", err);
+                if let Err(err) = process_range(bstart, bend, &_ctx).await {
+                    warn!("Error processing range, retrying: {}", err);
                     // Retry the batch if there's any error
+                    RANGE_RETRIES.inc();
                     continue;
                 }
                 break;
             }
            
-            PROCESSED_BATCHES.fetch_add(1, Ordering::Relaxed);
+            _ctx.progress.complete(bstart as u64, bend as u64);
             print_progress(i + 1, num_of_batches, bstart, bend);
-        });
+        }.instrument(info_span!("batch", start = bstart, end = bend)));
 
         tasks.push(task);
     }
@@ -169,47 +269,153 @@ async fn main() -> Result<(), Box<dyn Error>> {
         task.await?;
     }
 
-    println!("All batches processed!");
+    if let Err(err) = check_reorg(&ctx, reorg_depth).await {
+        warn!("Reorg check failed: {}", err);
+    }
+    if let Err(err) = reporter.publish().await {
+        warn!("Failed to publish indexer status: {}", err);
+    }
+    if let (Some(projections), Some(indexed)) = (&projections, ctx.progress.indexed()) {
+        projections.run(ctx.storage.as_ref(), indexed).await;
+    }
+    if let (Some(sinks), Some(indexed)) = (&sinks, ctx.progress.indexed()) {
+        sinks.run(ctx.storage.as_ref(), indexed).await;
+        if !sinks.healthy() {
+            warn!("Sinks are behind, the rest is published on the next start");
+        }
+    }
+    info!("All batches processed!");
 
    Ok(())
 }
 
-async fn process_range(
-    start: usize, 
-    end: usize, 
-    web3: Arc<Web3<Http>>, 
-    contract: Arc<Contract<Http>>, 
-    failed_batches: Arc<Mutex<Vec<(usize, usize)>>>,
-    ev_sighashs: Arc<Mutex<HashMap<[u8; 32],  (String, String)>>>, 
-    logger: Arc<FileLogger>,
-    client: Arc<Client>,
-    safe_file: SafeFile
-) -> Result<(), Box<dyn Error>> {
-    
-    let db = client.database(DB_NAME);
+// Rolls the index back to the lowest block near the head whose stored hash
+// the chain no longer has, then indexes everything that was done from there
+// on again.
+async fn check_reorg(ctx: &IndexerCtx, depth: u64) -> Result<(), Box<dyn Error>> {
+    let (Some(indexed), Some(head)) = (ctx.progress.indexed(), ctx.progress.head()) else { return Ok(()) };
+    let from = head.saturating_sub(depth - 1);
+    if from > indexed {
+        return Ok(());
+    }
+    let Some(fork) = reorgs::fork_point(&ctx.web3, ctx.storage.as_ref(), from, indexed).await? else { return Ok(()) };
+
+    let last = {
+        let _rollback = ctx.writes.write().await;
+        rollups::unwind(ctx.storage.as_ref(), ctx.rollups.as_ref(), fork).await?;
+        ctx.timestamps.forget(fork);
+        ctx.progress.rewind(fork)
+    };
+    REORGS.inc();
+    warn!(block = fork, "Chain reorganized below the indexed block {}, rolled back", indexed);
+    let Some(last) = last else { return Ok(()) };
+
+    let _writing = ctx.writes.read().await;
+    while let Err(err) = process_range(fork as usize, last as usize, ctx).await {
+        warn!("Error processing range, retrying: {}", err);
+        RANGE_RETRIES.inc();
+    }
+    ctx.progress.complete(fork, last);
+    info!("Indexed blocks {} to {} again", fork, last);
+    Ok(())
+}
+
+async fn process_range(start: usize, end: usize, ctx: &IndexerCtx) -> Result<(), Box<dyn Error>> {
 
     let bn_start = BlockNumber::Number(U64::from(start));
     let bn_end = BlockNumber::Number(U64::from(end));
     
-    let log_filter = FilterBuilder::default().address(vec![contract.address()]).from_block(bn_start).to_block(bn_end).build();
-    let logs_res = web3.eth().logs(log_filter).await;
+    // beacons are only watched for implementation changes
+    let mut addresses = ctx.registry.addresses();
+    addresses.extend(ctx.registry.beacons());
+    let log_filter = FilterBuilder::default().address(addresses).from_block(bn_start).to_block(bn_end).build();
+    let logs_res = ctx.web3.eth().logs(log_filter).await;
     if let Err(err) = logs_res {
-        // wait for the lock
-        let mut fb = failed_batches.lock().await;
-        fb.push((start.clone(), end.clone()));
-        drop(fb);
-        logger.log(LogLevel::Err, &format!("Log Fetch Failure ({}, {}): {}", start, end, err)).await;
+        ctx.progress.fail(start as u64, end as u64);
+        RANGE_FAILURES.with_label_values(&["logs"]).inc();
+        ctx.logger.log(LogLevel::Err, &format!("Log Fetch Failure ({}, {}): {}", start, end, err)).await;
         return Err(err.into());
     }
 
-    let logs = logs_res.unwrap();
+    let mut logs = logs_res.unwrap();
+    proxy::check_upgrades(&ctx.web3, &ctx.registry, &logs).await;
+    logs.retain(|log| ctx.registry.get(&log.address).is_some());
+
+    // token stage goes first: its writes are idempotent, so a failure here can
+    // retry the whole range before anything else was inserted
+    if let Some(tokens) = &ctx.tokens {
+        if let Err(err) = tokens.process_range(start, end, &ctx.web3, ctx.storage.as_ref(), &ctx.timestamps, &ctx.logger).await {
+            ctx.progress.fail(start as u64, end as u64);
+            RANGE_FAILURES.with_label_values(&["tokens"]).inc();
+            ctx.logger.log(LogLevel::Err, &format!("Token Stage Failure ({}, {}): {}", start, end, err)).await;
+            return Err(err);
+        }
+    }
     
     // println!("got logs: {}", logs.len());
-    let mut num_of_events = 0;
+    let bnums: Vec<u64> = logs.iter().filter_map(|log| log.block_number).map(|bn| bn.as_u64()).collect();
+    let block_times = match ctx.timestamps.resolve(&ctx.web3, &bnums).await {
+        Ok(times) => times,
+        Err(err) => {
+            ctx.progress.fail(start as u64, end as u64);
+            RANGE_FAILURES.with_label_values(&["timestamps"]).inc();
+            ctx.logger.log(LogLevel::Err, &format!("Block Timestamp Failure ({}, {}): {}", start, end, err)).await;
+            return Err(err.into());
+        }
+    };
+
+    // raw logs are kept so `redecode` can rebuild events_table without the node
+    let raw_docs: Vec<Document> = logs.iter()
+        .filter_map(|log| raw_logs::to_document(log, log.block_number.and_then(|bn| block_times.get(&bn.as_u64()).copied())))
+        .collect();
+    if let Err(err) = ctx.storage.insert(Table::RawLogs, raw_docs).await {
+        ctx.progress.fail(start as u64, end as u64);
+        RANGE_FAILURES.with_label_values(&["raw_logs"]).inc();
+        ctx.logger.log(LogLevel::Err, &format!("Raw Log Write Failure ({}, {}): {}", start, end, err)).await;
+        return Err(err.into());
+    }
+
+    // structured rows of the known events, what aggregations run on
+    let decoded_docs = decoded_event_docs(&ctx.registry, &logs, &block_times);
+    if let Err(err) = ctx.storage.insert(Table::DecodedEvents, decoded_docs.clone()).await {
+        ctx.progress.fail(start as u64, end as u64);
+        RANGE_FAILURES.with_label_values(&["decoded_events"]).inc();
+        ctx.logger.log(LogLevel::Err, &format!("Decoded Event Write Failure ({}, {}): {}", start, end, err)).await;
+        return Err(err.into());
+    }
+    if let Some(rollups) = &ctx.rollups {
+        if let Err(err) = rollups.apply(ctx.storage.as_ref(), &decoded_docs).await {
+            ctx.progress.fail(start as u64, end as u64);
+            RANGE_FAILURES.with_label_values(&["rollups"]).inc();
+            ctx.logger.log(LogLevel::Err, &format!("Rollup Failure ({}, {}): {}", start, end, err)).await;
+            return Err(err.into());
+        }
+    }
+
+    let (logs_decoded, bnum_map) = decode_logs(&ctx.registry, ctx.signatures.as_ref(), logs);
+    let (documents, num_of_events) = event_block_docs(&logs_decoded, &bnum_map, &block_times);
+
+    // println!("Document: {:#?}", documents);
+
+    let insert_result = ctx.storage.insert(Table::EventBlocks, documents.clone()).await;
+    if let Err(err) = insert_result {
+        ctx.logger.log(LogLevel::Err, &format!("Failed to insert documents to DB ({}, {}): {}", start, end, err)).await;
+
+        // Save documents to CSV file
+        DEAD_LETTERED.inc();
+        let _ = save_documents_to_csv(documents, &ctx.safe_file).await;
+    } else {
+        ctx.logger.log(LogLevel::Info, &format!("Stored {} events for ({}, {})", num_of_events, start, end)).await;
+    }
+    
+    Ok(())
+}
 
-    let (logs_decoded, bnum_map) = decode_logs(&contract.address(), logs, &ev_sighashs).await;
 
-    let documents: Vec<Document> = logs_decoded.iter().map(|(block_hash, events)| {
+// one events_table document per block, the events hex of the zlib compressed `::` joined strings
+fn event_block_docs(logs_decoded: &HashMap<H256, Vec<String>>, bnum_map: &HashMap<H256, i32>, block_times: &HashMap<u64, DateTime>) -> (Vec<Document>, usize) {
+    let mut num_of_events = 0;
+    let documents = logs_decoded.iter().map(|(block_hash, events)| {
         let joined = events.join("::");
         let events_string = encode(compress_it(&joined).unwrap());
         // println!("compressed: {} decompressed: {} original: {}", events_string.len(),decompress_it(&hex::decode(&events_string).unwrap()).unwrap().len(), joined.len());
@@ -217,135 +423,89 @@ async fn process_range(
         
         num_of_events += events.len();
 
+        let bnum = bnum_map.get(block_hash).unwrap();
         doc! {
-            "block_number":bnum_map.get(&block_hash).unwrap(),
+            "block_number": bnum,
             "block_hash": format!("{:?}", block_hash),
+            "block_time": block_times.get(&(*bnum as u64)).map(|t| Bson::DateTime(*t)).unwrap_or(Bson::Null),
             "events": events_string,
             "num_of_events": events.len() as i32,
         }
-    }).collect();   
-    // println!("Document: {:#?}", documents);
+    }).collect();
+    (documents, num_of_events)
+}
 
-    let insert_result = db.collection(EVT_COLLECTION).insert_many(documents.clone(), InsertManyOptions::default()).await;
-    if let Err(err) = insert_result {
-        logger.log(LogLevel::Err, &format!("Failed to insert documents to DB ({}, {}): {}", start, end, err)).await;
 
-        // Save documents to CSV file
-        let _ = save_documents_to_csv(documents, start, end, safe_file).await;
-    }
-    
-    Ok(())
+fn decoded_event_docs(registry: &ContractRegistry, logs: &[Log], block_times: &HashMap<u64, DateTime>) -> Vec<Document> {
+    logs.iter()
+        .filter_map(|log| DecodedEvent::decode(registry, log, log.block_number.and_then(|bn| block_times.get(&bn.as_u64()).copied())))
+        .map(|event| event.to_document())
+        .collect()
 }
 
-
+// indexed and non-indexed param types of a canonical event signature
 fn to_param_types(sig: &str, idx: &str) -> (Vec<ParamType>, Vec<ParamType>) {
-    let mut substr = String::new();
-    let mut s = false;
-    for c in sig.chars() {
-        if c == '(' {
-            s = true;
-            continue;
-            // m = true;
-        }
-        if c == ')' {
-            s = false
-        }
-        if s {
-            substr.push(c)
+    let types = signatures::parse_signature(sig).map(|(_, types)| types).unwrap_or_default();
+    let (mut iparam_types, mut niparam_types) = (Vec::new(), Vec::new());
+    for (kind, flag) in types.into_iter().zip(idx.split(',')) {
+        if flag == "1" {
+            iparam_types.push(kind);
+        } else {
+            niparam_types.push(kind);
         }
     }
-    // println!("event signature: {}", substr);
-    let idxs = idx.split(',');
-    // println!("IDXs: {:?}", idxs);
-    let clz = |(i, s)| {
-        match s {
-            "address" => ParamType::Address,
-            "bytes" => ParamType::Bytes,
-            "uint8" => ParamType::Uint(8),
-            "uint16" => ParamType::Uint(16),
-            "uint20" => ParamType::Uint(20),
-            "uint24" => ParamType::Uint(24),
-            "uint32" => ParamType::Uint(32),
-            "uint40" => ParamType::Uint(40),
-            "uint128" => ParamType::Uint(128),
-            "uint256" => ParamType::Uint(256),
-            "bool" => ParamType::Bool,
-            "string" => ParamType::String,
-            _ => ParamType::Int(256)
-        }
-    };
-    let i_params: Vec<&str> = substr.split(',').zip(idxs.clone()).filter(|(_, c)| if c == &"1" {true} else {false}).map(|s| s.0).collect();
-    let ni_params: Vec<&str> = substr.split(',').zip(idxs).filter(|(_, c)| if c == &"0" {true} else {false}).map(|s| s.0).collect();
-    // println!("i_params: {:#?}\nni_params: {:#?}", i_params, ni_params);
-    let iparam_types: Vec<ParamType> = i_params.into_iter().enumerate().map(clz).collect();
-    let niparam_types: Vec<ParamType> = ni_params.into_iter().enumerate().map(clz).collect();
     (iparam_types, niparam_types)
-
 }
 
-async fn decode_logs(
-    caddr: &H160,
-    logs: Vec<Log>, 
-    ev_sighashs: &Arc<Mutex<HashMap<[u8; 32], (String, String)>>>) -> (HashMap<H256, Vec<String>>, HashMap<H256, i32>) {
+// each log is decoded with the ABI version its contract had at the log's block
+fn decode_logs(
+    registry: &ContractRegistry,
+    signatures: Option<&SignatureDb>,
+    logs: Vec<Log>) -> (HashMap<H256, Vec<String>>, HashMap<H256, i32>) {
     
     let mut block_to_evts_map = HashMap::<H256, Vec<String>>::new();
     let mut bnum_map = HashMap::<H256, i32>::new();
-    let mut matched_evts: Vec<String> = Vec::new();
-    let mut matched_data_vec = Vec::<String>::new();
-    let len = logs.len();
 
-    for mut i in 0..len {
-        let log = logs.get(i).unwrap().clone();
-        
+    for log in logs {
         // println!("log address: {:?}", log.address);             
         let mut event_data_str = String::new();
         // let mut matched_data = Vec::<String>::new();
-        if &log.address == caddr {
+        let block = log.block_number.unwrap_or_default().as_u64();
+        if let Some(v) = log.topics.first().and_then(|sig| registry.event_at(&log.address, block, sig.as_bytes())) {
             let topics = log.topics;
             let data = log.data;
             // println!("Index: {:?}", log.log_index);
             // println!("Data: {:#?}", data);
+            let name = v.0.split('(').next().unwrap_or_default();
+            EVENTS_DECODED.with_label_values(&[name]).inc();
+            // push event signature
+            event_data_str.push_str(&v.0);
+
+            let (i_ptypes, ni_ptypes) = to_param_types(&v.0, &v.1);
             
-            let sig = topics[0].as_bytes();
-            let sighashs = ev_sighashs.lock().await;
-            if let Some(v) = sighashs.get(sig) {
-                // push event signature
-                event_data_str.push_str(&v.0);
-
-                let (i_ptypes, ni_ptypes) = to_param_types(&v.0, &v.1);
-                
-                // will only decode non-indexed params
-                if ni_ptypes.len() > 0 {
-                    let decoded = decode(&ni_ptypes, data.0.as_ref());
-                    
-                    if decoded.is_ok() {
-                        // println!("decoded: {:?}", decoded);
-                        // that means we have some indexed params here, so look into topics
-                        // push non-indexed params
-                        event_data_str.push_str(&format!(":NonIndexed({})", decoded.unwrap().into_iter().map(|token| format!("0x{}", token)).collect::<Vec<String>>().join(",")));
-                    }
+            // will only decode non-indexed params
+            if !ni_ptypes.is_empty() {
+                if let Ok(decoded) = decode(&ni_ptypes, data.0.as_ref()) {
+                    // println!("decoded: {:?}", decoded);
+                    // that means we have some indexed params here, so look into topics
+                    // push non-indexed params
+                    event_data_str.push_str(&format!(":NonIndexed({})", decoded.into_iter().map(|token| token_to_string(&token)).collect::<Vec<String>>().join(",")));
                 }
+            }
 
-                if i_ptypes.len() > 0 {
-                
-                    // println!("[O] Topics: {:#?}", topics);
-                    let t: String = topics.into_iter().skip(1).map(|topic| {
-                        let s = format!("{:?}", topic).trim_start_matches(|c| c == '0' || c == 'x').to_owned();
-                        if s.is_empty() {
-                            "0x0".to_owned()
-                        } else {
-                            format!("0x{}", s)
-                        }
-                    }).collect::<Vec<_>>().join(",");
-                    // println!("Topics: {:?}", t);
-                    // finally push Indexed params
-                    event_data_str.push_str(&format!(":Indexed({})", t));
-                }
-                
-                // println!("index: {:#?}\nnon-index: {:#?}", i_ptypes, ni_ptypes);
-                // matched_data.push()
+            if !i_ptypes.is_empty() {
+            
+                // println!("[O] Topics: {:#?}", topics);
+                let t: String = topics.iter().skip(1).zip(&i_ptypes).map(|(topic, kind)| topic_to_string(kind, topic)).collect::<Vec<_>>().join(",");
+                // println!("Topics: {:?}", t);
+                // finally push Indexed params
+                event_data_str.push_str(&format!(":Indexed({})", t));
             }
-            drop(sighashs);
+            
+            // println!("index: {:#?}\nnon-index: {:#?}", i_ptypes, ni_ptypes);
+            // matched_data.push()
+        } else {
+            event_data_str = unknown_event(&log, signatures);
         }
         // put the string into the vec under its block hash key in the map
         // no  matter what order are the logs in wrt block hash
@@ -354,7 +514,7 @@ async fn decode_logs(
         bnum_map.entry(bh)
             .or_insert(bn);
         block_to_evts_map.entry(bh)
-            .or_insert(Vec::new())
+            .or_default()
             .push(event_data_str);
     }
 
@@ -362,6 +522,35 @@ async fn decode_logs(
     (block_to_evts_map, bnum_map) 
 }
 
+// Logs no registered ABI knows, anonymous and topic-less ones included. With
+// a name from the signature database they are decoded on a best-effort basis
+// as `Unknown(sig):NonIndexed(..):Indexed(..)`; everything else is kept raw as
+// `Unknown[(sig)]:Topics(..):Data(..)`.
+fn unknown_event(log: &Log, signatures: Option<&SignatureDb>) -> String {
+    let sig = log.topics.first().zip(signatures).and_then(|(topic0, db)| db.event(topic0));
+    let guess = sig.as_deref()
+        .and_then(signatures::parse_signature)
+        .and_then(|(name, types)| Some((name, types.clone(), signatures::guess_event(&types, log.topics.len(), &log.data.0)?)));
+
+    let (Some(sig), Some((name, types, (indexed, tokens)))) = (&sig, guess) else {
+        EVENTS_DECODED.with_label_values(&["unknown"]).inc();
+        let label = sig.map(|s| format!("Unknown({})", s)).unwrap_or("Unknown".to_owned());
+        let topics = log.topics.iter().map(|t| format!("{:?}", t)).collect::<Vec<_>>().join(",");
+        return format!("{}:Topics({}):Data(0x{})", label, topics, encode(&log.data.0));
+    };
+    EVENTS_DECODED.with_label_values(&[&name]).inc();
+    let mut event_data_str = format!("Unknown({})", sig);
+    if !tokens.is_empty() {
+        event_data_str.push_str(&format!(":NonIndexed({})", tokens.into_iter().map(|token| token_to_string(&token)).collect::<Vec<String>>().join(",")));
+    }
+    let indexed_types: Vec<&ParamType> = types.iter().zip(&indexed).filter(|(_, i)| **i).map(|(t, _)| t).collect();
+    if !indexed_types.is_empty() {
+        let t: String = log.topics.iter().skip(1).zip(indexed_types).map(|(topic, kind)| topic_to_string(kind, topic)).collect::<Vec<_>>().join(",");
+        event_data_str.push_str(&format!(":Indexed({})", t));
+    }
+    event_data_str
+}
+
 fn compress_it(s: &str) -> Result<Vec<u8>, std::io::Error> {
     let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
     
@@ -369,6 +558,7 @@ fn compress_it(s: &str) -> Result<Vec<u8>, std::io::Error> {
     enc.finish()
 }
 
+#[allow(dead_code)]
 fn decompress_it(d: &[u8]) -> Result<String, std::io::Error> {
     let mut dec = ZlibDecoder::new(d);
     let mut s = String::new();
@@ -377,10 +567,10 @@ fn decompress_it(d: &[u8]) -> Result<String, std::io::Error> {
 }
 
 fn print_progress(current_batch: usize, total_batches: usize, start_block: usize, end_block: usize) {
-    println!("Batch {} of {}: Processing blocks {} to {}", current_batch, total_batches, start_block, end_block);
+    info!(batch = current_batch, total = total_batches, "Processed blocks {} to {}", start_block, end_block);
 }
 
-async fn save_documents_to_csv(documents: Vec<Document>, start: usize, end: usize, file: SafeFile) -> std::io::Result<()> {
+async fn save_documents_to_csv(documents: Vec<Document>, file: &SafeFile) -> std::io::Result<()> {
     let mut file = file.lock().await;
 
     // Write documents
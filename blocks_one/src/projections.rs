@@ -0,0 +1,34 @@
+use std::error::Error;
+
+use blocks_common::projections::{self, Projections};
+use blocks_storage::{Query, Storage, Table};
+use tracing::info;
+
+// `blocks_one projections rebuild <name> [--to N]` drops what a projection
+// stored and replays it from the first decoded event, up to the last one
+// stored or N. Run it with the indexer stopped, which would otherwise carry
+// on from the old checkpoint at the same time.
+pub async fn command(args: &[String], storage: &dyn Storage) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: blocks_one projections rebuild <name> [--to N]";
+    let (Some("rebuild"), Some(name)) = (args.first().map(String::as_str), args.get(1)) else {
+        return Err(usage.into());
+    };
+    let to = match (args.get(2).map(String::as_str), args.get(3)) {
+        (Some("--to"), Some(to)) => Some(to.parse::<u64>()?),
+        (None, _) => None,
+        _ => return Err(usage.into()),
+    };
+    let all = Projections::from_env()?.ok_or("none of PROJECTIONS, PROJECTIONS_PATH and WASM_PROJECTIONS_PATH is set")?;
+    let projection = all.get(name).ok_or_else(|| format!("no projection named {}", name))?;
+
+    let last = storage.find(Table::DecodedEvents, Query::new().sort("block_number", false).limit(1)).await?
+        .first()
+        .and_then(|d| d.get_i64("block_number").ok())
+        .map(|b| b as u64);
+    projections::reset(storage, name).await?;
+    if let Some(to) = to.or(last) {
+        projections::run(projection, storage, to).await.map_err(|err| err as Box<dyn Error>)?;
+    }
+    info!(projection = %name, "Projection rebuilt");
+    Ok(())
+}
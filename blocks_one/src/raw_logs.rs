@@ -0,0 +1,313 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::str::FromStr;
+
+use bson::{doc, Bson, DateTime, Document};
+use tracing::info;
+use web3::types::{Bytes, Index, Log, H160, H256, U256, U64};
+
+use blocks_common::registry::ContractRegistry;
+use blocks_common::rollups::Rollups;
+use blocks_common::signatures::SignatureDb;
+use blocks_storage::{Filter, Op, Query, Storage, Table};
+
+use crate::{decode_logs, decoded_event_docs, event_block_docs};
+
+// blocks read from raw_logs per round
+const REDECODE_CHUNK: u64 = 10_000;
+
+// document kept for every log of a registered contract
+pub fn to_document(log: &Log, block_time: Option<DateTime>) -> Option<Document> {
+    Some(doc! {
+        "block_number": log.block_number?.as_u64() as i64,
+        "block_hash": format!("{:?}", log.block_hash?),
+        "block_time": block_time.map(Bson::DateTime).unwrap_or(Bson::Null),
+        "tx_hash": log.transaction_hash.map(|h| format!("{:?}", h)),
+        "tx_index": log.transaction_index.map(|i| i.as_u64() as i64),
+        "log_index": log.log_index?.as_u64() as i64,
+        "address": format!("{:?}", log.address),
+        "topic0": log.topics.first().map(|t| format!("{:?}", t)),
+        "topics": log.topics.iter().map(|t| format!("{:?}", t)).collect::<Vec<_>>(),
+        "data": format!("0x{}", hex::encode(&log.data.0)),
+    })
+}
+
+fn int(doc: &Document, key: &str) -> Option<u64> {
+    match doc.get(key)? {
+        Bson::Int32(i) => Some(*i as u64),
+        Bson::Int64(i) => Some(*i as u64),
+        _ => None,
+    }
+}
+
+fn hash(doc: &Document, key: &str) -> Option<H256> {
+    H256::from_str(doc.get_str(key).ok()?).ok()
+}
+
+// the log as the node returned it, with the stored block time
+pub fn from_document(doc: &Document) -> Option<(Log, Option<DateTime>)> {
+    let topics = doc.get_array("topics").ok()?.iter()
+        .map(|t| t.as_str().and_then(|t| H256::from_str(t).ok()))
+        .collect::<Option<Vec<_>>>()?;
+    let data = hex::decode(doc.get_str("data").ok()?.trim_start_matches("0x")).ok()?;
+    let log = Log {
+        address: H160::from_str(doc.get_str("address").ok()?).ok()?,
+        topics,
+        data: Bytes(data),
+        block_hash: hash(doc, "block_hash"),
+        block_number: int(doc, "block_number").map(U64::from),
+        transaction_hash: hash(doc, "tx_hash"),
+        transaction_index: int(doc, "tx_index").map(Index::from),
+        log_index: int(doc, "log_index").map(U256::from),
+        transaction_log_index: None,
+        log_type: None,
+        removed: None,
+    };
+    Some((log, doc.get_datetime("block_time").ok().copied()))
+}
+
+#[derive(Debug, Default)]
+struct RedecodeArgs {
+    from: Option<u64>,
+    to: Option<u64>,
+    contract: Option<String>,
+    abi_version: Option<String>,
+}
+
+impl RedecodeArgs {
+    fn parse(args: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut parsed = RedecodeArgs::default();
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("missing value for {}", flag))?;
+            match flag.as_str() {
+                "--from" => parsed.from = Some(value.parse()?),
+                "--to" => parsed.to = Some(value.parse()?),
+                "--contract" => parsed.contract = Some(format!("{:?}", H160::from_str(value)?)),
+                "--abi-version" => parsed.abi_version = Some(value.clone()),
+                _ => return Err(format!("unknown option {}, expected --from, --to, --contract or --abi-version", flag).into()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    // --abi-version narrows to the contract and blocks that version is active in
+    fn apply_abi_version(&mut self, registry: &ContractRegistry) -> Result<(), Box<dyn Error>> {
+        let Some(version) = &self.abi_version else { return Ok(()) };
+        let matches: Vec<_> = registry.contracts()
+            .filter(|c| self.contract.as_ref().is_none_or(|a| *a == format!("{:?}", c.address)))
+            .filter_map(|c| c.version_range(version).map(|range| (c, range)))
+            .collect();
+        let (contract, (start, end)) = match matches.as_slice() {
+            [one] => *one,
+            [] => return Err(format!("no contract has ABI version {}", version).into()),
+            _ => return Err(format!("several contracts have ABI version {}, pick one with --contract", version).into()),
+        };
+        self.contract = Some(format!("{:?}", contract.address));
+        self.from = Some(self.from.map_or(start, |from| from.max(start)));
+        self.to = match (self.to, end) {
+            (Some(to), Some(end)) => Some(to.min(end)),
+            (to, end) => to.or(end),
+        };
+        info!(contract = %contract.name, version = %version, from = self.from, to = self.to, "Redecoding blocks of ABI version");
+        Ok(())
+    }
+}
+
+// first or last block in raw_logs
+async fn edge_block(storage: &dyn Storage, ascending: bool) -> Result<Option<u64>, Box<dyn Error>> {
+    let docs = storage.find(Table::RawLogs, Query::new().sort("block_number", ascending).limit(1)).await?;
+    Ok(docs.first().and_then(|d| int(d, "block_number")))
+}
+
+// `blocks_one redecode [--from N] [--to N] [--contract 0x..] [--abi-version v2]`
+// rebuilds the events_table, decoded_events and rollup documents of the range from raw_logs with the
+// current ABIs, without talking to the node. A contract or ABI version only
+// narrows which blocks are rebuilt; each of them is rebuilt from all of its
+// raw logs.
+pub async fn redecode(args: &[String], registry: &ContractRegistry, signatures: Option<&SignatureDb>, rollups: Option<&Rollups>, storage: &dyn Storage) -> Result<(), Box<dyn Error>> {
+    let mut args = RedecodeArgs::parse(args)?;
+    args.apply_abi_version(registry)?;
+    // the range never reaches past the stored raw logs
+    let (Some(first), Some(last)) = (edge_block(storage, true).await?, edge_block(storage, false).await?) else {
+        info!("No raw logs stored, nothing to redecode");
+        return Ok(());
+    };
+    let (from, to) = (args.from.unwrap_or(first).max(first), args.to.unwrap_or(last).min(last));
+
+    let (mut blocks, mut events) = (0, 0);
+    let mut start = from;
+    while start <= to {
+        let end = to.min(start + REDECODE_CHUNK - 1);
+        let range = vec![Filter::cmp("block_number", Op::Gte, start as i64), Filter::cmp("block_number", Op::Lte, end as i64)];
+        let filter = match &args.contract {
+            Some(contract) => {
+                let query = Query { filters: range, ..Query::new() }.filter(Filter::eq("address", contract.as_str()));
+                let touched: BTreeSet<i64> = storage.find(Table::RawLogs, query).await?.iter()
+                    .filter_map(|d| int(d, "block_number"))
+                    .map(|b| b as i64)
+                    .collect();
+                Filter::In("block_number".to_owned(), touched.into_iter().map(Bson::Int64).collect())
+            }
+            None => Filter::And(range),
+        };
+
+        let query = Query::new().filter(filter).sort("block_number", true).sort("log_index", true);
+        let mut logs = Vec::new();
+        let mut block_times = HashMap::new();
+        for doc in storage.find(Table::RawLogs, query).await? {
+            let (log, time) = from_document(&doc).ok_or("unreadable raw log")?;
+            if let (Some(bn), Some(time)) = (log.block_number, time) {
+                block_times.insert(bn.as_u64(), time);
+            }
+            logs.push(log);
+        }
+
+        let decoded = decoded_event_docs(registry, &logs, &block_times);
+        storage.upsert(Table::DecodedEvents, decoded.clone()).await?;
+        if let Some(rollups) = rollups {
+            rollups.apply(storage, &decoded).await?;
+        }
+        let (logs_decoded, bnum_map) = decode_logs(registry, signatures, logs);
+        let (documents, num_of_events) = event_block_docs(&logs_decoded, &bnum_map, &block_times);
+        blocks += documents.len();
+        events += num_of_events;
+        storage.upsert(Table::EventBlocks, documents).await?;
+        info!("Redecoded blocks {} to {}", start, end);
+        start = end + 1;
+    }
+
+    info!(blocks, events, "Redecode done");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use blocks_common::registry::AbiVersion;
+    use blocks_storage::sqlite::SqliteStorage;
+    use ethabi::{Contract as Abi, Token};
+    use web3::signing::keccak256;
+
+    use super::*;
+
+    const V1: &str = r#"[{"type": "event", "name": "Deposit", "anonymous": false, "inputs": [{"name": "amount", "type": "uint256", "indexed": false}]}]"#;
+    const V2: &str = r#"[{"type": "event", "name": "Deposit", "anonymous": false,
+        "inputs": [{"name": "user", "type": "address", "indexed": true}, {"name": "amount", "type": "uint256", "indexed": false}]}]"#;
+
+    fn vault() -> H160 {
+        H160::repeat_byte(0xaa)
+    }
+
+    fn registry() -> ContractRegistry {
+        let version = |name: &str, from_block: u64, abi: &str| AbiVersion::new(name, from_block, None, Abi::load(abi.as_bytes()).unwrap());
+        let mut registry = ContractRegistry::new();
+        registry.register_versions("vault", vault(), None, vec![version("v1", 0, V1), version("v2", 100, V2), version("v3", 200, V2)]).unwrap();
+        registry
+    }
+
+    fn log(address: H160, block: u64, topics: Vec<H256>, amount: u64) -> Log {
+        Log {
+            address,
+            topics,
+            data: Bytes(ethabi::encode(&[Token::Uint(U256::from(amount))])),
+            block_hash: Some(H256::from_low_u64_be(block)),
+            block_number: Some(U64::from(block)),
+            transaction_hash: Some(H256::repeat_byte(0x11)),
+            transaction_index: Some(Index::from(2)),
+            log_index: Some(U256::from(block % 7)),
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    fn v1_deposit(block: u64) -> Log {
+        log(vault(), block, vec![H256(keccak256(b"Deposit(uint256)"))], block)
+    }
+
+    fn v2_deposit(address: H160, block: u64) -> Log {
+        log(address, block, vec![H256(keccak256(b"Deposit(address,uint256)")), H256::from(H160::repeat_byte(0x01))], block)
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn logs_round_trip_through_documents() {
+        let time = DateTime::from_millis(1_700_000_000_000);
+        let log = v2_deposit(vault(), 150);
+        let doc = to_document(&log, Some(time)).unwrap();
+        assert_eq!(doc.get_i64("block_number").unwrap(), 150);
+        assert_eq!(from_document(&doc), Some((log.clone(), Some(time))));
+        assert_eq!(from_document(&to_document(&log, None).unwrap()), Some((log.clone(), None)));
+
+        // pending logs have no block yet and aren't kept
+        assert!(to_document(&Log { block_number: None, ..log }, None).is_none());
+        let mut broken = doc;
+        broken.insert("data", "0xzz");
+        assert!(from_document(&broken).is_none());
+    }
+
+    #[test]
+    fn parses_redecode_options() {
+        let parsed = RedecodeArgs::parse(&args(&["--from", "5", "--to", "9", "--contract", "0xAAaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"])).unwrap();
+        assert_eq!((parsed.from, parsed.to, parsed.contract), (Some(5), Some(9), Some(format!("{:?}", vault()))));
+        for bad in [&["--from"][..], &["--from", "x"], &["--since", "5"], &["--contract", "0x12"]] {
+            assert!(RedecodeArgs::parse(&args(bad)).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn abi_version_narrows_the_contract_and_blocks() {
+        let mut registry = registry();
+        let narrowed = |options: &[&str], registry: &ContractRegistry| -> Result<RedecodeArgs, Box<dyn Error>> {
+            let mut parsed = RedecodeArgs::parse(&args(options))?;
+            parsed.apply_abi_version(registry)?;
+            Ok(parsed)
+        };
+
+        let parsed = narrowed(&["--abi-version", "v2"], &registry).unwrap();
+        assert_eq!((parsed.contract.as_deref(), parsed.from, parsed.to), (Some(format!("{:?}", vault()).as_str()), Some(100), Some(199)));
+        let parsed = narrowed(&["--abi-version", "v2", "--from", "120", "--to", "500"], &registry).unwrap();
+        assert_eq!((parsed.from, parsed.to), (Some(120), Some(199)));
+        let parsed = narrowed(&["--abi-version", "v3", "--to", "500"], &registry).unwrap();
+        assert_eq!((parsed.from, parsed.to), (Some(200), Some(500)));
+        assert!(narrowed(&["--abi-version", "v9"], &registry).is_err());
+
+        registry.register("token", H160::repeat_byte(0xbb), Abi::load(V1.as_bytes()).unwrap());
+        let err = narrowed(&["--abi-version", "v1"], &registry).err().unwrap();
+        assert!(err.to_string().contains("--contract"), "{}", err);
+        let parsed = narrowed(&["--abi-version", "v1", "--contract", &format!("{:?}", H160::repeat_byte(0xbb))], &registry).unwrap();
+        assert_eq!((parsed.from, parsed.to), (Some(0), None));
+    }
+
+    async fn decoded(storage: &SqliteStorage) -> Vec<(i64, String)> {
+        let query = Query::new().sort("block_number", true);
+        storage.find(Table::DecodedEvents, query).await.unwrap().iter()
+            .map(|d| (d.get_i64("block_number").unwrap(), d.get_str("abi_version").unwrap().to_owned()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn redecodes_stored_logs_without_the_node() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        redecode(&[], &registry(), None, None, &storage).await.unwrap();
+
+        // an unregistered contract's log in the same block goes into events_table only
+        let other = Log { log_index: Some(U256::from(6)), ..v2_deposit(H160::repeat_byte(0xcc), 150) };
+        let logs = [v1_deposit(50), v2_deposit(vault(), 150), other, v2_deposit(vault(), 250)];
+        storage.insert(Table::RawLogs, logs.iter().filter_map(|l| to_document(l, None)).collect()).await.unwrap();
+
+        redecode(&args(&["--abi-version", "v2"]), &registry(), None, None, &storage).await.unwrap();
+        assert_eq!(decoded(&storage).await, vec![(150, "v2".to_owned())]);
+        let blocks = storage.find(Table::EventBlocks, Query::new()).await.unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].get_i32("num_of_events").unwrap(), 2);
+
+        redecode(&args(&["--to", "1000"]), &registry(), None, None, &storage).await.unwrap();
+        assert_eq!(decoded(&storage).await, vec![(50, "v1".to_owned()), (150, "v2".to_owned()), (250, "v3".to_owned())]);
+        assert_eq!(storage.find(Table::EventBlocks, Query::new()).await.unwrap().len(), 3);
+    }
+}
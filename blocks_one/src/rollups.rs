@@ -0,0 +1,80 @@
+use std::error::Error;
+
+use blocks_common::projections;
+use blocks_common::rollups::Rollups;
+use blocks_storage::{DbGroup, Filter, Op, Query, Storage, Table};
+use bson::Bson;
+use tracing::info;
+
+// blocks read from decoded_events per round
+const REBUILD_CHUNK: u64 = 10_000;
+
+// first or last block in decoded_events
+async fn edge_block(storage: &dyn Storage, ascending: bool) -> Result<Option<u64>, Box<dyn Error>> {
+    let docs = storage.find(Table::DecodedEvents, Query::new().sort("block_number", ascending).limit(1)).await?;
+    Ok(docs.first().and_then(|d| match d.get("block_number") {
+        Some(Bson::Int64(b)) => Some(*b as u64),
+        Some(Bson::Int32(b)) => Some(*b as u64),
+        _ => None,
+    }))
+}
+
+// `blocks_one rollups rebuild [--from N] [--to N]` recounts the ROLLUPS of
+// the range from decoded_events, for rollups enabled on an existing index or
+// derived rows left behind by an interrupted rollback.
+pub async fn command(args: &[String], storage: &dyn Storage) -> Result<(), Box<dyn Error>> {
+    let rollups = Rollups::from_env()?.ok_or("ROLLUPS is not set, nothing to rebuild")?;
+    let (mut from, mut to) = (None, None);
+    match args.first().map(String::as_str) {
+        Some("rebuild") => {}
+        _ => return Err("usage: blocks_one rollups rebuild [--from N] [--to N]".into()),
+    }
+    let mut flags = args[1..].iter();
+    while let Some(flag) = flags.next() {
+        let value: u64 = flags.next().ok_or_else(|| format!("missing value for {}", flag))?.parse()?;
+        match flag.as_str() {
+            "--from" => from = Some(value),
+            "--to" => to = Some(value),
+            _ => return Err(format!("unknown option {}, expected --from or --to", flag).into()),
+        }
+    }
+
+    let (Some(first), Some(last)) = (edge_block(storage, true).await?, edge_block(storage, false).await?) else {
+        info!("No decoded events stored, nothing to rebuild");
+        return Ok(());
+    };
+    let (from, to) = (from.unwrap_or(first).max(first), to.unwrap_or(last).min(last));
+    let mut start = from;
+    while start <= to {
+        let end = to.min(start + REBUILD_CHUNK - 1);
+        let query = Query::new()
+            .filter(Filter::cmp("block_number", Op::Gte, start as i64))
+            .filter(Filter::cmp("block_number", Op::Lte, end as i64));
+        let events = storage.find(Table::DecodedEvents, query).await?;
+        rollups.apply(storage, &events).await?;
+        info!("Rebuilt rollups of blocks {} to {}", start, end);
+        start = end + 1;
+    }
+    Ok(())
+}
+
+// `blocks_one rollback <block>` drops everything indexed from `block` on,
+// after a reorg deeper than the indexer noticed, then recounts the rollups
+// and puts projection entities back to their last remaining version.
+pub async fn rollback(args: &[String], storage: &dyn Storage) -> Result<(), Box<dyn Error>> {
+    let block: u64 = args.first().ok_or("usage: blocks_one rollback <block>")?.parse()?;
+    unwind(storage, Rollups::from_env()?.as_ref(), block).await?;
+    info!(block, "Rolled back");
+    Ok(())
+}
+
+// what a rollback to `block` takes, for the command and the reorg check alike
+pub async fn unwind(storage: &dyn Storage, rollups: Option<&Rollups>, block: u64) -> Result<(), Box<dyn Error>> {
+    let entities = projections::rolled_back(storage, block).await?;
+    match rollups {
+        Some(rollups) => rollups.rollback(storage, block).await?,
+        None => storage.rollback(DbGroup::Events, block).await?,
+    }
+    projections::restore(storage, entities).await?;
+    Ok(())
+}
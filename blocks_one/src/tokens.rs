@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+
+use async_std::sync::Mutex;
+use ethabi::{decode, ParamType, Token};
+use bson::{doc, Bson, Document};
+use web3::signing::keccak256;
+use web3::types::{BlockNumber, Bytes, CallRequest, FilterBuilder, Log, H160, H256, U256, U64};
+use tracing::{debug_span, Instrument};
+use web3::Web3;
+
+use blocks_common::timestamps::BlockTimestamps;
+use blocks_storage::{Filter, Query, Storage, Table};
+
+use blocks_common::logging::{LogLevel, Logger, TracingLogger};
+use blocks_common::metrics::EVENTS_DECODED;
+use blocks_common::rpc::RpcTransport;
+use blocks_common::values::{decimal_to_bson, scaled, uint_sort_key, uint_to_bson};
+
+// name(), symbol(), decimals()
+const NAME_SELECTOR: [u8; 4] = [0x06, 0xfd, 0xde, 0x03];
+const SYMBOL_SELECTOR: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+const DECIMALS_SELECTOR: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+
+lazy_static::lazy_static! {
+    static ref TRANSFER_TOPIC: H256 = H256(keccak256(b"Transfer(address,address,uint256)"));
+    static ref APPROVAL_TOPIC: H256 = H256(keccak256(b"Approval(address,address,uint256)"));
+    static ref TRANSFER_SINGLE_TOPIC: H256 = H256(keccak256(b"TransferSingle(address,address,address,uint256,uint256)"));
+    static ref TRANSFER_BATCH_TOPIC: H256 = H256(keccak256(b"TransferBatch(address,address,address,uint256[],uint256[])"));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Standard {
+    Erc20,
+    Erc721,
+    Erc1155,
+}
+
+impl Standard {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Standard::Erc20 => "erc20",
+            Standard::Erc721 => "erc721",
+            Standard::Erc1155 => "erc1155",
+        }
+    }
+}
+
+struct TokenTransfer {
+    standard: Standard,
+    operator: Option<H160>,
+    from: H160,
+    to: H160,
+    // erc721 / erc1155 only
+    token_id: Option<U256>,
+    // 1 for erc721
+    value: U256,
+}
+
+struct TokenApproval {
+    standard: Standard,
+    owner: H160,
+    spender: H160,
+    // allowance for erc20, token id for erc721
+    value: U256,
+}
+
+enum TokenEvent {
+    Transfers(Vec<TokenTransfer>),
+    Approval(TokenApproval),
+}
+
+// Transfer/Approval/TransferSingle/TransferBatch logs of any contract.
+// Enabled with TRACK_TOKENS=1.
+pub struct TokenTracker {
+    // tokens whose metadata is already stored, with their decimals
+    known_tokens: Mutex<HashMap<H160, Option<u8>>>,
+}
+
+impl TokenTracker {
+    pub fn from_env() -> Option<Self> {
+        match env::var("TRACK_TOKENS").unwrap_or_default().as_str() {
+            "1" | "true" => Some(TokenTracker { known_tokens: Mutex::new(HashMap::new()) }),
+            _ => None,
+        }
+    }
+
+    // Fetches and stores the token logs of a block range. Every write is keyed so
+    // a retried range doesn't double count balances.
+    pub async fn process_range(&self, start: usize, end: usize, web3: &Web3<RpcTransport>, storage: &dyn Storage, timestamps: &BlockTimestamps, logger: &TracingLogger) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let filter = FilterBuilder::default()
+            .topics(Some(vec![*TRANSFER_TOPIC, *APPROVAL_TOPIC, *TRANSFER_SINGLE_TOPIC, *TRANSFER_BATCH_TOPIC]), None, None, None)
+            .from_block(BlockNumber::Number(U64::from(start)))
+            .to_block(BlockNumber::Number(U64::from(end)))
+            .build();
+        let logs = web3.eth().logs(filter).await?;
+        let bnums: Vec<u64> = logs.iter().filter_map(|log| log.block_number).map(|n| n.as_u64()).collect();
+        let times = timestamps.resolve(web3, &bnums).await?;
+        let block_time = |bnum: u64| times.get(&bnum).map(|t| Bson::DateTime(*t)).unwrap_or(Bson::Null);
+
+        // (token, amount, document), the scaled amount is added once decimals are known
+        let mut transfers = Vec::<(H160, U256, Document)>::new();
+        let mut approvals = Vec::<(H160, U256, Document)>::new();
+        // (token, holder, token id, block) -> (credited, debited)
+        let mut changes = HashMap::<(H160, H160, Option<U256>, u64), (U256, U256)>::new();
+        let mut tokens = HashMap::<H160, Standard>::new();
+
+        for log in logs.iter() {
+            let Some(event) = decode_token_log(log) else { continue };
+            EVENTS_DECODED.with_label_values(&[event_name(&log.topics[0])]).inc();
+            let bnum = log.block_number.unwrap_or_default().as_u64();
+            let mut position = log_position(log);
+            position.insert("block_time", block_time(bnum));
+
+            match event {
+                TokenEvent::Transfers(items) => {
+                    for (i, t) in items.into_iter().enumerate() {
+                        tokens.entry(log.address).or_insert(t.standard);
+                        if !t.from.is_zero() {
+                            changes.entry((log.address, t.from, balance_id(&t), bnum)).or_default().1 += t.value;
+                        }
+                        if !t.to.is_zero() {
+                            changes.entry((log.address, t.to, balance_id(&t), bnum)).or_default().0 += t.value;
+                        }
+                        let mut d = position.clone();
+                        d.insert("_id", format!("{}:{}", position_key(log), i));
+                        d.insert("token", format!("{:?}", log.address));
+                        d.insert("standard", t.standard.as_str());
+                        d.insert("operator", t.operator.map(|o| Bson::String(format!("{:?}", o))).unwrap_or(Bson::Null));
+                        d.insert("from", format!("{:?}", t.from));
+                        d.insert("to", format!("{:?}", t.to));
+                        d.insert("token_id", t.token_id.as_ref().map(uint_to_bson).unwrap_or(Bson::Null));
+                        d.insert("value", uint_to_bson(&t.value));
+                        d.insert("value_sort", uint_sort_key(&t.value));
+                        transfers.push((log.address, t.value, d));
+                    }
+                }
+                TokenEvent::Approval(a) => {
+                    tokens.entry(log.address).or_insert(a.standard);
+                    let mut d = position.clone();
+                    d.insert("_id", position_key(log));
+                    d.insert("token", format!("{:?}", log.address));
+                    d.insert("standard", a.standard.as_str());
+                    d.insert("owner", format!("{:?}", a.owner));
+                    d.insert("spender", format!("{:?}", a.spender));
+                    d.insert("value", uint_to_bson(&a.value));
+                    d.insert("value_sort", uint_sort_key(&a.value));
+                    approvals.push((log.address, a.value, d));
+                }
+            }
+        }
+
+        let snapshots: Vec<Document> = changes.into_iter().filter(|(_, (cr, dr))| cr != dr).map(|((token, holder, id, bnum), (cr, dr))| {
+            let id_str = id.map(|id| id.to_string());
+            doc! {
+                "_id": format!("{:?}:{:?}:{}:{}", token, holder, id_str.unwrap_or_default(), bnum),
+                "token": format!("{:?}", token),
+                "holder": format!("{:?}", holder),
+                "token_id": id.as_ref().map(uint_to_bson).unwrap_or(Bson::Null),
+                "block_number": bnum as i64,
+                "block_time": block_time(bnum),
+                "delta": decimal_to_bson(signed_delta(cr, dr)),
+            }
+        }).collect();
+
+        let mut decimals = HashMap::<H160, u8>::new();
+        for (token, standard) in tokens {
+            let span = debug_span!("token", contract = ?token);
+            match self.ensure_metadata(token, standard, web3, storage).instrument(span).await {
+                Ok(Some(d)) => { decimals.insert(token, d); }
+                Ok(None) => {}
+                Err(err) => logger.log(LogLevel::Err, &format!("Failed to store metadata of token {:?}: {}", token, err)).await,
+            }
+        }
+        // whole units for tokens with decimals, nothing for the others
+        let with_scaled = |rows: Vec<(H160, U256, Document)>| -> Vec<Document> {
+            rows.into_iter().map(|(token, value, mut d)| {
+                if let Some(s) = decimals.get(&token).and_then(|d| scaled(&value.to_string(), *d)) {
+                    d.insert("value_scaled", s);
+                }
+                d
+            }).collect()
+        };
+
+        let num_of_transfers = transfers.len();
+        storage.insert(Table::TokenTransfers, with_scaled(transfers)).await?;
+        storage.insert(Table::TokenApprovals, with_scaled(approvals)).await?;
+        storage.insert(Table::TokenBalances, snapshots).await?;
+        Ok(num_of_transfers)
+    }
+
+    // name/symbol/decimals are read once per token, missing ones are stored as
+    // null. Returns the token's decimals.
+    async fn ensure_metadata(&self, token: H160, standard: Standard, web3: &Web3<RpcTransport>, storage: &dyn Storage) -> Result<Option<u8>, Box<dyn Error + Send + Sync>> {
+        if let Some(decimals) = self.known_tokens.lock().await.get(&token) {
+            return Ok(*decimals);
+        }
+        let id = format!("{:?}", token);
+
+        let stored = storage.find(Table::TokenMetadata, Query::new().filter(Filter::eq("_id", id.as_str())).limit(1)).await?;
+        let decimals = if let Some(metadata) = stored.first() {
+            metadata.get("decimals").and_then(Bson::as_i32).and_then(|d| u8::try_from(d).ok())
+        } else {
+            let name = call_string(web3, token, NAME_SELECTOR).await;
+            let symbol = call_string(web3, token, SYMBOL_SELECTOR).await;
+            let decimals = if standard == Standard::Erc20 { call_decimals(web3, token).await } else { None };
+
+            // insert keeps whatever another task stored first
+            let metadata = doc! {
+                "_id": &id,
+                "standard": standard.as_str(),
+                "name": name.map(Bson::String).unwrap_or(Bson::Null),
+                "symbol": symbol.map(Bson::String).unwrap_or(Bson::Null),
+                "decimals": decimals.map(|d| Bson::Int32(d as i32)).unwrap_or(Bson::Null),
+            };
+            storage.insert(Table::TokenMetadata, vec![metadata]).await?;
+            decimals
+        };
+
+        self.known_tokens.lock().await.insert(token, decimals);
+        Ok(decimals)
+    }
+}
+
+fn event_name(topic0: &H256) -> &'static str {
+    if *topic0 == *TRANSFER_TOPIC {
+        "Transfer"
+    } else if *topic0 == *APPROVAL_TOPIC {
+        "Approval"
+    } else if *topic0 == *TRANSFER_SINGLE_TOPIC {
+        "TransferSingle"
+    } else {
+        "TransferBatch"
+    }
+}
+
+fn decode_token_log(log: &Log) -> Option<TokenEvent> {
+    let topic0 = *log.topics.first()?;
+    let data = log.data.0.as_slice();
+
+    if topic0 == *TRANSFER_TOPIC || topic0 == *APPROVAL_TOPIC {
+        // erc20 keeps the amount in data, erc721 indexes the token id
+        let (standard, amount) = match log.topics.len() {
+            3 => (Standard::Erc20, U256::from_big_endian(data.get(..32)?)),
+            4 => (Standard::Erc721, U256::from_big_endian(log.topics[3].as_bytes())),
+            _ => return None,
+        };
+        let a = topic_address(&log.topics[1]);
+        let b = topic_address(&log.topics[2]);
+
+        if topic0 == *TRANSFER_TOPIC {
+            let (token_id, value) = match standard {
+                Standard::Erc721 => (Some(amount), U256::one()),
+                _ => (None, amount),
+            };
+            Some(TokenEvent::Transfers(vec![TokenTransfer { standard, operator: None, from: a, to: b, token_id, value }]))
+        } else {
+            Some(TokenEvent::Approval(TokenApproval { standard, owner: a, spender: b, value: amount }))
+        }
+    } else if topic0 == *TRANSFER_SINGLE_TOPIC || topic0 == *TRANSFER_BATCH_TOPIC {
+        if log.topics.len() != 4 {
+            return None;
+        }
+        let operator = Some(topic_address(&log.topics[1]));
+        let from = topic_address(&log.topics[2]);
+        let to = topic_address(&log.topics[3]);
+
+        let (ids, values) = if topic0 == *TRANSFER_SINGLE_TOPIC {
+            let tokens = decode(&[ParamType::Uint(256), ParamType::Uint(256)], data).ok()?;
+            (vec![tokens[0].clone()], vec![tokens[1].clone()])
+        } else {
+            let array = ParamType::Array(Box::new(ParamType::Uint(256)));
+            let mut tokens = decode(&[array.clone(), array], data).ok()?.into_iter();
+            (tokens.next()?.into_array()?, tokens.next()?.into_array()?)
+        };
+
+        let transfers = ids.into_iter().zip(values).filter_map(|(id, value)| {
+            Some(TokenTransfer {
+                standard: Standard::Erc1155,
+                operator,
+                from,
+                to,
+                token_id: Some(id.into_uint()?),
+                value: value.into_uint()?,
+            })
+        }).collect();
+        Some(TokenEvent::Transfers(transfers))
+    } else {
+        None
+    }
+}
+
+// erc721 balances count tokens per collection, erc1155 balances are per id
+fn balance_id(t: &TokenTransfer) -> Option<U256> {
+    match t.standard {
+        Standard::Erc1155 => t.token_id,
+        _ => None,
+    }
+}
+
+fn signed_delta(credited: U256, debited: U256) -> String {
+    if credited >= debited {
+        (credited - debited).to_string()
+    } else {
+        format!("-{}", debited - credited)
+    }
+}
+
+fn topic_address(topic: &H256) -> H160 {
+    H160::from_slice(&topic.as_bytes()[12..])
+}
+
+fn position_key(log: &Log) -> String {
+    format!("{:?}:{}", log.transaction_hash.unwrap_or_default(), log.log_index.unwrap_or_default())
+}
+
+fn log_position(log: &Log) -> Document {
+    doc! {
+        "block_number": log.block_number.unwrap_or_default().as_u64() as i64,
+        "block_hash": format!("{:?}", log.block_hash.unwrap_or_default()),
+        "tx_hash": format!("{:?}", log.transaction_hash.unwrap_or_default()),
+        "log_index": log.log_index.unwrap_or_default().as_u64() as i64,
+    }
+}
+
+async fn eth_call(web3: &Web3<RpcTransport>, to: H160, selector: [u8; 4]) -> Option<Vec<u8>> {
+    let call = CallRequest {
+        to: Some(to),
+        data: Some(Bytes(selector.to_vec())),
+        ..Default::default()
+    };
+    web3.eth().call(call, None).await.ok().map(|b| b.0)
+}
+
+// some older tokens return bytes32 instead of string
+async fn call_string(web3: &Web3<RpcTransport>, token: H160, selector: [u8; 4]) -> Option<String> {
+    let out = eth_call(web3, token, selector).await?;
+    if let Ok(mut tokens) = decode(&[ParamType::String], &out) {
+        if let Some(Token::String(s)) = tokens.pop() {
+            return Some(s);
+        }
+    }
+    if out.len() == 32 {
+        let s = String::from_utf8(out.into_iter().take_while(|b| *b != 0).collect()).ok()?;
+        return Some(s);
+    }
+    None
+}
+
+async fn call_decimals(web3: &Web3<RpcTransport>, token: H160) -> Option<u8> {
+    let out = eth_call(web3, token, DECIMALS_SELECTOR).await?;
+    let decimals = U256::from_big_endian(out.get(..32)?);
+    if decimals > U256::from(u8::MAX) {
+        return None;
+    }
+    Some(decimals.as_u32() as u8)
+}
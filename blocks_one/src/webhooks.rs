@@ -0,0 +1,33 @@
+use std::error::Error;
+use std::net::SocketAddr;
+
+use blocks_common::webhooks;
+
+const STUB_ADDR: &str = "127.0.0.1:9100";
+
+// `blocks_one webhooks stub [--addr 127.0.0.1:9100] [--secret S] [--fail N]`
+// runs a local receiver to point a subscription at, see webhooks::serve_stub.
+// Needs no storage or node.
+pub async fn command(args: &[String]) -> Option<Result<(), Box<dyn Error>>> {
+    if args.get(1).map(String::as_str) != Some("webhooks") {
+        return None;
+    }
+    Some(stub(&args[2..]).await)
+}
+
+async fn stub(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let usage = "usage: blocks_one webhooks stub [--addr 127.0.0.1:9100] [--secret S] [--fail N]";
+    if args.first().map(String::as_str) != Some("stub") {
+        return Err(usage.into());
+    }
+    let (mut addr, mut secret, mut fail) = (STUB_ADDR.parse::<SocketAddr>()?, None, 0);
+    for pair in args[1..].chunks(2) {
+        match pair {
+            [flag, value] if flag == "--addr" => addr = value.parse()?,
+            [flag, value] if flag == "--secret" => secret = Some(value.clone()),
+            [flag, value] if flag == "--fail" => fail = value.parse()?,
+            _ => return Err(usage.into()),
+        }
+    }
+    webhooks::serve_stub(addr, secret, fail).await
+}
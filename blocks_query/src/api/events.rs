@@ -0,0 +1,83 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::models::{parse_range, ActivityQuery, AggregateQuery, AggregateResult, DecodedEventQuery, EventQuery, HistogramQuery, Interval};
+use crate::repository::Repository;
+
+#[get("/events")]
+async fn events(repo: web::Data<Repository>, query: web::Query<EventQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    match repo.events(from, to, query.limit, query.skip).await {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+// e.g. /events/decoded?event=Transfer&filter=value >= 10^18 and to = 0x..
+#[get("/events/decoded")]
+async fn decoded_events(repo: web::Data<Repository>, query: web::Query<DecodedEventQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    let filters = match query.filters() {
+        Ok(filters) => filters,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    match repo.decoded_events(filters, from, to, query.limit, query.skip).await {
+        Ok(decoded) => HttpResponse::Ok().json(decoded),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/events/histogram")]
+async fn events_histogram(repo: web::Data<Repository>, query: web::Query<HistogramQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    match repo.events_histogram(from, to, query.interval.unwrap_or(Interval::Day)).await {
+        Ok(buckets) => HttpResponse::Ok().json(buckets),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+// e.g. /events/aggregate?event=Transfer&group_by=day&metric=sum&field=args.value
+#[get("/events/aggregate")]
+async fn events_aggregate(repo: web::Data<Repository>, query: web::Query<AggregateQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    let aggregation = match query.to_aggregation() {
+        Ok(aggregation) => aggregation,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    match repo.aggregate_events(aggregation, from, to).await {
+        Ok(groups) => HttpResponse::Ok().json(AggregateResult { group_by: query.group_names(), metric: query.metric_name().to_owned(), groups }),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/addresses/{address}/activity")]
+async fn address_activity(repo: web::Data<Repository>, path: web::Path<String>, query: web::Query<ActivityQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    match repo.address_activity(&path, &query, from, to).await {
+        Ok(Some(activity)) => HttpResponse::Ok().json(activity),
+        Ok(None) => HttpResponse::NotFound().json("addresses rollup with this granularity is not enabled, see ROLLUPS"),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(events)
+        .service(decoded_events)
+        .service(events_histogram)
+        .service(events_aggregate)
+        .service(address_activity);
+}
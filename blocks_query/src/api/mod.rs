@@ -0,0 +1,53 @@
+mod events;
+mod projections;
+mod status;
+mod tokens;
+mod webhooks;
+
+use actix_web::{get, web, HttpResponse, Responder};
+use blocks_common::metrics;
+
+use crate::models::{parse_range, FailedTxnQuery, HistogramQuery, Interval};
+use crate::repository::Repository;
+
+pub use webhooks::WebhookAdmin;
+
+#[get("/txns/failed")]
+async fn failed_txns(repo: web::Data<Repository>, query: web::Query<FailedTxnQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    match repo.failed_txns(&query, from, to).await {
+        Ok(txns) => HttpResponse::Ok().json(txns),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/txns/failed/histogram")]
+async fn failed_txns_histogram(repo: web::Data<Repository>, query: web::Query<HistogramQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    match repo.failed_txns_histogram(from, to, query.interval.unwrap_or(Interval::Day)).await {
+        Ok(buckets) => HttpResponse::Ok().json(buckets),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/metrics")]
+async fn prometheus() -> impl Responder {
+    HttpResponse::Ok().content_type(metrics::CONTENT_TYPE).body(metrics::render())
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(failed_txns)
+        .service(prometheus)
+        .service(failed_txns_histogram)
+        .configure(events::config)
+        .configure(projections::config)
+        .configure(status::config)
+        .configure(tokens::config)
+        .configure(webhooks::config);
+}
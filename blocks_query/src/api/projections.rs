@@ -0,0 +1,37 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::models::{DerivedEventQuery, PageQuery};
+use crate::repository::Repository;
+
+#[get("/projections/{projection}/{entity}")]
+async fn projection_entities(repo: web::Data<Repository>, path: web::Path<(String, String)>, query: web::Query<PageQuery>) -> impl Responder {
+    let (projection, entity) = path.into_inner();
+    match repo.projection_entities(&projection, &entity, query.limit, query.skip).await {
+        Ok(entities) => HttpResponse::Ok().json(entities),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/projections/{projection}/{entity}/{id}")]
+async fn projection_entity(repo: web::Data<Repository>, path: web::Path<(String, String, String)>) -> impl Responder {
+    let (projection, entity, id) = path.into_inner();
+    match repo.projection_entity(&projection, &entity, &id).await {
+        Ok(Some(entity)) => HttpResponse::Ok().json(entity),
+        Ok(None) => HttpResponse::NotFound().json("unknown entity"),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/events/derived")]
+async fn derived_events(repo: web::Data<Repository>, query: web::Query<DerivedEventQuery>) -> impl Responder {
+    match repo.derived_events(&query).await {
+        Ok(events) => HttpResponse::Ok().json(events),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(projection_entities)
+        .service(projection_entity)
+        .service(derived_events);
+}
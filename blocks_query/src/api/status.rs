@@ -0,0 +1,104 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use blocks_common::status::Health;
+
+use crate::models::StatusQuery;
+use crate::repository::Repository;
+
+// 200 while the server answers, the body lists the dependency checks
+#[get("/healthz")]
+async fn healthz(repo: web::Data<Repository>) -> impl Responder {
+    HttpResponse::Ok().json(Health::new(repo.checks().await))
+}
+
+#[get("/readyz")]
+async fn readyz(repo: web::Data<Repository>) -> impl Responder {
+    let health = Health::new(repo.checks().await);
+    if health.ok {
+        HttpResponse::Ok().json(health)
+    } else {
+        HttpResponse::ServiceUnavailable().json(health)
+    }
+}
+
+// what the indexers last published, `stale` rows mean the data lags the chain
+#[get("/status")]
+async fn status(repo: web::Data<Repository>, query: web::Query<StatusQuery>) -> impl Responder {
+    match repo.indexer_status(query.indexer.as_deref()).await {
+        Ok(rows) => HttpResponse::Ok().json(rows),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(healthz)
+        .service(readyz)
+        .service(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use blocks_common::progress::Mode;
+    use blocks_common::status::IndexerStatus;
+    use blocks_storage::sqlite::SqliteStorage;
+    use blocks_storage::{Storage, Table};
+    use mongodb::bson::DateTime;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn row(indexer: &str, contract: &str, mode: Mode, age_ms: i64) -> IndexerStatus {
+        IndexerStatus {
+            indexer: indexer.to_owned(),
+            contract: contract.to_owned(),
+            name: "token".to_owned(),
+            mode,
+            indexed_block: Some(90),
+            chain_head: Some(100),
+            lag: Some(10),
+            failed_ranges: vec![(5, 7)],
+            updated_at: DateTime::from_millis(DateTime::now().timestamp_millis() - age_ms),
+        }
+    }
+
+    async fn repo() -> web::Data<Repository> {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        let rows = [
+            row("blocks_one", "0xaa", Mode::Tail, 0),
+            row("blocks_one", "0xbb", Mode::Tail, 120_000),
+            row("blocks", "0xaa", Mode::Backfill, 0),
+        ];
+        storage.upsert(Table::IndexerStatus, rows.iter().map(IndexerStatus::to_document).collect()).await.unwrap();
+        web::Data::new(Repository::new(Arc::new(storage), None))
+    }
+
+    #[actix_web::test]
+    async fn marks_silent_and_backfilling_indexers_stale() {
+        let app = test::init_service(App::new().app_data(repo().await).configure(config)).await;
+        let rows: Vec<Value> = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/status").to_request()).await;
+        let summary: Vec<(&str, &str, bool)> = rows.iter()
+            .map(|r| (r["indexer"].as_str().unwrap(), r["contract"].as_str().unwrap(), r["stale"].as_bool().unwrap()))
+            .collect();
+        assert_eq!(summary, vec![("blocks", "0xaa", true), ("blocks_one", "0xaa", false), ("blocks_one", "0xbb", true)]);
+        assert_eq!(rows[1]["failed_ranges"], json!([[5, 7]]));
+        assert_eq!(rows[1]["lag"], json!(10));
+
+        let rows: Vec<Value> = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/status?indexer=blocks").to_request()).await;
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn reports_the_storage_check() {
+        let app = test::init_service(App::new().app_data(repo().await).configure(config)).await;
+        for uri in ["/healthz", "/readyz"] {
+            let res = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let health: Value = test::read_body_json(res).await;
+            assert_eq!(health, json!({ "ok": true, "checks": [{ "name": "storage", "ok": true }] }));
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::models::{parse_range, parse_value_range, BalanceQuery, HistogramQuery, Interval, PageQuery, TransferQuery, TransferSort};
+use crate::repository::{Repository, TransferFilter};
+
+#[get("/tokens/{token}")]
+async fn token_metadata(repo: web::Data<Repository>, path: web::Path<String>) -> impl Responder {
+    match repo.token_metadata(&path).await {
+        Ok(Some(metadata)) => HttpResponse::Ok().json(metadata),
+        Ok(None) => HttpResponse::NotFound().json("unknown token"),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/tokens/{token}/holders")]
+async fn holders(repo: web::Data<Repository>, path: web::Path<String>, query: web::Query<PageQuery>) -> impl Responder {
+    match repo.token_holders(&path, query.limit, query.skip).await {
+        Ok(holders) => HttpResponse::Ok().json(holders),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/tokens/{token}/balances/{holder}")]
+async fn balance(repo: web::Data<Repository>, path: web::Path<(String, String)>, query: web::Query<BalanceQuery>) -> impl Responder {
+    let (token, holder) = path.into_inner();
+    match repo.token_balance(&token, &holder, query.block).await {
+        Ok(balances) => HttpResponse::Ok().json(balances),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/tokens/{token}/transfers")]
+async fn token_transfers(repo: web::Data<Repository>, path: web::Path<String>, query: web::Query<TransferQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    let (min_value, max_value) = match parse_value_range(query.min_value.as_deref(), query.max_value.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    let filter = TransferFilter { token: Some(&path), address: query.address.as_deref(), from, to, min_value, max_value };
+    match repo.token_transfers(filter, query.sort.unwrap_or_default(), query.limit, query.skip).await {
+        Ok(transfers) => HttpResponse::Ok().json(transfers),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/tokens/{token}/transfers/histogram")]
+async fn token_transfers_histogram(repo: web::Data<Repository>, path: web::Path<String>, query: web::Query<HistogramQuery>) -> impl Responder {
+    let (from, to) = match parse_range(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(err) => return HttpResponse::BadRequest().json(err),
+    };
+    let filter = TransferFilter { token: Some(&path), address: None, from, to, min_value: None, max_value: None };
+    match repo.token_transfers_histogram(filter, query.interval.unwrap_or(Interval::Day)).await {
+        Ok(buckets) => HttpResponse::Ok().json(buckets),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/accounts/{address}/transfers")]
+async fn account_transfers(repo: web::Data<Repository>, path: web::Path<String>, query: web::Query<PageQuery>) -> impl Responder {
+    let filter = TransferFilter { token: None, address: Some(&path), from: None, to: None, min_value: None, max_value: None };
+    match repo.token_transfers(filter, TransferSort::Block, query.limit, query.skip).await {
+        Ok(transfers) => HttpResponse::Ok().json(transfers),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(token_metadata)
+        .service(holders)
+        .service(balance)
+        .service(token_transfers)
+        .service(token_transfers_histogram)
+        .service(account_transfers);
+}
@@ -0,0 +1,184 @@
+use std::env;
+
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::http::header;
+use actix_web::{delete, get, post, web, FromRequest, HttpRequest, HttpResponse, Responder};
+use blocks_common::webhooks::{NewSubscription, Targets};
+use futures::future::{ready, Ready};
+
+use crate::models::{CreatedWebhook, DeliveryQuery, Webhook};
+use crate::repository::Repository;
+
+// Subscriptions make the indexer send requests, so the /webhooks endpoints
+// want `Authorization: Bearer <WEBHOOK_ADMIN_TOKEN>` and answer 403 while no
+// token is set. Urls have to pass the Targets check (WEBHOOK_ALLOWED_HOSTS).
+pub struct WebhookAdmin {
+    token: Option<String>,
+    targets: Targets,
+}
+
+impl WebhookAdmin {
+    pub fn new(token: Option<String>, targets: Targets) -> Self {
+        WebhookAdmin { token, targets }
+    }
+
+    pub fn from_env() -> Self {
+        WebhookAdmin::new(env::var("WEBHOOK_ADMIN_TOKEN").ok().filter(|t| !t.is_empty()), Targets::from_env())
+    }
+}
+
+// taken by every /webhooks handler, fails the request without the token
+struct Admin;
+
+// constant time for tokens of the same length
+fn same_token(expected: &str, given: &str) -> bool {
+    expected.len() == given.len() && expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+impl FromRequest for Admin {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = req.app_data::<web::Data<WebhookAdmin>>().and_then(|admin| admin.token.clone());
+        let given = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).and_then(|v| v.strip_prefix("Bearer "));
+        ready(match (token, given) {
+            (None, _) => {
+                let message = "webhooks are disabled, set WEBHOOK_ADMIN_TOKEN";
+                Err(InternalError::from_response(message, HttpResponse::Forbidden().json(message)).into())
+            }
+            (Some(token), Some(given)) if same_token(&token, given) => Ok(Admin),
+            _ => {
+                let message = "missing or wrong bearer token";
+                let response = HttpResponse::Unauthorized().insert_header((header::WWW_AUTHENTICATE, "Bearer")).json(message);
+                Err(InternalError::from_response(message, response).into())
+            }
+        })
+    }
+}
+
+#[post("/webhooks")]
+async fn create_webhook(_: Admin, admin: web::Data<WebhookAdmin>, repo: web::Data<Repository>, body: web::Json<NewSubscription>) -> impl Responder {
+    if let Err(err) = body.validate() {
+        return HttpResponse::BadRequest().json(err);
+    }
+    if let Err(err) = admin.targets.check(&body.url).await {
+        return HttpResponse::BadRequest().json(err);
+    }
+    match repo.create_webhook(body.into_inner()).await {
+        Ok(subscription) => {
+            let secret = subscription.secret.clone();
+            HttpResponse::Created().json(CreatedWebhook { webhook: subscription.into(), secret })
+        }
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/webhooks")]
+async fn webhooks(_: Admin, repo: web::Data<Repository>) -> impl Responder {
+    match repo.webhooks().await {
+        Ok(subscriptions) => HttpResponse::Ok().json(subscriptions.into_iter().map(Webhook::from).collect::<Vec<_>>()),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/webhooks/{id}")]
+async fn webhook(_: Admin, repo: web::Data<Repository>, path: web::Path<String>) -> impl Responder {
+    match repo.webhook(&path).await {
+        Ok(Some(subscription)) => HttpResponse::Ok().json(Webhook::from(subscription)),
+        Ok(None) => HttpResponse::NotFound().json("unknown webhook"),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[delete("/webhooks/{id}")]
+async fn delete_webhook(_: Admin, repo: web::Data<Repository>, path: web::Path<String>) -> impl Responder {
+    match repo.delete_webhook(&path).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json("unknown webhook"),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+#[get("/webhooks/{id}/deliveries")]
+async fn webhook_deliveries(_: Admin, repo: web::Data<Repository>, path: web::Path<String>, query: web::Query<DeliveryQuery>) -> impl Responder {
+    match repo.webhook_deliveries(&path, &query).await {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(err) => HttpResponse::InternalServerError().json(err.to_string()),
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_webhook)
+        .service(webhooks)
+        .service(webhook)
+        .service(delete_webhook)
+        .service(webhook_deliveries);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use blocks_storage::sqlite::SqliteStorage;
+    use blocks_storage::Storage;
+
+    use super::*;
+
+    async fn repo() -> web::Data<Repository> {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        web::Data::new(Repository::new(Arc::new(storage), None))
+    }
+
+    fn get(auth: Option<&str>) -> test::TestRequest {
+        let req = test::TestRequest::get().uri("/webhooks");
+        match auth {
+            Some(auth) => req.insert_header((header::AUTHORIZATION, auth)),
+            None => req,
+        }
+    }
+
+    #[actix_web::test]
+    async fn needs_the_admin_token() {
+        let disabled = web::Data::new(WebhookAdmin::new(None, Targets::default()));
+        let app = test::init_service(App::new().app_data(repo().await).app_data(disabled).configure(config)).await;
+        assert_eq!(test::call_service(&app, get(Some("Bearer ")).to_request()).await.status(), StatusCode::FORBIDDEN);
+
+        let admin = web::Data::new(WebhookAdmin::new(Some("t0ken".to_owned()), Targets::default()));
+        let app = test::init_service(App::new().app_data(repo().await).app_data(admin).configure(config)).await;
+        for auth in [None, Some("t0ken"), Some("Bearer t0ke"), Some("Bearer t0ken2"), Some("Basic t0ken")] {
+            let res = test::call_service(&app, get(auth).to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{:?}", auth);
+            assert_eq!(res.headers().get(header::WWW_AUTHENTICATE).unwrap(), "Bearer");
+        }
+        assert_eq!(test::call_service(&app, get(Some("Bearer t0ken")).to_request()).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn refuses_private_urls_and_deletes_once() {
+        let admin = web::Data::new(WebhookAdmin::new(Some("t0ken".to_owned()), Targets::new(["127.0.0.1"])));
+        let app = test::init_service(App::new().app_data(repo().await).app_data(admin).configure(config)).await;
+        let create = |url: &str| test::TestRequest::post()
+            .uri("/webhooks")
+            .insert_header((header::AUTHORIZATION, "Bearer t0ken"))
+            .set_json(serde_json::json!({ "url": url, "from_block": 0 }))
+            .to_request();
+
+        for url in ["http://169.254.169.254/latest/meta-data", "http://10.0.0.1/hook", "http://[::1]:9100/hook"] {
+            assert_eq!(test::call_service(&app, create(url)).await.status(), StatusCode::BAD_REQUEST, "{}", url);
+        }
+        let created: serde_json::Value = test::call_and_read_body_json(&app, create("http://127.0.0.1:9100/hook")).await;
+        let id = created["id"].as_str().unwrap();
+
+        let delete = || test::TestRequest::delete()
+            .uri(&format!("/webhooks/{}", id))
+            .insert_header((header::AUTHORIZATION, "Bearer t0ken"))
+            .to_request();
+        assert_eq!(test::call_service(&app, delete()).await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(test::call_service(&app, delete()).await.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -2,10 +2,18 @@ mod api;
 mod models;
 mod repository;
 
-use actix_web::{get, App, HttpResponse, HttpServer, Responder};
-use mongodb::bson::oid::ObjectId;
+use std::time::Instant;
+
+use actix_web::dev::Service;
+use actix_web::{get, middleware, web, App, HttpResponse, HttpServer, Responder};
+use blocks_common::logging;
+use blocks_common::rollups::Rollups;
+use blocks_common::metrics::HTTP_DURATION;
+use futures::FutureExt;
 use serde::{Serialize, Deserialize};
 
+use repository::Repository;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Blok {
     
@@ -18,9 +26,40 @@ async fn home() -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    println!("Listening on localhost:8080");
-    HttpServer::new(|| App::new().service(home))
-            .bind(("localhost", 8080))?
-            .run()
-            .await
+    // LOG_LEVEL, LOG_FORMAT, LOG_SINKS, LOG_DIR, ... see blocks_common::logging
+    let _log_guard = logging::init("blocks_query").expect("Failed to set up logging");
+    // STORAGE_BACKEND picks mongo (MONGODB_URI) or postgres (POSTGRES_URL)
+    let storage = blocks_storage::from_env().await.expect("Failed to open storage");
+    // ROLLUPS, as given to blocks_one, lets aggregations read the rollup tables
+    let rollups = Rollups::from_env().expect("Invalid ROLLUPS");
+    let repo = web::Data::new(Repository::new(storage, rollups));
+    // WEBHOOK_ADMIN_TOKEN opens /webhooks, WEBHOOK_ALLOWED_HOSTS lets them point at private hosts
+    let webhook_admin = web::Data::new(api::WebhookAdmin::from_env());
+
+    tracing::info!("Listening on localhost:8080");
+    HttpServer::new(move || {
+        App::new()
+            .wrap(middleware::Logger::default())
+            // latency per route pattern, so path params don't blow up the label set
+            .wrap_fn(|req, srv| {
+                let started = Instant::now();
+                let route = req.match_pattern().unwrap_or("unmatched".to_owned());
+                let method = req.method().to_string();
+                srv.call(req).map(move |res| {
+                    let status = match &res {
+                        Ok(res) => res.status().as_u16().to_string(),
+                        Err(err) => err.as_response_error().status_code().as_u16().to_string(),
+                    };
+                    HTTP_DURATION.with_label_values(&[&route, &method, &status]).observe(started.elapsed().as_secs_f64());
+                    res
+                })
+            })
+            .app_data(repo.clone())
+            .app_data(webhook_admin.clone())
+            .service(home)
+            .configure(api::config)
+    })
+    .bind(("localhost", 8080))?
+    .run()
+    .await
 }
@@ -0,0 +1,158 @@
+use blocks_common::rollups::Granularity;
+use blocks_storage::{Aggregation, Group, GroupBy, Interval, Metric};
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::{event_filters, serialize_time};
+
+// decoded_events columns that can be grouped, counted or filtered on besides args.*
+const EVENT_COLUMNS: [&str; 6] = ["contract", "event", "signature", "abi_version", "address", "tx_hash"];
+
+#[derive(Debug, Deserialize)]
+pub struct AggregateQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // registered contract name
+    pub contract: Option<String>,
+    pub event: Option<String>,
+    pub address: Option<String>,
+    // see blocks_common::expr
+    pub filter: Option<String>,
+    // comma separated: hour, day, week, a column or args.<name>
+    pub group_by: Option<String>,
+    // count (default), sum or distinct, the latter two of `field`
+    pub metric: Option<String>,
+    pub field: Option<String>,
+    // `value` for the largest groups first, keys ascending otherwise
+    pub order: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AggregateResult {
+    pub group_by: Vec<String>,
+    pub metric: String,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityQuery {
+    // block, hour, day or total (default)
+    pub granularity: Option<Granularity>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
+
+// row of the addresses rollup
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Activity {
+    // block number, hour/day label or empty for the total
+    pub bucket: String,
+    #[serde(default, serialize_with = "serialize_time")]
+    pub bucket_time: Option<DateTime>,
+    pub count: i64,
+}
+
+fn event_path(path: &str) -> Result<String, String> {
+    let valid = match path.strip_prefix("args.") {
+        Some(arg) => !arg.is_empty() && arg.split('.').all(|k| !k.is_empty() && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')),
+        None => EVENT_COLUMNS.contains(&path),
+    };
+    if valid {
+        Ok(path.to_owned())
+    } else {
+        Err(format!("unknown field `{}`, expected one of {} or args.<name>", path, EVENT_COLUMNS.join(", ")))
+    }
+}
+
+impl AggregateQuery {
+    pub fn group_names(&self) -> Vec<String> {
+        self.group_by.as_deref().unwrap_or_default()
+            .split(',')
+            .map(|g| g.trim().to_owned())
+            .filter(|g| !g.is_empty())
+            .collect()
+    }
+
+    pub fn metric_name(&self) -> &str {
+        self.metric.as_deref().unwrap_or("count")
+    }
+
+    // everything but the time range, which the repository adds
+    pub fn to_aggregation(&self) -> Result<Aggregation, String> {
+        let group_by = self.group_names().iter().map(|g| Ok(match g.as_str() {
+            "hour" => GroupBy::Time(Interval::Hour),
+            "day" => GroupBy::Time(Interval::Day),
+            "week" => GroupBy::Time(Interval::Week),
+            path => GroupBy::Path(event_path(path)?),
+        })).collect::<Result<Vec<_>, String>>()?;
+
+        let field = || self.field.as_deref().ok_or(format!("metric {} needs a field", self.metric_name())).and_then(event_path);
+        let metric = match self.metric_name() {
+            "count" => Metric::Count,
+            "sum" => Metric::Sum(field()?),
+            "distinct" => Metric::Distinct(field()?),
+            other => return Err(format!("unknown metric `{}`, expected count, sum or distinct", other)),
+        };
+        let by_value = match self.order.as_deref() {
+            None | Some("key") => false,
+            Some("value") => true,
+            Some(other) => return Err(format!("unknown order `{}`, expected key or value", other)),
+        };
+
+        let filters = event_filters(self.contract.as_deref(), self.event.as_deref(), self.address.as_deref(), self.filter.as_deref())?;
+        Ok(Aggregation { filters, group_by, metric, by_value, limit: self.limit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(group_by: &str, metric: Option<&str>, field: Option<&str>) -> AggregateQuery {
+        AggregateQuery {
+            from: None,
+            to: None,
+            contract: None,
+            event: Some("Transfer".to_owned()),
+            address: None,
+            filter: None,
+            group_by: Some(group_by.to_owned()),
+            metric: metric.map(str::to_owned),
+            field: field.map(str::to_owned),
+            order: None,
+            limit: Some(10),
+        }
+    }
+
+    #[test]
+    fn parses_groups_and_metrics() {
+        let aggregation = query("day, args.from,", Some("sum"), Some("args.value")).to_aggregation().unwrap();
+        assert!(matches!(aggregation.group_by.as_slice(), [GroupBy::Time(Interval::Day), GroupBy::Path(p)] if p == "args.from"));
+        assert!(matches!(aggregation.metric, Metric::Sum(ref p) if p == "args.value"));
+        assert_eq!(aggregation.filters.len(), 1);
+        assert!(!aggregation.by_value);
+        assert_eq!(aggregation.limit, Some(10));
+
+        let aggregation = query("", None, None).to_aggregation().unwrap();
+        assert!(aggregation.group_by.is_empty());
+        assert!(matches!(aggregation.metric, Metric::Count));
+    }
+
+    #[test]
+    fn rejects_unknown_fields_and_metrics() {
+        assert!(query("args.", None, None).to_aggregation().unwrap_err().starts_with("unknown field `args.`"));
+        assert!(query("args.a-b", None, None).to_aggregation().is_err());
+        assert!(query("block_hash", None, None).to_aggregation().is_err());
+        assert_eq!(query("week", Some("sum"), None).to_aggregation().unwrap_err(), "metric sum needs a field");
+        assert!(query("week", Some("avg"), Some("args.value")).to_aggregation().unwrap_err().starts_with("unknown metric `avg`"));
+
+        let mut by_value = query("tx_hash", Some("distinct"), Some("args.to"));
+        by_value.order = Some("size".to_owned());
+        assert!(by_value.to_aggregation().unwrap_err().starts_with("unknown order `size`"));
+        by_value.order = Some("value".to_owned());
+        assert!(by_value.to_aggregation().unwrap().by_value);
+    }
+}
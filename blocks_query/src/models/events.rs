@@ -0,0 +1,93 @@
+use blocks_common::expr;
+use blocks_storage::Filter;
+use mongodb::bson::{DateTime, Document};
+use serde::{Deserialize, Serialize};
+
+use super::serialize_time;
+
+// events_table document as written by blocks_one
+#[derive(Debug, Deserialize)]
+pub struct StoredEventBlock {
+    pub block_number: i32,
+    pub block_hash: String,
+    #[serde(default)]
+    pub block_time: Option<DateTime>,
+    // hex of the zlib compressed, `::` joined event strings
+    pub events: String,
+    pub num_of_events: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventBlock {
+    pub block_number: i32,
+    pub block_hash: String,
+    #[serde(serialize_with = "serialize_time")]
+    pub block_time: Option<DateTime>,
+    pub events: Vec<String>,
+    pub num_of_events: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
+
+// decoded_events row
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DecodedEvent {
+    pub block_number: i64,
+    pub block_hash: String,
+    #[serde(default, serialize_with = "serialize_time")]
+    pub block_time: Option<DateTime>,
+    #[serde(default)]
+    pub tx_hash: Option<String>,
+    pub log_index: i64,
+    pub address: String,
+    pub contract: String,
+    pub abi_version: String,
+    pub event: String,
+    pub signature: String,
+    pub args: Document,
+}
+
+// e.g. /events/decoded?event=Transfer&filter=value > 10^18 and to = 0x..
+#[derive(Debug, Deserialize)]
+pub struct DecodedEventQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // registered contract name
+    pub contract: Option<String>,
+    pub event: Option<String>,
+    pub address: Option<String>,
+    // see blocks_common::expr
+    pub filter: Option<String>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
+
+impl DecodedEventQuery {
+    pub fn filters(&self) -> Result<Vec<Filter>, String> {
+        event_filters(self.contract.as_deref(), self.event.as_deref(), self.address.as_deref(), self.filter.as_deref())
+    }
+}
+
+// decoded_events conditions shared by the event endpoints, the time range aside
+pub fn event_filters(contract: Option<&str>, event: Option<&str>, address: Option<&str>, filter: Option<&str>) -> Result<Vec<Filter>, String> {
+    let mut filters = Vec::new();
+    if let Some(contract) = contract {
+        filters.push(Filter::eq("contract", contract));
+    }
+    if let Some(event) = event {
+        filters.push(Filter::eq("event", event));
+    }
+    if let Some(address) = address {
+        filters.push(Filter::eq("address", address.to_lowercase()));
+    }
+    if let Some(filter) = filter {
+        filters.push(expr::parse(filter).map_err(|err| format!("filter: {}", err))?.to_filter());
+    }
+    Ok(filters)
+}
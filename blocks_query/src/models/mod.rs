@@ -0,0 +1,69 @@
+mod analytics;
+mod events;
+mod projections;
+mod status;
+mod time;
+mod tokens;
+mod values;
+mod webhooks;
+
+use mongodb::bson::{DateTime, Document};
+use serde::{Deserialize, Serialize};
+
+pub use analytics::*;
+pub use events::*;
+pub use projections::*;
+pub use status::*;
+pub use time::*;
+pub use tokens::*;
+pub use values::*;
+pub use webhooks::*;
+
+// documents of the `blocks` crawler's txns_table
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Txn {
+    #[serde(deserialize_with = "deserialize_block_num")]
+    pub block_num: i64,
+    pub block_hash: String,
+    #[serde(default, serialize_with = "serialize_time")]
+    pub block_time: Option<DateTime>,
+    pub txn_hash: String,
+    #[serde(default)]
+    pub status: Option<i32>,
+    #[serde(default)]
+    pub selector: Option<String>,
+    #[serde(default)]
+    pub function: Option<String>,
+    #[serde(default)]
+    pub args: Option<Document>,
+    #[serde(default)]
+    pub revert: Option<Revert>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Revert {
+    pub kind: String,
+    pub reason: String,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub panic_code: Option<i64>,
+    #[serde(default)]
+    pub args: Option<Document>,
+    #[serde(default)]
+    pub data: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FailedTxnQuery {
+    // case-insensitive substring of the decoded reason
+    pub reason: Option<String>,
+    // error, panic, custom, unknown or not_reproduced
+    pub kind: Option<String>,
+    // custom error name
+    pub error: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
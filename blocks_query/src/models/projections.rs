@@ -0,0 +1,38 @@
+use mongodb::bson::{DateTime, Document};
+use serde::{Deserialize, Serialize};
+
+use super::serialize_time;
+
+// projection_entities row, the latest version of an entity
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectionEntity {
+    pub entity: String,
+    pub id: String,
+    // block of the last change
+    pub block_number: i64,
+    pub data: Document,
+}
+
+// derived_events row, emitted by a projection while handling a decoded event
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DerivedEvent {
+    pub projection: String,
+    pub block_number: i64,
+    // of the decoded event it came from
+    pub log_index: i64,
+    pub seq: i64,
+    #[serde(default, serialize_with = "serialize_time")]
+    pub block_time: Option<DateTime>,
+    #[serde(default)]
+    pub tx_hash: Option<String>,
+    pub name: String,
+    pub data: Document,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DerivedEventQuery {
+    pub projection: String,
+    pub name: Option<String>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
@@ -0,0 +1,29 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::serialize_time;
+
+// indexer_status document as published by the indexers
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexerStatus {
+    pub indexer: String,
+    pub contract: String,
+    pub name: String,
+    // backfill or tail
+    pub mode: String,
+    pub indexed_block: Option<i64>,
+    pub chain_head: Option<i64>,
+    pub lag: Option<i64>,
+    #[serde(default)]
+    pub failed_ranges: Vec<(i64, i64)>,
+    #[serde(default, serialize_with = "serialize_time")]
+    pub updated_at: Option<DateTime>,
+    // still backfilling or stopped publishing, set when read
+    #[serde(default)]
+    pub stale: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusQuery {
+    pub indexer: Option<String>,
+}
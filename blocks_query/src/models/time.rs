@@ -0,0 +1,40 @@
+use chrono::{DateTime as ChronoDateTime, NaiveDate, Utc};
+use mongodb::bson::DateTime;
+use serde::{ser, Deserialize, Serializer};
+
+pub use blocks_storage::Interval;
+
+
+#[derive(Debug, Deserialize)]
+pub struct HistogramQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub interval: Option<Interval>,
+}
+
+// accepts RFC 3339, a plain date (YYYY-MM-DD) or unix seconds
+pub fn parse_time(s: &str) -> Result<DateTime, String> {
+    if let Ok(secs) = s.parse::<i64>() {
+        return secs.checked_mul(1000).map(DateTime::from_millis).ok_or_else(|| format!("time `{}` is out of range", s));
+    }
+    if let Ok(t) = ChronoDateTime::parse_from_rfc3339(s) {
+        return Ok(DateTime::from_chrono(t.with_timezone(&Utc)));
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(DateTime::from_chrono(d.and_hms_opt(0, 0, 0).unwrap().and_utc()));
+    }
+    Err(format!("invalid time `{}`, expected RFC 3339, YYYY-MM-DD or unix seconds", s))
+}
+
+// [from, to) bounds, both optional
+pub fn parse_range(from: Option<&str>, to: Option<&str>) -> Result<(Option<DateTime>, Option<DateTime>), String> {
+    Ok((from.map(parse_time).transpose()?, to.map(parse_time).transpose()?))
+}
+
+// block times go out as RFC 3339 strings instead of extended json
+pub fn serialize_time<S: Serializer>(time: &Option<DateTime>, s: S) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(t) => s.serialize_some(&t.try_to_rfc3339_string().map_err(ser::Error::custom)?),
+        None => s.serialize_none(),
+    }
+}
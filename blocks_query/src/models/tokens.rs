@@ -0,0 +1,83 @@
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::{deserialize_decimal, deserialize_opt_decimal, serialize_time};
+
+// documents written by the blocks_one token module
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    #[serde(rename = "_id")]
+    pub token: String,
+    pub standard: String,
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub decimals: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenTransfer {
+    pub block_number: i64,
+    pub block_hash: String,
+    #[serde(default, serialize_with = "serialize_time")]
+    pub block_time: Option<DateTime>,
+    pub tx_hash: String,
+    pub log_index: i64,
+    pub token: String,
+    pub standard: String,
+    pub operator: Option<String>,
+    pub from: String,
+    pub to: String,
+    #[serde(default, deserialize_with = "deserialize_opt_decimal")]
+    pub token_id: Option<String>,
+    #[serde(deserialize_with = "deserialize_decimal")]
+    pub value: String,
+    // value in whole units, erc20 tokens with known decimals only
+    #[serde(default)]
+    pub value_scaled: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HolderBalance {
+    pub holder: String,
+    // erc1155 only
+    pub token_id: Option<String>,
+    pub balance: String,
+    // balance in whole units, erc20 tokens with known decimals only
+    pub balance_scaled: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferQuery {
+    // matches either side of the transfer
+    pub address: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // inclusive bounds of the amount in raw units
+    pub min_value: Option<String>,
+    pub max_value: Option<String>,
+    pub sort: Option<TransferSort>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
+
+// newest first by default, largest first with `value`
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferSort {
+    #[default]
+    Block,
+    Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BalanceQuery {
+    // balance after this block, latest when missing
+    pub block: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
@@ -0,0 +1,30 @@
+use blocks_common::values::{bson_to_decimal, sort_key};
+use mongodb::bson::Bson;
+use serde::{de, Deserialize, Deserializer};
+
+// Stored integers are Decimal128, int32/int64 or decimal strings depending
+// on their size and on who wrote them; the API hands them out as decimal
+// strings so clients don't lose precision either.
+pub fn deserialize_decimal<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    let value = Bson::deserialize(d)?;
+    bson_to_decimal(&value).ok_or_else(|| de::Error::custom(format!("`{}` is not an integer", value)))
+}
+
+pub fn deserialize_opt_decimal<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    match Bson::deserialize(d)? {
+        Bson::Null => Ok(None),
+        value => bson_to_decimal(&value).map(Some).ok_or_else(|| de::Error::custom(format!("`{}` is not an integer", value))),
+    }
+}
+
+// the `blocks` crawler used to write block numbers as strings
+pub fn deserialize_block_num<'de, D: Deserializer<'de>>(d: D) -> Result<i64, D::Error> {
+    let value = Bson::deserialize(d)?;
+    bson_to_decimal(&value).and_then(|n| n.parse().ok()).ok_or_else(|| de::Error::custom(format!("`{}` is not a block number", value)))
+}
+
+// [min, max] bounds of an amount in raw units, as keys of the `value_sort` column
+pub fn parse_value_range(min: Option<&str>, max: Option<&str>) -> Result<(Option<String>, Option<String>), String> {
+    let key = |s: &str| sort_key(s).ok_or_else(|| format!("invalid amount `{}`, expected an integer in raw units", s));
+    Ok((min.map(key).transpose()?, max.map(key).transpose()?))
+}
@@ -0,0 +1,71 @@
+use blocks_common::webhooks::{Condition, Subscription};
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+
+use super::serialize_time;
+
+// a subscription as the api shows it, without its secret
+#[derive(Debug, Serialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub contract: Option<String>,
+    pub event: Option<String>,
+    #[serde(rename = "where")]
+    pub conditions: Vec<Condition>,
+    pub filter: Option<String>,
+    pub from_block: i64,
+    #[serde(serialize_with = "serialize_time")]
+    pub created_at: Option<DateTime>,
+}
+
+impl From<Subscription> for Webhook {
+    fn from(s: Subscription) -> Self {
+        Webhook {
+            id: s.id,
+            url: s.url,
+            contract: s.contract,
+            event: s.event,
+            conditions: s.conditions,
+            filter: s.filter,
+            from_block: s.from_block,
+            created_at: Some(s.created_at),
+        }
+    }
+}
+
+// answer to POST /webhooks, the only one that carries the secret
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+// webhook_deliveries row, the outcome of the latest attempt
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub block_number: i64,
+    pub block_hash: String,
+    pub log_index: i64,
+    #[serde(default)]
+    pub tx_hash: Option<String>,
+    #[serde(default)]
+    pub event: Option<String>,
+    // delivered, retrying or failed
+    pub status: String,
+    pub attempts: i64,
+    #[serde(default)]
+    pub response_status: Option<i64>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default, serialize_with = "serialize_time")]
+    pub updated_at: Option<DateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryQuery {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
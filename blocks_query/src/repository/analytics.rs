@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use blocks_common::rollups::{Granularity, Rollup};
+use blocks_storage::{Aggregation, Filter, Group, Metric, Op, Query, StorageError, Table};
+use mongodb::bson::DateTime;
+
+use super::{decode, page_limit, time_filters, Repository, Result, MAX_LIMIT};
+use crate::models::{Activity, ActivityQuery};
+
+const DEFAULT_CACHE_SIZE: usize = 256;
+const DEFAULT_CACHE_TTL_SECS: u64 = 60;
+// windows ending this long ago are past any reorg and no longer change
+const SETTLED_AFTER: Duration = Duration::from_secs(3600);
+
+struct Entry {
+    stored: Instant,
+    // None for settled windows, kept until evicted
+    expires: Option<Instant>,
+    groups: Vec<Group>,
+}
+
+// Results of recent aggregations, keyed by the query. Settled windows stay
+// until the cache is full, open ones only for AGGREGATE_CACHE_TTL_SECS.
+pub struct AggregateCache {
+    size: usize,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl AggregateCache {
+    // AGGREGATE_CACHE_SIZE=0 turns caching off
+    pub fn from_env() -> Self {
+        let size = env::var("AGGREGATE_CACHE_SIZE").ok().and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_CACHE_SIZE);
+        let ttl = env::var("AGGREGATE_CACHE_TTL_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_CACHE_TTL_SECS);
+        AggregateCache { size, ttl: Duration::from_secs(ttl), entries: Mutex::new(HashMap::new()) }
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<Group>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        if entry.expires.is_some_and(|e| e <= Instant::now()) {
+            entries.remove(key);
+            return None;
+        }
+        Some(entry.groups.clone())
+    }
+
+    fn put(&self, key: String, settled: bool, groups: Vec<Group>) {
+        if self.size == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, e| e.expires.is_none_or(|e| e > now));
+        while entries.len() >= self.size {
+            let Some(oldest) = entries.iter().min_by_key(|(_, e)| e.stored).map(|(k, _)| k.clone()) else { break };
+            entries.remove(&oldest);
+        }
+        let expires = (!settled).then(|| now + self.ttl);
+        entries.insert(key, Entry { stored: now, expires, groups });
+    }
+}
+
+fn is_decimal(value: &str) -> bool {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+impl Repository {
+    // Count/sum/distinct over decoded_events in [from, to), or over the
+    // event rollups when they can answer it.
+    pub async fn aggregate_events(&self, mut aggregation: Aggregation, from: Option<DateTime>, to: Option<DateTime>) -> Result<Vec<Group>> {
+        aggregation.limit = Some(aggregation.limit.unwrap_or(MAX_LIMIT).clamp(1, MAX_LIMIT));
+        let (table, aggregation) = match self.rollups.as_ref().and_then(|r| r.plan(&aggregation, from, to)) {
+            Some(planned) => (Table::Rollups, planned),
+            None => {
+                aggregation.filters.extend(time_filters(from, to));
+                (Table::DecodedEvents, aggregation)
+            }
+        };
+
+        let key = format!("{:?} {:?}", table, aggregation);
+        if let Some(groups) = self.aggregates.get(&key) {
+            return Ok(groups);
+        }
+        let settled = to.is_some_and(|to| to.timestamp_millis() < DateTime::now().timestamp_millis() - SETTLED_AFTER.as_millis() as i64);
+        let exact = matches!(aggregation.metric, Metric::Sum(_));
+        let groups = self.storage.aggregate(table, aggregation).await?;
+        // sums of uint256 fields are returned as exact decimals, never rounded
+        if let Some(group) = groups.iter().find(|g| exact && !is_decimal(&g.value)) {
+            return Err(StorageError::Other(format!("sum `{}` of {:?} is not an exact integer", group.value, group.key)));
+        }
+        self.aggregates.put(key, settled, groups.clone());
+        Ok(groups)
+    }
+
+    // events the address took part in, None when ROLLUPS doesn't keep that granularity
+    pub async fn address_activity(&self, address: &str, query: &ActivityQuery, from: Option<DateTime>, to: Option<DateTime>) -> Result<Option<Vec<Activity>>> {
+        let granularity = query.granularity.unwrap_or(Granularity::Total);
+        if !self.rollups.as_ref().is_some_and(|r| r.is_enabled(Rollup::Addresses, granularity)) {
+            return Ok(None);
+        }
+        let mut q = Query::new()
+            .filter(Filter::eq("rollup", Rollup::Addresses.as_str()))
+            .filter(Filter::eq("granularity", granularity.as_str()))
+            .filter(Filter::eq("address", address.to_lowercase()))
+            .filter(Filter::cmp("count", Op::Gt, 0i64))
+            .sort("bucket_time", true)
+            .limit(page_limit(query.limit))
+            .skip(query.skip);
+        if let Some(from) = from {
+            q = q.filter(Filter::cmp("bucket_time", Op::Gte, from));
+        }
+        if let Some(to) = to {
+            q = q.filter(Filter::cmp("bucket_time", Op::Lt, to));
+        }
+        Ok(Some(decode(self.storage.find(Table::Rollups, q).await?)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use blocks_common::values::decimal_to_bson;
+    use blocks_storage::sqlite::SqliteStorage;
+    use blocks_storage::{GroupBy, Storage};
+    use mongodb::bson::doc;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn sums_are_exact_decimals() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        let big = format!("1{}", "0".repeat(40));
+        let events = [big.as_str(), big.as_str(), "3"].iter().enumerate().map(|(i, value)| doc! {
+            "block_number": 1_i64,
+            "block_hash": "0x01",
+            "log_index": i as i64,
+            "event": "Transfer",
+            "args": { "value": decimal_to_bson(value.to_string()) },
+        }).collect();
+        storage.insert(Table::DecodedEvents, events).await.unwrap();
+        let repository = Repository::new(Arc::new(storage), None);
+
+        let aggregation = Aggregation {
+            filters: vec![],
+            group_by: vec![GroupBy::Path("event".to_owned())],
+            metric: Metric::Sum("args.value".to_owned()),
+            by_value: true,
+            limit: None,
+        };
+        let groups = repository.aggregate_events(aggregation, None, None).await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].value, format!("2{}3", "0".repeat(39)));
+        assert!(is_decimal("-12") && !is_decimal("1.2E+40") && !is_decimal("-") && !is_decimal(""));
+    }
+}
@@ -0,0 +1,58 @@
+use std::io::Read;
+
+use blocks_storage::{Bucket, Filter, Interval, Query, Table};
+use flate2::read::ZlibDecoder;
+use mongodb::bson::DateTime;
+
+use super::{decode, page_limit, time_filters, Repository, Result};
+use crate::models::{DecodedEvent, EventBlock, StoredEventBlock};
+
+impl Repository {
+    // oldest first
+    pub async fn events(&self, from: Option<DateTime>, to: Option<DateTime>, limit: Option<i64>, skip: Option<u64>) -> Result<Vec<EventBlock>> {
+        let mut query = Query::new()
+            .sort("block_number", true)
+            .limit(page_limit(limit))
+            .skip(skip);
+        for filter in time_filters(from, to) {
+            query = query.filter(filter);
+        }
+        let stored: Vec<StoredEventBlock> = decode(self.storage.find(Table::EventBlocks, query).await?)?;
+
+        Ok(stored.into_iter().map(|b| EventBlock {
+            events: decompress_events(&b.events),
+            block_number: b.block_number,
+            block_hash: b.block_hash,
+            block_time: b.block_time,
+            num_of_events: b.num_of_events,
+        }).collect())
+    }
+
+    // decoded events matching all the filters, in chain order
+    pub async fn decoded_events(&self, filters: Vec<Filter>, from: Option<DateTime>, to: Option<DateTime>, limit: Option<i64>, skip: Option<u64>) -> Result<Vec<DecodedEvent>> {
+        let mut q = Query::new()
+            .sort("block_number", true)
+            .sort("log_index", true)
+            .limit(page_limit(limit))
+            .skip(skip);
+        for filter in filters.into_iter().chain(time_filters(from, to)) {
+            q = q.filter(filter);
+        }
+        decode(self.storage.find(Table::DecodedEvents, q).await?)
+    }
+
+    // blocks with events and number of events per hour/day
+    pub async fn events_histogram(&self, from: Option<DateTime>, to: Option<DateTime>, interval: Interval) -> Result<Vec<Bucket>> {
+        self.storage.histogram(Table::EventBlocks, time_filters(from, to), interval, Some("num_of_events")).await
+    }
+}
+
+// undecodable blobs come back as an empty list
+fn decompress_events(hex_str: &str) -> Vec<String> {
+    let Ok(bytes) = hex::decode(hex_str) else { return Vec::new() };
+    let mut s = String::new();
+    if ZlibDecoder::new(bytes.as_slice()).read_to_string(&mut s).is_err() {
+        return Vec::new();
+    }
+    s.split("::").map(|e| e.to_owned()).collect()
+}
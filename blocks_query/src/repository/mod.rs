@@ -0,0 +1,84 @@
+mod analytics;
+mod events;
+mod projections;
+mod status;
+mod tokens;
+mod webhooks;
+
+use std::sync::Arc;
+
+use blocks_common::rollups::Rollups;
+use blocks_storage::{Bucket, Filter, Interval, Op, Query, Storage, StorageError, Table};
+use mongodb::bson::{DateTime, Document};
+use serde::de::DeserializeOwned;
+
+use analytics::AggregateCache;
+pub use tokens::TransferFilter;
+
+use crate::models::{FailedTxnQuery, Txn};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+pub struct Repository {
+    storage: Arc<dyn Storage>,
+    aggregates: AggregateCache,
+    // ROLLUPS the indexer keeps, read instead of scanning events where they fit
+    rollups: Option<Rollups>,
+}
+
+fn page_limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
+
+// block_time conditions for [from, to)
+fn time_filters(from: Option<DateTime>, to: Option<DateTime>) -> Vec<Filter> {
+    let mut filters = Vec::new();
+    if let Some(from) = from {
+        filters.push(Filter::cmp("block_time", Op::Gte, from));
+    }
+    if let Some(to) = to {
+        filters.push(Filter::cmp("block_time", Op::Lt, to));
+    }
+    filters
+}
+
+fn decode<T: DeserializeOwned>(docs: Vec<Document>) -> Result<Vec<T>> {
+    docs.into_iter()
+        .map(|d| mongodb::bson::from_document(d).map_err(|err| StorageError::Other(err.to_string())))
+        .collect()
+}
+
+impl Repository {
+    pub fn new(storage: Arc<dyn Storage>, rollups: Option<Rollups>) -> Self {
+        Repository { storage, aggregates: AggregateCache::from_env(), rollups }
+    }
+
+    pub async fn failed_txns(&self, query: &FailedTxnQuery, from: Option<DateTime>, to: Option<DateTime>) -> Result<Vec<Txn>> {
+        let mut q = Query::new()
+            .filter(Filter::eq("status", 0))
+            .limit(page_limit(query.limit))
+            .skip(query.skip);
+        for filter in time_filters(from, to) {
+            q = q.filter(filter);
+        }
+        if let Some(reason) = &query.reason {
+            q = q.filter(Filter::Contains("revert.reason".to_owned(), reason.clone()));
+        }
+        if let Some(kind) = &query.kind {
+            q = q.filter(Filter::eq("revert.kind", kind.as_str()));
+        }
+        if let Some(error) = &query.error {
+            q = q.filter(Filter::eq("revert.error", error.as_str()));
+        }
+        decode(self.storage.find(Table::Txns, q).await?)
+    }
+
+    pub async fn failed_txns_histogram(&self, from: Option<DateTime>, to: Option<DateTime>, interval: Interval) -> Result<Vec<Bucket>> {
+        let mut filters = time_filters(from, to);
+        filters.push(Filter::eq("status", 0));
+        self.storage.histogram(Table::Txns, filters, interval, None).await
+    }
+}
@@ -0,0 +1,41 @@
+use blocks_storage::{Filter, Query, Table};
+
+use super::{decode, page_limit, Repository, Result};
+use crate::models::{DerivedEvent, DerivedEventQuery, ProjectionEntity};
+
+impl Repository {
+    // ordered by id
+    pub async fn projection_entities(&self, projection: &str, entity: &str, limit: Option<i64>, skip: Option<u64>) -> Result<Vec<ProjectionEntity>> {
+        let q = Query::new()
+            .filter(Filter::eq("projection", projection))
+            .filter(Filter::eq("entity", entity))
+            .sort("id", true)
+            .limit(page_limit(limit))
+            .skip(skip);
+        decode(self.storage.find(Table::ProjectionEntities, q).await?)
+    }
+
+    pub async fn projection_entity(&self, projection: &str, entity: &str, id: &str) -> Result<Option<ProjectionEntity>> {
+        let q = Query::new()
+            .filter(Filter::eq("projection", projection))
+            .filter(Filter::eq("entity", entity))
+            .filter(Filter::eq("id", id))
+            .limit(1);
+        Ok(decode(self.storage.find(Table::ProjectionEntities, q).await?)?.into_iter().next())
+    }
+
+    // in chain order
+    pub async fn derived_events(&self, query: &DerivedEventQuery) -> Result<Vec<DerivedEvent>> {
+        let mut q = Query::new()
+            .filter(Filter::eq("projection", query.projection.as_str()))
+            .sort("block_number", true)
+            .sort("log_index", true)
+            .sort("seq", true)
+            .limit(page_limit(query.limit))
+            .skip(query.skip);
+        if let Some(name) = &query.name {
+            q = q.filter(Filter::eq("name", name.as_str()));
+        }
+        decode(self.storage.find(Table::DerivedEvents, q).await?)
+    }
+}
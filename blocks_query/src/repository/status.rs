@@ -0,0 +1,30 @@
+use blocks_common::status::{check_storage, Check};
+use blocks_storage::{Filter, Query, Table};
+use mongodb::bson::DateTime;
+
+use super::{decode, Repository, Result};
+use crate::models::IndexerStatus;
+
+// indexers publish every 15s, a row older than this means the indexer is gone
+const STALE_AFTER_MS: i64 = 60_000;
+
+impl Repository {
+    pub async fn checks(&self) -> Vec<Check> {
+        vec![check_storage(self.storage.as_ref()).await]
+    }
+
+    pub async fn indexer_status(&self, indexer: Option<&str>) -> Result<Vec<IndexerStatus>> {
+        let mut q = Query::new().sort("indexer", true).sort("contract", true);
+        if let Some(indexer) = indexer {
+            q = q.filter(Filter::eq("indexer", indexer));
+        }
+        let mut rows: Vec<IndexerStatus> = decode(self.storage.find(Table::IndexerStatus, q).await?)?;
+
+        let now = DateTime::now().timestamp_millis();
+        for row in rows.iter_mut() {
+            let silent = row.updated_at.is_none_or(|t| now - t.timestamp_millis() > STALE_AFTER_MS);
+            row.stale = silent || row.mode != "tail";
+        }
+        Ok(rows)
+    }
+}
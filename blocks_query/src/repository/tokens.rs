@@ -0,0 +1,158 @@
+use blocks_common::values::scaled;
+use blocks_storage::{Aggregation, Bucket, Filter, GroupBy, Interval, Metric, Op, Query, StorageError, Table};
+use mongodb::bson::DateTime;
+use web3::types::U256;
+
+use super::{decode, page_limit, time_filters, Repository, Result};
+use crate::models::{HolderBalance, TokenMetadata, TokenTransfer, TransferSort};
+
+pub struct TransferFilter<'a> {
+    pub token: Option<&'a str>,
+    // matches sender or recipient
+    pub address: Option<&'a str>,
+    pub from: Option<DateTime>,
+    pub to: Option<DateTime>,
+    // inclusive amount bounds as `value_sort` keys, see models::parse_value_range
+    pub min_value: Option<String>,
+    pub max_value: Option<String>,
+}
+
+impl TransferFilter<'_> {
+    fn to_filters(&self) -> Vec<Filter> {
+        let mut filters = time_filters(self.from, self.to);
+        if let Some(token) = self.token {
+            filters.push(Filter::eq("token", token.to_lowercase()));
+        }
+        if let Some(address) = self.address {
+            let address = address.to_lowercase();
+            filters.push(Filter::Or(vec![Filter::eq("from", address.as_str()), Filter::eq("to", address.as_str())]));
+        }
+        if let Some(min) = &self.min_value {
+            filters.push(Filter::cmp("value_sort", Op::Gte, min.as_str()));
+        }
+        if let Some(max) = &self.max_value {
+            filters.push(Filter::cmp("value_sort", Op::Lte, max.as_str()));
+        }
+        filters
+    }
+}
+
+impl Repository {
+    pub async fn token_metadata(&self, token: &str) -> Result<Option<TokenMetadata>> {
+        let query = Query::new().filter(Filter::eq("_id", token.to_lowercase())).limit(1);
+        Ok(decode(self.storage.find(Table::TokenMetadata, query).await?)?.pop())
+    }
+
+    // newest first, or largest first when sorted by value
+    pub async fn token_transfers(&self, filter: TransferFilter<'_>, sort: TransferSort, limit: Option<i64>, skip: Option<u64>) -> Result<Vec<TokenTransfer>> {
+        let mut query = Query::new();
+        if let TransferSort::Value = sort {
+            query = query.sort("value_sort", false);
+        }
+        query = query
+            .sort("block_number", false)
+            .sort("log_index", false)
+            .limit(page_limit(limit))
+            .skip(skip);
+        query.filters = filter.to_filters();
+        decode(self.storage.find(Table::TokenTransfers, query).await?)
+    }
+
+    pub async fn token_transfers_histogram(&self, filter: TransferFilter<'_>, interval: Interval) -> Result<Vec<Bucket>> {
+        self.storage.histogram(Table::TokenTransfers, filter.to_filters(), interval, None).await
+    }
+
+    // balance of a holder after `block`, one entry per erc1155 id
+    pub async fn token_balance(&self, token: &str, holder: &str, block: Option<i64>) -> Result<Vec<HolderBalance>> {
+        let mut filters = vec![Filter::eq("token", token.to_lowercase()), Filter::eq("holder", holder.to_lowercase())];
+        if let Some(block) = block {
+            filters.push(Filter::cmp("block_number", Op::Lte, block));
+        }
+        self.balances(token, filters, None).await
+    }
+
+    // current holders ordered by balance
+    pub async fn token_holders(&self, token: &str, limit: Option<i64>, skip: Option<u64>) -> Result<Vec<HolderBalance>> {
+        let skip = skip.unwrap_or(0);
+        let filters = vec![Filter::eq("token", token.to_lowercase())];
+        let balances = self.balances(token, filters, Some(page_limit(limit).saturating_add(skip as i64))).await?;
+        Ok(balances.into_iter().skip(skip as usize).collect())
+    }
+
+    // Non-zero balances, largest first. The snapshots are per-block deltas, the
+    // database sums them per holder and erc1155 id so only the page comes back.
+    async fn balances(&self, token: &str, filters: Vec<Filter>, limit: Option<i64>) -> Result<Vec<HolderBalance>> {
+        let aggregation = Aggregation {
+            filters,
+            group_by: vec![GroupBy::Path("holder".to_owned()), GroupBy::Path("token_id".to_owned())],
+            metric: Metric::Sum("delta".to_owned()),
+            by_value: true,
+            limit,
+        };
+        let groups = self.storage.aggregate(Table::TokenBalances, aggregation).await?;
+        let decimals = self.token_decimals(token).await?;
+        let mut balances = Vec::new();
+        for group in groups {
+            // zero and (from missing history) negative sums sort last and aren't balances
+            if group.value.starts_with('-') {
+                continue;
+            }
+            let balance = U256::from_dec_str(&group.value)
+                .map_err(|_| StorageError::Other(format!("balance `{}` of {:?} is not an integer", group.value, group.key)))?;
+            let mut key = group.key.into_iter();
+            let Some(holder) = key.next().flatten() else { continue };
+            if balance.is_zero() {
+                continue;
+            }
+            balances.push(HolderBalance {
+                holder,
+                token_id: key.next().flatten(),
+                balance_scaled: decimals.and_then(|d| scaled(&balance.to_string(), d)),
+                balance: balance.to_string(),
+            });
+        }
+        Ok(balances)
+    }
+
+    async fn token_decimals(&self, token: &str) -> Result<Option<u8>> {
+        let metadata = self.token_metadata(token).await?;
+        Ok(metadata.and_then(|m| m.decimals).and_then(|d| u8::try_from(d).ok()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use blocks_common::values::decimal_to_bson;
+    use blocks_storage::sqlite::SqliteStorage;
+    use blocks_storage::Storage;
+    use mongodb::bson::doc;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn balances_past_decimal128_are_exact() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        let token = "0x00000000219ab540356cbb839cbe05303d7705fa";
+        let big = format!("1{}", "0".repeat(40));
+        let snapshots = [("0x01", big.clone(), 1), ("0x01", "7".to_owned(), 2), ("0x02", "5".to_owned(), 1), ("0x02", "-5".to_owned(), 2), ("0x03", "9".to_owned(), 1)];
+        let docs = snapshots.iter().map(|(holder, delta, block)| doc! {
+            "_id": format!("{}:{}", holder, block),
+            "token": token,
+            "holder": *holder,
+            "token_id": null,
+            "block_number": *block as i64,
+            "delta": decimal_to_bson(delta.clone()),
+        }).collect();
+        storage.insert(Table::TokenBalances, docs).await.unwrap();
+        let repository = Repository::new(Arc::new(storage), None);
+
+        let holders = repository.token_holders(token, None, None).await.unwrap();
+        let found: Vec<(&str, &str)> = holders.iter().map(|h| (h.holder.as_str(), h.balance.as_str())).collect();
+        assert_eq!(found, [("0x01", format!("1{}7", "0".repeat(39)).as_str()), ("0x03", "9")]);
+        let at_first = repository.token_balance(token, "0x01", Some(1)).await.unwrap();
+        assert_eq!(at_first[0].balance, big);
+    }
+}
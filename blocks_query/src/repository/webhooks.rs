@@ -0,0 +1,38 @@
+use blocks_common::webhooks::{self, NewSubscription, Subscription};
+use blocks_storage::{Filter, Query, Table};
+
+use super::{decode, page_limit, Repository, Result};
+use crate::models::{DeliveryQuery, WebhookDelivery};
+
+impl Repository {
+    pub async fn webhooks(&self) -> Result<Vec<Subscription>> {
+        webhooks::list(self.storage.as_ref()).await
+    }
+
+    pub async fn webhook(&self, id: &str) -> Result<Option<Subscription>> {
+        webhooks::get(self.storage.as_ref(), id).await
+    }
+
+    pub async fn create_webhook(&self, request: NewSubscription) -> Result<Subscription> {
+        webhooks::create(self.storage.as_ref(), request).await
+    }
+
+    // false for unknown ids
+    pub async fn delete_webhook(&self, id: &str) -> Result<bool> {
+        webhooks::delete(self.storage.as_ref(), id).await
+    }
+
+    // latest events first
+    pub async fn webhook_deliveries(&self, id: &str, query: &DeliveryQuery) -> Result<Vec<WebhookDelivery>> {
+        let mut q = Query::new()
+            .filter(Filter::eq("subscription", id))
+            .sort("block_number", false)
+            .sort("log_index", false)
+            .limit(page_limit(query.limit))
+            .skip(query.skip);
+        if let Some(status) = &query.status {
+            q = q.filter(Filter::eq("status", status.as_str()));
+        }
+        decode(self.storage.find(Table::WebhookDeliveries, q).await?)
+    }
+}
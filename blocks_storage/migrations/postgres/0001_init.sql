@@ -0,0 +1,130 @@
+-- every table keeps the full document in `doc` next to its typed columns
+
+CREATE TABLE blocks_table (
+    block_num           BIGINT,
+    block_hash          TEXT PRIMARY KEY,
+    block_time          TIMESTAMPTZ,
+    num_of_transactions INTEGER,
+    num_of_events       INTEGER,
+    event_signatures    TEXT,
+    doc                 JSONB NOT NULL
+);
+CREATE INDEX blocks_table_block_num ON blocks_table (block_num);
+CREATE INDEX blocks_table_block_time ON blocks_table (block_time);
+
+CREATE TABLE txns_table (
+    block_num          BIGINT,
+    block_hash         TEXT,
+    block_time         TIMESTAMPTZ,
+    txn_hash           TEXT PRIMARY KEY,
+    status             INTEGER,
+    input              TEXT,
+    selector           TEXT,
+    function           TEXT,
+    function_signature TEXT,
+    abi_contract       TEXT,
+    args               JSONB,
+    decode_error       TEXT,
+    revert             JSONB,
+    revert_kind        TEXT,
+    revert_reason      TEXT,
+    revert_error       TEXT,
+    doc                JSONB NOT NULL
+);
+CREATE INDEX txns_table_block_num ON txns_table (block_num);
+CREATE INDEX txns_table_block_time ON txns_table (block_time);
+CREATE INDEX txns_table_failed ON txns_table (revert_kind) WHERE status = 0;
+
+CREATE TABLE internal_txns_table (
+    block_num     BIGINT,
+    block_hash    TEXT,
+    block_time    TIMESTAMPTZ,
+    txn_hash      TEXT NOT NULL,
+    depth         INTEGER,
+    trace_address JSONB NOT NULL,
+    call_type     TEXT,
+    from_address  TEXT,
+    to_address    TEXT,
+    value         NUMERIC(78, 0),
+    gas           NUMERIC(78, 0),
+    gas_used      NUMERIC(78, 0),
+    input         TEXT,
+    output        TEXT,
+    error         TEXT,
+    doc           JSONB NOT NULL,
+    PRIMARY KEY (txn_hash, trace_address)
+);
+CREATE INDEX internal_txns_table_block_num ON internal_txns_table (block_num);
+CREATE INDEX internal_txns_table_from ON internal_txns_table (from_address);
+CREATE INDEX internal_txns_table_to ON internal_txns_table (to_address);
+
+CREATE TABLE events_table (
+    block_number  BIGINT,
+    block_hash    TEXT PRIMARY KEY,
+    block_time    TIMESTAMPTZ,
+    events        TEXT,
+    num_of_events INTEGER,
+    doc           JSONB NOT NULL
+);
+CREATE INDEX events_table_block_number ON events_table (block_number);
+CREATE INDEX events_table_block_time ON events_table (block_time);
+
+CREATE TABLE token_transfers (
+    id           TEXT PRIMARY KEY,
+    block_number BIGINT,
+    block_hash   TEXT,
+    block_time   TIMESTAMPTZ,
+    tx_hash      TEXT,
+    log_index    BIGINT,
+    token        TEXT,
+    standard     TEXT,
+    operator     TEXT,
+    from_address TEXT,
+    to_address   TEXT,
+    token_id     NUMERIC(78, 0),
+    value        NUMERIC(78, 0),
+    doc          JSONB NOT NULL
+);
+CREATE INDEX token_transfers_block_number ON token_transfers (block_number);
+CREATE INDEX token_transfers_token ON token_transfers (token, block_number);
+CREATE INDEX token_transfers_from ON token_transfers (from_address);
+CREATE INDEX token_transfers_to ON token_transfers (to_address);
+
+CREATE TABLE token_approvals (
+    id           TEXT PRIMARY KEY,
+    block_number BIGINT,
+    block_hash   TEXT,
+    block_time   TIMESTAMPTZ,
+    tx_hash      TEXT,
+    log_index    BIGINT,
+    token        TEXT,
+    standard     TEXT,
+    owner        TEXT,
+    spender      TEXT,
+    value        NUMERIC(78, 0),
+    doc          JSONB NOT NULL
+);
+CREATE INDEX token_approvals_block_number ON token_approvals (block_number);
+CREATE INDEX token_approvals_owner ON token_approvals (token, owner);
+
+CREATE TABLE token_balance_snapshots (
+    id           TEXT PRIMARY KEY,
+    token        TEXT,
+    holder       TEXT,
+    token_id     NUMERIC(78, 0),
+    block_number BIGINT,
+    block_time   TIMESTAMPTZ,
+    delta        NUMERIC(78, 0),
+    doc          JSONB NOT NULL
+);
+CREATE INDEX token_balance_snapshots_block_number ON token_balance_snapshots (block_number);
+CREATE INDEX token_balance_snapshots_holder ON token_balance_snapshots (token, holder, block_number);
+
+CREATE TABLE token_metadata (
+    token    TEXT PRIMARY KEY,
+    standard TEXT,
+    name     TEXT,
+    symbol   TEXT,
+    decimals INTEGER,
+    doc      JSONB NOT NULL
+);
@@ -0,0 +1,13 @@
+CREATE TABLE indexer_status (
+    indexer       TEXT NOT NULL,
+    contract      TEXT NOT NULL,
+    name          TEXT,
+    mode          TEXT,
+    indexed_block BIGINT,
+    chain_head    BIGINT,
+    lag           BIGINT,
+    failed_ranges JSONB,
+    updated_at    TIMESTAMPTZ,
+    doc           JSONB NOT NULL,
+    PRIMARY KEY (indexer, contract)
+);
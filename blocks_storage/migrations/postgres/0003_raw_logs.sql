@@ -0,0 +1,16 @@
+CREATE TABLE raw_logs (
+    block_number BIGINT,
+    block_hash   TEXT NOT NULL,
+    block_time   TIMESTAMPTZ,
+    tx_hash      TEXT,
+    tx_index     BIGINT,
+    log_index    BIGINT NOT NULL,
+    address      TEXT,
+    topic0       TEXT,
+    topics       JSONB,
+    data         TEXT,
+    doc          JSONB NOT NULL,
+    PRIMARY KEY (block_hash, log_index)
+);
+CREATE INDEX raw_logs_block_number ON raw_logs (block_number);
+CREATE INDEX raw_logs_address ON raw_logs (address, block_number);
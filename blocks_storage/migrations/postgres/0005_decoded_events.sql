@@ -0,0 +1,19 @@
+CREATE TABLE decoded_events (
+    block_number BIGINT,
+    block_hash   TEXT NOT NULL,
+    block_time   TIMESTAMPTZ,
+    tx_hash      TEXT,
+    log_index    BIGINT NOT NULL,
+    address      TEXT,
+    contract     TEXT,
+    abi_version  TEXT,
+    event        TEXT,
+    signature    TEXT,
+    args         JSONB,
+    doc          JSONB NOT NULL,
+    PRIMARY KEY (block_hash, log_index)
+);
+CREATE INDEX decoded_events_block_number ON decoded_events (block_number);
+CREATE INDEX decoded_events_event ON decoded_events (contract, event, block_time);
+CREATE INDEX decoded_events_block_time ON decoded_events (block_time);
+CREATE INDEX decoded_events_args ON decoded_events USING GIN (args jsonb_path_ops);
@@ -0,0 +1,14 @@
+CREATE TABLE derived_events (
+    projection   TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    log_index    BIGINT NOT NULL,
+    seq          BIGINT NOT NULL,
+    block_time   TIMESTAMPTZ,
+    tx_hash      TEXT,
+    name         TEXT,
+    data         JSONB,
+    doc          JSONB NOT NULL,
+    PRIMARY KEY (projection, block_number, log_index, seq)
+);
+CREATE INDEX derived_events_block_number ON derived_events (block_number);
+CREATE INDEX derived_events_name ON derived_events (projection, name, block_number);
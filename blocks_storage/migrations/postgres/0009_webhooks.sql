@@ -0,0 +1,36 @@
+CREATE TABLE webhook_subscriptions (
+    id         TEXT NOT NULL PRIMARY KEY,
+    url        TEXT,
+    secret     TEXT,
+    contract   TEXT,
+    event      TEXT,
+    conditions JSONB,
+    from_block BIGINT,
+    created_at TIMESTAMPTZ,
+    doc        JSONB NOT NULL
+);
+
+CREATE TABLE webhook_checkpoints (
+    subscription TEXT NOT NULL,
+    block_number BIGINT NOT NULL,
+    doc          JSONB NOT NULL,
+    PRIMARY KEY (subscription, block_number)
+);
+CREATE INDEX webhook_checkpoints_block_number ON webhook_checkpoints (block_number);
+
+CREATE TABLE webhook_deliveries (
+    subscription    TEXT NOT NULL,
+    block_number    BIGINT,
+    block_hash      TEXT NOT NULL,
+    log_index       BIGINT NOT NULL,
+    tx_hash         TEXT,
+    event           TEXT,
+    status          TEXT,
+    attempts        BIGINT,
+    response_status BIGINT,
+    error           TEXT,
+    updated_at      TIMESTAMPTZ,
+    doc             JSONB NOT NULL,
+    PRIMARY KEY (subscription, block_hash, log_index)
+);
+CREATE INDEX webhook_deliveries_block_number ON webhook_deliveries (subscription, block_number);
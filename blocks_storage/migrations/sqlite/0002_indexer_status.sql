@@ -0,0 +1,13 @@
+CREATE TABLE indexer_status (
+    indexer       TEXT NOT NULL,
+    contract      TEXT NOT NULL,
+    name          TEXT,
+    mode          TEXT,
+    indexed_block INTEGER,
+    chain_head    INTEGER,
+    lag           INTEGER,
+    failed_ranges TEXT,
+    updated_at    INTEGER,
+    doc           TEXT NOT NULL,
+    PRIMARY KEY (indexer, contract)
+);
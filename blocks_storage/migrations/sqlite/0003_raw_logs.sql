@@ -0,0 +1,16 @@
+CREATE TABLE raw_logs (
+    block_number INTEGER,
+    block_hash   TEXT NOT NULL,
+    block_time   INTEGER,
+    tx_hash      TEXT,
+    tx_index     INTEGER,
+    log_index    INTEGER NOT NULL,
+    address      TEXT,
+    topic0       TEXT,
+    topics       TEXT,
+    data         TEXT,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (block_hash, log_index)
+);
+CREATE INDEX raw_logs_block_number ON raw_logs (block_number);
+CREATE INDEX raw_logs_address ON raw_logs (address, block_number);
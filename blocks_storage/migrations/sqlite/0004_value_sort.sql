@@ -0,0 +1,11 @@
+-- sortable form of the amounts, see blocks_common::values::sort_key
+ALTER TABLE internal_txns_table ADD COLUMN value_sort TEXT;
+ALTER TABLE token_transfers ADD COLUMN value_sort TEXT;
+ALTER TABLE token_approvals ADD COLUMN value_sort TEXT;
+
+UPDATE internal_txns_table SET value_sort = '1' || substr('000000000000000000000000000000000000000000000000000000000000000000000000000000' || value, -78, 78) WHERE value IS NOT NULL;
+UPDATE token_transfers SET value_sort = '1' || substr('000000000000000000000000000000000000000000000000000000000000000000000000000000' || value, -78, 78) WHERE value IS NOT NULL;
+UPDATE token_approvals SET value_sort = '1' || substr('000000000000000000000000000000000000000000000000000000000000000000000000000000' || value, -78, 78) WHERE value IS NOT NULL;
+
+CREATE INDEX token_transfers_value ON token_transfers (token, value_sort);
+CREATE INDEX token_approvals_value ON token_approvals (token, value_sort);
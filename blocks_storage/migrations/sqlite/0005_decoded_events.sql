@@ -0,0 +1,18 @@
+CREATE TABLE decoded_events (
+    block_number INTEGER,
+    block_hash   TEXT NOT NULL,
+    block_time   INTEGER,
+    tx_hash      TEXT,
+    log_index    INTEGER NOT NULL,
+    address      TEXT,
+    contract     TEXT,
+    abi_version  TEXT,
+    event        TEXT,
+    signature    TEXT,
+    args         TEXT,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (block_hash, log_index)
+);
+CREATE INDEX decoded_events_block_number ON decoded_events (block_number);
+CREATE INDEX decoded_events_event ON decoded_events (contract, event, block_time);
+CREATE INDEX decoded_events_block_time ON decoded_events (block_time);
@@ -0,0 +1,16 @@
+CREATE TABLE rollups (
+    rollup       TEXT NOT NULL,
+    granularity  TEXT NOT NULL,
+    bucket       TEXT NOT NULL,
+    bucket_time  INTEGER,
+    contract     TEXT NOT NULL,
+    event        TEXT NOT NULL,
+    address      TEXT NOT NULL,
+    block_number INTEGER,
+    count        INTEGER NOT NULL,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (rollup, granularity, bucket, contract, event, address)
+);
+CREATE INDEX rollups_block_number ON rollups (block_number);
+CREATE INDEX rollups_bucket_time ON rollups (rollup, granularity, bucket_time);
+CREATE INDEX rollups_address ON rollups (rollup, granularity, address);
@@ -0,0 +1,29 @@
+CREATE TABLE projection_versions (
+    projection   TEXT NOT NULL,
+    entity       TEXT NOT NULL,
+    id           TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    deleted      INTEGER,
+    data         TEXT,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (projection, entity, id, block_number)
+);
+CREATE INDEX projection_versions_block_number ON projection_versions (block_number);
+
+CREATE TABLE projection_entities (
+    projection   TEXT NOT NULL,
+    entity       TEXT NOT NULL,
+    id           TEXT NOT NULL,
+    block_number INTEGER,
+    data         TEXT,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (projection, entity, id)
+);
+
+CREATE TABLE projection_checkpoints (
+    projection   TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (projection, block_number)
+);
+CREATE INDEX projection_checkpoints_block_number ON projection_checkpoints (block_number);
@@ -0,0 +1,14 @@
+CREATE TABLE derived_events (
+    projection   TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    log_index    INTEGER NOT NULL,
+    seq          INTEGER NOT NULL,
+    block_time   INTEGER,
+    tx_hash      TEXT,
+    name         TEXT,
+    data         TEXT,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (projection, block_number, log_index, seq)
+);
+CREATE INDEX derived_events_block_number ON derived_events (block_number);
+CREATE INDEX derived_events_name ON derived_events (projection, name, block_number);
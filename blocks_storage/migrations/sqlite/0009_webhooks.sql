@@ -0,0 +1,36 @@
+CREATE TABLE webhook_subscriptions (
+    id         TEXT NOT NULL PRIMARY KEY,
+    url        TEXT,
+    secret     TEXT,
+    contract   TEXT,
+    event      TEXT,
+    conditions TEXT,
+    from_block INTEGER,
+    created_at INTEGER,
+    doc        TEXT NOT NULL
+);
+
+CREATE TABLE webhook_checkpoints (
+    subscription TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (subscription, block_number)
+);
+CREATE INDEX webhook_checkpoints_block_number ON webhook_checkpoints (block_number);
+
+CREATE TABLE webhook_deliveries (
+    subscription    TEXT NOT NULL,
+    block_number    INTEGER,
+    block_hash      TEXT NOT NULL,
+    log_index       INTEGER NOT NULL,
+    tx_hash         TEXT,
+    event           TEXT,
+    status          TEXT,
+    attempts        INTEGER,
+    response_status INTEGER,
+    error           TEXT,
+    updated_at      INTEGER,
+    doc             TEXT NOT NULL,
+    PRIMARY KEY (subscription, block_hash, log_index)
+);
+CREATE INDEX webhook_deliveries_block_number ON webhook_deliveries (subscription, block_number);
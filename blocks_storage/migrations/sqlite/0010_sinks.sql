@@ -0,0 +1,13 @@
+CREATE TABLE sink_checkpoints (
+    sink         TEXT NOT NULL,
+    block_number INTEGER NOT NULL,
+    doc          TEXT NOT NULL,
+    PRIMARY KEY (sink, block_number)
+);
+CREATE INDEX sink_checkpoints_block_number ON sink_checkpoints (block_number);
+
+CREATE TABLE sink_cursors (
+    sink         TEXT NOT NULL PRIMARY KEY,
+    block_number INTEGER,
+    doc          TEXT NOT NULL
+);
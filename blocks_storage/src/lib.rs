@@ -0,0 +1,239 @@
+mod metered;
+pub mod mongo;
+pub mod postgres;
+mod schema;
+pub mod sqlite;
+mod values;
+
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bson::{Bson, Document};
+use serde::{Deserialize, Serialize};
+
+pub use metered::MeteredStorage;
+pub use schema::{Column, ColumnKind, DbGroup, Table, TableSchema};
+pub use values::{get_path, is_integer, sort_key, BigSum, DECIMAL128_DIGITS, SORT_DIGITS};
+
+const DEFAULT_SQLITE_PATH: &str = "./blocks.db";
+
+#[derive(Debug)]
+pub enum StorageError {
+    Mongo(mongodb::error::Error),
+    Postgres(tokio_postgres::Error),
+    Sqlite(rusqlite::Error),
+    Other(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Mongo(err) => write!(f, "mongodb: {}", err),
+            StorageError::Postgres(err) => write!(f, "postgres: {}", err),
+            StorageError::Sqlite(err) => write!(f, "sqlite: {}", err),
+            StorageError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<mongodb::error::Error> for StorageError {
+    fn from(err: mongodb::error::Error) -> Self {
+        StorageError::Mongo(err)
+    }
+}
+
+impl From<tokio_postgres::Error> for StorageError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        StorageError::Postgres(err)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        StorageError::Sqlite(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+// Conditions on document paths. Backends translate them to their own query
+// language; SQL backends only accept paths that have a typed column.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Cmp(String, Op, Bson),
+    In(String, Vec<Bson>),
+    // case-insensitive substring
+    Contains(String, String),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn eq(path: &str, value: impl Into<Bson>) -> Self {
+        Filter::Cmp(path.to_owned(), Op::Eq, value.into())
+    }
+
+    pub fn cmp(path: &str, op: Op, value: impl Into<Bson>) -> Self {
+        Filter::Cmp(path.to_owned(), op, value.into())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    // all of them have to match
+    pub filters: Vec<Filter>,
+    // (path, ascending)
+    pub sort: Vec<(String, bool)>,
+    pub limit: Option<i64>,
+    pub skip: Option<u64>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Query::default()
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn sort(mut self, path: &str, ascending: bool) -> Self {
+        self.sort.push((path.to_owned(), ascending));
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn skip(mut self, skip: Option<u64>) -> Self {
+        self.skip = skip;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Hour,
+    Day,
+    // ISO weeks, labelled with their monday
+    Week,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    // start of the hour/day, UTC
+    pub bucket: String,
+    pub count: i64,
+    // summed field if the histogram has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sum: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub enum GroupBy {
+    // hour/day/week of the table's time column
+    Time(Interval),
+    Path(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Metric {
+    Count,
+    // integers summed without rounding, except in mongo past 34 digits
+    Sum(String),
+    // number of different values of a path
+    Distinct(String),
+}
+
+// Count/sum/distinct count of the rows matching `filters`, one result per
+// combination of the `group_by` keys. Paths may point into json columns
+// (`args.value`) on every backend.
+#[derive(Debug, Clone)]
+pub struct Aggregation {
+    pub filters: Vec<Filter>,
+    pub group_by: Vec<GroupBy>,
+    pub metric: Metric,
+    // largest value first instead of ordered by the keys
+    pub by_value: bool,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Group {
+    // one per group_by entry, null where the row has no value
+    pub key: Vec<Option<String>>,
+    // decimal string, sums don't fit any json number
+    pub value: String,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // creates whatever the backend needs (tables, indexes) and applies pending migrations
+    async fn migrate(&self) -> Result<()>;
+
+    // cheapest round trip to the database, used by readiness checks
+    async fn ping(&self) -> Result<()>;
+
+    // rows whose key is already stored are skipped, so retried ranges are harmless
+    async fn insert(&self, table: Table, rows: Vec<Document>) -> Result<()>;
+
+    // rows replace whatever is stored under their key
+    async fn upsert(&self, table: Table, rows: Vec<Document>) -> Result<()>;
+
+    async fn find(&self, table: Table, query: Query) -> Result<Vec<Document>>;
+
+    // removes every row matching all of `filters`, returns how many
+    async fn delete(&self, table: Table, filters: Vec<Filter>) -> Result<u64>;
+
+    // counts rows per hour/day of the table's time column, optionally summing an integer path
+    async fn histogram(&self, table: Table, filters: Vec<Filter>, interval: Interval, sum_path: Option<&str>) -> Result<Vec<Bucket>>;
+
+    async fn aggregate(&self, table: Table, aggregation: Aggregation) -> Result<Vec<Group>>;
+
+    // drops the block scoped rows of `group` at or above `block`, used to unwind reorgs;
+    // an indexer passes the group it writes so it never touches another one's tables
+    async fn rollback(&self, group: DbGroup, block: u64) -> Result<()>;
+}
+
+// STORAGE_BACKEND = mongo (default, MONGODB_URI) | postgres (POSTGRES_URL)
+//                 | sqlite (SQLITE_PATH, ./blocks.db by default)
+pub async fn from_env() -> Result<Arc<dyn Storage>> {
+    let backend = env::var("STORAGE_BACKEND").unwrap_or("mongo".to_owned());
+    let storage: Arc<dyn Storage> = match backend.to_lowercase().as_str() {
+        "mongo" | "mongodb" => {
+            let uri = env::var("MONGODB_URI").map_err(|_| StorageError::Other("You must set the MONGODB_URI environment var!".to_owned()))?;
+            Arc::new(mongo::MongoStorage::connect(&uri).await?)
+        }
+        "postgres" | "postgresql" => {
+            let url = env::var("POSTGRES_URL").map_err(|_| StorageError::Other("You must set the POSTGRES_URL environment var!".to_owned()))?;
+            Arc::new(postgres::PgStorage::connect(&url).await?)
+        }
+        "sqlite" => {
+            let path = env::var("SQLITE_PATH").unwrap_or(DEFAULT_SQLITE_PATH.to_owned());
+            Arc::new(sqlite::SqliteStorage::open(&path)?)
+        }
+        other => return Err(StorageError::Other(format!("unknown STORAGE_BACKEND `{}`", other))),
+    };
+    let storage: Arc<dyn Storage> = Arc::new(MeteredStorage::new(storage));
+    storage.migrate().await?;
+    Ok(storage)
+}
@@ -0,0 +1,82 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use bson::Document;
+use lazy_static::lazy_static;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+
+use crate::schema::DbGroup;
+use crate::{Aggregation, Bucket, Filter, Group, Interval, Query, Result, Storage, Table};
+
+lazy_static! {
+    static ref DURATION: HistogramVec = register_histogram_vec!(
+        "storage_operation_duration_seconds",
+        "Storage latency by table and operation",
+        &["table", "op"]
+    )
+    .unwrap();
+    static ref ERRORS: IntCounterVec =
+        register_int_counter_vec!("storage_errors_total", "Failed storage operations by table and operation", &["table", "op"]).unwrap();
+}
+
+// Times every call of the wrapped backend, `from_env` hands this out.
+pub struct MeteredStorage {
+    inner: Arc<dyn Storage>,
+}
+
+impl MeteredStorage {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        MeteredStorage { inner }
+    }
+}
+
+async fn timed<T>(table: &str, op: &str, call: impl Future<Output = Result<T>>) -> Result<T> {
+    let started = Instant::now();
+    let result = call.await;
+    DURATION.with_label_values(&[table, op]).observe(started.elapsed().as_secs_f64());
+    if result.is_err() {
+        ERRORS.with_label_values(&[table, op]).inc();
+    }
+    result
+}
+
+#[async_trait]
+impl Storage for MeteredStorage {
+    async fn migrate(&self) -> Result<()> {
+        timed("", "migrate", self.inner.migrate()).await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        timed("", "ping", self.inner.ping()).await
+    }
+
+    async fn insert(&self, table: Table, rows: Vec<Document>) -> Result<()> {
+        timed(table.schema().name, "insert", self.inner.insert(table, rows)).await
+    }
+
+    async fn upsert(&self, table: Table, rows: Vec<Document>) -> Result<()> {
+        timed(table.schema().name, "upsert", self.inner.upsert(table, rows)).await
+    }
+
+    async fn find(&self, table: Table, query: Query) -> Result<Vec<Document>> {
+        timed(table.schema().name, "find", self.inner.find(table, query)).await
+    }
+
+    async fn histogram(&self, table: Table, filters: Vec<Filter>, interval: Interval, sum_path: Option<&str>) -> Result<Vec<Bucket>> {
+        timed(table.schema().name, "histogram", self.inner.histogram(table, filters, interval, sum_path)).await
+    }
+
+    async fn delete(&self, table: Table, filters: Vec<Filter>) -> Result<u64> {
+        timed(table.schema().name, "delete", self.inner.delete(table, filters)).await
+    }
+
+    async fn aggregate(&self, table: Table, aggregation: Aggregation) -> Result<Vec<Group>> {
+        timed(table.schema().name, "aggregate", self.inner.aggregate(table, aggregation)).await
+    }
+
+    async fn rollback(&self, group: DbGroup, block: u64) -> Result<()> {
+        timed("", "rollback", self.inner.rollback(group, block)).await
+    }
+}
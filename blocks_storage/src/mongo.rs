@@ -0,0 +1,568 @@
+use std::env;
+
+use async_trait::async_trait;
+use bson::{doc, Bson, Decimal128, Document, Regex};
+use futures::TryStreamExt;
+use mongodb::error::ErrorKind;
+use mongodb::options::{ClientOptions, FindOptions, IndexOptions, InsertManyOptions, ReplaceOptions, ResolverConfig, WriteConcern};
+use mongodb::{Client, Collection, IndexModel};
+
+use crate::schema::DbGroup;
+use crate::values::{integer_text, is_integer, key_string, BigSum, DECIMAL128_DIGITS, SORT_DIGITS};
+use crate::{Aggregation, Bucket, Filter, Group, GroupBy, Interval, Metric, Op, Query, Result, Storage, StorageError, Table};
+
+const DEFAULT_EVENTS_DB: &str = "Nexa_Events_Data_4";
+const DEFAULT_DIAGNOSTICS_DB: &str = "Nexa_Diagnostics";
+
+const DUPLICATE_KEY: i32 = 11000;
+
+pub struct MongoStorage {
+    client: Client,
+    events_db: String,
+    diagnostics_db: String,
+}
+
+impl MongoStorage {
+    // database names default to the historical ones and can be moved with
+    // MONGO_EVENTS_DB / MONGO_DIAGNOSTICS_DB
+    pub async fn connect(uri: &str) -> Result<Self> {
+        let mut options = ClientOptions::parse_with_resolver_config(uri, ResolverConfig::cloudflare()).await?;
+        options.write_concern = Some(WriteConcern::MAJORITY);
+
+        Ok(MongoStorage {
+            client: Client::with_options(options)?,
+            events_db: env::var("MONGO_EVENTS_DB").unwrap_or(DEFAULT_EVENTS_DB.to_owned()),
+            diagnostics_db: env::var("MONGO_DIAGNOSTICS_DB").unwrap_or(DEFAULT_DIAGNOSTICS_DB.to_owned()),
+        })
+    }
+
+    fn collection(&self, table: Table) -> Collection<Document> {
+        let schema = table.schema();
+        let db = match schema.group {
+            DbGroup::Events => &self.events_db,
+            DbGroup::Diagnostics => &self.diagnostics_db,
+        };
+        self.client.database(db).collection(schema.name)
+    }
+
+    // rows without an `_id` get their key as id
+    fn with_ids(table: Table, rows: Vec<Document>) -> Vec<Document> {
+        let schema = table.schema();
+        rows.into_iter().map(|mut row| {
+            if !row.contains_key("_id") {
+                row.insert("_id", key_string(schema, &row));
+            }
+            row
+        }).collect()
+    }
+}
+
+fn cmp_operator(op: Op) -> &'static str {
+    match op {
+        Op::Eq => "$eq",
+        Op::Ne => "$ne",
+        Op::Gt => "$gt",
+        Op::Gte => "$gte",
+        Op::Lt => "$lt",
+        Op::Lte => "$lte",
+    }
+}
+
+// Text of a Decimal128 or of a decimal string too wide for one, the two
+// forms big integers are written in. Plain ints are left to mongo, they are
+// the indexed block columns.
+fn big_integer(value: &Bson) -> Option<String> {
+    match value {
+        Bson::Int32(_) | Bson::Int64(_) => None,
+        value => integer_text(value).filter(|i| is_integer(i)),
+    }
+}
+
+// the number when a Decimal128 holds it exactly
+fn decimal(value: &str) -> Option<Bson> {
+    let fits = value.trim_start_matches('-').len() <= DECIMAL128_DIGITS;
+    value.parse::<Decimal128>().ok().filter(|_| fits).map(Bson::Decimal128)
+}
+
+// decimal strings with `sign` and between `min` and `max` digits
+fn digits(path: &str, sign: &str, min: usize, max: usize) -> Option<Document> {
+    (min <= max).then(|| doc! { path: { "$regex": format!("^{}[0-9]{{{},{}}}$", sign, min, max) } })
+}
+
+fn any_of(filters: Vec<Document>) -> Document {
+    match <[Document; 1]>::try_from(filters) {
+        Ok([single]) => single,
+        Err(filters) => doc! { "$or": filters },
+    }
+}
+
+// Integer comparisons by value, whether the documents hold a number or a
+// decimal string, like matching in memory and the sort keys of the SQL
+// backends. Mongo only orders numbers with numbers and strings with strings,
+// so decimal strings are told apart by sign and length, and compared as text
+// at the same length; they are taken to be written without leading zeros.
+fn integer_filter(path: &str, op: Op, value: &str) -> Document {
+    let equal = || {
+        let mut forms = vec![doc! { path: value }];
+        if let Some(decimal) = decimal(value) {
+            forms.push(doc! { path: decimal });
+        }
+        any_of(forms)
+    };
+    let (up, down) = match op {
+        Op::Eq => return equal(),
+        Op::Ne => return doc! { "$nor": [equal()] },
+        Op::Gt | Op::Gte => (true, op == Op::Gte),
+        Op::Lt | Op::Lte => (false, op == Op::Lte),
+    };
+    let negative = value.starts_with('-');
+    let (sign, other) = if negative { ("-", "") } else { ("", "-") };
+    let n = value.trim_start_matches('-').len();
+    let same_length = |operator: &str| doc! { path: { "$regex": format!("^{}[0-9]{{{}}}$", sign, n), operator: value } };
+
+    // further from zero: more digits, or as many and greater as text
+    let away = [digits(path, sign, n + 1, SORT_DIGITS), Some(same_length("$gt"))];
+    let toward = [digits(path, other, 1, SORT_DIGITS), digits(path, sign, 1, n - 1), Some(same_length("$lt"))];
+    let mut filters: Vec<Document> = if up != negative { away.into_iter().flatten().collect() } else { toward.into_iter().flatten().collect() };
+    let operator = cmp_operator(op);
+    match decimal(value) {
+        Some(decimal) => filters.push(doc! { path: { operator: decimal } }),
+        // wider than any stored number, which are all on one side
+        _ if up == negative => filters.push(doc! { path: { "$type": "number" } }),
+        _ => {}
+    }
+    if down {
+        filters.push(doc! { path: value });
+    }
+    any_of(filters)
+}
+
+pub fn to_mongo_filter(filter: &Filter) -> Document {
+    match filter {
+        Filter::Cmp(path, op, value) => match big_integer(value) {
+            Some(integer) => integer_filter(path, *op, &integer),
+            None => doc! { path: { cmp_operator(*op): value.clone() } },
+        },
+        Filter::In(path, values) => {
+            // big integers match as either form
+            let forms = values.iter().flat_map(|v| match (v, big_integer(v)) {
+                (Bson::Decimal128(_), Some(integer)) => vec![v.clone(), Bson::String(integer)],
+                _ => vec![v.clone()],
+            });
+            doc! { path: { "$in": forms.collect::<Vec<_>>() } }
+        }
+        Filter::Contains(path, needle) => doc! { path: Regex { pattern: regex::escape(needle), options: "i".to_owned() } },
+        Filter::And(filters) => doc! { "$and": filters.iter().map(to_mongo_filter).collect::<Vec<_>>() },
+        Filter::Or(filters) => doc! { "$or": filters.iter().map(to_mongo_filter).collect::<Vec<_>>() },
+        Filter::Not(filter) => doc! { "$nor": [to_mongo_filter(filter)] },
+    }
+}
+
+fn to_mongo_filters(filters: &[Filter]) -> Document {
+    match filters {
+        [] => Document::new(),
+        [single] => to_mongo_filter(single),
+        many => doc! { "$and": many.iter().map(to_mongo_filter).collect::<Vec<_>>() },
+    }
+}
+
+// start of the hour/day/week of a date field, as text
+fn bucket_expr(interval: Interval, time_path: &str) -> Bson {
+    let date = format!("${}", time_path);
+    let (format, date) = match interval {
+        Interval::Hour => ("%Y-%m-%dT%H:00:00Z", Bson::String(date)),
+        Interval::Day => ("%Y-%m-%d", Bson::String(date)),
+        Interval::Week => ("%Y-%m-%d", bson::bson!({ "$dateTrunc": { "date": date, "unit": "week", "startOfWeek": "monday" } })),
+    };
+    bson::bson!({ "$dateToString": { "format": format, "date": date } })
+}
+
+// group keys and values come back as whatever type the documents hold
+fn group_text(v: Option<&Bson>) -> Option<String> {
+    match v? {
+        Bson::Null => None,
+        Bson::String(s) => Some(s.clone()),
+        Bson::Int32(i) => Some(i.to_string()),
+        Bson::Int64(i) => Some(i.to_string()),
+        Bson::Decimal128(d) => Some(d.to_string()),
+        Bson::Boolean(b) => Some(b.to_string()),
+        other => Some(other.clone().into_relaxed_extjson().to_string()),
+    }
+}
+
+// Sums go through Decimal128, which rounds past 34 digits and prints big
+// values as 1.2E+40. The exact sum is kept in base 10^9 limbs instead: each
+// integer is cut into LIMB_DIGITS digit pieces from the right, every piece
+// summed as a long with the sign of its integer, and limb_total carries them
+// into one decimal. The Decimal128 sum is still what groups are ordered by.
+const LIMB_DIGITS: usize = 9;
+const LIMBS: usize = SORT_DIGITS.div_ceil(LIMB_DIGITS);
+
+// sign and digits of integers, 1 and "" for anything else
+fn integer_parts(path: &str) -> Document {
+    let text = doc! { "$convert": { "input": format!("${}", path), "to": "string", "onError": "", "onNull": "" } };
+    let integer = doc! { "$cond": [{ "$regexMatch": { "input": "$$text", "regex": "^-?[0-9]+$" } }, "$$text", ""] };
+    doc! { "$let": { "vars": { "text": text }, "in": { "$let": { "vars": { "integer": integer }, "in": {
+        "sign": { "$cond": [{ "$eq": [{ "$substrCP": ["$$integer", 0, 1] }, "-"] }, -1, 1] },
+        "digits": { "$ltrim": { "input": "$$integer", "chars": "-" } },
+    } } } } }
+}
+
+// l0 (lowest) to l8 of the integer_parts in `_integer`
+fn limb_sums() -> Document {
+    (0..LIMBS).map(|i| {
+        let low = (i * LIMB_DIGITS) as i64;
+        let length = doc! { "$strLenCP": "$_integer.digits" };
+        let start = doc! { "$max": [0, { "$subtract": [length.clone(), low + LIMB_DIGITS as i64] }] };
+        let count = doc! { "$max": [0, { "$min": [LIMB_DIGITS as i64, { "$subtract": [length, low] }] }] };
+        let piece = doc! { "$convert": { "input": { "$substrCP": ["$_integer.digits", start, count] }, "to": "long", "onError": 0_i64, "onNull": 0_i64 } };
+        (format!("l{}", i), Bson::Document(doc! { "$sum": { "$multiply": ["$_integer.sign", piece] } }))
+    }).collect()
+}
+
+// the exact sum of a group from its limbs
+fn limb_total(group: &Document) -> Result<String> {
+    let mut total = BigSum::default();
+    for i in 0..LIMBS {
+        let limb = match group.get(format!("l{}", i)) {
+            Some(Bson::Int32(l)) => *l as i64,
+            Some(Bson::Int64(l)) => *l,
+            None => 0,
+            // $sum turns longs that overflow into doubles
+            Some(other) => return Err(StorageError::Other(format!("sum limb {} is {}, too many rows to add up exactly", i, other))),
+        };
+        if limb != 0 {
+            total.add(&format!("{}{}", limb, "0".repeat(i * LIMB_DIGITS)));
+        }
+    }
+    Ok(total.value())
+}
+
+// $sum gives int32 or int64 depending on size
+fn number(v: Option<&Bson>) -> i64 {
+    match v {
+        Some(Bson::Int32(i)) => *i as i64,
+        Some(Bson::Int64(i)) => *i,
+        Some(Bson::Double(f)) => *f as i64,
+        _ => 0,
+    }
+}
+
+#[async_trait]
+impl Storage for MongoStorage {
+    // indexes on the block and time columns, creating existing ones is a no-op
+    async fn migrate(&self) -> Result<()> {
+        for table in Table::ALL {
+            let schema = table.schema();
+            let paths = schema.block_column.iter().chain(schema.time_column.iter());
+            let indexes: Vec<IndexModel> = paths.map(|path| {
+                IndexModel::builder()
+                    .keys(doc! { *path: 1 })
+                    .options(IndexOptions::builder().name(format!("{}_1", path)).build())
+                    .build()
+            }).collect();
+            if !indexes.is_empty() {
+                self.collection(table).create_indexes(indexes, None).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.client.database(&self.diagnostics_db).run_command(doc! { "ping": 1 }, None).await?;
+        Ok(())
+    }
+
+    async fn insert(&self, table: Table, rows: Vec<Document>) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let options = InsertManyOptions::builder().ordered(false).build();
+        match self.collection(table).insert_many(Self::with_ids(table, rows), options).await {
+            Ok(_) => Ok(()),
+            Err(err) => match err.kind.as_ref() {
+                ErrorKind::BulkWrite(failure) if failure.write_concern_error.is_none()
+                    && failure.write_errors.iter().flatten().all(|e| e.code == DUPLICATE_KEY) => Ok(()),
+                _ => Err(err.into()),
+            },
+        }
+    }
+
+    async fn upsert(&self, table: Table, rows: Vec<Document>) -> Result<()> {
+        let collection = self.collection(table);
+        let options = ReplaceOptions::builder().upsert(true).build();
+        for row in Self::with_ids(table, rows) {
+            let id = row.get("_id").cloned().unwrap_or(Bson::Null);
+            collection.replace_one(doc! { "_id": id }, row, options.clone()).await?;
+        }
+        Ok(())
+    }
+
+    async fn find(&self, table: Table, query: Query) -> Result<Vec<Document>> {
+        let mut sort = Document::new();
+        for (path, ascending) in query.sort.iter() {
+            sort.insert(path, if *ascending { 1 } else { -1 });
+        }
+        let options = FindOptions::builder()
+            .sort((!sort.is_empty()).then_some(sort))
+            .limit(query.limit)
+            .skip(query.skip)
+            .build();
+        let cursor = self.collection(table).find(to_mongo_filters(&query.filters), options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn delete(&self, table: Table, filters: Vec<Filter>) -> Result<u64> {
+        Ok(self.collection(table).delete_many(to_mongo_filters(&filters), None).await?.deleted_count)
+    }
+
+    async fn histogram(&self, table: Table, filters: Vec<Filter>, interval: Interval, sum_path: Option<&str>) -> Result<Vec<Bucket>> {
+        let Some(time_path) = table.schema().time_column else {
+            return Ok(Vec::new());
+        };
+        let mut filter = to_mongo_filters(&filters);
+        filter = doc! { "$and": [filter, { time_path: { "$type": "date" } }] };
+
+        let sum = sum_path.map(|p| Bson::String(format!("${}", p))).unwrap_or(Bson::Int32(0));
+        let pipeline = vec![
+            doc! { "$match": filter },
+            doc! { "$group": {
+                "_id": bucket_expr(interval, time_path),
+                "count": { "$sum": 1 },
+                "sum": { "$sum": sum },
+            } },
+            doc! { "$sort": { "_id": 1 } },
+        ];
+
+        let docs: Vec<Document> = self.collection(table).aggregate(pipeline, None).await?.try_collect().await?;
+        Ok(docs.into_iter().map(|d| Bucket {
+            bucket: d.get_str("_id").unwrap_or_default().to_owned(),
+            count: number(d.get("count")),
+            sum: sum_path.map(|_| number(d.get("sum"))),
+        }).collect())
+    }
+
+    // Sums convert strings too, so values stored as decimal strings count;
+    // past the 34 digits of a Decimal128 they are rounded.
+    async fn aggregate(&self, table: Table, aggregation: Aggregation) -> Result<Vec<Group>> {
+        let schema = table.schema();
+        let mut filters = aggregation.filters.clone();
+        let mut keys = Document::new();
+        for (i, group) in aggregation.group_by.iter().enumerate() {
+            let key = match group {
+                GroupBy::Time(interval) => {
+                    let path = schema.time_column.ok_or_else(|| StorageError::Other(format!("{} has no time column", schema.name)))?;
+                    filters.push(Filter::cmp(path, Op::Ne, Bson::Null));
+                    bucket_expr(*interval, path)
+                }
+                GroupBy::Path(path) => Bson::String(format!("${}", path)),
+            };
+            keys.insert(format!("k{}", i), key);
+        }
+
+        let mut pipeline = vec![doc! { "$match": to_mongo_filters(&filters) }];
+        match &aggregation.metric {
+            Metric::Count => pipeline.push(doc! { "$group": { "_id": keys.clone(), "value": { "$sum": 1 } } }),
+            Metric::Sum(path) => {
+                let value = doc! { "$convert": { "input": format!("${}", path), "to": "decimal", "onError": Bson::Null, "onNull": Bson::Null } };
+                let mut group = doc! { "_id": keys.clone(), "value": { "$sum": value } };
+                group.extend(limb_sums());
+                pipeline.push(doc! { "$addFields": { "_integer": integer_parts(path) } });
+                pipeline.push(doc! { "$group": group });
+            }
+            // one group per (keys, value) first, then those are counted
+            Metric::Distinct(path) => {
+                let mut inner = keys.clone();
+                inner.insert("value", format!("${}", path));
+                let outer: Document = keys.keys().map(|k| (k.clone(), Bson::String(format!("$_id.{}", k)))).collect();
+                pipeline.push(doc! { "$match": { path: { "$exists": true, "$ne": Bson::Null } } });
+                pipeline.push(doc! { "$group": { "_id": inner } });
+                pipeline.push(doc! { "$group": { "_id": outer, "value": { "$sum": 1 } } });
+            }
+        }
+        if aggregation.by_value {
+            pipeline.push(doc! { "$sort": { "value": -1 } });
+        } else {
+            let order: Document = keys.keys().map(|k| (format!("_id.{}", k), Bson::Int32(1))).collect();
+            if !order.is_empty() {
+                pipeline.push(doc! { "$sort": order });
+            }
+        }
+        if let Some(limit) = aggregation.limit {
+            pipeline.push(doc! { "$limit": limit.max(0) });
+        }
+
+        let docs: Vec<Document> = self.collection(table).aggregate(pipeline, None).await?.try_collect().await?;
+        docs.into_iter().map(|d| {
+            let id = d.get_document("_id").ok();
+            let value = match aggregation.metric {
+                Metric::Sum(_) => limb_total(&d)?,
+                _ => group_text(d.get("value")).unwrap_or("0".to_owned()),
+            };
+            Ok(Group { key: (0..aggregation.group_by.len()).map(|i| group_text(id.and_then(|id| id.get(format!("k{}", i))))).collect(), value })
+        }).collect()
+    }
+
+    async fn rollback(&self, group: DbGroup, block: u64) -> Result<()> {
+        for (table, path) in Table::block_scoped(group) {
+            self.collection(table).delete_many(doc! { path: { "$gte": block as i64 } }, None).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::*;
+    use crate::values::{get_path, sort_key};
+
+    // the number a stored value or a filter value stands for
+    fn number(value: &Bson) -> Option<String> {
+        match value {
+            Bson::Int32(i) => Some(i.to_string()),
+            Bson::Int64(i) => Some(i.to_string()),
+            Bson::Decimal128(d) => Some(d.to_string()),
+            _ => None,
+        }
+    }
+
+    // mongo's order within a type: numbers with numbers, strings with strings
+    fn compare(a: &Bson, b: &Bson) -> Option<Ordering> {
+        match (a, b) {
+            (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+            _ => Some(sort_key(&number(a)?)?.cmp(&sort_key(&number(b)?)?)),
+        }
+    }
+
+    fn equal(a: Option<&Bson>, b: &Bson) -> bool {
+        a.is_some_and(|a| compare(a, b).map_or(a == b, Ordering::is_eq))
+    }
+
+    // the part of mongo's query language to_mongo_filter writes
+    fn eval(filter: &Document, doc: &Document) -> bool {
+        filter.iter().all(|(key, condition)| match (key.as_str(), condition) {
+            ("$or", Bson::Array(filters)) => filters.iter().any(|f| eval(f.as_document().unwrap(), doc)),
+            ("$and", Bson::Array(filters)) => filters.iter().all(|f| eval(f.as_document().unwrap(), doc)),
+            ("$nor", Bson::Array(filters)) => !filters.iter().any(|f| eval(f.as_document().unwrap(), doc)),
+            (path, Bson::Document(operators)) if operators.keys().all(|k| k.starts_with('$')) => {
+                let actual = get_path(doc, path);
+                operators.iter().all(|(operator, value)| match operator.as_str() {
+                    "$eq" => equal(actual, value),
+                    "$ne" => !equal(actual, value),
+                    "$in" => value.as_array().unwrap().iter().any(|v| equal(actual, v)),
+                    "$type" => actual.is_some_and(|a| number(a).is_some()),
+                    "$regex" => match actual {
+                        Some(Bson::String(s)) => regex::Regex::new(value.as_str().unwrap()).unwrap().is_match(s),
+                        _ => false,
+                    },
+                    operator => actual.and_then(|a| compare(a, value)).is_some_and(|ordering| match operator {
+                        "$gt" => ordering.is_gt(),
+                        "$gte" => ordering.is_ge(),
+                        "$lt" => ordering.is_lt(),
+                        _ => ordering.is_le(),
+                    }),
+                })
+            }
+            (path, value) => equal(get_path(doc, path), value),
+        })
+    }
+
+    // how the SQL backends compare: integers in any form by their sort keys
+    fn expected(stored: Option<&Bson>, op: Op, value: &str) -> bool {
+        let stored = stored.and_then(|s| match s {
+            Bson::String(s) => Some(s.clone()),
+            s => number(s),
+        });
+        let ordering = stored.and_then(|s| sort_key(&s)).map(|key| key.cmp(&sort_key(value).unwrap()));
+        match op {
+            Op::Eq => ordering.is_some_and(Ordering::is_eq),
+            Op::Ne => !ordering.is_some_and(Ordering::is_eq),
+            Op::Gt => ordering.is_some_and(Ordering::is_gt),
+            Op::Gte => ordering.is_some_and(Ordering::is_ge),
+            Op::Lt => ordering.is_some_and(Ordering::is_lt),
+            Op::Lte => ordering.is_some_and(Ordering::is_le),
+        }
+    }
+
+    // Decimal128 up to 34 digits, decimal strings past that
+    fn stored(decimal: &str) -> Bson {
+        match decimal.trim_start_matches('-').len() <= DECIMAL128_DIGITS {
+            true => Bson::Decimal128(decimal.parse().unwrap()),
+            false => Bson::String(decimal.to_owned()),
+        }
+    }
+
+    // what the $group of limb_sums holds for `values`
+    fn limbs(values: &[&str]) -> Document {
+        let mut sums = [0_i64; LIMBS];
+        for value in values {
+            let (sign, digits) = value.strip_prefix('-').map_or((1, *value), |d| (-1, d));
+            for (i, sum) in sums.iter_mut().enumerate() {
+                let end = digits.len().saturating_sub(i * LIMB_DIGITS);
+                let piece = &digits[end.saturating_sub(LIMB_DIGITS)..end];
+                *sum += sign * piece.parse::<i64>().unwrap_or(0);
+            }
+        }
+        sums.iter().enumerate().map(|(i, s)| (format!("l{}", i), Bson::Int64(*s))).collect()
+    }
+
+    #[test]
+    fn sums_are_exact_past_decimal128() {
+        let u256_max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        let cases: [(&[&str], &str); 5] = [
+            (&["1", "2"], "3"),
+            (&["999999999", "1"], "1000000000"),
+            (&["10000000000000000000000000000000000", "7"], "10000000000000000000000000000000007"),
+            (&[u256_max, "-1"], "115792089237316195423570985008687907853269984665640564039457584007913129639934"),
+            (&["5", "-1000000000000000000000000000000000000000"], "-999999999999999999999999999999999999995"),
+        ];
+        for (values, total) in cases {
+            assert_eq!(limb_total(&limbs(values)).unwrap(), total, "{:?}", values);
+        }
+        assert_eq!(limb_total(&doc! {}).unwrap(), "0");
+        assert!(limb_total(&doc! { "l0": 1.5e19 }).is_err());
+        assert_eq!(limb_sums().len(), LIMBS);
+    }
+
+    #[test]
+    fn big_integers_compare_by_value() {
+        let mut values = vec!["0".to_owned(), "1".to_owned(), "9".to_owned(), "10".to_owned()];
+        for digits in [17, 18, 19, 33, 34, 35, 77, 78] {
+            for first in ['1', '5', '9'] {
+                values.push(format!("{}{}", first, "0".repeat(digits - 1)));
+                values.push(format!("-{}{}", first, "0".repeat(digits - 1)));
+            }
+        }
+        let mut docs: Vec<Document> = values.iter().map(|v| doc! { "args": { "value": stored(v) } }).collect();
+        docs.push(doc! { "args": { "value": 5_i64 } });
+        docs.push(doc! { "args": { "value": "5" } });
+        docs.push(doc! { "args": { "value": "not a number" } });
+        docs.push(doc! { "args": {} });
+
+        let mut literals = vec!["5".to_owned(), "0".to_owned(), format!("1{}", "0".repeat(34))];
+        for digits in ["123456789012345678", "1234567890123456789012345678901234", &format!("1{}", "0".repeat(77))] {
+            literals.push(digits.to_owned());
+            literals.push(format!("-{}", digits));
+        }
+        for literal in &literals {
+            for op in [Op::Eq, Op::Ne, Op::Gt, Op::Gte, Op::Lt, Op::Lte] {
+                let filter = to_mongo_filter(&Filter::Cmp("args.value".to_owned(), op, stored(literal)));
+                for doc in &docs {
+                    let value = get_path(doc, "args.value");
+                    assert_eq!(eval(&filter, doc), expected(value, op, literal), "{:?} {:?} {} with {}", value, op, literal, filter);
+                }
+            }
+            let filter = to_mongo_filter(&Filter::In("args.value".to_owned(), vec![stored(literal)]));
+            for doc in &docs {
+                let value = get_path(doc, "args.value");
+                assert_eq!(eval(&filter, doc), expected(value, Op::Eq, literal), "{:?} in {}", value, literal);
+            }
+        }
+
+        // block columns keep a plain, indexable comparison
+        let filter = to_mongo_filter(&Filter::cmp("block_number", Op::Gte, 5_i64));
+        assert_eq!(filter, doc! { "block_number": { "$gte": 5_i64 } });
+    }
+}
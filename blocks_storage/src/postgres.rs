@@ -0,0 +1,537 @@
+use async_trait::async_trait;
+use bson::{Bson, Document};
+use bytes::Bytes;
+use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod};
+use futures::{pin_mut, SinkExt};
+use tokio_postgres::types::ToSql;
+use tokio_postgres::NoTls;
+
+use crate::schema::{Column, ColumnKind, DbGroup, TableSchema};
+use crate::values::{dedupe, from_doc_json, get_path, integer_text, to_doc_json, to_sql_text};
+use crate::{Aggregation, Bucket, Filter, Group, GroupBy, Interval, Metric, Op, Query, Result, Storage, StorageError, Table};
+
+// applied in order, each one exactly once
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("0001_init", include_str!("../migrations/postgres/0001_init.sql")),
+    ("0002_indexer_status", include_str!("../migrations/postgres/0002_indexer_status.sql")),
+    ("0003_raw_logs", include_str!("../migrations/postgres/0003_raw_logs.sql")),
+    ("0004_value_sort", include_str!("../migrations/postgres/0004_value_sort.sql")),
+    ("0005_decoded_events", include_str!("../migrations/postgres/0005_decoded_events.sql")),
+    ("0006_rollups", include_str!("../migrations/postgres/0006_rollups.sql")),
+    ("0007_projections", include_str!("../migrations/postgres/0007_projections.sql")),
+    ("0008_derived_events", include_str!("../migrations/postgres/0008_derived_events.sql")),
+    ("0009_webhooks", include_str!("../migrations/postgres/0009_webhooks.sql")),
+    ("0010_sinks", include_str!("../migrations/postgres/0010_sinks.sql")),
+    ("0011_webhook_filters", include_str!("../migrations/postgres/0011_webhook_filters.sql")),
+];
+
+const POOL_SIZE: usize = 16;
+
+type Param = Box<dyn ToSql + Sync + Send>;
+
+pub struct PgStorage {
+    pool: Pool,
+}
+
+impl PgStorage {
+    pub async fn connect(url: &str) -> Result<Self> {
+        let config: tokio_postgres::Config = url.parse()?;
+        let manager = Manager::from_config(config, NoTls, ManagerConfig { recycling_method: RecyclingMethod::Fast });
+        let pool = Pool::builder(manager)
+            .max_size(POOL_SIZE)
+            .build()
+            .map_err(|err| StorageError::Other(format!("postgres pool: {}", err)))?;
+        Ok(PgStorage { pool })
+    }
+
+    async fn client(&self) -> Result<Object> {
+        self.pool.get().await.map_err(|err| StorageError::Other(format!("postgres pool: {}", err)))
+    }
+
+    // COPY into a temp table, then move the rows over in one statement so
+    // conflicts are resolved by the primary key
+    async fn write(&self, table: Table, rows: Vec<Document>, update: bool) -> Result<()> {
+        let schema = table.schema();
+        let rows = dedupe(schema, rows);
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let columns = schema.column_names();
+        let staging = format!("staging_{}", schema.name);
+        let conflict = if update {
+            let set = schema.columns.iter()
+                .map(|c| c.name)
+                .chain(["doc"])
+                .map(|name| format!("{0} = EXCLUDED.{0}", name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("DO UPDATE SET {}", set)
+        } else {
+            "DO NOTHING".to_owned()
+        };
+
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        tx.batch_execute(&format!("CREATE TEMP TABLE {} (LIKE {}) ON COMMIT DROP", staging, schema.name)).await?;
+
+        let sink = tx.copy_in(&format!("COPY {} ({}) FROM STDIN", staging, columns)).await?;
+        pin_mut!(sink);
+        sink.send(Bytes::from(copy_text(schema, &rows))).await?;
+        sink.as_mut().finish().await?;
+
+        tx.batch_execute(&format!(
+            "INSERT INTO {0} ({1}) SELECT {1} FROM {2} ON CONFLICT ({3}) {4}",
+            schema.name, columns, staging, schema.key_column_names()?, conflict,
+        )).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+fn sql_type(kind: ColumnKind) -> &'static str {
+    match kind {
+        ColumnKind::Text => "text",
+        ColumnKind::Int => "integer",
+        ColumnKind::BigInt => "bigint",
+        ColumnKind::Numeric => "numeric",
+        ColumnKind::Bool => "boolean",
+        ColumnKind::Timestamp => "timestamptz",
+        ColumnKind::Json => "jsonb",
+    }
+}
+
+// rows in COPY text format: tab separated, \N for NULL
+fn copy_text(schema: &TableSchema, rows: &[Document]) -> String {
+    let mut out = String::new();
+    for row in rows {
+        let fields = schema.columns.iter()
+            .map(|c| to_sql_text(get_path(row, c.path), c.kind))
+            .chain([Some(to_doc_json(row))]);
+        let line = fields
+            .map(|f| f.map(|s| copy_escape(&s)).unwrap_or("\\N".to_owned()))
+            .collect::<Vec<_>>()
+            .join("\t");
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+fn copy_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+// Every value goes over the wire as text and is cast on the server, so one
+// param type covers all columns.
+struct SqlFilter<'a> {
+    schema: &'a TableSchema,
+    params: Vec<Param>,
+}
+
+impl<'a> SqlFilter<'a> {
+    fn new(schema: &'a TableSchema) -> Self {
+        SqlFilter { schema, params: Vec::new() }
+    }
+
+    fn param(&mut self, value: Param) -> String {
+        self.params.push(value);
+        format!("${}", self.params.len())
+    }
+
+    fn value(&self, column: &Column, value: &Bson) -> Result<String> {
+        to_sql_text(Some(value), column.kind)
+            .ok_or_else(|| StorageError::Other(format!("`{}` is not a valid value for {}", value, column.path)))
+    }
+
+    // sql expression of a typed column or a path inside a json column
+    fn expr(&self, path: &str) -> Result<String> {
+        let (column, keys) = self.schema.resolve(path)?;
+        Ok(if keys.is_empty() { column.name.to_owned() } else { json_expr(column, &keys) })
+    }
+
+    // left hand side, param text and its cast for a comparison. Integers
+    // compare with json values as numerics, text that isn't one never matches.
+    fn operand(&self, path: &str, value: &Bson) -> Result<(String, String, &'static str)> {
+        let (column, keys) = self.schema.resolve(path)?;
+        if keys.is_empty() {
+            return Ok((column.name.to_owned(), self.value(column, value)?, sql_type(column.kind)));
+        }
+        let expr = json_expr(column, &keys);
+        if let Some(text) = integer_text(value) {
+            return Ok((json_numeric(&expr), text, "numeric"));
+        }
+        match value {
+            Bson::Boolean(b) => Ok((expr, b.to_string(), "text")),
+            Bson::String(s) => Ok((expr, s.clone(), "text")),
+            other => Err(StorageError::Other(format!("`{}` can't be compared with {}", other, path))),
+        }
+    }
+
+    fn all(&mut self, filters: &[Filter]) -> Result<String> {
+        if filters.is_empty() {
+            return Ok("TRUE".to_owned());
+        }
+        let parts = filters.iter().map(|f| self.translate(f)).collect::<Result<Vec<_>>>()?;
+        Ok(parts.join(" AND "))
+    }
+
+    fn translate(&mut self, filter: &Filter) -> Result<String> {
+        match filter {
+            Filter::Cmp(path, op, value) => {
+                if matches!(value, Bson::Null) {
+                    let expr = self.expr(path)?;
+                    return match op {
+                        Op::Eq => Ok(format!("{} IS NULL", expr)),
+                        Op::Ne => Ok(format!("{} IS NOT NULL", expr)),
+                        _ => Ok("FALSE".to_owned()),
+                    };
+                }
+                let (expr, text, cast) = self.operand(path, value)?;
+                let op = match op {
+                    Op::Eq => "=",
+                    Op::Ne => "IS DISTINCT FROM",
+                    Op::Gt => ">",
+                    Op::Gte => ">=",
+                    Op::Lt => "<",
+                    Op::Lte => "<=",
+                };
+                let p = self.param(Box::new(text));
+                Ok(format!("{} {} {}::text::{}", expr, op, p, cast))
+            }
+            Filter::In(path, values) => {
+                if values.is_empty() {
+                    return Ok("FALSE".to_owned());
+                }
+                let operands = values.iter().map(|v| self.operand(path, v)).collect::<Result<Vec<_>>>()?;
+                // json values of mixed kinds don't share one left hand side
+                if operands.iter().any(|(expr, _, _)| *expr != operands[0].0) {
+                    let parts: Vec<String> = operands.into_iter().map(|(expr, text, cast)| {
+                        let p = self.param(Box::new(text));
+                        format!("{} = {}::text::{}", expr, p, cast)
+                    }).collect();
+                    return Ok(format!("({})", parts.join(" OR ")));
+                }
+                let (expr, cast) = (operands[0].0.clone(), operands[0].2);
+                let texts: Vec<String> = operands.into_iter().map(|(_, text, _)| text).collect();
+                let p = self.param(Box::new(texts));
+                Ok(format!("{} = ANY({}::text[]::{}[])", expr, p, cast))
+            }
+            Filter::Contains(path, needle) => {
+                let expr = self.expr(path)?;
+                let p = self.param(Box::new(needle.clone()));
+                Ok(format!("strpos(lower({}::text), lower({}::text)) > 0", expr, p))
+            }
+            Filter::And(filters) => Ok(format!("({})", self.all(filters)?)),
+            Filter::Or(filters) => {
+                if filters.is_empty() {
+                    return Ok("FALSE".to_owned());
+                }
+                let parts = filters.iter().map(|f| self.translate(f)).collect::<Result<Vec<_>>>()?;
+                Ok(format!("({})", parts.join(" OR ")))
+            }
+            // NULL columns count as not matching the inner filter, like mongo's $nor
+            Filter::Not(filter) => Ok(format!("NOT COALESCE(({}), FALSE)", self.translate(filter)?)),
+        }
+    }
+
+    fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect()
+    }
+}
+
+// Text of a value below a json column. Decimal128 values are stored as
+// {"$numberDecimal": ".."} in the json, so that form is looked at first.
+fn json_expr(column: &Column, keys: &[&str]) -> String {
+    let path = keys.join(",");
+    format!("COALESCE({0} #>> '{{{1},$numberDecimal}}', {0} #>> '{{{1}}}')", column.name, path)
+}
+
+// the json text as numeric, NULL where it isn't an integer
+fn json_numeric(expr: &str) -> String {
+    format!("(CASE WHEN {0} ~ '^-?[0-9]+$' THEN ({0})::numeric END)", expr)
+}
+
+// the statement of an aggregation, its params and the number of group keys
+fn aggregate_sql<'a>(schema: &'a TableSchema, aggregation: &Aggregation) -> Result<(String, SqlFilter<'a>, usize)> {
+    let mut filter = SqlFilter::new(schema);
+    let mut conditions = vec![filter.all(&aggregation.filters)?];
+    let mut keys = Vec::new();
+    for group in &aggregation.group_by {
+        match group {
+            GroupBy::Time(interval) => {
+                let path = schema.time_column.ok_or_else(|| StorageError::Other(format!("{} has no time column", schema.name)))?;
+                let time = schema.typed_column(path)?.name;
+                conditions.push(format!("{} IS NOT NULL", time));
+                keys.push(bucket_expr(*interval, time));
+            }
+            GroupBy::Path(path) => keys.push(format!("({})::text", filter.expr(path)?)),
+        }
+    }
+    // numeric so ORDER BY compares numbers, sent back as text
+    let metric = match &aggregation.metric {
+        Metric::Count => "count(*)::numeric".to_owned(),
+        Metric::Sum(path) => {
+            let (column, json_keys) = schema.resolve(path)?;
+            let value = if json_keys.is_empty() { format!("{}::numeric", column.name) } else { json_numeric(&json_expr(column, &json_keys)) };
+            format!("COALESCE(sum({}), 0)", value)
+        }
+        Metric::Distinct(path) => format!("count(DISTINCT {})::numeric", filter.expr(path)?),
+    };
+
+    let mut columns: Vec<String> = keys.iter().enumerate().map(|(i, k)| format!("{} AS k{}", k, i)).collect();
+    columns.push(format!("({})::text AS value", metric));
+    let positions = (1..=keys.len()).map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+    let mut sql = format!("SELECT {} FROM {} WHERE {}", columns.join(", "), schema.name, conditions.join(" AND "));
+    if !keys.is_empty() {
+        sql.push_str(&format!(" GROUP BY {}", positions));
+    }
+    if aggregation.by_value {
+        sql.push_str(&format!(" ORDER BY {} DESC", metric));
+    } else if !keys.is_empty() {
+        sql.push_str(&format!(" ORDER BY {}", positions));
+    }
+    if let Some(limit) = aggregation.limit {
+        sql.push_str(&format!(" LIMIT {}", limit.max(0)));
+    }
+    Ok((sql, filter, keys.len()))
+}
+
+// one delete per block scoped table of `group`, bound to the block as $1
+fn rollback_statements(group: DbGroup) -> Result<Vec<String>> {
+    Table::block_scoped(group).map(|(table, path)| {
+        let schema = table.schema();
+        Ok(format!("DELETE FROM {} WHERE {} >= $1", schema.name, schema.typed_column(path)?.name))
+    }).collect()
+}
+
+// start of the hour/day/week of a timestamptz column, as text
+fn bucket_expr(interval: Interval, time: &str) -> String {
+    let (unit, format) = match interval {
+        Interval::Hour => ("hour", "YYYY-MM-DD\"T\"HH24:00:00\"Z\""),
+        Interval::Day => ("day", "YYYY-MM-DD"),
+        Interval::Week => ("week", "YYYY-MM-DD"),
+    };
+    format!("to_char(date_trunc('{}', {} AT TIME ZONE 'UTC'), '{}')", unit, time, format)
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    async fn migrate(&self) -> Result<()> {
+        let mut client = self.client().await?;
+        client.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version    TEXT PRIMARY KEY,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+        ).await?;
+
+        for (version, sql) in MIGRATIONS {
+            let applied = client.query_opt("SELECT 1 FROM schema_migrations WHERE version = $1", &[version]).await?;
+            if applied.is_some() {
+                continue;
+            }
+            let tx = client.transaction().await?;
+            tx.batch_execute(sql).await?;
+            tx.execute("INSERT INTO schema_migrations (version) VALUES ($1)", &[version]).await?;
+            tx.commit().await?;
+        }
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.client().await?.batch_execute("SELECT 1").await?;
+        Ok(())
+    }
+
+    async fn insert(&self, table: Table, rows: Vec<Document>) -> Result<()> {
+        self.write(table, rows, false).await
+    }
+
+    async fn upsert(&self, table: Table, rows: Vec<Document>) -> Result<()> {
+        self.write(table, rows, true).await
+    }
+
+    async fn find(&self, table: Table, query: Query) -> Result<Vec<Document>> {
+        let schema = table.schema();
+        let mut filter = SqlFilter::new(schema);
+        let mut sql = format!("SELECT doc::text FROM {} WHERE {}", schema.name, filter.all(&query.filters)?);
+
+        if !query.sort.is_empty() {
+            let order = query.sort.iter()
+                .map(|(path, asc)| filter.expr(path).map(|e| format!("{} {}", e, if *asc { "ASC" } else { "DESC" })))
+                .collect::<Result<Vec<_>>>()?;
+            sql.push_str(&format!(" ORDER BY {}", order.join(", ")));
+        }
+        if let Some(limit) = query.limit {
+            sql.push_str(&format!(" LIMIT {}", limit.max(0)));
+        }
+        if let Some(skip) = query.skip {
+            sql.push_str(&format!(" OFFSET {}", skip));
+        }
+
+        let client = self.client().await?;
+        let rows = client.query(&sql, &filter.params()).await?;
+        rows.iter().map(|row| {
+            let text: String = row.get(0);
+            from_doc_json(&text).ok_or_else(|| StorageError::Other(format!("unreadable document in {}", schema.name)))
+        }).collect()
+    }
+
+    async fn delete(&self, table: Table, filters: Vec<Filter>) -> Result<u64> {
+        let schema = table.schema();
+        let mut filter = SqlFilter::new(schema);
+        let sql = format!("DELETE FROM {} WHERE {}", schema.name, filter.all(&filters)?);
+        let client = self.client().await?;
+        Ok(client.execute(&sql, &filter.params()).await?)
+    }
+
+    async fn histogram(&self, table: Table, filters: Vec<Filter>, interval: Interval, sum_path: Option<&str>) -> Result<Vec<Bucket>> {
+        let schema = table.schema();
+        let Some(time_path) = schema.time_column else {
+            return Ok(Vec::new());
+        };
+        let time = schema.typed_column(time_path)?.name;
+        let sum = match sum_path {
+            Some(path) => format!("sum({})::bigint", schema.typed_column(path)?.name),
+            None => "NULL::bigint".to_owned(),
+        };
+
+        let mut filter = SqlFilter::new(schema);
+        let sql = format!(
+            "SELECT {bucket} AS bucket, count(*), {sum}
+             FROM {table} WHERE {time} IS NOT NULL AND {filters}
+             GROUP BY 1 ORDER BY 1",
+            bucket = bucket_expr(interval, time),
+            table = schema.name,
+            filters = filter.all(&filters)?,
+        );
+
+        let client = self.client().await?;
+        let rows = client.query(&sql, &filter.params()).await?;
+        Ok(rows.iter().map(|row| Bucket {
+            bucket: row.get(0),
+            count: row.get(1),
+            sum: if sum_path.is_some() { Some(row.get::<_, Option<i64>>(2).unwrap_or_default()) } else { None },
+        }).collect())
+    }
+
+    async fn aggregate(&self, table: Table, aggregation: Aggregation) -> Result<Vec<Group>> {
+        let (sql, filter, keys) = aggregate_sql(table.schema(), &aggregation)?;
+        let client = self.client().await?;
+        let rows = client.query(&sql, &filter.params()).await?;
+        Ok(rows.iter().map(|row| Group {
+            key: (0..keys).map(|i| row.get(i)).collect(),
+            value: row.get(keys),
+        }).collect())
+    }
+
+    async fn rollback(&self, group: DbGroup, block: u64) -> Result<()> {
+        let mut client = self.client().await?;
+        let tx = client.transaction().await?;
+        for sql in rollback_statements(group)? {
+            tx.execute(&sql, &[&(block as i64)]).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+
+    use super::*;
+
+    fn params(filter: &SqlFilter) -> Vec<String> {
+        filter.params.iter().map(|p| format!("{:?}", p)).collect()
+    }
+
+    // args.value as a numeric, NULL where it isn't an integer
+    const VALUE: &str = "(CASE WHEN COALESCE(args #>> '{value,$numberDecimal}', args #>> '{value}') ~ '^-?[0-9]+$' \
+                         THEN (COALESCE(args #>> '{value,$numberDecimal}', args #>> '{value}'))::numeric END)";
+    const FROM: &str = "COALESCE(args #>> '{from,$numberDecimal}', args #>> '{from}')";
+
+    fn aggregation(filters: Vec<Filter>, group_by: Vec<GroupBy>, metric: Metric) -> Aggregation {
+        Aggregation { filters, group_by, metric, by_value: false, limit: None }
+    }
+
+    #[test]
+    fn builds_aggregate_statements() {
+        let events = Table::DecodedEvents.schema();
+        let mut top = aggregation(
+            vec![Filter::eq("event", "Transfer"), Filter::cmp("args.value", Op::Gte, Bson::Int64(10))],
+            vec![GroupBy::Time(Interval::Day), GroupBy::Path("args.from".to_owned())],
+            Metric::Sum("args.value".to_owned()),
+        );
+        top.by_value = true;
+        top.limit = Some(5);
+        let (sql, filter, keys) = aggregate_sql(events, &top).unwrap();
+        let sum = format!("COALESCE(sum({}), 0)", VALUE);
+        assert_eq!(sql, format!(
+            "SELECT to_char(date_trunc('day', block_time AT TIME ZONE 'UTC'), 'YYYY-MM-DD') AS k0, ({})::text AS k1, ({})::text AS value \
+             FROM decoded_events WHERE event = $1::text::text AND {} >= $2::text::numeric AND block_time IS NOT NULL \
+             GROUP BY 1, 2 ORDER BY {} DESC LIMIT 5",
+            FROM, sum, VALUE, sum,
+        ));
+        assert_eq!((params(&filter), keys), (vec![r#""Transfer""#.to_owned(), r#""10""#.to_owned()], 2));
+
+        let (sql, filter, keys) = aggregate_sql(events, &aggregation(Vec::new(), vec![GroupBy::Path("contract".to_owned())], Metric::Count)).unwrap();
+        assert_eq!(sql, "SELECT (contract)::text AS k0, (count(*)::numeric)::text AS value FROM decoded_events WHERE TRUE GROUP BY 1 ORDER BY 1");
+        assert_eq!((filter.params.len(), keys), (0, 1));
+
+        let (sql, _, keys) = aggregate_sql(events, &aggregation(Vec::new(), Vec::new(), Metric::Distinct("args.from".to_owned()))).unwrap();
+        assert_eq!(sql, format!("SELECT (count(DISTINCT {})::numeric)::text AS value FROM decoded_events WHERE TRUE", FROM));
+        assert_eq!(keys, 0);
+
+        assert!(aggregate_sql(events, &aggregation(Vec::new(), Vec::new(), Metric::Sum("nope".to_owned()))).is_err());
+        let no_time = aggregation(Vec::new(), vec![GroupBy::Time(Interval::Hour)], Metric::Count);
+        assert!(aggregate_sql(Table::SinkCursors.schema(), &no_time).is_err());
+    }
+
+    #[test]
+    fn translates_filters() {
+        let mut filter = SqlFilter::new(Table::DecodedEvents.schema());
+        let sql = filter.translate(&Filter::And(vec![
+            // json values of mixed kinds get one comparison each
+            Filter::In("args.value".to_owned(), vec![Bson::Int64(1), Bson::String("x".to_owned())]),
+            Filter::In("block_number".to_owned(), vec![Bson::Int64(1), Bson::Int64(2)]),
+            Filter::In("block_number".to_owned(), Vec::new()),
+            Filter::cmp("tx_hash", Op::Eq, Bson::Null),
+            Filter::cmp("tx_hash", Op::Ne, "0xab"),
+            Filter::Not(Box::new(Filter::Contains("contract".to_owned(), "tok".to_owned()))),
+        ])).unwrap();
+        assert_eq!(sql, format!(
+            "(({} = $1::text::numeric OR COALESCE(args #>> '{{value,$numberDecimal}}', args #>> '{{value}}') = $2::text::text) \
+             AND block_number = ANY($3::text[]::bigint[]) AND FALSE AND tx_hash IS NULL AND tx_hash IS DISTINCT FROM $4::text::text \
+             AND NOT COALESCE((strpos(lower(contract::text), lower($5::text)) > 0), FALSE))",
+            VALUE,
+        ));
+        assert_eq!(params(&filter), vec![r#""1""#, r#""x""#, r#"["1", "2"]"#, r#""0xab""#, r#""tok""#]);
+
+        assert!(filter.translate(&Filter::cmp("args.value", Op::Eq, Bson::Double(1.5))).is_err());
+        assert!(filter.translate(&Filter::cmp("block_number", Op::Eq, "ten")).is_err());
+        assert_eq!(filter.translate(&Filter::cmp("tx_hash", Op::Gt, Bson::Null)).unwrap(), "FALSE");
+    }
+
+    #[test]
+    fn rolls_back_the_group_tables_only() {
+        let events = rollback_statements(DbGroup::Events).unwrap();
+        assert!(events.contains(&"DELETE FROM decoded_events WHERE block_number >= $1".to_owned()));
+        assert!(events.contains(&"DELETE FROM token_balance_snapshots WHERE block_number >= $1".to_owned()));
+        assert!(events.iter().all(|sql| sql.ends_with("block_number >= $1")), "{:?}", events);
+        assert_eq!(rollback_statements(DbGroup::Diagnostics).unwrap(), vec![
+            "DELETE FROM blocks_table WHERE block_num >= $1",
+            "DELETE FROM txns_table WHERE block_num >= $1",
+            "DELETE FROM internal_txns_table WHERE block_num >= $1",
+        ]);
+    }
+
+    #[test]
+    fn escapes_copy_rows() {
+        let text = copy_text(Table::SinkCursors.schema(), &[doc! { "sink": "a\tb\\c\nd", "block_number": 5i64 }, doc! { "sink": "e" }]);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], [r#"a\tb\\c\nd"#, "5", r#"{"sink":"a\\tb\\\\c\\nd","block_number":{"$numberLong":"5"}}"#].join("\t"));
+        assert_eq!(lines[1], ["e", r"\N", r#"{"sink":"e"}"#].join("\t"));
+    }
+}
@@ -0,0 +1,581 @@
+use crate::{Result, StorageError};
+
+// Tables shared by every backend. Mongo stores the documents as they are and
+// only uses the key and block column; SQL backends get one typed column per
+// entry in `columns` next to the full document.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnKind {
+    Text,
+    Int,
+    BigInt,
+    // 256-bit integers, Decimal128 or decimal strings in documents. Sorting
+    // and ranges go through a `*_sort` text column next to it, see
+    // blocks_common::values::sort_key
+    Numeric,
+    Bool,
+    Timestamp,
+    Json,
+}
+
+#[derive(Debug)]
+pub struct Column {
+    // dotted path inside the document
+    pub path: &'static str,
+    // sql column name
+    pub name: &'static str,
+    pub kind: ColumnKind,
+}
+
+// mongo database a table lives in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DbGroup {
+    // written by the `blocks` crawler
+    Diagnostics,
+    // written by blocks_one
+    Events,
+}
+
+#[derive(Debug)]
+pub struct TableSchema {
+    pub name: &'static str,
+    pub group: DbGroup,
+    // paths that make a row unique, the idempotency key of every write
+    pub key: &'static [&'static str],
+    // path holding the block number, used to unwind reorgs
+    pub block_column: Option<&'static str>,
+    // path holding the block timestamp, used by histograms
+    pub time_column: Option<&'static str>,
+    pub columns: &'static [Column],
+}
+
+impl TableSchema {
+    pub fn column(&self, path: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.path == path)
+    }
+
+    // SQL backends can only filter, sort and key on typed columns
+    pub(crate) fn typed_column(&self, path: &str) -> Result<&Column> {
+        self.column(path).ok_or_else(|| StorageError::Other(format!("{} has no column for `{}`", self.name, path)))
+    }
+
+    // A typed column, or a json column and the keys below it for paths like
+    // `args.value`. Keys end up inside sql text, so only identifiers pass.
+    pub(crate) fn resolve<'p>(&self, path: &'p str) -> Result<(&Column, Vec<&'p str>)> {
+        if let Some(column) = self.column(path) {
+            return Ok((column, Vec::new()));
+        }
+        let json = self.columns.iter()
+            .filter(|c| c.kind == ColumnKind::Json)
+            .find_map(|c| Some((c, path.strip_prefix(c.path)?.strip_prefix('.')?)));
+        let Some((column, rest)) = json else {
+            return Err(StorageError::Other(format!("{} has no column for `{}`", self.name, path)));
+        };
+        let keys: Vec<&str> = rest.split('.').collect();
+        if keys.iter().any(|k| k.is_empty() || !k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+            return Err(StorageError::Other(format!("invalid path `{}`", path)));
+        }
+        Ok((column, keys))
+    }
+
+    // typed columns followed by `doc`
+    pub(crate) fn column_names(&self) -> String {
+        self.columns.iter().map(|c| c.name).chain(["doc"]).collect::<Vec<_>>().join(", ")
+    }
+
+    pub(crate) fn key_column_names(&self) -> Result<String> {
+        let names = self.key.iter().map(|path| self.typed_column(path).map(|c| c.name)).collect::<Result<Vec<_>>>()?;
+        Ok(names.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Table {
+    Blocks,
+    Txns,
+    InternalTxns,
+    EventBlocks,
+    RawLogs,
+    DecodedEvents,
+    Rollups,
+    ProjectionVersions,
+    ProjectionEntities,
+    ProjectionCheckpoints,
+    DerivedEvents,
+    WebhookSubscriptions,
+    WebhookCheckpoints,
+    WebhookDeliveries,
+    SinkCheckpoints,
+    SinkCursors,
+    TokenTransfers,
+    TokenApprovals,
+    TokenBalances,
+    TokenMetadata,
+    IndexerStatus,
+}
+
+impl Table {
+    pub const ALL: [Table; 21] = [
+        Table::Blocks,
+        Table::Txns,
+        Table::InternalTxns,
+        Table::EventBlocks,
+        Table::RawLogs,
+        Table::DecodedEvents,
+        Table::Rollups,
+        Table::ProjectionVersions,
+        Table::ProjectionEntities,
+        Table::ProjectionCheckpoints,
+        Table::DerivedEvents,
+        Table::WebhookSubscriptions,
+        Table::WebhookCheckpoints,
+        Table::WebhookDeliveries,
+        Table::SinkCheckpoints,
+        Table::SinkCursors,
+        Table::TokenTransfers,
+        Table::TokenApprovals,
+        Table::TokenBalances,
+        Table::TokenMetadata,
+        Table::IndexerStatus,
+    ];
+
+    pub fn schema(&self) -> &'static TableSchema {
+        match self {
+            Table::Blocks => &BLOCKS,
+            Table::Txns => &TXNS,
+            Table::InternalTxns => &INTERNAL_TXNS,
+            Table::EventBlocks => &EVENT_BLOCKS,
+            Table::RawLogs => &RAW_LOGS,
+            Table::DecodedEvents => &DECODED_EVENTS,
+            Table::Rollups => &ROLLUPS,
+            Table::ProjectionVersions => &PROJECTION_VERSIONS,
+            Table::ProjectionEntities => &PROJECTION_ENTITIES,
+            Table::ProjectionCheckpoints => &PROJECTION_CHECKPOINTS,
+            Table::DerivedEvents => &DERIVED_EVENTS,
+            Table::WebhookSubscriptions => &WEBHOOK_SUBSCRIPTIONS,
+            Table::WebhookCheckpoints => &WEBHOOK_CHECKPOINTS,
+            Table::WebhookDeliveries => &WEBHOOK_DELIVERIES,
+            Table::SinkCheckpoints => &SINK_CHECKPOINTS,
+            Table::SinkCursors => &SINK_CURSORS,
+            Table::TokenTransfers => &TOKEN_TRANSFERS,
+            Table::TokenApprovals => &TOKEN_APPROVALS,
+            Table::TokenBalances => &TOKEN_BALANCES,
+            Table::TokenMetadata => &TOKEN_METADATA,
+            Table::IndexerStatus => &INDEXER_STATUS,
+        }
+    }
+
+    // the tables of `group` holding block scoped rows, with their block column
+    pub fn block_scoped(group: DbGroup) -> impl Iterator<Item = (Table, &'static str)> {
+        Table::ALL.into_iter().filter(move |table| table.schema().group == group)
+            .filter_map(|table| Some((table, table.schema().block_column?)))
+    }
+}
+
+const fn col(path: &'static str, name: &'static str, kind: ColumnKind) -> Column {
+    Column { path, name, kind }
+}
+
+use ColumnKind::*;
+
+static BLOCKS: TableSchema = TableSchema {
+    name: "blocks_table",
+    group: DbGroup::Diagnostics,
+    key: &["block_hash"],
+    block_column: Some("block_num"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("block_num", "block_num", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("block_time", "block_time", Timestamp),
+        col("num_of_transactions", "num_of_transactions", Int),
+        col("num_of_events", "num_of_events", Int),
+        col("event_signatures", "event_signatures", Text),
+    ],
+};
+
+static TXNS: TableSchema = TableSchema {
+    name: "txns_table",
+    group: DbGroup::Diagnostics,
+    key: &["txn_hash"],
+    block_column: Some("block_num"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("block_num", "block_num", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("block_time", "block_time", Timestamp),
+        col("txn_hash", "txn_hash", Text),
+        col("status", "status", Int),
+        col("input", "input", Text),
+        col("selector", "selector", Text),
+        col("function", "function", Text),
+        col("function_signature", "function_signature", Text),
+        col("abi_contract", "abi_contract", Text),
+        col("args", "args", Json),
+        col("decode_error", "decode_error", Text),
+        col("revert", "revert", Json),
+        col("revert.kind", "revert_kind", Text),
+        col("revert.reason", "revert_reason", Text),
+        col("revert.error", "revert_error", Text),
+    ],
+};
+
+static INTERNAL_TXNS: TableSchema = TableSchema {
+    name: "internal_txns_table",
+    group: DbGroup::Diagnostics,
+    key: &["txn_hash", "trace_address"],
+    block_column: Some("block_num"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("block_num", "block_num", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("block_time", "block_time", Timestamp),
+        col("txn_hash", "txn_hash", Text),
+        col("depth", "depth", Int),
+        col("trace_address", "trace_address", Json),
+        col("call_type", "call_type", Text),
+        col("from", "from_address", Text),
+        col("to", "to_address", Text),
+        col("value", "value", Numeric),
+        col("value_sort", "value_sort", Text),
+        col("gas", "gas", Numeric),
+        col("gas_used", "gas_used", Numeric),
+        col("input", "input", Text),
+        col("output", "output", Text),
+        col("error", "error", Text),
+    ],
+};
+
+static EVENT_BLOCKS: TableSchema = TableSchema {
+    name: "events_table",
+    group: DbGroup::Events,
+    key: &["block_hash"],
+    block_column: Some("block_number"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("block_number", "block_number", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("block_time", "block_time", Timestamp),
+        col("events", "events", Text),
+        col("num_of_events", "num_of_events", Int),
+    ],
+};
+
+// logs of the registered contracts as the node returned them, enough to
+// rebuild events_table without the node
+static RAW_LOGS: TableSchema = TableSchema {
+    name: "raw_logs",
+    group: DbGroup::Events,
+    key: &["block_hash", "log_index"],
+    block_column: Some("block_number"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("block_number", "block_number", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("block_time", "block_time", Timestamp),
+        col("tx_hash", "tx_hash", Text),
+        col("tx_index", "tx_index", BigInt),
+        col("log_index", "log_index", BigInt),
+        col("address", "address", Text),
+        col("topic0", "topic0", Text),
+        col("topics", "topics", Json),
+        col("data", "data", Text),
+    ],
+};
+
+// one row per log of a registered contract its ABI decodes, args by param
+// name; what aggregations and handlers read
+static DECODED_EVENTS: TableSchema = TableSchema {
+    name: "decoded_events",
+    group: DbGroup::Events,
+    key: &["block_hash", "log_index"],
+    block_column: Some("block_number"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("block_number", "block_number", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("block_time", "block_time", Timestamp),
+        col("tx_hash", "tx_hash", Text),
+        col("log_index", "log_index", BigInt),
+        col("address", "address", Text),
+        col("contract", "contract", Text),
+        col("abi_version", "abi_version", Text),
+        col("event", "event", Text),
+        col("signature", "signature", Text),
+        col("args", "args", Json),
+    ],
+};
+
+// Event counts kept by the indexer, see blocks_common::rollups. Block rows
+// are the source, hour/day/total rows are recomputed from them; dimensions a
+// rollup doesn't use are empty strings so they can be part of the key.
+static ROLLUPS: TableSchema = TableSchema {
+    name: "rollups",
+    group: DbGroup::Events,
+    key: &["rollup", "granularity", "bucket", "contract", "event", "address"],
+    block_column: Some("block_number"),
+    time_column: Some("bucket_time"),
+    columns: &[
+        col("rollup", "rollup", Text),
+        col("granularity", "granularity", Text),
+        col("bucket", "bucket", Text),
+        col("bucket_time", "bucket_time", Timestamp),
+        col("contract", "contract", Text),
+        col("event", "event", Text),
+        col("address", "address", Text),
+        // block rows only
+        col("block_number", "block_number", BigInt),
+        col("count", "count", BigInt),
+    ],
+};
+
+// Entities written by blocks_common::projections. Every block an entity
+// changes in leaves a version, which is what reorgs unwind and handlers read;
+// projection_entities holds the latest one of each for the api.
+static PROJECTION_VERSIONS: TableSchema = TableSchema {
+    name: "projection_versions",
+    group: DbGroup::Events,
+    key: &["projection", "entity", "id", "block_number"],
+    block_column: Some("block_number"),
+    time_column: None,
+    columns: &[
+        col("projection", "projection", Text),
+        col("entity", "entity", Text),
+        col("id", "id", Text),
+        col("block_number", "block_number", BigInt),
+        col("deleted", "deleted", Bool),
+        col("data", "data", Json),
+    ],
+};
+
+static PROJECTION_ENTITIES: TableSchema = TableSchema {
+    name: "projection_entities",
+    group: DbGroup::Events,
+    key: &["projection", "entity", "id"],
+    block_column: None,
+    time_column: None,
+    columns: &[
+        col("projection", "projection", Text),
+        col("entity", "entity", Text),
+        col("id", "id", Text),
+        col("block_number", "block_number", BigInt),
+        col("data", "data", Json),
+    ],
+};
+
+// one row per processed chunk, the highest is where a projection resumes
+static PROJECTION_CHECKPOINTS: TableSchema = TableSchema {
+    name: "projection_checkpoints",
+    group: DbGroup::Events,
+    key: &["projection", "block_number"],
+    block_column: Some("block_number"),
+    time_column: None,
+    columns: &[
+        col("projection", "projection", Text),
+        col("block_number", "block_number", BigInt),
+    ],
+};
+
+// events a projection emits while handling one, `seq` orders those of the
+// same source log
+static DERIVED_EVENTS: TableSchema = TableSchema {
+    name: "derived_events",
+    group: DbGroup::Events,
+    key: &["projection", "block_number", "log_index", "seq"],
+    block_column: Some("block_number"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("projection", "projection", Text),
+        col("block_number", "block_number", BigInt),
+        col("log_index", "log_index", BigInt),
+        col("seq", "seq", BigInt),
+        col("block_time", "block_time", Timestamp),
+        col("tx_hash", "tx_hash", Text),
+        col("name", "name", Text),
+        col("data", "data", Json),
+    ],
+};
+
+// see blocks_common::webhooks, managed through blocks_query's /webhooks
+static WEBHOOK_SUBSCRIPTIONS: TableSchema = TableSchema {
+    name: "webhook_subscriptions",
+    group: DbGroup::Events,
+    key: &["id"],
+    block_column: None,
+    time_column: None,
+    columns: &[
+        col("id", "id", Text),
+        col("url", "url", Text),
+        col("secret", "secret", Text),
+        col("contract", "contract", Text),
+        col("event", "event", Text),
+        col("where", "conditions", Json),
+        col("filter", "filter_expr", Text),
+        col("from_block", "from_block", BigInt),
+        col("created_at", "created_at", Timestamp),
+    ],
+};
+
+// like projection_checkpoints, the highest is where a subscription resumes
+static WEBHOOK_CHECKPOINTS: TableSchema = TableSchema {
+    name: "webhook_checkpoints",
+    group: DbGroup::Events,
+    key: &["subscription", "block_number"],
+    block_column: Some("block_number"),
+    time_column: None,
+    columns: &[
+        col("subscription", "subscription", Text),
+        col("block_number", "block_number", BigInt),
+    ],
+};
+
+// One row per matched event and subscription, updated on every attempt.
+// What was sent stays logged through reorgs, the block hash keeps events of
+// a replaced block apart from the ones sent before.
+static WEBHOOK_DELIVERIES: TableSchema = TableSchema {
+    name: "webhook_deliveries",
+    group: DbGroup::Events,
+    key: &["subscription", "block_hash", "log_index"],
+    block_column: None,
+    time_column: Some("updated_at"),
+    columns: &[
+        col("subscription", "subscription", Text),
+        col("block_number", "block_number", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("log_index", "log_index", BigInt),
+        col("tx_hash", "tx_hash", Text),
+        col("event", "event", Text),
+        col("status", "status", Text),
+        col("attempts", "attempts", BigInt),
+        col("response_status", "response_status", BigInt),
+        col("error", "error", Text),
+        col("updated_at", "updated_at", Timestamp),
+    ],
+};
+
+// see blocks_common::sinks, the highest is what a sink has published through
+static SINK_CHECKPOINTS: TableSchema = TableSchema {
+    name: "sink_checkpoints",
+    group: DbGroup::Events,
+    key: &["sink", "block_number"],
+    block_column: Some("block_number"),
+    time_column: None,
+    columns: &[
+        col("sink", "sink", Text),
+        col("block_number", "block_number", BigInt),
+    ],
+};
+
+// The highest block a sink has published, which a rollback leaves alone: a
+// cursor above the checkpoint means published blocks were unwound and the
+// sink owes its consumers a reorg message.
+static SINK_CURSORS: TableSchema = TableSchema {
+    name: "sink_cursors",
+    group: DbGroup::Events,
+    key: &["sink"],
+    block_column: None,
+    time_column: None,
+    columns: &[
+        col("sink", "sink", Text),
+        col("block_number", "block_number", BigInt),
+    ],
+};
+
+static TOKEN_TRANSFERS: TableSchema = TableSchema {
+    name: "token_transfers",
+    group: DbGroup::Events,
+    key: &["_id"],
+    block_column: Some("block_number"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("_id", "id", Text),
+        col("block_number", "block_number", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("block_time", "block_time", Timestamp),
+        col("tx_hash", "tx_hash", Text),
+        col("log_index", "log_index", BigInt),
+        col("token", "token", Text),
+        col("standard", "standard", Text),
+        col("operator", "operator", Text),
+        col("from", "from_address", Text),
+        col("to", "to_address", Text),
+        col("token_id", "token_id", Numeric),
+        col("value", "value", Numeric),
+        col("value_sort", "value_sort", Text),
+    ],
+};
+
+static TOKEN_APPROVALS: TableSchema = TableSchema {
+    name: "token_approvals",
+    group: DbGroup::Events,
+    key: &["_id"],
+    block_column: Some("block_number"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("_id", "id", Text),
+        col("block_number", "block_number", BigInt),
+        col("block_hash", "block_hash", Text),
+        col("block_time", "block_time", Timestamp),
+        col("tx_hash", "tx_hash", Text),
+        col("log_index", "log_index", BigInt),
+        col("token", "token", Text),
+        col("standard", "standard", Text),
+        col("owner", "owner", Text),
+        col("spender", "spender", Text),
+        col("value", "value", Numeric),
+        col("value_sort", "value_sort", Text),
+    ],
+};
+
+static TOKEN_BALANCES: TableSchema = TableSchema {
+    name: "token_balance_snapshots",
+    group: DbGroup::Events,
+    key: &["_id"],
+    block_column: Some("block_number"),
+    time_column: Some("block_time"),
+    columns: &[
+        col("_id", "id", Text),
+        col("token", "token", Text),
+        col("holder", "holder", Text),
+        col("token_id", "token_id", Numeric),
+        col("block_number", "block_number", BigInt),
+        col("block_time", "block_time", Timestamp),
+        col("delta", "delta", Numeric),
+    ],
+};
+
+static TOKEN_METADATA: TableSchema = TableSchema {
+    name: "token_metadata",
+    group: DbGroup::Events,
+    key: &["_id"],
+    block_column: None,
+    time_column: None,
+    columns: &[
+        col("_id", "token", Text),
+        col("standard", "standard", Text),
+        col("name", "name", Text),
+        col("symbol", "symbol", Text),
+        col("decimals", "decimals", Int),
+    ],
+};
+
+// one row per (indexer, contract), rewritten periodically by every running
+// indexer so blocks_query can report how fresh the data is
+static INDEXER_STATUS: TableSchema = TableSchema {
+    name: "indexer_status",
+    group: DbGroup::Diagnostics,
+    key: &["indexer", "contract"],
+    block_column: None,
+    time_column: None,
+    columns: &[
+        col("indexer", "indexer", Text),
+        col("contract", "contract", Text),
+        col("name", "name", Text),
+        col("mode", "mode", Text),
+        col("indexed_block", "indexed_block", BigInt),
+        col("chain_head", "chain_head", BigInt),
+        col("lag", "lag", BigInt),
+        col("failed_ranges", "failed_ranges", Json),
+        col("updated_at", "updated_at", Timestamp),
+    ],
+};
@@ -0,0 +1,576 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bson::{Bson, Document};
+use rusqlite::functions::{Aggregate, Context, FunctionFlags};
+use rusqlite::types::{Value, ValueRef};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use tokio::task;
+
+use crate::schema::{Column, ColumnKind, DbGroup, TableSchema};
+use crate::values::{dedupe, from_doc_json, get_path, integer_text, sort_key, to_doc_json, to_sql_text, BigSum};
+use crate::{Aggregation, Bucket, Filter, Group, GroupBy, Interval, Metric, Op, Query, Result, Storage, StorageError, Table};
+
+// applied in order, each one exactly once
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("0001_init", include_str!("../migrations/sqlite/0001_init.sql")),
+    ("0002_indexer_status", include_str!("../migrations/sqlite/0002_indexer_status.sql")),
+    ("0003_raw_logs", include_str!("../migrations/sqlite/0003_raw_logs.sql")),
+    ("0004_value_sort", include_str!("../migrations/sqlite/0004_value_sort.sql")),
+    ("0005_decoded_events", include_str!("../migrations/sqlite/0005_decoded_events.sql")),
+    ("0006_rollups", include_str!("../migrations/sqlite/0006_rollups.sql")),
+    ("0007_projections", include_str!("../migrations/sqlite/0007_projections.sql")),
+    ("0008_derived_events", include_str!("../migrations/sqlite/0008_derived_events.sql")),
+    ("0009_webhooks", include_str!("../migrations/sqlite/0009_webhooks.sql")),
+    ("0010_sinks", include_str!("../migrations/sqlite/0010_sinks.sql")),
+    ("0011_webhook_filters", include_str!("../migrations/sqlite/0011_webhook_filters.sql")),
+];
+
+// Single file database for laptops, tests and small deployments. rusqlite is
+// blocking, so every call runs on tokio's blocking pool behind one connection.
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    // ":memory:" gives a throwaway database
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL; PRAGMA busy_timeout = 5000;")?;
+        register_functions(&conn)?;
+        Ok(SqliteStorage { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|_| StorageError::Other("sqlite connection poisoned".to_owned()))?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|err| StorageError::Other(format!("sqlite task: {}", err)))?
+    }
+
+    // one prepared statement per batch inside a transaction, conflicts are
+    // resolved by the primary key
+    async fn write(&self, table: Table, rows: Vec<Document>, update: bool) -> Result<()> {
+        let schema = table.schema();
+        let rows = dedupe(schema, rows);
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let conflict = if update {
+            let set = schema.columns.iter()
+                .map(|c| c.name)
+                .chain(["doc"])
+                .map(|name| format!("{0} = excluded.{0}", name))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("DO UPDATE SET {}", set)
+        } else {
+            "DO NOTHING".to_owned()
+        };
+        let placeholders = vec!["?"; schema.columns.len() + 1].join(", ");
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT ({}) {}",
+            schema.name, schema.column_names(), placeholders, schema.key_column_names()?, conflict,
+        );
+        let values: Vec<Vec<Value>> = rows.iter().map(|row| row_values(schema, row)).collect();
+
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            {
+                let mut stmt = tx.prepare(&sql)?;
+                for row in values {
+                    stmt.execute(params_from_iter(row))?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        }).await
+    }
+}
+
+fn row_values(schema: &TableSchema, row: &Document) -> Vec<Value> {
+    schema.columns.iter()
+        .map(|c| to_sqlite_value(get_path(row, c.path), c.kind).unwrap_or(Value::Null))
+        .chain([Value::Text(to_doc_json(row))])
+        .collect()
+}
+
+// Value for a typed column, None for NULL or values the column can't hold.
+// Timestamps are stored as unix milliseconds so they compare as numbers.
+fn to_sqlite_value(value: Option<&Bson>, kind: ColumnKind) -> Option<Value> {
+    match (kind, value?) {
+        (_, Bson::Null) => None,
+        (ColumnKind::Timestamp, Bson::DateTime(t)) => Some(Value::Integer(t.timestamp_millis())),
+        (ColumnKind::Bool, Bson::Boolean(b)) => Some(Value::Integer(*b as i64)),
+        (ColumnKind::Int | ColumnKind::BigInt, v) => to_sql_text(Some(v), kind)?.parse::<i64>().ok().map(Value::Integer),
+        (ColumnKind::Timestamp | ColumnKind::Bool, _) => None,
+        (_, v) => to_sql_text(Some(v), kind).map(Value::Text),
+    }
+}
+
+// builds the WHERE clause with positional params
+struct SqlFilter<'a> {
+    schema: &'a TableSchema,
+    params: Vec<Value>,
+}
+
+impl<'a> SqlFilter<'a> {
+    fn new(schema: &'a TableSchema) -> Self {
+        SqlFilter { schema, params: Vec::new() }
+    }
+
+    fn value(&self, column: &Column, value: &Bson) -> Result<Value> {
+        to_sqlite_value(Some(value), column.kind)
+            .ok_or_else(|| StorageError::Other(format!("`{}` is not a valid value for {}", value, column.path)))
+    }
+
+    // sql expression of a typed column or a path inside a json column
+    fn expr(&self, path: &str) -> Result<String> {
+        let (column, keys) = self.schema.resolve(path)?;
+        Ok(if keys.is_empty() { column.name.to_owned() } else { json_expr(column, &keys) })
+    }
+
+    // left hand side and param of a comparison. Integers compare with json
+    // values through their sort keys, so Decimal128 and decimal strings order
+    // as numbers.
+    fn operand(&self, path: &str, value: &Bson) -> Result<(String, Value)> {
+        let (column, keys) = self.schema.resolve(path)?;
+        if keys.is_empty() {
+            return Ok((column.name.to_owned(), self.value(column, value)?));
+        }
+        let expr = json_expr(column, &keys);
+        if let Some(key) = integer_text(value).and_then(|i| sort_key(&i)) {
+            return Ok((format!("sort_key({})", expr), Value::Text(key)));
+        }
+        match value {
+            // json_extract gives 1/0 for booleans
+            Bson::Boolean(b) => Ok((expr, Value::Integer(*b as i64))),
+            Bson::String(s) => Ok((expr, Value::Text(s.clone()))),
+            other => Err(StorageError::Other(format!("`{}` can't be compared with {}", other, path))),
+        }
+    }
+
+    fn all(&mut self, filters: &[Filter]) -> Result<String> {
+        if filters.is_empty() {
+            return Ok("1".to_owned());
+        }
+        let parts = filters.iter().map(|f| self.translate(f)).collect::<Result<Vec<_>>>()?;
+        Ok(parts.join(" AND "))
+    }
+
+    fn translate(&mut self, filter: &Filter) -> Result<String> {
+        match filter {
+            Filter::Cmp(path, op, value) => {
+                if matches!(value, Bson::Null) {
+                    let expr = self.expr(path)?;
+                    return match op {
+                        Op::Eq => Ok(format!("{} IS NULL", expr)),
+                        Op::Ne => Ok(format!("{} IS NOT NULL", expr)),
+                        _ => Ok("0".to_owned()),
+                    };
+                }
+                let (expr, value) = self.operand(path, value)?;
+                self.params.push(value);
+                let op = match op {
+                    Op::Eq => "=",
+                    Op::Ne => "IS NOT",
+                    Op::Gt => ">",
+                    Op::Gte => ">=",
+                    Op::Lt => "<",
+                    Op::Lte => "<=",
+                };
+                Ok(format!("{} {} ?", expr, op))
+            }
+            Filter::In(path, values) => {
+                if values.is_empty() {
+                    return Ok("0".to_owned());
+                }
+                let operands = values.iter().map(|v| self.operand(path, v)).collect::<Result<Vec<_>>>()?;
+                // json values of mixed kinds don't share one left hand side
+                if operands.iter().any(|(expr, _)| *expr != operands[0].0) {
+                    let parts: Vec<String> = operands.into_iter().map(|(expr, value)| {
+                        self.params.push(value);
+                        format!("{} = ?", expr)
+                    }).collect();
+                    return Ok(format!("({})", parts.join(" OR ")));
+                }
+                let expr = operands[0].0.clone();
+                self.params.extend(operands.into_iter().map(|(_, value)| value));
+                Ok(format!("{} IN ({})", expr, vec!["?"; values.len()].join(", ")))
+            }
+            Filter::Contains(path, needle) => {
+                let expr = self.expr(path)?;
+                self.params.push(Value::Text(needle.clone()));
+                Ok(format!("instr(lower({}), lower(?)) > 0", expr))
+            }
+            Filter::And(filters) => Ok(format!("({})", self.all(filters)?)),
+            Filter::Or(filters) => {
+                if filters.is_empty() {
+                    return Ok("0".to_owned());
+                }
+                let parts = filters.iter().map(|f| self.translate(f)).collect::<Result<Vec<_>>>()?;
+                Ok(format!("({})", parts.join(" OR ")))
+            }
+            // NULL columns count as not matching the inner filter, like mongo's $nor
+            Filter::Not(filter) => Ok(format!("NOT COALESCE(({}), 0)", self.translate(filter)?)),
+        }
+    }
+}
+
+// Value below a json column. Decimal128 values are stored as
+// {"$numberDecimal": ".."} in the json, so that form is looked at first.
+fn json_expr(column: &Column, keys: &[&str]) -> String {
+    let path = keys.join(".");
+    format!("COALESCE(json_extract({0}, '$.{1}.\"$numberDecimal\"'), json_extract({0}, '$.{1}'))", column.name, path)
+}
+
+// one delete per block scoped table of `group`, bound to the block as ?1
+fn rollback_statements(group: DbGroup) -> Result<Vec<String>> {
+    Table::block_scoped(group).map(|(table, path)| {
+        let schema = table.schema();
+        Ok(format!("DELETE FROM {} WHERE {} >= ?1", schema.name, schema.typed_column(path)?.name))
+    }).collect()
+}
+
+// start of the hour/day/week of a unix millis column, as text
+fn bucket_expr(interval: Interval, time: &str) -> String {
+    match interval {
+        Interval::Hour => format!("strftime('%Y-%m-%dT%H:00:00Z', {} / 1000, 'unixepoch')", time),
+        Interval::Day => format!("strftime('%Y-%m-%d', {} / 1000, 'unixepoch')", time),
+        // the monday on or before the day
+        Interval::Week => format!("strftime('%Y-%m-%d', {} / 1000, 'unixepoch', '-6 days', 'weekday 1')", time),
+    }
+}
+
+// text of an integer, text or real argument, None for anything else
+fn arg_text(value: ValueRef<'_>) -> Option<String> {
+    match value {
+        ValueRef::Integer(i) => Some(i.to_string()),
+        ValueRef::Real(f) if f.fract() == 0.0 => Some(format!("{:.0}", f)),
+        ValueRef::Text(t) => std::str::from_utf8(t).ok().map(str::to_owned),
+        _ => None,
+    }
+}
+
+// big_sum(x): lossless sum of integers and decimal strings, as text
+struct BigSumAggregate;
+
+impl Aggregate<BigSum, String> for BigSumAggregate {
+    fn init(&self, _: &mut Context<'_>) -> rusqlite::Result<BigSum> {
+        Ok(BigSum::default())
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, sum: &mut BigSum) -> rusqlite::Result<()> {
+        let value = ctx.get_raw(0);
+        if value == ValueRef::Null {
+            return Ok(());
+        }
+        match arg_text(value) {
+            Some(text) if sum.add(&text) => Ok(()),
+            _ => Err(rusqlite::Error::UserFunctionError(format!("big_sum: {:?} is not an integer", value).into())),
+        }
+    }
+
+    fn finalize(&self, _: &mut Context<'_>, sum: Option<BigSum>) -> rusqlite::Result<String> {
+        Ok(sum.unwrap_or_default().value())
+    }
+}
+
+// sort_key(x) and big_sum(x), used by json path comparisons and aggregations
+fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC;
+    conn.create_scalar_function("sort_key", 1, flags, |ctx| Ok(arg_text(ctx.get_raw(0)).and_then(|t| sort_key(&t))))?;
+    conn.create_aggregate_function("big_sum", 1, flags, BigSumAggregate)
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> Result<()> {
+        self.with_conn(|conn| {
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version    TEXT PRIMARY KEY,
+                    applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+                )",
+            )?;
+            for (version, sql) in MIGRATIONS {
+                let applied = conn
+                    .query_row("SELECT 1 FROM schema_migrations WHERE version = ?1", params![version], |_| Ok(()))
+                    .optional()?;
+                if applied.is_some() {
+                    continue;
+                }
+                let tx = conn.transaction()?;
+                tx.execute_batch(sql)?;
+                tx.execute("INSERT INTO schema_migrations (version) VALUES (?1)", params![version])?;
+                tx.commit()?;
+            }
+            Ok(())
+        }).await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.with_conn(|conn| Ok(conn.execute_batch("SELECT 1")?)).await
+    }
+
+    async fn insert(&self, table: Table, rows: Vec<Document>) -> Result<()> {
+        self.write(table, rows, false).await
+    }
+
+    async fn upsert(&self, table: Table, rows: Vec<Document>) -> Result<()> {
+        self.write(table, rows, true).await
+    }
+
+    async fn find(&self, table: Table, query: Query) -> Result<Vec<Document>> {
+        let schema = table.schema();
+        let mut filter = SqlFilter::new(schema);
+        let mut sql = format!("SELECT doc FROM {} WHERE {}", schema.name, filter.all(&query.filters)?);
+
+        if !query.sort.is_empty() {
+            let order = query.sort.iter()
+                .map(|(path, asc)| filter.expr(path).map(|e| format!("{} {}", e, if *asc { "ASC" } else { "DESC" })))
+                .collect::<Result<Vec<_>>>()?;
+            sql.push_str(&format!(" ORDER BY {}", order.join(", ")));
+        }
+        // sqlite only takes OFFSET after a LIMIT, -1 is no limit
+        if query.limit.is_some() || query.skip.is_some() {
+            sql.push_str(&format!(" LIMIT {}", query.limit.map(|l| l.max(0)).unwrap_or(-1)));
+        }
+        if let Some(skip) = query.skip {
+            sql.push_str(&format!(" OFFSET {}", skip));
+        }
+
+        let params = filter.params;
+        let texts = self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params_from_iter(params), |row| row.get::<_, String>(0))?;
+            Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+        }).await?;
+
+        texts.iter().map(|text| {
+            from_doc_json(text).ok_or_else(|| StorageError::Other(format!("unreadable document in {}", schema.name)))
+        }).collect()
+    }
+
+    async fn delete(&self, table: Table, filters: Vec<Filter>) -> Result<u64> {
+        let schema = table.schema();
+        let mut filter = SqlFilter::new(schema);
+        let sql = format!("DELETE FROM {} WHERE {}", schema.name, filter.all(&filters)?);
+        let params = filter.params;
+        self.with_conn(move |conn| Ok(conn.execute(&sql, params_from_iter(params))? as u64)).await
+    }
+
+    async fn histogram(&self, table: Table, filters: Vec<Filter>, interval: Interval, sum_path: Option<&str>) -> Result<Vec<Bucket>> {
+        let schema = table.schema();
+        let Some(time_path) = schema.time_column else {
+            return Ok(Vec::new());
+        };
+        let time = schema.typed_column(time_path)?.name;
+        let sum = match sum_path {
+            Some(path) => format!("sum({})", schema.typed_column(path)?.name),
+            None => "NULL".to_owned(),
+        };
+
+        let mut filter = SqlFilter::new(schema);
+        let sql = format!(
+            "SELECT {bucket} AS bucket, count(*), {sum}
+             FROM {table} WHERE {time} IS NOT NULL AND {filters}
+             GROUP BY 1 ORDER BY 1",
+            bucket = bucket_expr(interval, time),
+            table = schema.name,
+            filters = filter.all(&filters)?,
+        );
+
+        let params = filter.params;
+        let has_sum = sum_path.is_some();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params_from_iter(params), |row| {
+                Ok(Bucket {
+                    bucket: row.get(0)?,
+                    count: row.get(1)?,
+                    sum: if has_sum { Some(row.get::<_, Option<i64>>(2)?.unwrap_or_default()) } else { None },
+                })
+            })?;
+            Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+        }).await
+    }
+
+    async fn aggregate(&self, table: Table, aggregation: Aggregation) -> Result<Vec<Group>> {
+        let schema = table.schema();
+        let mut filter = SqlFilter::new(schema);
+        let mut conditions = vec![filter.all(&aggregation.filters)?];
+        let mut keys = Vec::new();
+        for group in &aggregation.group_by {
+            match group {
+                GroupBy::Time(interval) => {
+                    let path = schema.time_column.ok_or_else(|| StorageError::Other(format!("{} has no time column", schema.name)))?;
+                    let time = schema.typed_column(path)?.name;
+                    conditions.push(format!("{} IS NOT NULL", time));
+                    keys.push(bucket_expr(*interval, time));
+                }
+                GroupBy::Path(path) => keys.push(format!("CAST({} AS TEXT)", filter.expr(path)?)),
+            }
+        }
+        let (metric, order) = match &aggregation.metric {
+            Metric::Count => ("count(*)".to_owned(), "value DESC"),
+            Metric::Sum(path) => (format!("big_sum({})", filter.expr(path)?), "sort_key(value) DESC"),
+            Metric::Distinct(path) => (format!("count(DISTINCT {})", filter.expr(path)?), "value DESC"),
+        };
+
+        let columns = keys.iter().chain([&metric]).enumerate()
+            .map(|(i, expr)| if i < keys.len() { format!("{} AS k{}", expr, i) } else { format!("{} AS value", expr) })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let positions = (1..=keys.len()).map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+        let mut sql = format!("SELECT {} FROM {} WHERE {}", columns, schema.name, conditions.join(" AND "));
+        if !keys.is_empty() {
+            sql.push_str(&format!(" GROUP BY {}", positions));
+        }
+        if aggregation.by_value {
+            sql.push_str(&format!(" ORDER BY {}", order));
+        } else if !keys.is_empty() {
+            sql.push_str(&format!(" ORDER BY {}", positions));
+        }
+        if let Some(limit) = aggregation.limit {
+            sql.push_str(&format!(" LIMIT {}", limit.max(0)));
+        }
+
+        let params = filter.params;
+        let width = keys.len();
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params_from_iter(params), |row| {
+                let key = (0..width).map(|i| row.get::<_, Option<String>>(i)).collect::<rusqlite::Result<Vec<_>>>()?;
+                let value = arg_text(row.get_ref(width)?).unwrap_or("0".to_owned());
+                Ok(Group { key, value })
+            })?;
+            Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+        }).await
+    }
+
+    async fn rollback(&self, group: DbGroup, block: u64) -> Result<()> {
+        let statements = rollback_statements(group)?;
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            for sql in statements {
+                tx.execute(&sql, params![block as i64])?;
+            }
+            tx.commit()?;
+            Ok(())
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, DateTime};
+
+    use super::*;
+
+    // 2024-01-01 is a monday
+    const JAN_1: i64 = 1_704_067_200_000;
+    const HOUR: i64 = 3_600_000;
+
+    fn event(block: i64, millis: i64, from: &str, value: Bson) -> Document {
+        doc! {
+            "block_number": block,
+            "block_hash": format!("0x{:064x}", block),
+            "block_time": DateTime::from_millis(millis),
+            "log_index": 0i64,
+            "address": "0xaa",
+            "contract": "token",
+            "abi_version": "v1",
+            "event": "Transfer",
+            "signature": "Transfer(address,address,uint256)",
+            "args": { "from": from, "value": value },
+        }
+    }
+
+    async fn storage() -> SqliteStorage {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        storage.migrate().await.unwrap();
+        let big = format!("1{}", "0".repeat(40));
+        storage.insert(Table::DecodedEvents, vec![
+            event(1, JAN_1 + HOUR / 6, "0x01", Bson::Int64(5)),
+            event(2, JAN_1 + HOUR * 5 / 6, "0x01", Bson::Decimal128("7".parse().unwrap())),
+            event(3, JAN_1 + HOUR + HOUR / 12, "0x02", Bson::String(big.clone())),
+            event(4, JAN_1 + HOUR * 34, "0x02", Bson::String(big)),
+            event(5, JAN_1 + HOUR * 24 * 7, "0x03", Bson::Int64(-2)),
+        ]).await.unwrap();
+        storage
+    }
+
+    fn by(group_by: Vec<GroupBy>, metric: Metric) -> Aggregation {
+        Aggregation { filters: Vec::new(), group_by, metric, by_value: false, limit: None }
+    }
+
+    fn pairs(groups: Vec<Group>) -> Vec<(Vec<Option<String>>, String)> {
+        groups.into_iter().map(|g| (g.key, g.value)).collect()
+    }
+
+    fn key(k: &str) -> Vec<Option<String>> {
+        vec![Some(k.to_owned())]
+    }
+
+    #[tokio::test]
+    async fn buckets_by_hour_day_and_monday_weeks() {
+        let storage = storage().await;
+        let hours = storage.aggregate(Table::DecodedEvents, by(vec![GroupBy::Time(Interval::Hour)], Metric::Count)).await.unwrap();
+        assert_eq!(pairs(hours), vec![
+            (key("2024-01-01T00:00:00Z"), "2".to_owned()),
+            (key("2024-01-01T01:00:00Z"), "1".to_owned()),
+            (key("2024-01-02T10:00:00Z"), "1".to_owned()),
+            (key("2024-01-08T00:00:00Z"), "1".to_owned()),
+        ]);
+        let days = storage.aggregate(Table::DecodedEvents, by(vec![GroupBy::Time(Interval::Day)], Metric::Count)).await.unwrap();
+        assert_eq!(pairs(days), vec![
+            (key("2024-01-01"), "3".to_owned()),
+            (key("2024-01-02"), "1".to_owned()),
+            (key("2024-01-08"), "1".to_owned()),
+        ]);
+        let weeks = storage.aggregate(Table::DecodedEvents, by(vec![GroupBy::Time(Interval::Week)], Metric::Count)).await.unwrap();
+        assert_eq!(pairs(weeks), vec![(key("2024-01-01"), "4".to_owned()), (key("2024-01-08"), "1".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn sums_args_exactly_whatever_form_they_are_stored_in() {
+        let storage = storage().await;
+        let sums = storage.aggregate(Table::DecodedEvents, by(vec![GroupBy::Path("args.from".to_owned())], Metric::Sum("args.value".to_owned()))).await.unwrap();
+        assert_eq!(pairs(sums), vec![
+            (key("0x01"), "12".to_owned()),
+            (key("0x02"), format!("2{}", "0".repeat(40))),
+            (key("0x03"), "-2".to_owned()),
+        ]);
+
+        let mut largest = by(vec![GroupBy::Path("args.from".to_owned())], Metric::Sum("args.value".to_owned()));
+        largest.by_value = true;
+        largest.limit = Some(2);
+        let largest = storage.aggregate(Table::DecodedEvents, largest).await.unwrap();
+        assert_eq!(largest.iter().map(|g| g.key[0].as_deref().unwrap()).collect::<Vec<_>>(), vec!["0x02", "0x01"]);
+
+        let mut filtered = by(Vec::new(), Metric::Distinct("args.from".to_owned()));
+        filtered.filters = vec![Filter::cmp("args.value", Op::Gt, Bson::Int64(6))];
+        assert_eq!(pairs(storage.aggregate(Table::DecodedEvents, filtered).await.unwrap()), vec![(Vec::new(), "2".to_owned())]);
+    }
+
+    #[tokio::test]
+    async fn rollback_leaves_other_groups_alone() {
+        let storage = storage().await;
+        storage.insert(Table::Blocks, vec![doc! { "block_num": 4i64, "block_hash": "0xb4", "block_time": DateTime::from_millis(JAN_1) }]).await.unwrap();
+        storage.rollback(DbGroup::Events, 3).await.unwrap();
+        let blocks: Vec<i64> = storage.find(Table::DecodedEvents, Query::new()).await.unwrap().iter().map(|e| e.get_i64("block_number").unwrap()).collect();
+        assert_eq!(blocks, vec![1, 2]);
+        assert_eq!(storage.find(Table::Blocks, Query::new()).await.unwrap().len(), 1);
+        storage.rollback(DbGroup::Diagnostics, 4).await.unwrap();
+        assert!(storage.find(Table::Blocks, Query::new()).await.unwrap().is_empty());
+    }
+}
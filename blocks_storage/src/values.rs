@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use bson::{Bson, Document};
+use primitive_types::U512;
+
+use crate::schema::{ColumnKind, TableSchema};
+
+// value at a dotted path
+pub fn get_path<'a>(doc: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut parts = path.split('.');
+    let mut value = doc.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_document()?.get(part)?;
+    }
+    Some(value)
+}
+
+fn key_part(value: Option<&Bson>) -> String {
+    match value {
+        None | Some(Bson::Null) => String::new(),
+        Some(Bson::String(s)) => s.clone(),
+        Some(Bson::Array(items)) => items.iter().map(|i| key_part(Some(i))).collect::<Vec<_>>().join("."),
+        Some(other) => other.to_string(),
+    }
+}
+
+// the row's key paths joined into one string, used as mongo `_id`
+pub fn key_string(schema: &TableSchema, doc: &Document) -> String {
+    schema.key.iter().map(|path| key_part(get_path(doc, path))).collect::<Vec<_>>().join(":")
+}
+
+// keeps the last row per key, a batch may not touch the same key twice in sql
+pub fn dedupe(schema: &TableSchema, rows: Vec<Document>) -> Vec<Document> {
+    let mut positions = HashMap::<String, usize>::new();
+    let mut deduped = Vec::<Document>::with_capacity(rows.len());
+    for row in rows {
+        let key = key_string(schema, &row);
+        match positions.get(&key) {
+            Some(i) => deduped[*i] = row,
+            None => {
+                positions.insert(key, deduped.len());
+                deduped.push(row);
+            }
+        }
+    }
+    deduped
+}
+
+// Text form of a value for a typed sql column, None for NULL or values the
+// column can't hold.
+pub fn to_sql_text(value: Option<&Bson>, kind: ColumnKind) -> Option<String> {
+    let value = value?;
+    match (kind, value) {
+        (_, Bson::Null) => None,
+        (ColumnKind::Json, v) => Some(v.clone().into_relaxed_extjson().to_string()),
+        (ColumnKind::Timestamp, Bson::DateTime(t)) => t.try_to_rfc3339_string().ok(),
+        (ColumnKind::Timestamp, _) => None,
+        (ColumnKind::Bool, Bson::Boolean(b)) => Some(b.to_string()),
+        (ColumnKind::Bool, _) => None,
+        (ColumnKind::Int | ColumnKind::BigInt | ColumnKind::Numeric, Bson::Int32(i)) => Some(i.to_string()),
+        (ColumnKind::Int | ColumnKind::BigInt | ColumnKind::Numeric, Bson::Int64(i)) => Some(i.to_string()),
+        (ColumnKind::Int | ColumnKind::BigInt, Bson::String(s)) => s.parse::<i64>().ok().map(|i| i.to_string()),
+        (ColumnKind::Numeric, Bson::String(s)) => is_decimal(s).then(|| s.clone()),
+        (ColumnKind::Numeric, Bson::Decimal128(d)) => Some(d.to_string()),
+        (ColumnKind::Int | ColumnKind::BigInt | ColumnKind::Numeric, _) => None,
+        (ColumnKind::Text, Bson::String(s)) => Some(s.clone()),
+        (ColumnKind::Text, v) => Some(v.to_string()),
+    }
+}
+
+// significant digits a decimal128 holds exactly
+pub const DECIMAL128_DIGITS: usize = 34;
+// digits of 2^256, every 256-bit magnitude fits
+pub const SORT_DIGITS: usize = 78;
+
+fn is_decimal(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+// signed decimal of at most 256 bits' worth of digits
+pub fn is_integer(s: &str) -> bool {
+    is_decimal(s) && s.trim_start_matches('-').len() <= SORT_DIGITS
+}
+
+// Integer a filter value stands for when compared with a json path, where
+// Decimal128, int and decimal string all mean the same number. Strings only
+// count when they are too long for a Decimal128, the form integers that
+// don't fit one are written in.
+pub fn integer_text(value: &Bson) -> Option<String> {
+    let text = match value {
+        Bson::Int32(i) => i.to_string(),
+        Bson::Int64(i) => i.to_string(),
+        Bson::Decimal128(d) => d.to_string(),
+        Bson::String(s) if s.trim_start_matches('-').len() > DECIMAL128_DIGITS => s.clone(),
+        _ => return None,
+    };
+    is_decimal(&text).then_some(text)
+}
+
+// Fixed width digits that compare as strings the way the signed integers
+// compare as numbers: "1" and the zero padded value for non-negatives, "0"
+// and the nines' complement for negatives. Only digits, so no collation
+// reorders them.
+pub fn sort_key(decimal: &str) -> Option<String> {
+    let (negative, digits) = match decimal.strip_prefix('-') {
+        Some(digits) => (!digits.trim_start_matches('0').is_empty(), digits),
+        None => (false, decimal),
+    };
+    if !is_integer(decimal) {
+        return None;
+    }
+    let padded = format!("{:0>width$}", digits, width = SORT_DIGITS);
+    if negative {
+        let complement: String = padded.chars().map(|c| (b'9' - c as u8 + b'0') as char).collect();
+        Some(format!("0{}", complement))
+    } else {
+        Some(format!("1{}", padded))
+    }
+}
+
+// Sum of signed decimal strings without the float rounding sqlite's sum()
+// does past 2^53. 512 bits leave room for any number of 256-bit values.
+#[derive(Debug, Default)]
+pub struct BigSum {
+    credited: U512,
+    debited: U512,
+}
+
+impl BigSum {
+    pub fn add(&mut self, decimal: &str) -> bool {
+        if !is_decimal(decimal) {
+            return false;
+        }
+        let (negative, digits) = match decimal.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, decimal),
+        };
+        let Ok(value) = U512::from_dec_str(digits) else { return false };
+        let total = if negative { &mut self.debited } else { &mut self.credited };
+        *total = total.saturating_add(value);
+        true
+    }
+
+    pub fn value(&self) -> String {
+        if self.credited >= self.debited {
+            (self.credited - self.debited).to_string()
+        } else {
+            format!("-{}", self.debited - self.credited)
+        }
+    }
+}
+
+// full document as canonical extended json, reads turn it back without losing types
+pub fn to_doc_json(doc: &Document) -> String {
+    Bson::Document(doc.clone()).into_canonical_extjson().to_string()
+}
+
+pub fn from_doc_json(s: &str) -> Option<Document> {
+    let value: serde_json::Value = serde_json::from_str(s).ok()?;
+    match Bson::try_from(value).ok()? {
+        Bson::Document(doc) => Some(doc),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_keys_order_like_the_numbers() {
+        let max = "9".repeat(SORT_DIGITS);
+        let numbers = [format!("-{}", max), "-1000".into(), "-999".into(), "-1".into(), "0".into(), "1".into(), "999".into(), "1000".into(), max.clone()];
+        let keys: Vec<String> = numbers.iter().map(|n| sort_key(n).unwrap()).collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]), "{:?}", keys);
+        assert!(keys.iter().all(|k| k.len() == SORT_DIGITS + 1));
+        // -0 and leading zeros don't make another number
+        assert_eq!(sort_key("-0"), sort_key("0"));
+        assert_eq!(sort_key("007"), sort_key("7"));
+    }
+
+    #[test]
+    fn sort_keys_cover_78_digits_only() {
+        let u256_max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        assert_eq!(u256_max.len(), SORT_DIGITS);
+        assert!(sort_key(u256_max).unwrap() > sort_key(&"9".repeat(SORT_DIGITS - 1)).unwrap());
+        assert_eq!(sort_key(&"1".repeat(SORT_DIGITS + 1)), None);
+        assert_eq!(sort_key("1.5"), None);
+        assert_eq!(sort_key("-"), None);
+        assert_eq!(sort_key(""), None);
+    }
+
+    #[test]
+    fn sort_keys_agree_across_the_decimal128_boundary() {
+        let widest = "9".repeat(DECIMAL128_DIGITS);
+        let next = format!("1{}", "0".repeat(DECIMAL128_DIGITS));
+        assert!(sort_key(&widest).unwrap() < sort_key(&next).unwrap());
+        assert!(sort_key(&format!("-{}", next)).unwrap() < sort_key(&format!("-{}", widest)).unwrap());
+    }
+
+    #[test]
+    fn integer_text_takes_strings_only_past_decimal128() {
+        let widest = "9".repeat(DECIMAL128_DIGITS);
+        let next = format!("1{}", "0".repeat(DECIMAL128_DIGITS));
+        assert_eq!(integer_text(&Bson::Decimal128(widest.parse().unwrap())), Some(widest.clone()));
+        assert_eq!(integer_text(&Bson::String(widest)), None);
+        assert_eq!(integer_text(&Bson::String(next.clone())), Some(next.clone()));
+        assert_eq!(integer_text(&Bson::String(format!("-{}", next))), Some(format!("-{}", next)));
+        assert_eq!(integer_text(&Bson::Int64(-5)), Some("-5".to_owned()));
+        assert_eq!(integer_text(&Bson::String(format!("{}x", next))), None);
+    }
+
+    #[test]
+    fn big_sums_are_exact() {
+        let u256_max = "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+        let mut sum = BigSum::default();
+        assert!(sum.add(u256_max));
+        assert!(sum.add(u256_max));
+        assert_eq!(sum.value(), "231584178474632390847141970017375815706539969331281128078915168015826259279870");
+
+        let mut sum = BigSum::default();
+        for n in ["9007199254740993", "-1", "-9007199254740993", "-5"] {
+            assert!(sum.add(n));
+        }
+        assert_eq!(sum.value(), "-6");
+        assert!(!sum.add("1e3"));
+        assert!(!sum.add(""));
+        assert_eq!(sum.value(), "-6");
+    }
+}